@@ -0,0 +1,86 @@
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use log::{info, warn};
+
+/// Name every branded string falls back to
+pub const DEFAULT_APP_NAME: &str = "ProxyCat";
+
+/// File in the data directory the branding is read from
+pub const BRANDING_FILE: &str = "branding.json";
+
+/// Names shown to users, so the tool can be redistributed under another name
+/// Unset fields are derived from `app_name`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Branding {
+    /// Name of the application, used in the CLI help
+    pub app_name: String,
+    /// Tray icon tooltip; defaults to the app name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<String>,
+    /// Title and heading of the web interface; defaults to "<app name> Configuration"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_title: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            app_name: DEFAULT_APP_NAME.to_string(),
+            tooltip: None,
+            page_title: None,
+        }
+    }
+}
+
+impl Branding {
+    /// Reads the branding from `BRANDING_FILE` in `data_dir`
+    /// A missing file gives the default branding, as does one that doesn't parse
+    pub fn load(data_dir: &Path) -> Self {
+        let path = data_dir.join(BRANDING_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str::<Self>(&content) {
+            Ok(branding) => {
+                info!("Loaded branding for {} from {}", branding.app_name, path.display());
+                branding
+            }
+            Err(e) => {
+                warn!("Ignoring branding file {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Replaces the fields given on the command line
+    pub fn with_overrides(mut self, app_name: Option<String>, tooltip: Option<String>, page_title: Option<String>) -> Self {
+        if let Some(app_name) = app_name {
+            self.app_name = app_name;
+        }
+        self.tooltip = tooltip.or(self.tooltip);
+        self.page_title = page_title.or(self.page_title);
+        self
+    }
+
+    /// The app name, or the default if it is blank
+    pub fn app_name(&self) -> &str {
+        let name = self.app_name.trim();
+        if name.is_empty() { DEFAULT_APP_NAME } else { name }
+    }
+
+    /// Tooltip for the tray icon
+    pub fn tooltip(&self) -> String {
+        self.tooltip.clone().unwrap_or_else(|| self.app_name().to_string())
+    }
+
+    /// Title and heading of the web interface
+    pub fn page_title(&self) -> String {
+        self.page_title.clone().unwrap_or_else(|| format!("{} Configuration", self.app_name()))
+    }
+
+    /// Description shown in the CLI help
+    pub fn about(&self) -> String {
+        format!("\n\n{}: a system utility to manage Windows proxy settings via a PAC file.", self.app_name())
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+use std::time::Duration;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use log::{info, warn, debug};
+use crate::error::{Result, ProxyCatError};
+use crate::pac::{self, PacConfig, SharedPacConfig, CURRENT_CONFIG_FILE};
+use crate::constants::APP_CONFIG;
+
+/// How long to wait after a change before reloading, so editors can finish writing
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches the configuration file and swaps external edits into the shared config
+/// The returned watcher must be kept alive for as long as watching should continue
+pub fn spawn_config_watcher(config: SharedPacConfig) -> Result<RecommendedWatcher> {
+    info!("Watching {} for external changes...", CURRENT_CONFIG_FILE);
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if is_config_change(&event) => {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Config file watcher error: {}", e),
+    })
+    .map_err(|e| ProxyCatError::Pac(format!("Failed to create config file watcher: {}", e)))?;
+
+    // Watch the directory rather than the file so replace-by-rename saves are seen too
+    let file = APP_CONFIG.data_path(CURRENT_CONFIG_FILE)?;
+    let dir = file.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    watcher.watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| ProxyCatError::Pac(format!("Failed to watch {}: {}", dir.display(), e)))?;
+
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            // Our own saves trigger events too; skip them only while the file still holds what we wrote
+            let content = APP_CONFIG.data_path(CURRENT_CONFIG_FILE).ok().and_then(|path| std::fs::read(path).ok());
+            if content.is_some_and(|content| PacConfig::is_own_save(&content)) {
+                debug!("Ignoring config file change caused by our own save");
+                continue;
+            }
+            reload_config(&config).await;
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Returns true if the event creates or modifies the configuration file
+fn is_config_change(event: &Event) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        && event.paths.iter().any(|path| path.file_name() == Path::new(CURRENT_CONFIG_FILE).file_name())
+}
+
+/// Loads and validates the configuration file, replacing the shared config if it differs
+async fn reload_config(config: &SharedPacConfig) {
+    let reloaded = match PacConfig::load_current().and_then(|c| c.validate().map(|_| c)) {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            warn!("Ignoring invalid change to {}: {}", CURRENT_CONFIG_FILE, e);
+            return;
+        }
+    };
+
+    let mut config = config.write().await;
+    if *config != reloaded {
+        *config = reloaded;
+        pac::mark_config_changed();
+        info!("Reloaded configuration from {} after external change", CURRENT_CONFIG_FILE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::common::{rule, shared, temp_data_dir_async};
+
+    #[tokio::test]
+    async fn external_edit_is_reloaded() {
+        let (_guard, _) = temp_data_dir_async("config_watch").await;
+        let config = shared(PacConfig::default());
+        let _watcher = spawn_config_watcher(config.clone()).unwrap();
+        // An edit right after one of our own saves must still be picked up
+        PacConfig::default().save_current().unwrap();
+
+        let mut edited = PacConfig::default();
+        edited.proxy_rules.push_back(rule("edited.example.com", "proxy.corp", 8080));
+        let version = pac::config_version();
+        std::fs::write(APP_CONFIG.data_path(CURRENT_CONFIG_FILE).unwrap(), serde_json::to_string(&edited).unwrap()).unwrap();
+
+        for _ in 0..50 {
+            if *config.read().await == edited {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(*config.read().await, edited);
+        assert!(pac::config_version() > version);
+    }
+
+    #[tokio::test]
+    async fn invalid_edit_is_ignored() {
+        let (_guard, _) = temp_data_dir_async("config_watch_invalid").await;
+        let config = shared(PacConfig::default());
+        let mut invalid = PacConfig::default();
+        invalid.proxy_rules.push_back(rule("", "proxy.corp", 8080));
+        std::fs::write(APP_CONFIG.data_path(CURRENT_CONFIG_FILE).unwrap(), serde_json::to_string(&invalid).unwrap()).unwrap();
+
+        reload_config(&config).await;
+        assert_eq!(*config.read().await, PacConfig::default());
+    }
+}
@@ -1,7 +1,8 @@
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::{Mutex, MutexGuard};
 use std::sync::Arc;
 use std::sync::LazyLock;
+use std::path::PathBuf;
 use crate::error::{Result, ProxyCatError};
 
 /// Application-wide constants
@@ -14,16 +15,25 @@ pub struct AppConfig {
     pub master_pac_path: &'static LazyLock<Arc<Mutex<String>>>,
     /// The full URL for the master PAC file
     pub master_pac_url: &'static LazyLock<Arc<Mutex<String>>>,
-    /// The path to the configuration file
+    /// The path to the configuration file, relative to the data directory
     pub config_file: &'static str,
+    /// Directory every file ProxyCat reads or writes is resolved against
+    pub data_dir: &'static LazyLock<Arc<Mutex<PathBuf>>>,
+    /// Whether the web interface reloads the lists on its own
+    pub auto_refresh: &'static AtomicBool,
+    /// Whether the PAC is served without ProxyCat's comments
+    pub minify_pac: &'static AtomicBool,
 }
 
 static PORT: AtomicU16 = AtomicU16::new(12112);
+static AUTO_REFRESH: AtomicBool = AtomicBool::new(true);
+static MINIFY_PAC: AtomicBool = AtomicBool::new(false);
 static DEFAULT_HOST: &str = "127.0.0.1";
 static DEFAULT_PAC_PATH: &str = "/master.pac";
 
 static HOST: LazyLock<Arc<Mutex<String>>> = LazyLock::new(|| Arc::new(Mutex::new(DEFAULT_HOST.to_string())));
 static PAC_PATH: LazyLock<Arc<Mutex<String>>> = LazyLock::new(|| Arc::new(Mutex::new(DEFAULT_PAC_PATH.to_string())));
+static DATA_DIR: LazyLock<Arc<Mutex<PathBuf>>> = LazyLock::new(|| Arc::new(Mutex::new(default_data_dir())));
 static MASTER_PAC_URL: LazyLock<Arc<Mutex<String>>> = LazyLock::new(|| {
     Arc::new(Mutex::new(format!("http://{}:{}{}", DEFAULT_HOST, PORT.load(Ordering::SeqCst), DEFAULT_PAC_PATH)))
 });
@@ -35,6 +45,9 @@ pub static APP_CONFIG: AppConfig = AppConfig {
     master_pac_path: &PAC_PATH,
     master_pac_url: &MASTER_PAC_URL,
     config_file: "proxycat_config.json",
+    data_dir: &DATA_DIR,
+    auto_refresh: &AUTO_REFRESH,
+    minify_pac: &MINIFY_PAC,
 };
 
 impl AppConfig {
@@ -72,8 +85,22 @@ impl AppConfig {
         Ok(Self::lock_mutex(self.host, "host")?.clone())
     }
 
+    /// Whether `host:port` reaches ProxyCat's own server
+    /// The server listens on loopback, so any loopback name counts as well as the configured host
+    pub fn is_own_address(&self, host: &str, port: u16) -> Result<bool> {
+        if port != self.get_port() {
+            return Ok(false);
+        }
+        let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+        let is_loopback = host.eq_ignore_ascii_case("localhost")
+            || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        Ok(is_loopback || host.eq_ignore_ascii_case(&self.get_host()?))
+    }
+
     /// Updates the PAC path and returns the new PAC URL
+    /// The path is normalized with `normalize_pac_path` first
     pub fn update_pac_path(&self, new_path: String) -> Result<String> {
+        let new_path = normalize_pac_path(&new_path)?;
         let host = Self::lock_mutex(self.host, "host")?;
         let port = self.get_port();
         let new_url = format!("http://{}:{}{}", *host, port, new_path);
@@ -91,4 +118,74 @@ impl AppConfig {
     pub fn get_pac_url(&self) -> Result<String> {
         Ok(Self::lock_mutex(self.master_pac_url, "master_pac_url")?.clone())
     }
-} 
\ No newline at end of file
+
+    /// Turns the web interface's automatic list reloading on or off
+    pub fn update_auto_refresh(&self, enabled: bool) {
+        self.auto_refresh.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Whether the web interface reloads the lists on its own
+    pub fn get_auto_refresh(&self) -> bool {
+        self.auto_refresh.load(Ordering::SeqCst)
+    }
+
+    /// Turns ProxyCat's comments in the generated PAC on or off
+    pub fn update_minify_pac(&self, minify: bool) {
+        self.minify_pac.store(minify, Ordering::SeqCst);
+    }
+
+    /// Whether the PAC is served without ProxyCat's comments
+    pub fn get_minify_pac(&self) -> bool {
+        self.minify_pac.load(Ordering::SeqCst)
+    }
+
+    /// Switches to a new data directory, creating it if it doesn't exist
+    pub fn update_data_dir(&self, new_dir: PathBuf) -> Result<()> {
+        std::fs::create_dir_all(&new_dir).map_err(|e| ProxyCatError::Internal(format!(
+            "Failed to create data directory {}: {}", new_dir.display(), e
+        )))?;
+        *Self::lock_mutex(self.data_dir, "data_dir")? = new_dir;
+        Ok(())
+    }
+
+    /// Gets the current data directory
+    pub fn get_data_dir(&self) -> Result<PathBuf> {
+        Ok(Self::lock_mutex(self.data_dir, "data_dir")?.clone())
+    }
+
+    /// Resolves a file name against the data directory; absolute paths are kept as they are
+    pub fn data_path(&self, file_name: &str) -> Result<PathBuf> {
+        Ok(Self::lock_mutex(self.data_dir, "data_dir")?.join(file_name))
+    }
+}
+
+/// The per-user data directory, `%APPDATA%\ProxyCat`
+/// Falls back to the current directory when APPDATA isn't set
+pub fn default_data_dir() -> PathBuf {
+    std::env::var_os("APPDATA")
+        .map(|appdata| PathBuf::from(appdata).join("ProxyCat"))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Normalizes a PAC path so it always starts with '/'
+/// Rejects empty paths and characters that are not valid in a URL path, as well
+/// as ':' and '*', which the router would treat as parameters
+pub fn normalize_pac_path(path: &str) -> Result<String> {
+    let path = path.trim();
+    if path.is_empty() || path == "/" {
+        return Err(ProxyCatError::Internal("PAC path must not be empty".to_string()));
+    }
+
+    let is_path_char = |c: char| c.is_ascii_alphanumeric() || "-._~!$&'()+,;=@%/".contains(c);
+    if let Some(invalid) = path.chars().find(|c| !is_path_char(*c)) {
+        return Err(ProxyCatError::Internal(format!(
+            "Invalid PAC path '{}': character '{}' is not allowed", path, invalid
+        )));
+    }
+
+    if path.starts_with('/') {
+        Ok(path.to_string())
+    } else {
+        Ok(format!("/{}", path))
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Serialize, Deserialize};
+use crate::error::{Result, ProxyCatError};
+
+/// Username and password for a proxy, stored outside the PAC configuration
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyCredential {
+    pub username: String,
+    pub password: String,
+}
+
+/// A stored credential as reported to clients; the password is never included
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CredentialSummary {
+    pub key: String,
+    pub username: String,
+}
+
+/// Keeps proxy credentials out of the configuration file
+/// Lets the credential handling run against something other than the Windows Credential Manager
+pub trait CredentialStore {
+    /// Returns the stored keys with their usernames, sorted by key
+    fn list(&self) -> Result<Vec<CredentialSummary>>;
+    /// Returns the credential stored under `key`, if any
+    fn get(&self, key: &str) -> Result<Option<ProxyCredential>>;
+    /// Stores `credential` under `key`, replacing any existing one
+    fn set(&self, key: &str, credential: &ProxyCredential) -> Result<()>;
+    /// Removes the credential stored under `key`; returns false if there was none
+    fn delete(&self, key: &str) -> Result<bool>;
+}
+
+/// Checks that a credential key is non-empty and only uses letters, digits, '-', '_' or '.'
+pub fn validate_credential_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.len() > 64 || !key.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(ProxyCatError::Internal(format!(
+            "Invalid credential key '{}': use up to 64 letters, digits, '-', '_' or '.'", key
+        )));
+    }
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use axum::response::{IntoResponse, Response};
 use axum::http::StatusCode;
+use serde::Serialize;
 use std::fmt;
 use std::io;
 
@@ -14,6 +15,9 @@ pub enum ProxyCatError {
     TrayIcon(String),
     Menu(String),
     Network(String),
+    Fetch(FetchError),
+    Conflict(String),
+    NotFound(String),
     Internal(String),
 }
 
@@ -29,6 +33,9 @@ impl fmt::Display for ProxyCatError {
             ProxyCatError::TrayIcon(e) => write!(f, "Tray icon error: {}", e),
             ProxyCatError::Menu(e) => write!(f, "Menu error: {}", e),
             ProxyCatError::Network(e) => write!(f, "Network error: {}", e),
+            ProxyCatError::Fetch(e) => write!(f, "Fetch error: {}", e),
+            ProxyCatError::Conflict(e) => write!(f, "Conflict: {}", e),
+            ProxyCatError::NotFound(e) => write!(f, "Not found: {}", e),
             ProxyCatError::Internal(e) => write!(f, "Internal error: {}", e),
         }
     }
@@ -36,6 +43,50 @@ impl fmt::Display for ProxyCatError {
 
 impl std::error::Error for ProxyCatError {}
 
+/// Which stage of fetching a remote PAC file failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchErrorKind {
+    /// The host name did not resolve
+    Dns,
+    /// The host resolved but the connection was refused or unreachable
+    Connect,
+    /// The server took too long to answer
+    Timeout,
+    /// The server answered with an error status
+    HttpStatus,
+    /// The response body could not be read
+    Body,
+    Other,
+}
+
+/// A failed fetch of a remote PAC file, classified so users can tell a bad host name
+/// from a server that is down or refusing the request
+#[derive(Debug, Clone, Serialize)]
+pub struct FetchError {
+    pub url: String,
+    pub kind: FetchErrorKind,
+    /// The HTTP status for `HttpStatus` failures
+    pub status: Option<u16>,
+    pub detail: String,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FetchErrorKind::Dns => write!(f, "DNS lookup failed for {}: {}", self.url, self.detail),
+            FetchErrorKind::Connect => write!(f, "Could not connect to {}: {}", self.url, self.detail),
+            FetchErrorKind::Timeout => write!(f, "Timed out fetching {}", self.url),
+            FetchErrorKind::HttpStatus => match self.status {
+                Some(status) => write!(f, "{} returned HTTP {}", self.url, status),
+                None => write!(f, "{} returned an error status: {}", self.url, self.detail),
+            },
+            FetchErrorKind::Body => write!(f, "Failed to read PAC file content from {}: {}", self.url, self.detail),
+            FetchErrorKind::Other => write!(f, "Failed to fetch PAC file from {}: {}", self.url, self.detail),
+        }
+    }
+}
+
 impl From<io::Error> for ProxyCatError {
     fn from(err: io::Error) -> Self {
         ProxyCatError::Io(err)
@@ -55,6 +106,10 @@ impl IntoResponse for ProxyCatError {
             ProxyCatError::TrayIcon(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Tray icon error: {}", e)),
             ProxyCatError::Menu(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Menu error: {}", e)),
             ProxyCatError::Network(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Network error: {}", e)),
+            ProxyCatError::Fetch(e) if e.kind == FetchErrorKind::Timeout => (StatusCode::GATEWAY_TIMEOUT, format!("Fetch error: {}", e)),
+            ProxyCatError::Fetch(e) => (StatusCode::BAD_GATEWAY, format!("Fetch error: {}", e)),
+            ProxyCatError::Conflict(e) => (StatusCode::CONFLICT, format!("Conflict: {}", e)),
+            ProxyCatError::NotFound(e) => (StatusCode::NOT_FOUND, format!("Not found: {}", e)),
             // Use BAD_REQUEST for internal logic errors that might indicate a bad client request
             ProxyCatError::Internal(e) => (StatusCode::BAD_REQUEST, format!("Internal error: {}", e)),
         };
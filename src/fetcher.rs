@@ -0,0 +1,205 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::error::Error as _;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Serialize, Deserialize};
+use crate::error::{Result, ProxyCatError, FetchError, FetchErrorKind};
+use crate::pac_syntax::validate_pac_text;
+
+/// Fetches PAC files over the network
+/// Lets the external PAC pipeline run against something other than a live server
+pub trait PacFetcher: Sync {
+    /// Returns the body of the PAC file at `url`
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String>> + Send;
+
+    /// Fetches `url` unless the server says it hasn't changed since `validators` were returned
+    /// Fetchers without conditional request support always return the full body
+    fn fetch_conditional(&self, url: &str, _validators: &FetchValidators) -> impl Future<Output = Result<ConditionalFetch>> + Send {
+        async move {
+            Ok(ConditionalFetch::Modified {
+                content: self.fetch(url).await?,
+                validators: FetchValidators::default(),
+            })
+        }
+    }
+}
+
+/// Cache validators a server returned with a PAC file, sent back on the next refresh
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchValidators {
+    /// The `ETag` response header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The `Last-Modified` response header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl FetchValidators {
+    /// Whether there is nothing to send with a conditional request
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Result of a conditional fetch
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionalFetch {
+    /// The server sent a body, with the validators to use next time
+    Modified { content: String, validators: FetchValidators },
+    /// The server answered 304; the previously fetched content is still current
+    NotModified,
+}
+
+/// Fetches PAC files with reqwest, reusing one client for all requests
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+static SHARED_FETCHER: LazyLock<ReqwestFetcher> = LazyLock::new(ReqwestFetcher::new);
+
+impl ReqwestFetcher {
+    /// Creates a fetcher with its own client
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Returns the process-wide fetcher
+    pub fn shared() -> &'static Self {
+        &SHARED_FETCHER
+    }
+}
+
+impl Default for ReqwestFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacFetcher for ReqwestFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let response = self.client.get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| fetch_error(url, &e, classify_request_error(&e)))?;
+        response.text()
+            .await
+            .map_err(|e| fetch_error(url, &e, FetchErrorKind::Body))
+    }
+
+    async fn fetch_conditional(&self, url: &str, validators: &FetchValidators) -> Result<ConditionalFetch> {
+        let mut request = self.client.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request.send()
+            .await
+            .map_err(|e| fetch_error(url, &e, classify_request_error(&e)))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(ConditionalFetch::NotModified);
+        }
+        let response = response.error_for_status()
+            .map_err(|e| fetch_error(url, &e, classify_request_error(&e)))?;
+
+        let header = |name| response.headers()
+            .get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string);
+        let validators = FetchValidators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let content = response.text()
+            .await
+            .map_err(|e| fetch_error(url, &e, FetchErrorKind::Body))?;
+        Ok(ConditionalFetch::Modified { content, validators })
+    }
+}
+
+/// Works out which stage of a request failed
+/// reqwest reports DNS failures as connect errors, so the source chain is checked
+/// for the resolver's "dns error"
+pub fn classify_request_error(e: &reqwest::Error) -> FetchErrorKind {
+    if e.is_timeout() {
+        FetchErrorKind::Timeout
+    } else if e.is_status() {
+        FetchErrorKind::HttpStatus
+    } else if e.is_connect() {
+        let mut source = e.source();
+        while let Some(cause) = source {
+            if cause.to_string().starts_with("dns error") {
+                return FetchErrorKind::Dns;
+            }
+            source = cause.source();
+        }
+        FetchErrorKind::Connect
+    } else {
+        FetchErrorKind::Other
+    }
+}
+
+fn fetch_error(url: &str, e: &reqwest::Error, kind: FetchErrorKind) -> ProxyCatError {
+    // The innermost cause says more than reqwest's "error sending request"
+    let mut detail = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        detail = cause.to_string();
+        source = cause.source();
+    }
+    ProxyCatError::Fetch(FetchError {
+        url: url.to_string(),
+        kind,
+        status: e.status().map(|status| status.as_u16()),
+        detail,
+    })
+}
+
+/// Wraps another fetcher and only accepts content that is a real PAC file:
+/// it must define FindProxyForURL and parse as JavaScript
+/// Used for sources that cannot be trusted to serve a PAC, such as WPAD
+pub struct ValidatingFetcher<'a, F> {
+    inner: &'a F,
+}
+
+impl<'a, F: PacFetcher> ValidatingFetcher<'a, F> {
+    pub fn new(inner: &'a F) -> Self {
+        Self { inner }
+    }
+}
+
+impl<F: PacFetcher> PacFetcher for ValidatingFetcher<'_, F> {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let content = self.inner.fetch(url).await?;
+        check_pac_content(url, &content)?;
+        Ok(content)
+    }
+
+    async fn fetch_conditional(&self, url: &str, validators: &FetchValidators) -> Result<ConditionalFetch> {
+        let fetched = self.inner.fetch_conditional(url, validators).await?;
+        if let ConditionalFetch::Modified { content, .. } = &fetched {
+            check_pac_content(url, content)?;
+        }
+        Ok(fetched)
+    }
+}
+
+/// Rejects content that doesn't define FindProxyForURL or doesn't parse
+fn check_pac_content(url: &str, content: &str) -> Result<()> {
+    let validation = validate_pac_text(content);
+    if !validation.function_found {
+        return Err(ProxyCatError::Pac(format!("Content from {} is not a PAC file: no FindProxyForURL function", url)));
+    }
+    if let Some(error) = validation.syntax_error {
+        return Err(ProxyCatError::Pac(format!("Content from {} is not a valid PAC file: {}", url, error.message)));
+    }
+    Ok(())
+}
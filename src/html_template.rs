@@ -1,8 +1,37 @@
+/// Replaced with the live configuration as JSON when the page is served
+pub const INITIAL_CONFIG_PLACEHOLDER: &str = "__INITIAL_CONFIG__JSON__";
+
+/// Replaced with the ETag of the configuration seeded into the page
+pub const INITIAL_VERSION_PLACEHOLDER: &str = "__INITIAL_CONFIG__VERSION__";
+
+/// Replaced with the branded page title, in the <title> and the heading
+pub const PAGE_TITLE_PLACEHOLDER: &str = "__PAGE_TITLE__";
+
+/// Returns the page titled `page_title` with `config_json` and its `etag` seeded in,
+/// so the lists render even when the API cannot be reached from the browser
+/// The config goes in last so text inside it is never mistaken for a placeholder
+pub fn render(config_json: &str, etag: &str, page_title: &str) -> String {
+    HTML_TEMPLATE
+        .replace(PAGE_TITLE_PLACEHOLDER, &escape_html(page_title))
+        .replacen(INITIAL_VERSION_PLACEHOLDER, &script_safe_json(&serde_json::Value::from(etag).to_string()), 1)
+        .replacen(INITIAL_CONFIG_PLACEHOLDER, &script_safe_json(config_json), 1)
+}
+
+/// Escapes text for use in HTML element content
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Escapes `<` so a value such as "</script>" cannot end the inline script early
+fn script_safe_json(json: &str) -> String {
+    json.replace('<', "\\u003c")
+}
+
 pub const HTML_TEMPLATE: &str = r#"
 <!DOCTYPE html>
 <html>
 <head>
-    <title>ProxyCat Configuration</title>
+    <title>__PAGE_TITLE__</title>
     <style>
         body { 
             font-family: Arial, sans-serif; 
@@ -86,6 +115,13 @@ pub const HTML_TEMPLATE: &str = r#"
         .add-form button:hover {
             background: #45a049;
         }
+        .search {
+            display: block;
+            margin-bottom: 10px;
+            padding: 6px;
+            width: 50%;
+        }
+
         .add-button {
             background: #4CAF50;
             color: white;
@@ -106,11 +142,22 @@ pub const HTML_TEMPLATE: &str = r#"
         .form-row input {
             flex: 1;
         }
+        .note-button {
+            margin-left: auto;
+            padding: 2px 8px;
+            font-size: 0.8em;
+            cursor: pointer;
+        }
     </style>
 </head>
 <body>
     <div class="header">
-        <h1>ProxyCat Configuration</h1>
+        <h1>__PAGE_TITLE__</h1>
+        <button class="add-button" onclick="applyPac()" title="Make Windows and browsers reload the PAC now">Apply</button>
+        <button class="add-button" onclick="loadConfig()" title="Reload the lists from the server">Refresh</button>
+        <label title="Reload the lists whenever the configuration changes">
+            <input type="checkbox" id="autoRefresh" checked onchange="setAutoRefresh(this.checked)"> Auto-refresh
+        </label>
     </div>
     
     <div class="container">
@@ -118,10 +165,11 @@ pub const HTML_TEMPLATE: &str = r#"
             <div class="section">
                 <h2>Proxy Rules</h2>
                 <button class="add-button" onclick="showAddForm('proxyRules')">Add New Proxy Rule</button>
+                <input type="text" class="search" placeholder="Search" oninput="searchList('proxyRules', this.value)">
                 <div id="proxyRules" class="list"></div>
                 <div id="proxyRulesForm" class="add-form" style="display: none;">
                     <div class="form-row">
-                        <input type="text" id="proxyRuleHost" placeholder="Host (e.g. * or example.com)">
+                        <input type="text" id="proxyRuleHost" placeholder="Host (e.g. *, example.com or 203.0.113.0/24)">
                     </div>
                     <div class="form-row">
                         <input type="text" id="proxyRuleProxyHost" placeholder="Proxy Host">
@@ -129,17 +177,34 @@ pub const HTML_TEMPLATE: &str = r#"
                     <div class="form-row">
                         <input type="number" id="proxyRulePort" placeholder="Proxy Port">
                     </div>
+                    <div class="form-row">
+                        <input type="text" id="proxyRuleNote" placeholder="Note (optional)">
+                    </div>
+                    <div class="form-row">
+                        <label><input type="checkbox" id="proxyRuleHttpsOnly"> Only https: URLs</label>
+                    </div>
                     <button onclick="addProxyRule()">Add</button>
+                    <button onclick="previewProxyRule()">Preview</button>
                     <button onclick="hideAddForm('proxyRules')">Cancel</button>
                 </div>
             </div>
             <div class="section">
                 <h2>Bypass List</h2>
                 <button class="add-button" onclick="showAddForm('bypassList')">Add New Bypass Rule</button>
+                <input type="text" class="search" placeholder="Search" oninput="searchList('bypassList', this.value)">
                 <div id="bypassList" class="list"></div>
                 <div id="bypassListForm" class="add-form" style="display: none;">
                     <div class="form-row">
-                        <input type="text" id="bypassHost" placeholder="Host to bypass">
+                        <input type="text" id="bypassHost" placeholder="Host to bypass, or URL pattern (e.g. */healthz)">
+                    </div>
+                    <div class="form-row">
+                        <select id="bypassMatch">
+                            <option value="host">Match host</option>
+                            <option value="url">Match full URL</option>
+                        </select>
+                    </div>
+                    <div class="form-row">
+                        <input type="text" id="bypassNote" placeholder="Note (optional)">
                     </div>
                     <button onclick="addBypassRule()">Add</button>
                     <button onclick="hideAddForm('bypassList')">Cancel</button>
@@ -148,11 +213,15 @@ pub const HTML_TEMPLATE: &str = r#"
             <div class="section">
                 <h2>External PAC Functions</h2>
                 <button class="add-button" onclick="showAddForm('externalPacFunctions')">Add New PAC URL</button>
+                <input type="text" class="search" placeholder="Search" oninput="searchList('externalPacFunctions', this.value)">
                 <div id="externalPacFunctions" class="list"></div>
                 <div id="externalPacFunctionsForm" class="add-form" style="display: none;">
                     <div class="form-row">
                         <input type="text" id="pacUrl" placeholder="PAC file URL">
                     </div>
+                    <div class="form-row">
+                        <input type="text" id="pacUrlNote" placeholder="Note (optional)">
+                    </div>
                     <button onclick="addPacUrl()">Add</button>
                     <button onclick="hideAddForm('externalPacFunctions')">Cancel</button>
                 </div>
@@ -165,11 +234,37 @@ pub const HTML_TEMPLATE: &str = r#"
         </div>
     </div>
 
+    <script>
+        // Configuration at the time the page was served, so the lists render before the first fetch
+        window.__INITIAL_CONFIG__ = __INITIAL_CONFIG__JSON__;
+        window.__INITIAL_CONFIG_VERSION__ = __INITIAL_CONFIG__VERSION__;
+    </script>
     <script>
         console.log("Script starting...");
 
-        // Get the current port from the server
-        const currentPort = window.location.port;
+        // Version of the config the lists were rendered from, sent back with
+        // index-based changes so the server can reject them if it has moved on
+        let configVersion = null;
+
+        async function postIndexed(path, options = {}) {
+            const headers = Object.assign({}, options.headers || {});
+            if (configVersion !== null) {
+                headers['If-Match'] = configVersion;
+            }
+            const response = await fetch(path, Object.assign({}, options, {
+                method: 'POST',
+                headers: headers
+            }));
+            if (response.status === 409) {
+                alert('The configuration changed since it was loaded. The lists have been refreshed, please try again.');
+                loadConfig();
+                return null;
+            }
+            if (!response.ok) {
+                throw new Error(`HTTP error! status: ${response.status}`);
+            }
+            return response;
+        }
 
         function showAddForm(formId) {
             document.getElementById(formId + 'Form').style.display = 'block';
@@ -179,27 +274,38 @@ pub const HTML_TEMPLATE: &str = r#"
             document.getElementById(formId + 'Form').style.display = 'none';
         }
 
-        async function addProxyRule() {
+        // Builds a proxy rule from the add form, or returns null if a field is missing
+        function proxyRuleFromForm() {
             const host = document.getElementById('proxyRuleHost').value;
             const proxyHost = document.getElementById('proxyRuleProxyHost').value;
             const port = parseInt(document.getElementById('proxyRulePort').value);
+            const note = document.getElementById('proxyRuleNote').value.trim();
+            const httpsOnly = document.getElementById('proxyRuleHttpsOnly').checked;
 
             if (!host || !proxyHost || !port) {
                 alert('Please fill in all fields');
-                return;
+                return null;
             }
 
-            const item = {
+            return {
                 rule: {
                     host: host,
                     proxy_host: proxyHost,
-                    proxy_port: port
+                    proxy_port: port,
+                    https_only: httpsOnly
                 },
-                enabled: true
+                enabled: true,
+                note: note || null
             };
+        }
+
+        // Shows the PAC as it would look with the rule in the form added, without saving it
+        async function previewProxyRule() {
+            const item = proxyRuleFromForm();
+            if (!item) return;
 
             try {
-                const response = await fetch(`http://127.0.0.1:${currentPort}/add-item`, {
+                const response = await fetch("/pac-content", {
                     method: 'POST',
                     headers: {
                         'Content-Type': 'application/json',
@@ -211,22 +317,53 @@ pub const HTML_TEMPLATE: &str = r#"
                 });
 
                 if (!response.ok) {
-                    throw new Error(`HTTP error! status: ${response.status}`);
+                    throw new Error(await response.text());
+                }
+
+                document.getElementById("pacPreview").textContent = await response.text();
+            } catch (error) {
+                console.error('Error previewing proxy rule:', error);
+                alert(`Failed to preview proxy rule: ${error.message}`);
+            }
+        }
+
+        async function addProxyRule() {
+            const item = proxyRuleFromForm();
+            if (!item) return;
+
+            try {
+                const response = await fetch("/add-item", {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        list_type: 'proxy_rules',
+                        item: item
+                    })
+                });
+
+                if (!response.ok) {
+                    throw new Error(await response.text());
                 }
 
                 hideAddForm('proxyRules');
                 document.getElementById('proxyRuleHost').value = '';
                 document.getElementById('proxyRuleProxyHost').value = '';
                 document.getElementById('proxyRulePort').value = '';
+                document.getElementById('proxyRuleNote').value = '';
+                document.getElementById('proxyRuleHttpsOnly').checked = false;
                 loadConfig();
             } catch (error) {
                 console.error('Error adding proxy rule:', error);
-                alert('Failed to add proxy rule');
+                alert(`Failed to add proxy rule: ${error.message}`);
             }
         }
 
         async function addBypassRule() {
             const host = document.getElementById('bypassHost').value;
+            const note = document.getElementById('bypassNote').value.trim();
+            const matchMode = document.getElementById('bypassMatch').value;
 
             if (!host) {
                 alert('Please enter a host');
@@ -235,11 +372,13 @@ pub const HTML_TEMPLATE: &str = r#"
 
             const item = {
                 host: host,
-                enabled: true
+                enabled: true,
+                note: note || null,
+                match: matchMode
             };
 
             try {
-                const response = await fetch(`http://127.0.0.1:${currentPort}/add-item`, {
+                const response = await fetch("/add-item", {
                     method: 'POST',
                     headers: {
                         'Content-Type': 'application/json',
@@ -251,20 +390,23 @@ pub const HTML_TEMPLATE: &str = r#"
                 });
 
                 if (!response.ok) {
-                    throw new Error(`HTTP error! status: ${response.status}`);
+                    throw new Error(await response.text());
                 }
 
                 hideAddForm('bypassList');
                 document.getElementById('bypassHost').value = '';
+                document.getElementById('bypassNote').value = '';
+                document.getElementById('bypassMatch').value = 'host';
                 loadConfig();
             } catch (error) {
                 console.error('Error adding bypass rule:', error);
-                alert('Failed to add bypass rule');
+                alert(`Failed to add bypass rule: ${error.message}`);
             }
         }
 
         async function addPacUrl() {
             const url = document.getElementById('pacUrl').value;
+            const note = document.getElementById('pacUrlNote').value.trim();
 
             if (!url) {
                 alert('Please enter a URL');
@@ -272,7 +414,7 @@ pub const HTML_TEMPLATE: &str = r#"
             }
 
             try {
-                const response = await fetch(`http://127.0.0.1:${currentPort}/add-item`, {
+                const response = await fetch("/add-item", {
                     method: 'POST',
                     headers: {
                         'Content-Type': 'application/json',
@@ -285,7 +427,8 @@ pub const HTML_TEMPLATE: &str = r#"
                                 function_name: 'FindProxyForURL_' + url.replace(/[^a-zA-Z0-9]/g, '_'),
                                 function_text: ''
                             },
-                            enabled: true
+                            enabled: true,
+                            note: note || null
                         }
                     })
                 });
@@ -296,6 +439,7 @@ pub const HTML_TEMPLATE: &str = r#"
 
                 hideAddForm('externalPacFunctions');
                 document.getElementById('pacUrl').value = '';
+                document.getElementById('pacUrlNote').value = '';
                 loadConfig();
             } catch (error) {
                 console.error('Error adding PAC URL:', error);
@@ -303,13 +447,27 @@ pub const HTML_TEMPLATE: &str = r#"
             }
         }
 
+        // Re-applies the PAC URL so Windows and browsers drop their cached copy
+        async function applyPac() {
+            try {
+                const response = await fetch("/apply", { method: 'POST' });
+                if (!response.ok) {
+                    throw new Error(await response.text());
+                }
+            } catch (error) {
+                console.error('Error applying PAC:', error);
+                alert(`Failed to apply PAC: ${error.message}`);
+            }
+        }
+
         async function loadConfig() {
             try {
                 console.log("Fetching config...");
-                const response = await fetch(`http://127.0.0.1:${currentPort}/config`);
+                const response = await fetch("/config");
                 if (!response.ok) {
                     throw new Error(`HTTP error! status: ${response.status}`);
                 }
+                configVersion = response.headers.get('ETag');
                 const config = await response.json();
                 console.log("Received config:", config);
                 updateLists(config);
@@ -326,11 +484,41 @@ pub const HTML_TEMPLATE: &str = r#"
                 return;
             }
 
-            updateList("proxyRules", config.proxy_rules);
-            updateList("bypassList", config.bypass_list);
-            updateList("externalPacFunctions", config.external_pac_functions);
+            showList("proxyRules", config.proxy_rules);
+            showList("bypassList", config.bypass_list);
+            showList("externalPacFunctions", config.external_pac_functions);
         }
 
+        // Search text per list; a list with a search shows only the matching items
+        const searchQueries = {};
+
+        function showList(listId, items) {
+            if (searchQueries[listId]) {
+                searchList(listId, searchQueries[listId]);
+            } else {
+                updateList(listId, (items || []).map((item, index) => ({ index, item })));
+            }
+        }
+
+        async function searchList(listId, query) {
+            searchQueries[listId] = query.trim();
+            if (!searchQueries[listId]) {
+                loadConfig();
+                return;
+            }
+            try {
+                const params = new URLSearchParams({ list: listId, q: searchQueries[listId] });
+                const response = await fetch(`/config/search?${params}`);
+                if (!response.ok) {
+                    throw new Error(`HTTP error! status: ${response.status}`);
+                }
+                updateList(listId, await response.json());
+            } catch (error) {
+                console.error(`Error searching ${listId}:`, error);
+            }
+        }
+
+        // items are { index, item } pairs so filtered lists keep each item's real index
         function updateList(listId, items) {
             console.log(`Updating ${listId} with items:`, items);
             const list = document.getElementById(listId);
@@ -345,23 +533,20 @@ pub const HTML_TEMPLATE: &str = r#"
                 return;
             }
 
-            items.forEach((item, index) => {
+            items.forEach(({ index, item }) => {
                 const div = document.createElement("div");
                 div.className = "item";
-                div.draggable = true;
+                div.draggable = !item.system;
                 
                 const checkbox = document.createElement("input");
                 checkbox.type = "checkbox";
                 checkbox.checked = item.enabled;
+                checkbox.disabled = !!item.system;
                 checkbox.addEventListener("change", async () => {
                     try {
-                        const response = await fetch(`http://127.0.0.1:${currentPort}/toggle/${listId}/${index}`, {
-                            method: "POST"
-                        });
-                        if (!response.ok) {
-                            throw new Error(`HTTP error! status: ${response.status}`);
+                        if (await postIndexed(`/toggle/${listId}/${index}`)) {
+                            loadConfig();
                         }
-                        loadConfig();
                     } catch (error) {
                         console.error("Error toggling item:", error);
                     }
@@ -371,21 +556,113 @@ pub const HTML_TEMPLATE: &str = r#"
                 switch(listId) {
                     case "proxyRules":
                         text = `${item.rule.host} -> ${item.rule.proxy_host}:${item.rule.proxy_port}`;
+                        if (item.rule.https_only) {
+                            text += " (https only)";
+                        }
                         break;
                     case "bypassList":
-                        text = item.host;
+                        text = item.match === "url" ? `URL ${item.host}` : item.host;
+                        if (item.system) {
+                            text += " [system]";
+                        }
                         break;
                     case "externalPacFunctions":
-                        text = `${item.function.function_name} (${item.function.original_url})`;
+                        text = `${item.display_name || item.function.function_name} (${item.function.original_url})`;
+                        if (item.pinned) {
+                            text += " [pinned]";
+                        }
+                        if (item.authoritative) {
+                            text += " [authoritative]";
+                        }
                         break;
                     default:
                         text = "Unknown item type";
                 }
 
+                if (item.note) {
+                    text += ` — ${item.note}`;
+                }
+
                 const textNode = document.createTextNode(text);
                 div.appendChild(checkbox);
                 div.appendChild(textNode);
 
+                const noteButton = document.createElement("button");
+                noteButton.className = "note-button";
+                noteButton.textContent = "Note";
+                noteButton.addEventListener("click", async () => {
+                    const note = prompt("Note for this item (leave empty to remove):", item.note || "");
+                    if (note === null) return;
+                    try {
+                        const response = await postIndexed(`/note/${listId}/${index}`, {
+                            headers: {
+                                "Content-Type": "application/json",
+                            },
+                            body: JSON.stringify({ note: note })
+                        });
+                        if (response) {
+                            loadConfig();
+                        }
+                    } catch (error) {
+                        console.error("Error updating note:", error);
+                    }
+                });
+                div.appendChild(noteButton);
+
+                if (listId === "externalPacFunctions") {
+                    const renameButton = document.createElement("button");
+                    renameButton.className = "note-button";
+                    renameButton.textContent = "Rename";
+                    renameButton.addEventListener("click", async () => {
+                        const name = prompt("Display name (leave empty to show the function name):", item.display_name || "");
+                        if (name === null) return;
+                        try {
+                            const response = await postIndexed(`/external/${index}/rename`, {
+                                headers: {
+                                    "Content-Type": "application/json",
+                                },
+                                body: JSON.stringify({ display_name: name })
+                            });
+                            if (response) {
+                                loadConfig();
+                            }
+                        } catch (error) {
+                            console.error("Error renaming external PAC function:", error);
+                        }
+                    });
+                    div.appendChild(renameButton);
+
+                    const pinButton = document.createElement("button");
+                    pinButton.className = "note-button";
+                    pinButton.textContent = item.pinned ? "Unpin" : "Pin";
+                    pinButton.title = "Pinned functions keep their content and are not refreshed";
+                    pinButton.addEventListener("click", async () => {
+                        try {
+                            if (await postIndexed(`/external/${index}/pin`)) {
+                                loadConfig();
+                            }
+                        } catch (error) {
+                            console.error("Error pinning external PAC function:", error);
+                        }
+                    });
+                    div.appendChild(pinButton);
+
+                    const authoritativeButton = document.createElement("button");
+                    authoritativeButton.className = "note-button";
+                    authoritativeButton.textContent = item.authoritative ? "Not authoritative" : "Authoritative";
+                    authoritativeButton.title = "An authoritative function's DIRECT is final; otherwise DIRECT falls through to the next check";
+                    authoritativeButton.addEventListener("click", async () => {
+                        try {
+                            if (await postIndexed(`/external/${index}/authoritative`)) {
+                                loadConfig();
+                            }
+                        } catch (error) {
+                            console.error("Error updating external PAC function:", error);
+                        }
+                    });
+                    div.appendChild(authoritativeButton);
+                }
+
                 div.addEventListener("dragstart", (e) => {
                     e.dataTransfer.setData("text/plain", index.toString());
                 });
@@ -397,17 +674,14 @@ pub const HTML_TEMPLATE: &str = r#"
                 div.addEventListener("drop", async (e) => {
                     e.preventDefault();
                     const fromIndex = parseInt(e.dataTransfer.getData("text/plain"));
-                    const toIndex = index;
-                    if (fromIndex === toIndex) return;
+                    if (fromIndex === index) return;
+                    // The server inserts before toIndex, so dropping further down lands after the target
+                    const toIndex = fromIndex < index ? index + 1 : index;
 
                     try {
-                        const response = await fetch(`http://127.0.0.1:${currentPort}/move/${listId}/${fromIndex}/${toIndex}`, {
-                            method: "POST"
-                        });
-                        if (!response.ok) {
-                            throw new Error(`HTTP error! status: ${response.status}`);
+                        if (await postIndexed(`/move/${listId}/${fromIndex}/${toIndex}`)) {
+                            loadConfig();
                         }
-                        loadConfig();
                     } catch (error) {
                         console.error("Error moving item:", error);
                     }
@@ -431,12 +705,103 @@ pub const HTML_TEMPLATE: &str = r#"
             }
         }
 
-        // Initial load
+        // Whether the lists reload on their own; off leaves it to the Refresh button
+        let autoRefresh = true;
+        let pushConnected = false;
+
+        // Polling is only used while the push channel is down
+        let pollTimer = null;
+
+        function updatePolling() {
+            if (autoRefresh && !pushConnected) {
+                startPolling();
+            } else {
+                stopPolling();
+            }
+        }
+
+        async function loadStatus() {
+            try {
+                const response = await fetch("/status");
+                if (!response.ok) {
+                    throw new Error(`HTTP error! status: ${response.status}`);
+                }
+                const status = await response.json();
+                autoRefresh = status.auto_refresh !== false;
+                document.getElementById("autoRefresh").checked = autoRefresh;
+                updatePolling();
+            } catch (error) {
+                console.error("Error loading status:", error);
+            }
+        }
+
+        async function setAutoRefresh(enabled) {
+            try {
+                const response = await fetch("/settings/auto-refresh", {
+                    method: 'POST',
+                    headers: { "Content-Type": "application/json" },
+                    body: JSON.stringify({ auto_refresh: enabled })
+                });
+                if (!response.ok) {
+                    throw new Error(await response.text());
+                }
+                autoRefresh = (await response.json()).auto_refresh;
+            } catch (error) {
+                console.error("Error updating auto-refresh:", error);
+                autoRefresh = enabled;
+            }
+            document.getElementById("autoRefresh").checked = autoRefresh;
+            updatePolling();
+            if (autoRefresh) {
+                loadConfig();
+            }
+        }
+
+        function startPolling() {
+            if (pollTimer === null) {
+                pollTimer = setInterval(loadConfig, 5000);
+            }
+        }
+
+        function stopPolling() {
+            if (pollTimer !== null) {
+                clearInterval(pollTimer);
+                pollTimer = null;
+            }
+        }
+
+        // Reload whenever the server reports a new config version
+        function connectPush() {
+            const scheme = window.location.protocol === "https:" ? "wss" : "ws";
+            const socket = new WebSocket(`${scheme}://${window.location.host}/ws`);
+            socket.addEventListener("open", () => {
+                console.log("Push channel connected");
+                pushConnected = true;
+                updatePolling();
+            });
+            socket.addEventListener("message", () => {
+                if (autoRefresh) {
+                    loadConfig();
+                }
+            });
+            socket.addEventListener("close", () => {
+                console.log("Push channel closed, falling back to polling");
+                pushConnected = false;
+                updatePolling();
+                setTimeout(connectPush, 5000);
+            });
+        }
+
+        // Initial load, rendering the seeded config first in case the API is unreachable
         console.log("Performing initial load...");
+        if (window.__INITIAL_CONFIG__) {
+            configVersion = window.__INITIAL_CONFIG_VERSION__;
+            updateLists(window.__INITIAL_CONFIG__);
+        }
         loadConfig();
-
-        // Refresh every 5 seconds
-        setInterval(loadConfig, 5000);
+        updatePolling();
+        loadStatus();
+        connectPush();
     </script>
 </body>
 </html>
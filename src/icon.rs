@@ -3,9 +3,78 @@ use std::fs::File;
 use std::io::BufWriter;
 use log::{info, debug};
 use crate::error::{Result, ProxyCatError};
+use crate::constants::APP_CONFIG;
 
-pub fn create_icon() -> Result<()> {
+/// Name of the icon file written to the data directory
+pub const ICON_FILE: &str = "icon.ico";
+
+/// Colors used to draw the tray icon and favicon
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IconTheme {
+    /// Fill of the cat's face
+    pub fill: Rgba<u8>,
+    /// Outline and whiskers
+    pub border: Rgba<u8>,
+    /// Ears
+    pub accent: Rgba<u8>,
+}
+
+impl Default for IconTheme {
+    /// The original ProxyCat blue
+    fn default() -> Self {
+        Self {
+            fill: Rgba([120, 140, 180, 255]),
+            border: Rgba([0, 90, 200, 255]),
+            accent: Rgba([0, 120, 255, 255]),
+        }
+    }
+}
+
+impl IconTheme {
+    /// Derives a theme from a single accent color
+    /// The border is a darker shade and the fill a greyed-out tint of the accent
+    pub fn from_accent(accent: Rgba<u8>) -> Self {
+        let shade = |c: u8, factor: f32| (c as f32 * factor).round() as u8;
+        let tint = |c: u8| ((c as f32 + 150.0) / 2.0).round() as u8;
+        let [r, g, b, _] = accent.0;
+        Self {
+            fill: Rgba([tint(r), tint(g), tint(b), 255]),
+            border: Rgba([shade(r, 0.75), shade(g, 0.75), shade(b, 0.75), 255]),
+            accent: Rgba([r, g, b, 255]),
+        }
+    }
+}
+
+/// Parses a color given as "RRGGBB" or "#RRGGBB"
+pub fn parse_hex_color(hex: &str) -> Result<Rgba<u8>> {
+    let digits = hex.trim().trim_start_matches('#');
+    let invalid = || ProxyCatError::Icon(format!("Invalid icon color '{}', expected RRGGBB", hex));
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| invalid());
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+/// Creates the icon file used for the tray and favicon
+pub fn create_icon(theme: &IconTheme) -> Result<()> {
     info!("Creating application icon...");
+    let img = render_icon(theme);
+
+    // Save as ICO file
+    debug!("Saving icon to file...");
+    let file = File::create(APP_CONFIG.data_path(ICON_FILE)?)
+        .map_err(|e| ProxyCatError::Icon(format!("Failed to create icon file: {}", e)))?;
+    let writer = BufWriter::new(file);
+    img.write_with_encoder(image::codecs::ico::IcoEncoder::new(writer))
+        .map_err(|e| ProxyCatError::Icon(format!("Failed to write icon: {}", e)))?;
+    
+    info!("Icon created successfully");
+    Ok(())
+}
+
+/// Draws the cat icon with the given colors
+pub fn render_icon(theme: &IconTheme) -> RgbaImage {
     let size = 32u32;
     let mut img = RgbaImage::new(size, size);
     
@@ -18,11 +87,11 @@ pub fn create_icon() -> Result<()> {
                           (y as f32 - center_y).powi(2)).sqrt();
             
             if distance <= size as f32 / 2.5 {
-                // Main circle (gray-blue)
-                img.put_pixel(x, y, Rgba([120, 140, 180, 255]));
+                // Main circle
+                img.put_pixel(x, y, theme.fill);
             } else if distance <= size as f32 / 2.0 {
-                // Border (darker blue)
-                img.put_pixel(x, y, Rgba([0, 90, 200, 255]));
+                // Border
+                img.put_pixel(x, y, theme.border);
             } else {
                 // Transparent background
                 img.put_pixel(x, y, Rgba([0, 0, 0, 0]));
@@ -39,7 +108,7 @@ pub fn create_icon() -> Result<()> {
                            (y as f32 - size as f32 / 4.0).powi(2)).sqrt();
             
             if left_ear <= size as f32 / 6.0 || right_ear <= size as f32 / 6.0 {
-                img.put_pixel(x, y, Rgba([0, 120, 255, 255]));
+                img.put_pixel(x, y, theme.accent);
             }
         }
     }
@@ -76,7 +145,7 @@ pub fn create_icon() -> Result<()> {
         for x in (size/6..size/2).step_by(1) {
             let y = y_offset as u32;
             if x < size && y < size {
-                img.put_pixel(x, y, Rgba([0, 90, 200, 255]));
+                img.put_pixel(x, y, theme.border);
             }
         }
 
@@ -84,19 +153,108 @@ pub fn create_icon() -> Result<()> {
         for x in (size/2..5*size/6).step_by(1) {
             let y = y_offset as u32;
             if x < size && y < size {
-                img.put_pixel(x, y, Rgba([0, 90, 200, 255]));
+                img.put_pixel(x, y, theme.border);
             }
         }
     }
 
-    // Save as ICO file
-    debug!("Saving icon to file...");
-    let file = File::create("icon.ico")
-        .map_err(|e| ProxyCatError::Icon(format!("Failed to create icon file: {}", e)))?;
-    let writer = BufWriter::new(file);
-    img.write_with_encoder(image::codecs::ico::IcoEncoder::new(writer))
-        .map_err(|e| ProxyCatError::Icon(format!("Failed to write icon: {}", e)))?;
-    
-    info!("Icon created successfully");
-    Ok(())
-} 
\ No newline at end of file
+    img
+}
+
+/// 3x5 pixel glyphs for the badge digits, one row per entry, high bit on the left
+const DIGIT_GLYPHS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Glyph shown when there are more rules than fit in the badge
+const PLUS_GLYPH: [u8; 5] = [0b000, 0b010, 0b111, 0b010, 0b000];
+
+/// Draws the cat icon with a badge showing how many proxy rules are enabled
+/// A count of 0 draws a grey dot to show everything goes DIRECT; counts over 9 show "+"
+pub fn render_icon_with_badge(theme: &IconTheme, enabled_rules: usize) -> RgbaImage {
+    let mut img = render_icon(theme);
+    let (center_x, center_y, radius) = (25.0f32, 25.0f32, 6.5f32);
+    let badge = if enabled_rules == 0 {
+        Rgba([150, 150, 150, 255])
+    } else {
+        Rgba([0, 160, 60, 255])
+    };
+
+    for y in 18..32u32 {
+        for x in 18..32u32 {
+            let distance = ((x as f32 - center_x).powi(2) + (y as f32 - center_y).powi(2)).sqrt();
+            if distance <= radius {
+                img.put_pixel(x, y, badge);
+            }
+        }
+    }
+
+    if enabled_rules > 0 {
+        let glyph = DIGIT_GLYPHS.get(enabled_rules).unwrap_or(&PLUS_GLYPH);
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3u32 {
+                if bits & (0b100 >> col) != 0 {
+                    img.put_pixel(24 + col, 23 + row as u32, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+    }
+
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colors_parse_with_or_without_hash() {
+        assert_eq!(parse_hex_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_hex_color(" 00A0ff ").unwrap(), Rgba([0, 160, 255, 255]));
+        for invalid in ["", "#fff", "ff80001", "gg0000", "#ff 000"] {
+            assert!(parse_hex_color(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn theme_is_derived_from_the_accent() {
+        let theme = IconTheme::from_accent(Rgba([200, 40, 0, 255]));
+        assert_eq!(theme.accent, Rgba([200, 40, 0, 255]));
+        assert_eq!(theme.border, Rgba([150, 30, 0, 255]));
+        assert_eq!(theme.fill, Rgba([175, 95, 75, 255]));
+    }
+
+    #[test]
+    fn icon_is_drawn_in_the_theme_colors() {
+        let theme = IconTheme::from_accent(Rgba([200, 40, 0, 255]));
+        let img = render_icon(&theme);
+        assert_eq!(*img.get_pixel(16, 8), theme.fill);
+        assert_eq!(*img.get_pixel(16, 2), theme.border);
+        assert_eq!(*img.get_pixel(8, 8), theme.accent);
+        assert_eq!(img.get_pixel(0, 0).0[3], 0);
+        assert_ne!(render_icon(&IconTheme::default()), img);
+    }
+
+    #[test]
+    fn badge_shows_whether_rules_are_enabled() {
+        let theme = IconTheme::default();
+        let none = render_icon_with_badge(&theme, 0);
+        let three = render_icon_with_badge(&theme, 3);
+        assert_ne!(none, three);
+        assert_eq!(*none.get_pixel(25, 25), Rgba([150, 150, 150, 255]));
+        assert_eq!(*three.get_pixel(25, 19), Rgba([0, 160, 60, 255]));
+        // The digit is drawn in white over the badge
+        assert_eq!(*three.get_pixel(24, 23), Rgba([255, 255, 255, 255]));
+        assert_ne!(three, render_icon_with_badge(&theme, 4));
+        assert_eq!(render_icon_with_badge(&theme, 10), render_icon_with_badge(&theme, 42));
+    }
+}
@@ -0,0 +1,13 @@
+//! ProxyCat's configuration model and PAC generation
+//! Kept separate from the tray application so it can be reused and tested
+//! without the UI or the Win32 APIs, which live in the binary
+
+pub mod branding;
+pub mod constants;
+pub mod credentials;
+pub mod error;
+pub mod fetcher;
+pub mod pac;
+pub mod pac_eval;
+pub mod pac_syntax;
+pub mod proxy_config;
@@ -1,4 +1,5 @@
 use crate::error::{Result, ProxyCatError};
+use crate::constants::APP_CONFIG;
 use config::Config;
 use env_logger::{Builder, WriteStyle};
 use log::{LevelFilter, info, warn};
@@ -7,6 +8,7 @@ use std::str::FromStr;
 
 #[derive(Debug, serde::Deserialize)]
 pub struct LogConfig {
+    /// Log file, relative to the data directory unless absolute
     pub log_file: String,
 }
 
@@ -26,29 +28,60 @@ pub fn init_logging_with_level(level: &str) -> Result<()> {
     // Create a new builder
     let mut builder = Builder::new();
     
-    // Set the log level based only on the command-line parameter
-    builder.filter_level(param_level);
+    // Let every record through the logger itself; the global max level set
+    // below does the filtering so it can be changed at runtime
+    builder.filter_level(LevelFilter::Trace);
     
     // Configure console output
     builder.write_style(WriteStyle::Always);
     
     // Configure file output
-    if let Ok(file) = File::create(&config.log_file) {
+    if let Ok(file) = File::create(APP_CONFIG.data_path(&config.log_file)?) {
         builder.target(env_logger::Target::Pipe(Box::new(file)));
     }
     
     // Initialize the logger
-    builder.init();
+    log::set_boxed_logger(Box::new(builder.build()))
+        .map_err(|e| ProxyCatError::Logging(format!("Failed to install logger: {}", e)))?;
+
+    // Set the log level based only on the command-line parameter
+    log::set_max_level(param_level);
     
     Ok(())
 }
 
+/// Returns the log level currently in effect
+pub fn current_level() -> LevelFilter {
+    log::max_level()
+}
+
+/// Changes the log level of the running logger
+pub fn set_level(level: &str) -> Result<LevelFilter> {
+    let level = LevelFilter::from_str(level.trim()).map_err(|_| {
+        ProxyCatError::Internal(format!(
+            "Invalid log level '{}': use off, error, warn, info, debug or trace", level
+        ))
+    })?;
+    log::set_max_level(level);
+    info!("Log level set to {}", level);
+    Ok(level)
+}
+
 fn load_config() -> Result<LogConfig> {
-    let config = Config::builder()
-        .add_source(config::File::with_name("config").required(false))
-        .add_source(config::File::with_name("config.local").required(false))
+    let config_path = APP_CONFIG.data_path("config")?;
+    let local_config_path = APP_CONFIG.data_path("config.local")?;
+    // A typo in the config file shouldn't stop the app, so fall back to the defaults
+    let config = match Config::builder()
+        .add_source(config::File::from(config_path).required(false))
+        .add_source(config::File::from(local_config_path).required(false))
         .build()
-        .map_err(|e| ProxyCatError::Logging(format!("Failed to build config: {}", e)))?;
+    {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Could not read config file: {}. Using default logging values.", e);
+            return Ok(LogConfig::default());
+        }
+    };
     
     match config.get::<LogConfig>("logging") {
         Ok(log_config) => {
@@ -70,4 +103,37 @@ fn parse_level(level_str: &str) -> LevelFilter {
 pub fn init_logging(level: String) {
     let param_level = parse_level(level.as_str());
     println!("Logging level set to: {}", param_level);
-} */
\ No newline at end of file
+} */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::debug;
+
+    #[tokio::test]
+    async fn debug_records_are_written_after_raising_the_level() {
+        let (_guard, _) = crate::tests::common::temp_data_dir_async("logging_level").await;
+        init_logging_with_level("info").unwrap();
+
+        debug!("record before the change");
+        assert_eq!(set_level(" debug ").unwrap(), LevelFilter::Debug);
+        assert_eq!(current_level(), LevelFilter::Debug);
+        debug!("record after the change");
+        assert!(set_level("verbose").is_err());
+        assert_eq!(current_level(), LevelFilter::Debug, "an invalid level leaves the old one");
+        log::logger().flush();
+        log::set_max_level(LevelFilter::Off);
+
+        let log = std::fs::read_to_string(APP_CONFIG.data_path("proxycat.log").unwrap()).unwrap();
+        assert!(!log.contains("record before the change"));
+        assert!(log.contains("record after the change"));
+    }
+
+    #[tokio::test]
+    async fn a_broken_config_file_falls_back_to_the_defaults() {
+        let (_guard, dir) = crate::tests::common::temp_data_dir_async("logging_broken_config").await;
+        std::fs::write(dir.join("config.toml"), "[logging\nlog_file = ").unwrap();
+
+        let config = load_config().expect("a typo in the config file is not fatal");
+        assert_eq!(config.log_file, LogConfig::default().log_file);
+    }
+}
@@ -1,42 +1,65 @@
 #![windows_subsystem = "windows"]
+use proxycat::{constants, error, pac, pac_eval, pac_syntax, proxy_config};
+use proxycat::branding::Branding;
+use proxycat::credentials::{CredentialStore, ProxyCredential};
+use proxycat::fetcher::{FetchValidators, PacFetcher, ReqwestFetcher};
 use crate::error::{Result, ProxyCatError};
 use axum::{
     response::Html,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
-    response::IntoResponse,
-    http::StatusCode,
-    extract::{State, Json, Path},
+    response::{IntoResponse, Response},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    extract::{FromRef, State, Json, Path, Query, Request},
+    middleware::{self, Next},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
 };
-use tower_http::cors::CorsLayer;
-use tray_icon::{TrayIconBuilder, TrayIconEvent, Icon};
-use tray_icon::menu::{Menu, MenuEvent, MenuItem};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tray_icon::{TrayIcon, TrayIconBuilder, TrayIconEvent, Icon};
+use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem};
 use std::sync::Arc;
+use futures::stream::{self, StreamExt};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use open::that;
-use windows::Win32::UI::WindowsAndMessaging::{DispatchMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE};
+use windows::Win32::UI::WindowsAndMessaging::{DispatchMessageW, MessageBoxW, PeekMessageW, TranslateMessage, IDYES, MB_ICONWARNING, MB_OK, MB_YESNO, MSG, PM_REMOVE};
 use windows::Win32::Foundation::HWND;
+use windows::core::HSTRING;
 use crossbeam_channel::TryRecvError;
 use std::fs;
-use serde::{Deserialize, Serialize};
-use log::{info, error, warn, debug};
-use clap::Parser;
-use std::sync::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use log::{info, error, warn, debug, trace};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::sync::{Mutex, MutexGuard};
 
 #[cfg(windows)]
 use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
 
-mod error;
 mod icon;
-mod pac;
-mod proxy_config;
-mod constants;
 mod logging;
 mod pac_urls;
 mod html_template;
-use pac::{SharedPacConfig, generate_pac_content, ProxyRuleItem, BypassListItem, ExternalPacFunctionItem};
-use proxy_config::ProxyConfig;
+mod profiles;
+mod system_events;
+mod config_watch;
+mod windows_proxy;
+mod windows_credentials;
+#[cfg(test)]
+mod tests;
+use pac::{SharedPacConfig, generate_pac_content, ProxyRuleItem, BypassListItem, ExternalPacFunction, ExternalPacFunctionItem, ExternalPacHealth, PacConfig, ConfigDiff};
+use proxy_config::{ExitPolicy, MonitorMode, PacApplyStatus, SystemProxy};
+use windows_proxy::WindowsProxy;
+use windows_credentials::WindowsCredentials;
+use system_events::{ResumeDetector, SystemEvent};
+use profiles::{NetworkProfileMapping, ProfileStore};
+use pac_urls::StartupPacReport;
 use constants::APP_CONFIG;
 
 #[derive(Parser, Debug)]
@@ -57,6 +80,249 @@ struct Args {
     /// Log level (error, warn, info, debug, trace)
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// Print the resolved configuration and exit without starting the app
+    #[arg(long)]
+    print_config: bool,
+
+    /// Print HTTP_PROXY, HTTPS_PROXY and NO_PROXY for cmd and PowerShell and exit without starting the app
+    #[arg(long)]
+    export_env: bool,
+
+    /// Write a .reg file pointing Windows at ProxyCat's PAC URL and exit without starting the app
+    #[arg(long, value_name = "PATH")]
+    export_reg: Option<PathBuf>,
+
+    /// Accent color for the tray icon and favicon, as RRGGBB hex
+    #[arg(long, value_name = "HEX")]
+    icon_color: Option<String>,
+
+    /// Add bypass entries from a NO_PROXY style string (e.g. "localhost,.corp,10.0.0.0/8")
+    #[arg(long, value_name = "NO_PROXY")]
+    import_no_proxy: Option<String>,
+
+    /// Reload the configuration file when it is edited outside ProxyCat
+    #[arg(long)]
+    watch_config: bool,
+
+    /// Additional origin allowed to call the HTTP API (may be repeated)
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    cors_origins: Vec<String>,
+
+    /// How long the tray "Open" action waits for the server to answer before reporting it as down
+    #[arg(long, value_name = "MS", default_value_t = 1500)]
+    open_probe_timeout_ms: u64,
+
+    /// What to do with the Windows auto-config URL on exit
+    #[arg(long, value_enum, default_value_t = ExitPolicy::Restore)]
+    on_exit: ExitPolicy,
+
+    /// What to do when another app changes the Windows auto-config URL
+    #[arg(long, value_enum, default_value_t = MonitorMode::Import)]
+    monitor_mode: MonitorMode,
+
+    /// Open the web interface in the browser once the server is listening
+    #[arg(long)]
+    open: bool,
+
+    /// Import the PAC file served by the `wpad` host at startup
+    #[arg(long)]
+    enable_wpad: bool,
+
+    /// Start with the web interface's automatic list reloading turned off
+    #[arg(long)]
+    no_auto_refresh: bool,
+
+    /// How often the tray event loop checks for events while active
+    #[arg(long, value_name = "MS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    event_poll_ms: u64,
+
+    /// Longest the tray event loop sleeps between checks once it has been idle for a while
+    #[arg(long, value_name = "MS", default_value_t = 250)]
+    event_poll_max_ms: u64,
+
+    /// Serve the PAC without ProxyCat's header and note comments
+    #[arg(long)]
+    minify_pac: bool,
+
+    /// Most external PAC files fetched at once when loading or refreshing them
+    #[arg(long, value_name = "N", default_value_t = 4, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=64))]
+    fetch_concurrency: usize,
+
+    /// Let browsers cache the PAC file for this many seconds (at most a year); 0 sends no-cache
+    #[arg(long, value_name = "SECONDS", default_value_t = 0, value_parser = clap::value_parser!(u64).range(..=PAC_CACHE_MAX_SECONDS))]
+    pac_cache_seconds: u64,
+
+    /// Directory for the configuration, log and icon files [default: %APPDATA%\ProxyCat]
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+
+    /// Name to show instead of ProxyCat; overrides branding.json in the data directory
+    #[arg(long, value_name = "NAME")]
+    app_name: Option<String>,
+
+    /// Tray icon tooltip [default: the app name]
+    #[arg(long, value_name = "TEXT")]
+    tooltip: Option<String>,
+
+    /// Title of the web interface [default: "<app name> Configuration"]
+    #[arg(long, value_name = "TEXT")]
+    page_title: Option<String>,
+}
+
+/// Shared state available to every HTTP handler
+#[derive(Clone)]
+struct AppState {
+    /// The live PAC configuration
+    pac_config: SharedPacConfig,
+    /// Channel to the task that owns the HTTP listener
+    server_commands: UnboundedSender<ServerCommand>,
+    /// Extra origins allowed by the CORS policy
+    cors_origins: Arc<Vec<String>>,
+    /// Whether the tray icon could be created
+    tray_available: bool,
+    /// Outcome of loading the default PAC sources, filled in as they load after startup
+    startup_report: Arc<Mutex<StartupPacReport>>,
+    /// How long browsers may cache the PAC file, 0 for no caching
+    pac_cache_seconds: u64,
+    /// Names shown in the web interface
+    branding: Arc<Branding>,
+    /// Whether the PAC URL is applied to Windows, updated by the startup retries and /apply
+    pac_apply: Arc<Mutex<PacApplyStatus>>,
+    /// Where the proxy credentials rules refer to are kept
+    credentials: Arc<dyn CredentialStore + Send + Sync>,
+    /// Most external PAC files fetched at once, from --fetch-concurrency
+    fetch_concurrency: usize,
+}
+
+impl FromRef<AppState> for SharedPacConfig {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.pac_config)
+    }
+}
+
+/// Tries at applying the PAC URL on startup before the user is told it failed
+const INITIAL_APPLY_ATTEMPTS: u32 = 5;
+
+/// Wait after the first failed startup apply, doubled after each further failure
+const INITIAL_APPLY_DELAY: Duration = Duration::from_millis(500);
+
+/// PAC fetches between the summary lines logged for them
+const PAC_FETCH_SUMMARY_EVERY: u64 = 100;
+
+/// State for the request logging middleware
+struct RequestLog {
+    /// Path the PAC is served on; its fetches are summarized instead of logged one by one
+    pac_path: String,
+    /// PAC fetches since the last summary line
+    pac_fetches: Mutex<PacFetchStats>,
+}
+
+/// Running totals for PAC fetches
+#[derive(Debug, Default)]
+struct PacFetchStats {
+    count: u64,
+    total: Duration,
+    slowest: Duration,
+}
+
+/// Idle event loop iterations spent at each poll delay before it doubles
+const POLL_BACKOFF_STEP: u32 = 100;
+
+/// Lengthens the event loop's sleep while nothing happens, up to a cap
+/// Any activity drops it straight back to the base interval
+struct PollBackoff {
+    base: Duration,
+    max: Duration,
+    idle_iterations: u32,
+}
+
+impl PollBackoff {
+    fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            idle_iterations: 0,
+        }
+    }
+
+    /// Returns how long to sleep after an iteration that did or didn't handle an event
+    fn next_delay(&mut self, active: bool) -> Duration {
+        if active {
+            self.idle_iterations = 0;
+            return self.base;
+        }
+        self.idle_iterations = self.idle_iterations.saturating_add(1);
+        let doublings = (self.idle_iterations / POLL_BACKOFF_STEP).min(16);
+        self.base.saturating_mul(1 << doublings).min(self.max)
+    }
+}
+
+/// Requests for the task that owns the HTTP server
+enum ServerCommand {
+    /// Rebuild the router from the current settings and replace the running server
+    /// Carries a listener already bound to a new port, or None to rebind the current one,
+    /// and changed settings to commit just before the rebuild, so they only take effect
+    /// once the server task has accepted the restart
+    Restart {
+        listener: Option<TcpListener>,
+        settings: Option<ServerSettings>,
+    },
+}
+
+/// A running HTTP server that can be shut down gracefully
+struct RunningServer {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl RunningServer {
+    /// Stops accepting connections and waits for in-flight requests to finish
+    async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// Host, port and PAC path the server is running with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ServerSettings {
+    host: String,
+    port: u16,
+    pac_path: String,
+    pac_url: String,
+}
+
+/// Requested changes to the server settings; omitted fields are left unchanged
+#[derive(Debug, Deserialize)]
+struct ServerSettingsUpdate {
+    host: Option<String>,
+    port: Option<u16>,
+    pac_path: Option<String>,
+}
+
+/// Build metadata reported by /version
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    /// Short hash of the commit the binary was built from
+    git_hash: &'static str,
+    /// Unix time the binary was built at
+    build_timestamp: u64,
+    /// Target triple, e.g. x86_64-pc-windows-msvc
+    target: &'static str,
+}
+
+/// Body of /settings/auto-refresh requests
+#[derive(Debug, Serialize, Deserialize)]
+struct AutoRefreshBody {
+    auto_refresh: bool,
+}
+
+/// Body of a NO_PROXY import request
+#[derive(Debug, Deserialize)]
+struct NoProxyImportRequest {
+    no_proxy: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,6 +331,195 @@ struct AddItemRequest {
     item: serde_json::Value,
 }
 
+/// Query parameters for /config/search
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    /// Which list to search: proxyRules, bypassList or externalPacFunctions
+    list: String,
+    /// Text to look for; empty matches everything
+    #[serde(default)]
+    q: String,
+    /// Only return enabled (true) or disabled (false) items
+    enabled: Option<bool>,
+}
+
+/// Query parameters for /config
+#[derive(Debug, Deserialize)]
+struct ConfigQuery {
+    /// Set to false to leave disabled items out; the indices then no longer match the lists
+    #[serde(default = "default_include_disabled")]
+    include_disabled: bool,
+}
+
+fn default_include_disabled() -> bool {
+    true
+}
+
+/// Response body for /compact
+#[derive(Debug, Serialize)]
+struct CompactResponse {
+    /// Number of disabled external PAC functions removed
+    removed: usize,
+}
+
+/// Query parameters for /health
+#[derive(Debug, Deserialize)]
+struct HealthQuery {
+    /// Re-apply the PAC URL if the system no longer points at it
+    #[serde(default)]
+    heal: bool,
+}
+
+/// Query parameters for changes that refuse to touch system bypass entries
+#[derive(Debug, Deserialize)]
+struct ForceQuery {
+    /// Allow the change even if it affects a system bypass entry
+    #[serde(default)]
+    force: bool,
+}
+
+/// Longest TTL accepted by --pac-cache-seconds
+const PAC_CACHE_MAX_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// Query parameters for the PAC file
+#[derive(Debug, Deserialize)]
+struct PacQuery {
+    /// "1" or "true" to download the PAC instead of showing it inline
+    download: Option<String>,
+}
+
+/// Query parameters for /add-item
+#[derive(Debug, Deserialize)]
+struct AddItemQuery {
+    /// What to do when an identical item is already in the list
+    #[serde(default)]
+    dedupe: DedupePolicy,
+}
+
+/// How /add-item handles an item that is already in the list
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DedupePolicy {
+    /// Refuse the request
+    #[default]
+    Reject,
+    /// Re-enable the existing item instead of adding a copy
+    Enable,
+}
+
+/// What /add-item did with the item
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AddItemOutcome {
+    Added,
+    /// An identical item was already in the list and has been enabled
+    ReEnabled,
+}
+
+/// Response body for /apply
+#[derive(Debug, Serialize)]
+struct ApplyResponse {
+    /// Whether Windows had been pointed somewhere else before the PAC was re-applied
+    drifted: bool,
+}
+
+/// Response body for /add-item
+#[derive(Debug, Serialize)]
+struct AddItemResponse {
+    outcome: AddItemOutcome,
+}
+
+/// Query parameters for /add-items
+#[derive(Debug, Deserialize)]
+struct AddItemsQuery {
+    /// Reject the whole batch if any item fails
+    #[serde(default)]
+    atomic: bool,
+}
+
+/// An add-item request that has been parsed and validated
+enum NewItem {
+    ProxyRule(ProxyRuleItem),
+    Bypass(BypassListItem),
+    External(ExternalPacFunctionItem),
+}
+
+/// Outcome of one item in an /add-items batch
+#[derive(Debug, Serialize)]
+struct AddItemResult {
+    /// Position of the item in the request
+    index: usize,
+    /// Whether the item was added (or, in an atomic batch, would have been)
+    ok: bool,
+    /// Why the item was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Response body for /add-items
+#[derive(Debug, Serialize)]
+struct AddItemsResponse {
+    /// Whether any item was added to the configuration
+    committed: bool,
+    results: Vec<AddItemResult>,
+}
+
+/// Body of /note requests
+#[derive(Debug, Deserialize)]
+struct NoteRequest {
+    /// The new note; null or blank removes it
+    note: Option<String>,
+}
+
+/// Body of /external/:index/rename requests
+#[derive(Debug, Deserialize)]
+struct RenameRequest {
+    /// The new label; null or blank goes back to the generated function name
+    display_name: Option<String>,
+}
+
+/// Body of /evaluate requests
+#[derive(Debug, Deserialize)]
+struct EvaluateRequest {
+    /// URL to get the proxy decision for
+    url: String,
+}
+
+/// Body of /evaluate/batch requests
+#[derive(Debug, Deserialize)]
+struct EvaluateBatchRequest {
+    /// URLs to get the proxy decision for, at most `EVALUATE_BATCH_LIMIT`
+    urls: Vec<String>,
+}
+
+/// Most URLs one /evaluate/batch request may evaluate
+const EVALUATE_BATCH_LIMIT: usize = 500;
+
+/// Body of /validate-pac requests
+#[derive(Debug, Deserialize)]
+struct ValidatePacRequest {
+    /// The pasted PAC file
+    text: String,
+}
+
+/// Body of /log-level requests and responses
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevelBody {
+    level: String,
+}
+
+/// Health of a single external PAC source as reported by /external/health
+#[derive(Debug, Serialize)]
+struct ExternalHealthEntry {
+    function_name: String,
+    original_url: String,
+    enabled: bool,
+    health: ExternalPacHealth,
+}
+
+/// How often external PAC sources are re-fetched
+const EXTERNAL_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 /// Main entry point for the ProxyCat application
 /// This function initializes the system tray icon, HTTP server, and event handling
 #[tokio::main]
@@ -75,168 +530,272 @@ async fn main() -> Result<()> {
         AttachConsole(ATTACH_PARENT_PROCESS);
     }
 
-    // Parse command line arguments
-    let args = Args::parse();
+    // Parse command line arguments; the help is branded from the default data directory,
+    // since --data-dir isn't known until the arguments are parsed
+    let help_branding = Branding::load(&constants::default_data_dir());
+    let matches = Args::command().about(help_branding.about()).get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Resolve the data directory first; the log file lives there too
+    let data_dir = args.data_dir.clone().unwrap_or_else(constants::default_data_dir);
+    APP_CONFIG.update_data_dir(data_dir)?;
 
     // Initialize logging with the specified level
     logging::init_logging_with_level(&args.log_level)?;
     info!("Starting ProxyCat application...");
     info!("Command line arguments: {:?}", args);
+    info!("Using data directory {}", APP_CONFIG.get_data_dir()?.display());
+    let branding = Arc::new(Branding::load(&APP_CONFIG.get_data_dir()?)
+        .with_overrides(args.app_name.clone(), args.tooltip.clone(), args.page_title.clone()));
+    APP_CONFIG.update_auto_refresh(!args.no_auto_refresh);
+    APP_CONFIG.update_minify_pac(args.minify_pac);
 
     // Get initial host, port, pac_path for comparison
     let initial_host = APP_CONFIG.get_host()?;
     let initial_port = APP_CONFIG.get_port();
     let initial_pac_path = APP_CONFIG.get_pac_path()?;
+    let pac_path = constants::normalize_pac_path(&args.pac_path)?;
 
     // Update port, host, and PAC path if specified
     let pac_url = if args.port != initial_port || 
                     args.host != initial_host || 
-                    args.pac_path != initial_pac_path {
+                    pac_path != initial_pac_path {
         if args.port != initial_port {
             APP_CONFIG.update_port(args.port)?;
         }
         if args.host != initial_host {
             APP_CONFIG.update_host(args.host.clone())?;
         }
-        if args.pac_path != initial_pac_path {
-            APP_CONFIG.update_pac_path(args.pac_path.clone())?;
+        if pac_path != initial_pac_path {
+            APP_CONFIG.update_pac_path(pac_path)?;
         }
         APP_CONFIG.get_pac_url()?
     } else {
         APP_CONFIG.get_pac_url()?
     };
 
+    if args.print_config {
+        print!("{}", effective_config_summary(&WindowsProxy)?);
+        return Ok(());
+    }
+
+    if args.export_env {
+        print!("{}", pac::proxy_env_script(&PacConfig::load_current()?));
+        return Ok(());
+    }
+
+    if let Some(path) = &args.export_reg {
+        fs::write(path, proxy_config::registry_export(&pac_url))
+            .map_err(|e| ProxyCatError::Internal(format!("Failed to write {}: {}", path.display(), e)))?;
+        info!("Exported registry settings to {}", path.display());
+        return Ok(());
+    }
+
     // Create and save the icon for the system tray
     info!("Creating tray icon file...");
-    icon::create_icon()?;
+    let icon_theme = match &args.icon_color {
+        Some(color) => icon::IconTheme::from_accent(icon::parse_hex_color(color)?),
+        None => icon::IconTheme::default(),
+    };
+    icon::create_icon(&icon_theme)?;
     info!("Tray icon file created successfully");
 
     // Initialize PAC configuration from Windows settings
     info!("Initializing PAC configuration...");
-    let pac_config = pac::create_shared_config();
-    let pac_config_clone = Arc::clone(&pac_config);
+    let pac_config = proxy_config::create_shared_config(&WindowsProxy);
     info!("PAC configuration initialized successfully");
-    
-    // Load additional proxy rules from external PAC files
-    let mut config = pac_config_clone.write().await;
-    
-    // Load default PAC URLs from our configuration
-    let pac_urls = pac_urls::get_pac_urls();
-    for pac_url in pac_urls {
-        info!("Loading PAC file: {}", pac_url.description);
-        config.load_external_pac(&pac_url.url).await;
+
+    if let Some(no_proxy) = &args.import_no_proxy {
+        let mut config = pac_config.write().await;
+        let added = config.merge_bypass_entries(pac::parse_no_proxy(no_proxy));
+        info!("Imported {} bypass entries from --import-no-proxy", added);
+        config.save_current()?;
     }
-    
-    drop(config);
+
+    // Pick up external edits to the configuration file if requested
+    let _config_watcher = if args.watch_config {
+        match config_watch::spawn_config_watcher(Arc::clone(&pac_config)) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                error!("Failed to start config file watcher: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Set up the system tray menu
     info!("Setting up tray menu...");
     let menu = Menu::new();
     let open_item = MenuItem::new("Open", true, None);
+    let master_item = CheckMenuItem::new("PAC enabled", true, pac_config.read().await.enabled, None);
     let exit_item = MenuItem::new("Exit", true, None);
     menu.append(&open_item)
         .map_err(|e| ProxyCatError::Menu(format!("Failed to append 'Open' item: {}", e)))?;
+    menu.append(&master_item)
+        .map_err(|e| ProxyCatError::Menu(format!("Failed to append 'PAC enabled' item: {}", e)))?;
     menu.append(&exit_item)
         .map_err(|e| ProxyCatError::Menu(format!("Failed to append 'Exit' item: {}", e)))?;
 
     // Store menu item IDs for event handling
     let open_id = open_item.id().clone();
+    let master_id = master_item.id().clone();
     let exit_id = exit_item.id().clone();
     debug!("Menu items created - Open ID: {:?}, Exit ID: {:?}", open_id, exit_id);
 
     // Create and configure the system tray icon
-    info!("Loading icon from file...");
-    let icon = Icon::from_path("icon.ico", None)
-        .map_err(|e| ProxyCatError::Icon(format!("Failed to load icon: {}", e)))?;
-    info!("Creating tray icon...");
-    #[allow(clippy::arc_with_non_send_sync)]
-    let tray_icon = Arc::new(Mutex::new(
-        TrayIconBuilder::new()
-            .with_menu(Box::new(menu))
-            .with_tooltip("ProxyCat")
-            .with_icon(icon)
-            .build()
-            .map_err(|e| ProxyCatError::TrayIcon(format!("Failed to build tray icon: {}", e)))?
-    ));
-    info!("Tray icon created successfully");
+    let tray_icon = optional_tray(|| build_tray_icon(menu, &branding.tooltip()));
 
     // Set up event receivers for menu and tray icon events
     info!("Setting up event receivers...");
     let menu_event_receiver = MenuEvent::receiver();
     let tray_event_receiver = TrayIconEvent::receiver();
-    let _tray_icon_ref = Arc::clone(&tray_icon);
 
-    // Start the HTTP server in a separate thread
+    // Start the HTTP server in a separate task that can restart it with new settings
     info!("Starting HTTP server thread...");
-    let current_pac_path = APP_CONFIG.get_pac_path()?;
-    let server_pac_config = Arc::clone(&pac_config);
+    // Validate the CORS origins up front so a typo fails startup rather than the server task
+    let _ = cors_layer(&APP_CONFIG.get_host()?, APP_CONFIG.get_port(), &args.cors_origins)?;
+    let (server_command_tx, server_command_rx) = tokio::sync::mpsc::unbounded_channel();
+    let open_probe_timeout = Duration::from_millis(args.open_probe_timeout_ms);
+    let startup_report = Arc::new(Mutex::new(StartupPacReport::default()));
+    let pac_apply = Arc::new(Mutex::new(PacApplyStatus::default()));
+    let server_state = AppState {
+        pac_config: Arc::clone(&pac_config),
+        server_commands: server_command_tx.clone(),
+        cors_origins: Arc::new(args.cors_origins.clone()),
+        tray_available: tray_icon.is_some(),
+        startup_report: Arc::clone(&startup_report),
+        pac_cache_seconds: args.pac_cache_seconds,
+        branding: Arc::clone(&branding),
+        pac_apply: Arc::clone(&pac_apply),
+        credentials: Arc::new(WindowsCredentials),
+        fetch_concurrency: args.fetch_concurrency,
+    };
+    let (server_ready_tx, server_ready_rx) = oneshot::channel();
+    tokio::spawn(run_server(server_state, server_command_rx, server_ready_tx));
+
+    // Wait for the listener so Windows never fetches the PAC before it is served
+    let server_ready = matches!(server_ready_rx.await, Ok(true));
+    if server_ready {
+        debug!("HTTP server is listening");
+    } else {
+        warn!("HTTP server is not listening; the PAC file will be unavailable until it restarts");
+    }
+
+    // Load the default PAC sources now the tray and server are up, so a slow source can't delay them
+    let startup_config = Arc::clone(&pac_config);
+    let startup_pac_urls = pac_urls::get_pac_urls(args.enable_wpad);
+    let fetch_concurrency = args.fetch_concurrency;
     tokio::spawn(async move {
-        let app = Router::new()
-            .route("/", get(handler))
-            .route("/favicon.ico", get(favicon_handler))
-            .route(&current_pac_path, get(pac_handler))
-            .route("/config", get(config_handler))
-            .route("/toggle/:list_id/:index", post(toggle_handler))
-            .route("/move/:list_id/:from_index/:to_index", post(move_handler))
-            .route("/pac-content", get(pac_content_handler))
-            .route("/add-item", post(add_item_handler))
-            .layer(CorsLayer::permissive())
-            .with_state(server_pac_config);
-
-        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], APP_CONFIG.get_port()));
-        info!("Starting server on http://{}", addr);
-        let listener = match tokio::net::TcpListener::bind(addr).await {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Failed to bind TCP listener: {}", e);
-                // Cannot return error directly from spawn, log and exit?
-                // For now, just log and the thread will panic later.
-                // Consider sending error back via a channel if needed.
-                return;
-            }
-        };
-        if let Err(e) = axum::serve(listener, app).await {
-             error!("Axum server failed: {}", e);
-             // Log error, thread will terminate.
-        }
+        pac_urls::load_startup_pacs(&startup_config, ReqwestFetcher::shared(), startup_pac_urls, fetch_concurrency, &startup_report).await;
     });
 
+    if args.open {
+        open_when_ready(server_ready, || open_interface(open_probe_timeout, server_command_tx.clone(), Arc::clone(&branding)));
+    }
+
     // Set Windows proxy configuration to use the local PAC file
     info!("Setting Windows proxy configuration to use local PAC file...");
-    if let Err(e) = ProxyConfig::set_pac_file(&pac_url) {
-        error!("Failed to set Windows proxy configuration: {}", e);
-    } else {
-        info!("Successfully set Windows proxy configuration to use local PAC file");
-    }
+    // Remember what was configured before so it can be put back on exit
+    let previous_pac_url = WindowsProxy.get_pac_file().ok();
+    // Retried in the background, since Windows can refuse the setting early in a session
+    let apply_url = pac_url.clone();
+    let apply_title = HSTRING::from(branding.app_name());
+    tokio::task::spawn_blocking(move || {
+        let status = proxy_config::set_pac_file_with_retry(&WindowsProxy, &apply_url, INITIAL_APPLY_ATTEMPTS, INITIAL_APPLY_DELAY);
+        *lock_or_recover(&pac_apply, "PAC apply status") = status.clone();
+        match status.error {
+            None => info!("Successfully set Windows proxy configuration to use local PAC file"),
+            Some(e) => {
+                let message = format!(
+                    "Failed to set Windows proxy configuration after {} attempts: {}\n\nNo proxy settings are applied. Use /apply to try again.",
+                    status.attempts, e
+                );
+                error!("{}", message);
+                unsafe {
+                    MessageBoxW(HWND(0), &HSTRING::from(message), &apply_title, MB_OK | MB_ICONWARNING);
+                }
+            }
+        }
+    });
+
+    // Re-apply the PAC as soon as the system resumes or the network changes
+    info!("Starting system event listener...");
+    let (system_event_tx, mut system_event_rx) = tokio::sync::mpsc::unbounded_channel();
+    system_events::spawn_network_change_listener(system_event_tx.clone());
+    let network_config = Arc::clone(&pac_config);
+    tokio::spawn(async move {
+        while let Some(event) = system_event_rx.recv().await {
+            // Switch to the profile mapped to the new network before Windows re-reads the PAC
+            if event == SystemEvent::NetworkChanged {
+                switch_network_profile(&network_config).await;
+            }
+            let result = APP_CONFIG.get_pac_url()
+                .and_then(|pac_url| system_events::reapply_pac(&WindowsProxy, &pac_url, event));
+            if let Err(e) = result {
+                error!("Failed to re-apply proxy configuration after {:?}: {}", event, e);
+            }
+        }
+    });
+
+    // Periodically re-fetch external PAC sources, disabling ones that keep failing
+    info!("Starting external PAC refresh task...");
+    let refresh_config = Arc::clone(&pac_config);
+    let fetch_concurrency = args.fetch_concurrency;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXTERNAL_REFRESH_INTERVAL);
+        // The sources were just loaded at startup, so skip the immediate first tick
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            pac::refresh_external_functions(&refresh_config, ReqwestFetcher::shared(), fetch_concurrency).await;
+        }
+    });
+
+    // Keep a handle for the tray badge and master switch, which are updated from the event loop
+    let badge_config = Arc::clone(&pac_config);
+    let mut badge_changes = pac::subscribe_config_changes();
+    let mut badge_count: Option<usize> = None;
 
     // Add this after setting the initial proxy configuration and before the event loop
     info!("Starting proxy configuration monitor...");
+    let monitor_mode = args.monitor_mode;
     let _proxy_monitor_handle = tokio::spawn(async move {
-        let mut last_config = ProxyConfig::get_pac_file().ok();
+        let mut last_config = WindowsProxy.get_pac_file().ok();
+        let mut resume_detector = ResumeDetector::new(Duration::from_secs(30));
         loop {
+            // A long gap between ticks means the machine was asleep
+            if resume_detector.tick() {
+                info!("System resume detected");
+                let _ = system_event_tx.send(SystemEvent::Resumed);
+            }
+
+            // Our URL can change at runtime through /settings/server
+            let pac_url = match APP_CONFIG.get_pac_url() {
+                Ok(pac_url) => pac_url,
+                Err(e) => {
+                    error!("Failed to get PAC URL for proxy monitor: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
             // Check current system proxy configuration
-            if let Ok(current_config) = ProxyConfig::get_pac_file() {
+            if let Ok(current_config) = WindowsProxy.get_pac_file() {
                 // If configuration changed and it's not our PAC file
                 if last_config != Some(current_config.clone()) && 
                    current_config != pac_url {
-                    info!("System proxy configuration changed: {}", current_config);
-                    
-                    // Load external PAC configuration into our shared config
-                    let mut pac_config = pac_config.write().await;
-                    pac_config.load_external_pac(&current_config).await;
-                    info!("Loaded external PAC configuration from {}", current_config);
-                    
-                    // Save the updated configuration
-                    if let Err(e) = pac_config.save_current() {
-                        error!("Failed to save configuration after loading external PAC: {}", e);
-                    }
-                    drop(pac_config);
-
-                    // Restore our PAC file configuration
-                    if let Err(e) = ProxyConfig::set_pac_file(&pac_url) {
+                    let result = system_events::handle_foreign_pac(
+                        &WindowsProxy,
+                        &pac_config,
+                        ReqwestFetcher::shared(),
+                        monitor_mode,
+                        &current_config,
+                        &pac_url,
+                    ).await;
+                    if let Err(e) = result {
                         error!("Failed to restore proxy configuration: {}", e);
-                    } else {
-                        info!("Successfully restored proxy configuration");
                     }
                 }
                 last_config = Some(current_config);
@@ -250,19 +809,28 @@ async fn main() -> Result<()> {
     info!("Starting event handling in main thread...");
     let mut event_count = 0;
     let mut msg = MSG::default();
+    let mut backoff = PollBackoff::new(
+        Duration::from_millis(args.event_poll_ms),
+        Duration::from_millis(args.event_poll_max_ms),
+    );
 
     loop {
+        // Whether anything happened this iteration, which resets the poll backoff
+        let mut active = false;
+
         // Process Windows messages to keep the application responsive
         unsafe {
             while PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE).as_bool() {
                 TranslateMessage(&msg);
                 DispatchMessageW(&msg);
+                active = true;
             }
         }
 
         // Handle tray icon events
         match tray_event_receiver.try_recv() {
             Ok(event) => {
+                active = true;
                 match event {
                     TrayIconEvent::Click { button, button_state, .. } => {
                         // Only log clicks, not movements
@@ -278,18 +846,7 @@ async fn main() -> Result<()> {
                     }
                     TrayIconEvent::DoubleClick { .. } => {
                         debug!("Double click detected, opening URL...");
-                        let open_url = match (APP_CONFIG.get_host(), APP_CONFIG.get_port()) {
-                            (Ok(host), port) => format!("http://{}:{}", host, port),
-                            (Err(e), port) => {
-                                error!("Failed to get host for opening URL: {}", e);
-                                // Fallback to localhost if getting host fails
-                                format!("http://127.0.0.1:{}", port)
-                            }
-                        };
-                        match that(open_url) {
-                            Ok(_) => debug!("URL opened successfully"),
-                            Err(e) => error!("Failed to open URL: {}", e),
-                        }
+                        open_interface(open_probe_timeout, server_command_tx.clone(), Arc::clone(&branding));
                     }
                     _ => {} // Ignore other events like Enter, Leave, Move
                 }
@@ -304,34 +861,35 @@ async fn main() -> Result<()> {
         // Handle menu events
         match menu_event_receiver.try_recv() {
             Ok(event) => {
+                active = true;
                 match event.id() {
                     id if *id == open_id => {
                         info!("Opening ProxyCat interface...");
-                        let open_url = match (APP_CONFIG.get_host(), APP_CONFIG.get_port()) {
-                            (Ok(host), port) => format!("http://{}:{}", host, port),
-                            (Err(e), port) => {
-                                error!("Failed to get host for opening menu action: {}", e);
-                                // Fallback to localhost if getting host fails
-                                format!("http://127.0.0.1:{}", port)
+                        open_interface(open_probe_timeout, server_command_tx.clone(), Arc::clone(&branding));
+                    }
+                    id if *id == master_id => {
+                        // The menu has already flipped the check mark
+                        let enabled = master_item.is_checked();
+                        let config = Arc::clone(&badge_config);
+                        tokio::spawn(async move {
+                            if let Err(e) = set_master_switch(&config, enabled).await {
+                                error!("Failed to switch the PAC {}: {}", if enabled { "on" } else { "off" }, e);
                             }
-                        };
-                        match that(open_url) {
-                            Ok(_) => info!("Browser opened successfully"),
-                            Err(e) => error!("Failed to open browser: {}", e),
-                        }
+                        });
                     }
                     id if *id == exit_id => {
                         info!("Shutting down ProxyCat...");
-                        // Remove the tray icon before exiting
-                        let lock_result = tray_icon.lock();
-                        match lock_result {
-                            Ok(guard) => {
-                                if let Err(e) = guard.set_visible(false) {
-                                     error!("Failed to hide tray icon: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to lock tray icon mutex: {}", e);
+                        let result = APP_CONFIG.get_pac_url().and_then(|pac_url| {
+                            proxy_config::apply_exit_policy(&WindowsProxy, args.on_exit, &pac_url, previous_pac_url.as_deref())
+                        });
+                        if let Err(e) = result {
+                            error!("Failed to apply exit policy {:?}: {}", args.on_exit, e);
+                        }
+                        // Remove the tray icon before exiting, even if the mutex was poisoned
+                        if let Some(tray_icon) = &tray_icon {
+                            let guard = lock_or_recover(tray_icon, "tray icon");
+                            if let Err(e) = guard.set_visible(false) {
+                                error!("Failed to hide tray icon: {}", e);
                             }
                         }
                         std::process::exit(0);
@@ -346,30 +904,385 @@ async fn main() -> Result<()> {
             }
         }
 
+        // Redraw the tray badge when the number of enabled proxy rules changes
+        if let Some(tray_icon) = &tray_icon {
+            if badge_count.is_none() || badge_changes.has_changed().unwrap_or(false) {
+                // Skip this iteration rather than block the event loop on a busy config
+                if let Ok(config) = badge_config.try_read() {
+                    badge_changes.borrow_and_update();
+                    active = true;
+                    let count = config.proxy_rules.iter().filter(|item| item.enabled).count();
+                    master_item.set_checked(config.enabled);
+                    drop(config);
+                    if badge_count != Some(count) {
+                        let guard = lock_or_recover(tray_icon, "tray icon");
+                        if let Err(e) = update_tray_badge(&guard, &icon_theme, count) {
+                            error!("Failed to update tray icon badge: {}", e);
+                        }
+                        badge_count = Some(count);
+                    }
+                }
+            }
+        }
+
         // Log event loop iteration count periodically
         event_count += 1;
         if event_count % 250 == 0 {
             debug!("Event loop iteration: {}", event_count);
         }
 
-        thread::sleep(Duration::from_millis(10));
+        thread::sleep(backoff.next_delay(active));
+    }
+}
+
+/// Returns the address the HTTP server listens on
+fn server_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+/// Whether a PAC URL with `host` reaches the listener from `server_addr`
+fn serves_loopback_host(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost") || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip == server_addr(0).ip())
+}
+
+/// Builds the router for the current host, port and PAC path
+fn build_router(state: &AppState) -> Result<Router> {
+    let pac_path = APP_CONFIG.get_pac_path()?;
+    let cors = cors_layer(&APP_CONFIG.get_host()?, APP_CONFIG.get_port(), &state.cors_origins)?;
+    let request_log = Arc::new(RequestLog {
+        pac_path: pac_path.clone(),
+        pac_fetches: Mutex::new(PacFetchStats::default()),
+    });
+    Ok(Router::new()
+        .route("/", get(handler))
+        .route("/favicon.ico", get(favicon_handler))
+        .route("/status", get(status_handler))
+        .route("/health", get(health_handler))
+        .route("/version", get(version_handler))
+        .route("/ws", get(ws_handler))
+        .route(&pac_path, get(pac_handler))
+        .route("/tagged/:file", get(tagged_pac_handler))
+        .route("/config", get(config_handler))
+        .route("/config/diff", get(config_diff_handler))
+        .route("/config/search", get(config_search_handler))
+        .route("/external/health", get(external_health_handler))
+        .route("/external/:index/rename", post(rename_external_handler))
+        .route("/external/:index/pin", post(pin_external_handler))
+        .route("/external/:index/authoritative", post(authoritative_external_handler))
+        .route("/log-level", get(log_level_handler).post(set_log_level_handler))
+        .route("/toggle/:list_id/:index", post(toggle_handler))
+        .route("/note/:list_id/:index", post(note_handler))
+        .route("/move/:list_id/:from_index/:to_index", post(move_handler))
+        .route("/delete/:list_id/:index", post(delete_handler))
+        .route("/pac-content", get(pac_content_handler).post(pac_preview_handler))
+        .route("/pac-content/hash", get(pac_hash_handler))
+        .route("/proxy-config.json", get(proxy_config_json_handler))
+        .route("/export/reg", get(export_reg_handler))
+        .route("/export/env", get(export_env_handler))
+        .route("/validate-pac", post(validate_pac_handler))
+        .route("/evaluate", post(evaluate_handler))
+        .route("/evaluate/batch", post(evaluate_batch_handler))
+        .route("/evaluate/without/:index", post(evaluate_without_handler))
+        .route("/add-item", post(add_item_handler))
+        .route("/add-items", post(add_items_handler))
+        .route("/set-list/:list_id", post(set_list_handler))
+        .route("/import", post(import_handler))
+        .route("/import/no-proxy", post(import_no_proxy_handler))
+        .route("/reset/from-windows", post(reset_from_windows_handler))
+        .route("/undo", post(undo_handler))
+        .route("/compact", post(compact_handler))
+        .route("/apply", post(apply_handler))
+        .route("/apply/preview", get(apply_preview_handler))
+        .route("/credentials", get(credentials_handler).post(store_credential_handler))
+        .route("/credentials/:key", delete(delete_credential_handler))
+        .route("/profiles", get(profiles_handler))
+        .route("/profiles/:name", post(create_profile_handler).delete(delete_profile_handler))
+        .route("/profiles/network", get(network_profiles_handler).put(set_network_profiles_handler))
+        .route("/profiles/:name/activate", post(activate_profile_handler))
+        .route("/settings/server", get(server_settings_handler).post(update_server_settings_handler))
+        .route("/settings/auto-refresh", post(auto_refresh_handler))
+        .route("/master-switch/:state", post(master_switch_handler))
+        .layer(cors)
+        .layer(middleware::from_fn_with_state(request_log, log_requests))
+        .with_state(state.clone()))
+}
+
+/// Logs the method, path, status and latency of every request at debug level
+/// PAC fetches are frequent, so they are logged at trace level and summarized
+/// at debug level every `PAC_FETCH_SUMMARY_EVERY` fetches
+async fn log_requests(State(log): State<Arc<RequestLog>>, request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    let status = response.status();
+
+    let line = request_log_line(&method, &path, status, elapsed);
+    if path != log.pac_path {
+        debug!("{}", line);
+        return response;
+    }
+
+    trace!("{}", line);
+    let mut stats = lock_or_recover(&log.pac_fetches, "PAC fetch stats");
+    stats.count += 1;
+    stats.total += elapsed;
+    stats.slowest = stats.slowest.max(elapsed);
+    if stats.count >= PAC_FETCH_SUMMARY_EVERY {
+        debug!(
+            "Served {} {} times, average {:?}, slowest {:?}",
+            path,
+            stats.count,
+            stats.total / stats.count as u32,
+            stats.slowest
+        );
+        *stats = PacFetchStats::default();
+    }
+    response
+}
+
+/// Formats the log line for one request, e.g. `GET /status -> 200 in 1.2ms`
+fn request_log_line(method: &Method, path: &str, status: StatusCode, elapsed: Duration) -> String {
+    format!("{} {} -> {} in {:?}", method, path, status.as_u16(), elapsed)
+}
+
+/// Owns the HTTP server for the lifetime of the app, restarting it on request
+/// `ready` fires once the first bind attempt finishes, with whether it succeeded
+async fn run_server(state: AppState, mut commands: UnboundedReceiver<ServerCommand>, ready: oneshot::Sender<bool>) {
+    let mut running = bind_and_start(&state).await;
+    let _ = ready.send(running.is_some());
+
+    while let Some(ServerCommand::Restart { listener, settings }) = commands.recv().await {
+        if let Some(settings) = settings {
+            if let Err(e) = commit_server_settings(&settings) {
+                error!("Failed to change server settings: {}", e);
+            }
+        }
+        match listener {
+            Some(listener) => {
+                // Bring the new server up before stopping the old one
+                let next = start_server(listener, &state);
+                if let Some(previous) = running.take() {
+                    previous.stop().await;
+                }
+                running = next;
+            }
+            None => {
+                // Same port, so the old listener has to go first
+                if let Some(previous) = running.take() {
+                    previous.stop().await;
+                }
+                running = bind_and_start(&state).await;
+            }
+        }
+    }
+}
+
+/// Binds the configured port and starts serving on it
+/// A failure is shown to the user, since nothing can fetch the PAC until it is fixed
+async fn bind_and_start(state: &AppState) -> Option<RunningServer> {
+    let addr = server_addr(APP_CONFIG.get_port());
+    match TcpListener::bind(addr).await {
+        Ok(listener) => start_server(listener, state),
+        Err(e) => {
+            let message = bind_error_message(addr, &e);
+            error!("{}", message);
+            let text = HSTRING::from(message);
+            let title = HSTRING::from(state.branding.app_name());
+            tokio::task::spawn_blocking(move || unsafe {
+                MessageBoxW(HWND(0), &text, &title, MB_OK | MB_ICONWARNING);
+            });
+            None
+        }
+    }
+}
+
+/// Why binding the server's port failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindFailure {
+    /// Denied by a firewall or security policy
+    Blocked,
+    /// Another program is listening on the port
+    InUse,
+    Other,
+}
+
+impl BindFailure {
+    fn classify(e: &std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => BindFailure::Blocked,
+            std::io::ErrorKind::AddrInUse => BindFailure::InUse,
+            _ => BindFailure::Other,
+        }
+    }
+}
+
+/// Explains a failure to bind `addr` in terms of what the user can do about it
+fn bind_error_message(addr: SocketAddr, e: &std::io::Error) -> String {
+    match BindFailure::classify(e) {
+        BindFailure::Blocked => format!(
+            "Listening on {} was denied, most likely by a firewall or security policy. \
+             Ask for the port to be allowed or pick another with --port ({})", addr, e
+        ),
+        BindFailure::InUse => format!(
+            "Port {} is already in use by another program. Close it or pick another port with --port ({})",
+            addr.port(), e
+        ),
+        BindFailure::Other => format!("Failed to bind TCP listener on {}: {}", addr, e),
+    }
+}
+
+/// Serves the router on an already bound listener in a background task
+fn start_server(listener: TcpListener, state: &AppState) -> Option<RunningServer> {
+    let app = match build_router(state) {
+        Ok(app) => app,
+        Err(e) => {
+            error!("Failed to build router: {}", e);
+            return None;
+        }
+    };
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        if let Ok(addr) = listener.local_addr() {
+            info!("Starting server on http://{}", addr);
+        }
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                let _ = signal.await;
+            })
+            .await;
+        if let Err(e) = result {
+            error!("Axum server failed: {}", e);
+        }
+    });
+    Some(RunningServer { shutdown, handle })
+}
+
+/// Reads the server settings currently in effect
+fn current_server_settings() -> Result<ServerSettings> {
+    Ok(ServerSettings {
+        host: APP_CONFIG.get_host()?,
+        port: APP_CONFIG.get_port(),
+        pac_path: APP_CONFIG.get_pac_path()?,
+        pac_url: APP_CONFIG.get_pac_url()?,
+    })
+}
+
+/// Makes `settings` the current host, port and PAC path
+fn commit_server_settings(settings: &ServerSettings) -> Result<()> {
+    APP_CONFIG.update_host(settings.host.clone())?;
+    APP_CONFIG.update_pac_path(settings.pac_path.clone())?;
+    APP_CONFIG.update_port(settings.port)?;
+    Ok(())
+}
+
+/// Describes the resolved settings, saved rules and Windows proxy state
+/// Reads only; nothing here starts the server or changes Windows settings
+fn effective_config_summary(proxy: &impl SystemProxy) -> Result<String> {
+    let mut out = String::new();
+    out.push_str(&format!("ProxyCat {}\n", env!("CARGO_PKG_VERSION")));
+    out.push_str(&format!("Host:         {}\n", APP_CONFIG.get_host()?));
+    out.push_str(&format!("Port:         {}\n", APP_CONFIG.get_port()));
+    out.push_str(&format!("PAC path:     {}\n", APP_CONFIG.get_pac_path()?));
+    out.push_str(&format!("PAC URL:      {}\n", APP_CONFIG.get_pac_url()?));
+    out.push_str(&format!("Data dir:     {}\n", APP_CONFIG.get_data_dir()?.display()));
+    let config_path = APP_CONFIG.data_path(APP_CONFIG.config_file)?;
+    out.push_str(&format!("Config file:  {}\n", config_path.display()));
+
+    match PacConfig::load_from_file(&config_path) {
+        Ok(config) => {
+            out.push_str(&format!(
+                "Proxy rules:  {} ({} enabled)\n",
+                config.proxy_rules.len(),
+                config.proxy_rules.iter().filter(|item| item.enabled).count()
+            ));
+            out.push_str(&format!(
+                "Bypass list:  {} ({} enabled)\n",
+                config.bypass_list.len(),
+                config.bypass_list.iter().filter(|item| item.enabled).count()
+            ));
+            out.push_str(&format!(
+                "External PAC: {} ({} enabled)\n",
+                config.external_pac_functions.len(),
+                config.external_pac_functions.iter().filter(|item| item.enabled).count()
+            ));
+        }
+        Err(e) => out.push_str(&format!("Saved config: unavailable ({})\n", e)),
+    }
+
+    match proxy.current_config() {
+        Ok(windows) => {
+            let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "(none)".to_string());
+            out.push_str(&format!("Windows proxy enabled:    {}\n", windows.use_proxy));
+            out.push_str(&format!("Windows proxy server:     {}\n", show(&windows.proxy_server)));
+            out.push_str(&format!("Windows proxy bypass:     {}\n", show(&windows.proxy_bypass)));
+            out.push_str(&format!("Windows auto-config URL:  {}\n", show(&windows.auto_config_url)));
+        }
+        Err(e) => out.push_str(&format!("Windows proxy settings: unavailable ({})\n", e)),
+    }
+
+    Ok(out)
+}
+
+/// Builds the CORS policy for the HTTP API
+/// Only the UI's own origins are allowed unless more are passed with --cors-origin
+fn cors_layer(host: &str, port: u16, extra_origins: &[String]) -> Result<CorsLayer> {
+    let mut origins = vec![
+        format!("http://127.0.0.1:{}", port),
+        format!("http://localhost:{}", port),
+    ];
+    let own_origin = format!("http://{}:{}", host, port);
+    if !origins.contains(&own_origin) {
+        origins.push(own_origin);
     }
+    origins.extend(extra_origins.iter().map(|origin| origin.trim_end_matches('/').to_string()));
+    debug!("Allowed CORS origins: {:?}", origins);
+
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin)
+                .map_err(|e| ProxyCatError::Internal(format!("Invalid CORS origin {}: {}", origin, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::ACCEPT, header::IF_MATCH])
+        .expose_headers([header::ETAG]))
+}
+
+/// Locks a mutex, recovering the guard if a panic elsewhere poisoned it
+/// Used on shutdown paths where we must still reach the protected value
+fn lock_or_recover<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering poisoned {} mutex", name);
+        poisoned.into_inner()
+    })
 }
 
 /// Handles requests to the root path ("/")
-/// Returns the main application HTML page
-async fn handler() -> Html<String> {
+/// Returns the main application HTML page with the current configuration seeded in
+async fn handler(State(state): State<AppState>) -> Result<Html<String>> {
     debug!("Handling root path request");
-    let html = html_template::HTML_TEMPLATE.to_string();
+    let config = state.pac_config.read().await;
+    let config_json = serde_json::to_string(&*config)
+        .map_err(|e| ProxyCatError::Pac(format!("Failed to serialize PAC config: {}", e)))?;
+    let etag = format!("\"{}\"", pac::config_version());
+    let html = html_template::render(&config_json, &etag, &state.branding.page_title());
     debug!("Sending HTML response");
-    Html(html)
+    Ok(Html(html))
 }
 
 /// Handles requests for the favicon
 /// Returns the application icon file
 async fn favicon_handler() -> impl IntoResponse {
     debug!("Handling favicon request");
-    match fs::read("icon.ico") {
+    let contents = APP_CONFIG.data_path(icon::ICON_FILE)
+        .and_then(|path| fs::read(path).map_err(|e| ProxyCatError::Icon(e.to_string())));
+    match contents {
         Ok(contents) => {
             debug!("Sending favicon response");
             (
@@ -385,19 +1298,88 @@ async fn favicon_handler() -> impl IntoResponse {
     }
 }
 
-/// Handles requests for the PAC file
-/// Returns the current PAC configuration in JavaScript format
-async fn pac_handler(axum::extract::State(config): axum::extract::State<SharedPacConfig>) -> impl IntoResponse {
-    debug!("Handling PAC file request");
+/// Handles requests for the SHA-256 fingerprint of the generated PAC
+/// Lets tooling check that several machines serve the same merged PAC
+async fn pac_hash_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+    debug!("Handling PAC hash request");
     let config = config.read().await;
-    let content = generate_pac_content(&config);
-    debug!("Sending PAC file response");
-    
+    let hash = pac::pac_content_hash(&config);
     (
         StatusCode::OK,
-        [("Content-Type", "application/x-ns-proxy-autoconfig")],
+        [
+            ("Content-Type", "text/plain"),
+            ("Cache-Control", "no-cache"),
+        ],
+        hash
+    )
+}
+
+/// Handles requests for the PAC file
+/// Returns the current PAC configuration in JavaScript format
+/// `?download=1` serves it as an attachment named proxy.pac
+async fn pac_handler(
+    State(state): State<AppState>,
+    Query(query): Query<PacQuery>,
+) -> impl IntoResponse {
+    debug!("Handling PAC file request");
+    let config = state.pac_config.read().await;
+    let content = generate_pac_content(&config);
+    debug!("Sending PAC file response");
+    
+    let mut response = (
+        StatusCode::OK,
+        [("Content-Type", "application/x-ns-proxy-autoconfig; charset=utf-8")],
         content
-    ).into_response()
+    ).into_response();
+    insert_pac_cache_headers(response.headers_mut(), state.pac_cache_seconds, SystemTime::now());
+    if matches!(query.download.as_deref(), Some("1" | "true")) {
+        response.headers_mut().insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_static("attachment; filename=\"proxy.pac\""),
+        );
+    }
+    response
+}
+
+/// Handles requests for a tagged view of the PAC, e.g. /tagged/dev.pac
+/// The view has the items tagged "dev" plus all untagged items
+async fn tagged_pac_handler(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> Result<Response> {
+    let Some(tag) = file.strip_suffix(".pac") else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    debug!("Handling tagged PAC file request for {tag}");
+    pac::check_tags(&[tag.to_string()])?;
+    let content = generate_pac_content(&state.pac_config.read().await.tagged_view(tag));
+
+    let mut response = (
+        StatusCode::OK,
+        [("Content-Type", "application/x-ns-proxy-autoconfig; charset=utf-8")],
+        content
+    ).into_response();
+    insert_pac_cache_headers(response.headers_mut(), state.pac_cache_seconds, SystemTime::now());
+    Ok(response)
+}
+
+/// Sets how long browsers may cache the PAC file
+/// With no TTL the PAC is no-cache, so rule changes apply on the next fetch; with a
+/// TTL, max-age takes precedence and Expires is set for clients that only honour that
+fn insert_pac_cache_headers(headers: &mut HeaderMap, cache_seconds: u64, now: SystemTime) {
+    if cache_seconds == 0 {
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        return;
+    }
+    let values = [
+        (header::CACHE_CONTROL, format!("max-age={}", cache_seconds)),
+        (header::EXPIRES, httpdate::fmt_http_date(now + Duration::from_secs(cache_seconds))),
+    ];
+    for (name, value) in values {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
 }
 
 /// Handles requests for the PAC file content
@@ -412,36 +1394,636 @@ async fn pac_content_handler(State(config): State<SharedPacConfig>) -> impl Into
         [
             ("Content-Type", "text/plain"),
             ("Cache-Control", "no-cache"),
-            ("Access-Control-Allow-Origin", "*"),
         ],
         content
     )
 }
 
+/// Handles requests for the configuration as a Chrome extension ProxyConfig
+async fn proxy_config_json_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+    debug!("Handling proxy-config.json request");
+    let config = config.read().await;
+    (
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(pac::generate_proxy_config_json(&config))
+    )
+}
+
+/// Handles requests for a .reg file that points Windows at the PAC URL
+async fn export_reg_handler() -> Result<impl IntoResponse> {
+    debug!("Handling registry export request");
+    let reg = proxy_config::registry_export(&APP_CONFIG.get_pac_url()?);
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"proxycat.reg\""),
+        ],
+        reg
+    ))
+}
+
+/// Handles requests for proxy environment variables matching the configuration
+async fn export_env_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+    debug!("Handling environment export request");
+    let config = config.read().await;
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", "text/plain; charset=utf-8"),
+            ("Cache-Control", "no-cache"),
+        ],
+        pac::proxy_env_script(&config)
+    )
+}
+
+/// Returns the PAC as it would be generated with one more item added
+/// Takes the same body as /add-item but leaves the configuration untouched
+async fn pac_preview_handler(
+    State(config): State<SharedPacConfig>,
+    Json(request): Json<AddItemRequest>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling PAC preview request: {:?}", request);
+    let mut preview = config.read().await.clone();
+
+    match request.list_type.as_str() {
+        "proxy_rules" => {
+            let mut item = parse_item::<ProxyRuleItem>(&request.list_type, request.item)?;
+            item.normalize()?;
+            preview.proxy_rules.push_back(item);
+        }
+        "bypass_list" => {
+            let item = parse_item::<BypassListItem>(&request.list_type, request.item)?;
+            item.validate()?;
+            preview.bypass_list.push_back(item);
+            preview.ensure_system_bypass();
+        }
+        "external_pac_functions" => {
+            let mut item = parse_item::<ExternalPacFunctionItem>(&request.list_type, request.item)?;
+            item.validate()?;
+            // Fetch the function so the preview shows what would really be inlined
+            (item.function, item.validators) = PacConfig::fetch_external_function(ReqwestFetcher::shared(), &item.function.original_url).await?;
+            preview.external_pac_functions.push_back(item);
+        }
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {}", request.list_type))),
+    }
+
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "text/plain"),
+            ("Cache-Control", "no-cache"),
+        ],
+        generate_pac_content(&preview)
+    ))
+}
+
+/// Runs the generated PAC for a URL and returns its decision
+/// Imported functions can use the usual PAC helpers, including DNS lookups
+async fn evaluate_handler(
+    State(config): State<SharedPacConfig>,
+    Json(request): Json<EvaluateRequest>,
+) -> Result<Json<pac_eval::PacEvaluation>> {
+    debug!("Handling evaluate request for {}", request.url);
+    let content = generate_pac_content(&*config.read().await);
+    let evaluation = tokio::task::spawn_blocking(move || pac_eval::evaluate_pac(&content, &request.url))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("PAC evaluation task failed: {}", e)))??;
+    Ok(Json(evaluation))
+}
+
+/// Runs the generated PAC for a list of URLs and returns the decision for each
+async fn evaluate_batch_handler(
+    State(config): State<SharedPacConfig>,
+    Json(request): Json<EvaluateBatchRequest>,
+) -> Result<Json<Vec<pac_eval::BatchEvaluation>>> {
+    debug!("Handling batch evaluate request for {} URLs", request.urls.len());
+    if request.urls.len() > EVALUATE_BATCH_LIMIT {
+        return Err(ProxyCatError::Internal(format!(
+            "Too many URLs ({}), at most {} can be evaluated at once", request.urls.len(), EVALUATE_BATCH_LIMIT
+        )));
+    }
+    let content = generate_pac_content(&*config.read().await);
+    let evaluations = tokio::task::spawn_blocking(move || pac_eval::evaluate_pac_batch(&content, &request.urls))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("PAC evaluation task failed: {}", e)))??;
+    Ok(Json(evaluations))
+}
+
+/// Runs the generated PAC for a URL with and without the external function at `index`
+/// Nothing is changed; this shows whether that function is what decides the URL's proxy
+async fn evaluate_without_handler(
+    State(config): State<SharedPacConfig>,
+    Path(index): Path<usize>,
+    Json(request): Json<EvaluateRequest>,
+) -> Result<Json<pac_eval::ExclusionEvaluation>> {
+    debug!("Handling evaluate request for {} without external PAC function at index {index}", request.url);
+    let config = config.read().await.clone();
+    let evaluation = tokio::task::spawn_blocking(move || pac_eval::evaluate_without_external(&config, index, &request.url))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("PAC evaluation task failed: {}", e)))??;
+    Ok(Json(evaluation))
+}
+
+/// Checks a pasted PAC file without importing it
+/// Reports where FindProxyForURL is and the first JavaScript syntax error, if any
+async fn validate_pac_handler(Json(request): Json<ValidatePacRequest>) -> impl IntoResponse {
+    debug!("Validating pasted PAC file ({} bytes)", request.text.len());
+    let validation = pac_syntax::validate_pac_text(&request.text);
+    if let Some(err) = &validation.syntax_error {
+        debug!("Pasted PAC file has a syntax error: {}", err.message);
+    }
+    Json(validation)
+}
+
 /// Handles requests for the current configuration
-async fn config_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+/// Returns JSON by default, or YAML when the Accept header asks for it
+async fn config_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Query(query): Query<ConfigQuery>,
+) -> Result<Response> {
     debug!("Handling config request");
     let config = config.read().await;
-    let config_clone = config.clone();
+    let config_clone = if query.include_disabled {
+        config.clone()
+    } else {
+        config.without_disabled()
+    };
+    let etag = format!("\"{}\"", pac::config_version());
     debug!("Sending config response: {:?}", config_clone);
-    (
+
+    if is_yaml_media_type(headers.get(header::ACCEPT)) {
+        let yaml = serde_yaml::to_string(&config_clone)
+            .map_err(|e| ProxyCatError::Pac(format!("Failed to serialize PAC config as YAML: {}", e)))?;
+        return Ok((
+            StatusCode::OK,
+            [
+                ("Content-Type", "application/yaml"),
+                ("Cache-Control", "no-cache"),
+                ("ETag", etag.as_str()),
+            ],
+            yaml
+        ).into_response());
+    }
+
+    Ok((
         StatusCode::OK,
         [
             ("Content-Type", "application/json"),
             ("Cache-Control", "no-cache"),
-            ("Access-Control-Allow-Origin", "*"),
+            ("ETag", etag.as_str()),
         ],
         Json(config_clone)
+    ).into_response())
+}
+
+/// Handles requests to replace the whole configuration
+/// The body is parsed as YAML when the Content-Type says so, JSON otherwise
+/// The replaced configuration is kept as the undo snapshot
+async fn import_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<StatusCode> {
+    debug!("Handling import request");
+    let imported: PacConfig = if is_yaml_media_type(headers.get(header::CONTENT_TYPE)) {
+        serde_yaml::from_str(&body)
+            .map_err(|e| ProxyCatError::Internal(format!("Failed to parse YAML config: {}", e)))?
+    } else {
+        serde_json::from_str(&body)
+            .map_err(|e| ProxyCatError::Internal(format!("Failed to parse JSON config: {}", e)))?
+    };
+
+    // Hold imported items to the same checks as items added one at a time
+    let mut imported = imported;
+    imported.proxy_rules.iter_mut().try_for_each(ProxyRuleItem::normalize)?;
+    imported.validate()?;
+
+    let mut config = config.write().await;
+    check_version(&headers)?;
+    config.save_undo_snapshot()?;
+    *config = imported;
+    config.ensure_system_bypass();
+    config.save_current()?;
+    info!("Imported configuration with {} proxy rules", config.proxy_rules.len());
+
+    Ok(StatusCode::OK)
+}
+
+/// Handles requests to rebuild the configuration from the current Windows proxy settings
+/// The replaced configuration is kept as the undo snapshot
+async fn reset_from_windows_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+) -> Result<Json<PacConfig>> {
+    reset_from_windows(&config, &WindowsProxy, &headers).await
+}
+
+/// Replaces the configuration with one built from `proxy`'s current settings
+async fn reset_from_windows(
+    config: &SharedPacConfig,
+    proxy: &impl SystemProxy,
+    headers: &HeaderMap,
+) -> Result<Json<PacConfig>> {
+    debug!("Handling reset from Windows request");
+    let windows_config = proxy.current_config()?;
+
+    let mut config = config.write().await;
+    check_version(headers)?;
+    config.save_undo_snapshot()?;
+    *config = windows_config.to_pac_config();
+    config.save_current()?;
+    info!("Reset configuration from Windows settings with {} proxy rules", config.proxy_rules.len());
+
+    Ok(Json(config.clone()))
+}
+
+/// Handles requests to permanently remove the disabled external PAC functions
+/// The configuration before compaction is kept as the undo snapshot
+async fn compact_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+) -> Result<Json<CompactResponse>> {
+    debug!("Handling compact request");
+    let mut config = config.write().await;
+    check_version(&headers)?;
+    if config.external_pac_functions.iter().all(|item| item.enabled) {
+        return Ok(Json(CompactResponse { removed: 0 }));
+    }
+
+    config.save_undo_snapshot()?;
+    let removed = config.compact_external_functions();
+    config.save_current()?;
+    info!("Removed {} disabled external PAC functions", removed);
+
+    Ok(Json(CompactResponse { removed }))
+}
+
+/// Handles requests to restore the configuration saved before the last destructive change
+async fn undo_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+) -> Result<Json<PacConfig>> {
+    debug!("Handling undo request");
+    let mut config = config.write().await;
+    check_version(&headers)?;
+    let snapshot = PacConfig::take_undo_snapshot()
+        .map_err(|e| ProxyCatError::Internal(format!("Nothing to undo: {}", e)))?;
+    *config = snapshot;
+    config.save_current()?;
+    info!("Restored configuration from undo snapshot");
+
+    Ok(Json(config.clone()))
+}
+
+/// Rejects a mutation whose If-Match header names a configuration version that is
+/// no longer current, so changes based on stale indices don't hit the wrong item
+/// Requests without the header are always allowed. Call with the write lock held
+fn check_version(headers: &HeaderMap) -> Result<()> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(());
+    };
+    let value = value.to_str()
+        .map_err(|_| ProxyCatError::Internal("If-Match header is not valid text".to_string()))?
+        .trim();
+    if value == "*" {
+        return Ok(());
+    }
+
+    let expected = value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<u64>()
+        .map_err(|_| ProxyCatError::Internal(format!("Invalid If-Match version: {}", value)))?;
+    let current = pac::config_version();
+    if expected != current {
+        return Err(ProxyCatError::Conflict(format!(
+            "Configuration changed since version {} (now {}), reload and try again", expected, current
+        )));
+    }
+    Ok(())
+}
+
+/// Returns true if the header value names a YAML media type
+fn is_yaml_media_type(value: Option<&HeaderValue>) -> bool {
+    value
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            let v = v.to_ascii_lowercase();
+            v.contains("application/yaml") || v.contains("application/x-yaml") || v.contains("text/yaml")
+        })
+        .unwrap_or(false)
+}
+
+/// Handles searches within one list of the live configuration
+async fn config_search_handler(
+    State(config): State<SharedPacConfig>,
+    Query(query): Query<SearchQuery>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling config search request: {:?}", query);
+    let config = config.read().await;
+    let hits = config.search(&query.list, &query.q, query.enabled)?;
+    Ok((
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(hits)
+    ))
+}
+
+/// Handles requests for the differences between the saved and live configuration
+async fn config_diff_handler(State(config): State<SharedPacConfig>) -> Result<impl IntoResponse> {
+    debug!("Handling config diff request");
+    let config = config.read().await;
+    let saved = PacConfig::load_current()?;
+    let diff = ConfigDiff::between(&saved, &config);
+    debug!("Sending config diff response, has changes: {}", diff.has_changes);
+    Ok((
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/json"),
+            ("Cache-Control", "no-cache"),
+        ],
+        Json(diff)
+    ))
+}
+
+/// Opens the interface requested with --open, unless the server isn't listening
+/// Returns whether it was opened
+fn open_when_ready(server_ready: bool, open: impl FnOnce()) -> bool {
+    if !server_ready {
+        warn!("Not opening the interface on startup because the server isn't listening");
+        return false;
+    }
+    info!("Opening ProxyCat interface on startup...");
+    open();
+    true
+}
+
+/// Builds the tray icon, or returns None if that fails
+/// Without a notification area (e.g. some RDP or headless sessions) building fails;
+/// the server and proxy management still work, so startup carries on without the tray
+fn optional_tray<T>(build: impl FnOnce() -> Result<T>) -> Option<Arc<Mutex<T>>> {
+    match build() {
+        Ok(tray_icon) => {
+            info!("Tray icon created successfully");
+            #[allow(clippy::arc_with_non_send_sync)]
+            Some(Arc::new(Mutex::new(tray_icon)))
+        }
+        Err(e) => {
+            error!("{}, continuing without a tray icon", e);
+            None
+        }
+    }
+}
+
+/// Loads the icon file and builds the tray icon with the given menu
+fn build_tray_icon(menu: Menu, tooltip: &str) -> Result<TrayIcon> {
+    info!("Loading icon from file...");
+    let icon = Icon::from_path(APP_CONFIG.data_path(icon::ICON_FILE)?, None)
+        .map_err(|e| ProxyCatError::Icon(format!("Failed to load icon: {}", e)))?;
+    info!("Creating tray icon...");
+    TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip(tooltip)
+        .with_icon(icon)
+        .build()
+        .map_err(|e| ProxyCatError::TrayIcon(format!("Failed to build tray icon: {}", e)))
+}
+
+/// Replaces the tray icon with one badged with the number of enabled proxy rules
+fn update_tray_badge(tray_icon: &TrayIcon, theme: &icon::IconTheme, enabled_rules: usize) -> Result<()> {
+    let img = icon::render_icon_with_badge(theme, enabled_rules);
+    let (width, height) = img.dimensions();
+    let badged = Icon::from_rgba(img.into_raw(), width, height)
+        .map_err(|e| ProxyCatError::Icon(format!("Failed to build badged icon: {}", e)))?;
+    tray_icon.set_icon(Some(badged))
+        .map_err(|e| ProxyCatError::TrayIcon(format!("Failed to set tray icon: {}", e)))?;
+    debug!("Tray badge updated to {} enabled proxy rules", enabled_rules);
+    Ok(())
+}
+
+/// Opens the web interface in the browser once the server answers
+/// If the server can't be reached, asks the user whether to restart it instead
+/// of opening a page that would only show a connection error
+fn open_interface(probe_timeout: Duration, server_commands: UnboundedSender<ServerCommand>, branding: Arc<Branding>) {
+    let open_url = match (APP_CONFIG.get_host(), APP_CONFIG.get_port()) {
+        (Ok(host), port) => format!("http://{}:{}", host, port),
+        (Err(e), port) => {
+            error!("Failed to get host for opening URL: {}", e);
+            // Fallback to localhost if getting host fails
+            format!("http://127.0.0.1:{}", port)
+        }
+    };
+
+    tokio::spawn(async move {
+        if probe_server(&open_url, probe_timeout).await {
+            match that(&open_url) {
+                Ok(_) => info!("Browser opened successfully"),
+                Err(e) => error!("Failed to open browser: {}", e),
+            }
+            return;
+        }
+
+        warn!("Server at {} is not responding, not opening the browser", open_url);
+        // tray-icon can't show balloons, and one couldn't ask about the restart anyway
+        let text = HSTRING::from(format!(
+            "The {} server at {} is not responding.\n\nRestart the server?", branding.app_name(), open_url
+        ));
+        let title = HSTRING::from(branding.app_name());
+        let restart = tokio::task::spawn_blocking(move || unsafe {
+            MessageBoxW(HWND(0), &text, &title, MB_YESNO | MB_ICONWARNING) == IDYES
+        }).await.unwrap_or(false);
+
+        if restart {
+            info!("Restarting server at the user's request");
+            if server_commands.send(ServerCommand::Restart { listener: None, settings: None }).is_err() {
+                error!("Server task is no longer running, cannot restart it");
+            }
+        }
+    });
+}
+
+/// Returns true if the server at `base_url` answers its /status endpoint within `timeout`
+async fn probe_server(base_url: &str, timeout: Duration) -> bool {
+    // The server is local, so a proxy configured for the machine must not be used
+    let client = match reqwest::Client::builder().timeout(timeout).no_proxy().build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to build client for server probe: {}", e);
+            return false;
+        }
+    };
+    match client.get(format!("{}/status", base_url)).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(e) => {
+            debug!("Server probe of {} failed: {}", base_url, e);
+            false
+        }
+    }
+}
+
+/// Handles WebSocket connections that are told whenever the configuration changes
+async fn ws_handler(ws: WebSocketUpgrade) -> Response {
+    debug!("Handling WebSocket upgrade");
+    ws.on_upgrade(push_config_changes)
+}
+
+/// Sends the current configuration version, then a message on every change,
+/// until the client disconnects
+async fn push_config_changes(mut socket: WebSocket) {
+    let mut changes = pac::subscribe_config_changes();
+    loop {
+        let version = *changes.borrow_and_update();
+        let message = serde_json::json!({ "version": version }).to_string();
+        if socket.send(Message::Text(message)).await.is_err() {
+            break;
+        }
+
+        // Clients don't send anything meaningful, so incoming messages only matter
+        // for noticing that the connection closed
+        let changed = loop {
+            tokio::select! {
+                changed = changes.changed() => break changed.is_ok(),
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break false,
+                    Some(Ok(_)) => {}
+                },
+            }
+        };
+        if !changed {
+            break;
+        }
+    }
+    debug!("WebSocket client disconnected");
+}
+
+/// Handles liveness checks, used by the tray before opening the interface
+async fn status_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "ok",
+        "tray_available": state.tray_available,
+        "auto_refresh": APP_CONFIG.get_auto_refresh(),
+        "startup_pac": &*lock_or_recover(&state.startup_report, "startup report"),
+        "pac_apply": &*lock_or_recover(&state.pac_apply, "PAC apply status"),
+    }))
+}
+
+/// Handles watchdog checks that Windows points at our PAC URL
+/// 200 when it does, 503 otherwise; `?heal=true` re-applies the URL first if it drifted
+async fn health_handler(Query(query): Query<HealthQuery>) -> Result<impl IntoResponse> {
+    debug!("Handling health request: {:?}", query);
+    tokio::task::spawn_blocking(move || pac_health(&WindowsProxy, query.heal))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("Health check task failed: {}", e)))?
+}
+
+/// Checks `proxy` against the PAC URL, healing it first if asked to
+fn pac_health(proxy: &impl SystemProxy, heal: bool) -> Result<impl IntoResponse> {
+    let pac_url = APP_CONFIG.get_pac_url()?;
+    let health = system_events::check_pac_health(proxy, &pac_url, heal);
+    let status = if health.in_sync { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok((
+        status,
+        [("Cache-Control", "no-cache")],
+        Json(health)
+    ))
+}
+
+/// Handles requests for the version and build metadata
+async fn version_handler() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or_default(),
+        target: env!("BUILD_TARGET"),
+    })
+}
+
+/// Handles requests to turn the whole PAC on or off with `on` or `off`
+/// Off serves a PAC that sends everything DIRECT while every rule is kept
+async fn master_switch_handler(
+    State(config): State<SharedPacConfig>,
+    Path(state): Path<String>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling master switch request: {state}");
+    let enabled = match state.as_str() {
+        "on" => true,
+        "off" => false,
+        _ => return Err(ProxyCatError::Internal(format!("Invalid master switch state '{}', use on or off", state))),
+    };
+    set_master_switch(&config, enabled).await?;
+    Ok((StatusCode::OK, if enabled { "PAC enabled" } else { "PAC disabled, all requests go DIRECT" }))
+}
+
+/// Turns the master switch on or off, saving only if it changed
+async fn set_master_switch(config: &SharedPacConfig, enabled: bool) -> Result<()> {
+    let mut config = config.write().await;
+    if config.enabled != enabled {
+        config.enabled = enabled;
+        config.save_current()?;
+        info!("PAC master switch turned {}", if enabled { "on" } else { "off" });
+    }
+    Ok(())
+}
+
+/// Handles requests to turn the web interface's automatic list reloading on or off
+async fn auto_refresh_handler(Json(request): Json<AutoRefreshBody>) -> Json<AutoRefreshBody> {
+    debug!("Handling auto-refresh request: {:?}", request);
+    APP_CONFIG.update_auto_refresh(request.auto_refresh);
+    Json(AutoRefreshBody {
+        auto_refresh: APP_CONFIG.get_auto_refresh(),
+    })
+}
+
+/// Handles requests for the current log level
+async fn log_level_handler() -> Json<LogLevelBody> {
+    debug!("Handling log level request");
+    Json(LogLevelBody {
+        level: logging::current_level().to_string().to_lowercase(),
+    })
+}
+
+/// Handles requests to change the log level at runtime
+async fn set_log_level_handler(Json(request): Json<LogLevelBody>) -> Result<Json<LogLevelBody>> {
+    debug!("Handling set log level request: {:?}", request);
+    let level = logging::set_level(&request.level)?;
+    Ok(Json(LogLevelBody {
+        level: level.to_string().to_lowercase(),
+    }))
+}
+
+/// Handles requests for the fetch health of each external PAC source
+async fn external_health_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+    debug!("Handling external health request");
+    let config = config.read().await;
+    let entries: Vec<ExternalHealthEntry> = config.external_pac_functions
+        .iter()
+        .map(|item| ExternalHealthEntry {
+            function_name: item.function.function_name.clone(),
+            original_url: item.function.original_url.clone(),
+            enabled: item.enabled,
+            health: item.health.clone(),
+        })
+        .collect();
+    (
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(entries)
     )
 }
 
 /// Handles requests to toggle an item's enabled state
 async fn toggle_handler(
     State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
     Path((list_id, index)): Path<(String, usize)>,
+    Query(query): Query<ForceQuery>,
 ) -> Result<impl IntoResponse> {
     debug!("Handling toggle request for {list_id} at index {index}");
     let mut config = config.write().await;
+    check_version(&headers)?;
     
     match list_id.as_str() {
         "proxyRules" => {
@@ -453,6 +2035,7 @@ async fn toggle_handler(
         }
         "bypassList" => {
             if let Some(item) = config.bypass_list.get_mut(index) {
+                check_not_system(item, "toggled", query.force)?;
                 item.enabled = !item.enabled;
             } else {
                  return Err(ProxyCatError::Internal(format!("Invalid index {index} for bypassList")));
@@ -461,6 +2044,8 @@ async fn toggle_handler(
         "externalPacFunctions" => {
             if let Some(item) = config.external_pac_functions.get_mut(index) {
                 item.enabled = !item.enabled;
+                // A manual toggle takes precedence over the refresh task's decision
+                item.health.auto_disabled = false;
             } else {
                  return Err(ProxyCatError::Internal(format!("Invalid index {index} for externalPacFunctions")));
             }
@@ -474,87 +2059,800 @@ async fn toggle_handler(
     Ok((StatusCode::OK, "Item toggled successfully"))
 }
 
+/// Handles requests to set or clear an item's note
+async fn note_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path((list_id, index)): Path<(String, usize)>,
+    Json(request): Json<NoteRequest>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling note request for {list_id} at index {index}");
+    let note = request.note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+    pac::check_label(&note)?;
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    let slot = match list_id.as_str() {
+        "proxyRules" => config.proxy_rules.get_mut(index).map(|item| &mut item.note),
+        "bypassList" => config.bypass_list.get_mut(index).map(|item| &mut item.note),
+        "externalPacFunctions" => config.external_pac_functions.get_mut(index).map(|item| &mut item.note),
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
+    };
+    *slot.ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {index} for {list_id}")))? = note;
+
+    config.save_current()?;
+
+    Ok((StatusCode::OK, "Note updated successfully"))
+}
+
+/// Handles requests to set the label shown for an external PAC function
+/// Only the display name changes; the function name called from the PAC stays the same
+async fn rename_external_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path(index): Path<usize>,
+    Json(request): Json<RenameRequest>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling rename request for external PAC function at index {index}");
+    let display_name = request.display_name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    pac::check_label(&display_name)?;
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    let item = config.external_pac_functions.get_mut(index)
+        .ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {index} for externalPacFunctions")))?;
+    item.display_name = display_name;
+
+    config.save_current()?;
+
+    Ok((StatusCode::OK, "External PAC function renamed successfully"))
+}
+
+/// Handles requests to pin or unpin an external PAC function
+/// A pinned function keeps its current content until it is unpinned
+async fn pin_external_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path(index): Path<usize>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling pin request for external PAC function at index {index}");
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    let item = config.external_pac_functions.get_mut(index)
+        .ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {index} for externalPacFunctions")))?;
+    item.pinned = !item.pinned;
+    let message = if item.pinned {
+        "External PAC function pinned successfully"
+    } else {
+        "External PAC function unpinned successfully"
+    };
+
+    config.save_current()?;
+
+    Ok((StatusCode::OK, message))
+}
+
+/// Handles requests to toggle whether an external PAC function's DIRECT is final
+async fn authoritative_external_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path(index): Path<usize>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling authoritative request for external PAC function at index {index}");
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    let item = config.external_pac_functions.get_mut(index)
+        .ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {index} for externalPacFunctions")))?;
+    item.authoritative = !item.authoritative;
+    let message = if item.authoritative {
+        "External PAC function is now authoritative"
+    } else {
+        "External PAC function is no longer authoritative"
+    };
+
+    config.save_current()?;
+
+    Ok((StatusCode::OK, message))
+}
+
 /// Handles requests to move an item within a list
 async fn move_handler(
     State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
     Path((list_id, from_index, to_index)): Path<(String, usize, usize)>,
+    Query(query): Query<ForceQuery>,
 ) -> Result<impl IntoResponse> {
     debug!("Handling move request for {list_id} from {from_index} to {to_index}");
     let mut config = config.write().await;
+    check_version(&headers)?;
     
-    let error_msg = |idx: usize| format!("Invalid index {} in move operation", idx);
+    let moved = match list_id.as_str() {
+        "proxyRules" => move_within(&mut config.proxy_rules, &list_id, from_index, to_index)?,
+        "bypassList" => {
+            if let Some(item) = config.bypass_list.get(from_index) {
+                check_not_system(item, "moved", query.force)?;
+            }
+            // Nothing may be moved in front of the system entries either
+            let system_count = config.bypass_list.iter().take_while(|item| item.system).count();
+            if to_index < system_count && !query.force {
+                return Err(ProxyCatError::Internal(
+                    "Bypass entries can't be moved above the loopback defaults".to_string()
+                ));
+            }
+            move_within(&mut config.bypass_list, &list_id, from_index, to_index)?
+        }
+        "externalPacFunctions" => move_within(&mut config.external_pac_functions, &list_id, from_index, to_index)?,
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
+    };
 
+    // Save the configuration after moving
+    if moved {
+        config.save_current()?;
+    } else {
+        debug!("Move of {list_id} item {from_index} to {to_index} leaves the order unchanged");
+    }
+
+    Ok((StatusCode::OK, "Item moved successfully"))
+}
+
+/// Handles requests to remove an item from a list
+/// System bypass entries can't be removed, since loading the config adds them back;
+/// they can be disabled with a forced toggle instead
+async fn delete_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path((list_id, index)): Path<(String, usize)>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling delete request for {list_id} at index {index}");
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    let invalid_index = || ProxyCatError::Internal(format!("Invalid index {index} for {list_id}"));
     match list_id.as_str() {
         "proxyRules" => {
-            if from_index < config.proxy_rules.len() && to_index <= config.proxy_rules.len() {
-                let item = config.proxy_rules.remove(from_index)
-                    .ok_or_else(|| ProxyCatError::Internal(error_msg(from_index)))?;
-                config.proxy_rules.insert(to_index, item);
-            } else {
-                 return Err(ProxyCatError::Internal(format!("Invalid indices for proxyRules: from={}, to={}", from_index, to_index)));
-            }
+            config.proxy_rules.remove(index).ok_or_else(invalid_index)?;
         }
         "bypassList" => {
-            if from_index < config.bypass_list.len() && to_index <= config.bypass_list.len() {
-                let item = config.bypass_list.remove(from_index)
-                     .ok_or_else(|| ProxyCatError::Internal(error_msg(from_index)))?;
-                config.bypass_list.insert(to_index, item);
-            } else {
-                 return Err(ProxyCatError::Internal(format!("Invalid indices for bypassList: from={}, to={}", from_index, to_index)));
+            let item = config.bypass_list.get(index).ok_or_else(invalid_index)?;
+            if item.system {
+                return Err(ProxyCatError::Internal(format!(
+                    "Bypass entry {} is a loopback default and can't be deleted; disable it with force=true instead", item.host
+                )));
             }
+            config.bypass_list.remove(index);
         }
         "externalPacFunctions" => {
-             if from_index < config.external_pac_functions.len() && to_index <= config.external_pac_functions.len() {
-                 let item = config.external_pac_functions.remove(from_index)
-                     .ok_or_else(|| ProxyCatError::Internal(error_msg(from_index)))?;
-                config.external_pac_functions.insert(to_index, item);
-            } else {
-                 return Err(ProxyCatError::Internal(format!("Invalid indices for externalPacFunctions: from={}, to={}", from_index, to_index)));
-            }
+            config.external_pac_functions.remove(index).ok_or_else(invalid_index)?;
         }
-         _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
     }
 
-    // Save the configuration after moving
     config.save_current()?;
 
-    Ok((StatusCode::OK, "Item moved successfully"))
+    Ok((StatusCode::OK, "Item deleted successfully"))
+}
+
+/// Rejects a change to a system bypass entry unless it is forced
+fn check_not_system(item: &BypassListItem, action: &str, force: bool) -> Result<()> {
+    if item.system && !force {
+        return Err(ProxyCatError::Internal(format!(
+            "Bypass entry {} is a loopback default and can't be {}; pass force=true to override", item.host, action
+        )));
+    }
+    Ok(())
+}
+
+/// Moves the item at `from_index` so it sits before the item currently at `to_index`
+/// (`to_index` equal to the length moves it to the end). Returns false if the order is unchanged
+fn move_within<T>(list: &mut VecDeque<T>, list_id: &str, from_index: usize, to_index: usize) -> Result<bool> {
+    if from_index >= list.len() || to_index > list.len() {
+        return Err(ProxyCatError::Internal(format!(
+            "Invalid indices for {list_id}: from={from_index}, to={to_index}"
+        )));
+    }
+    // Removing the item shifts everything after it up by one
+    let target = if to_index > from_index { to_index - 1 } else { to_index };
+    if target == from_index {
+        return Ok(false);
+    }
+    let item = list.remove(from_index)
+        .ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {} in move operation", from_index)))?;
+    list.insert(target, item);
+    Ok(true)
+}
+
+/// Handles requests to add several items to any list with a single save
+async fn add_items_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AddItemsQuery>,
+    Json(requests): Json<Vec<AddItemRequest>>,
+) -> Result<Response> {
+    add_items(&state.pac_config, ReqwestFetcher::shared(), state.fetch_concurrency, &headers, query.atomic, requests).await
+}
+
+/// Adds a batch of items, fetching external PAC files with `fetcher`, at most `concurrency` at once,
+/// before the configuration is locked
+/// Invalid and duplicate items are reported per item; with `atomic` any failure rejects the batch
+async fn add_items(
+    config: &SharedPacConfig,
+    fetcher: &impl PacFetcher,
+    concurrency: usize,
+    headers: &HeaderMap,
+    atomic: bool,
+    requests: Vec<AddItemRequest>,
+) -> Result<Response> {
+    debug!("Handling add items request with {} items, atomic: {}", requests.len(), atomic);
+    let mut items: Vec<Result<NewItem>> = requests.into_iter().map(parse_new_item).collect();
+
+    // Fetch external PAC files concurrently, bounded so a large batch doesn't flood the network
+    let urls: Vec<(usize, String)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| match item {
+            Ok(NewItem::External(external)) => Some((index, external.function.original_url.clone())),
+            _ => None,
+        })
+        .collect();
+    let fetched: Vec<(usize, Result<(ExternalPacFunction, FetchValidators)>)> = stream::iter(urls)
+        .map(|(index, url)| async move { (index, PacConfig::fetch_external_function(fetcher, &url).await) })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    for (index, fetched) in fetched {
+        match fetched {
+            Ok((function, validators)) => {
+                if let Ok(NewItem::External(external)) = &mut items[index] {
+                    external.function = function;
+                    external.validators = validators;
+                }
+            }
+            Err(e) => items[index] = Err(e),
+        }
+    }
+
+    let mut config = config.write().await;
+    check_version(headers)?;
+    // Add to a copy so an atomic batch that fails leaves the configuration untouched,
+    // and so duplicates are caught against the items added earlier in the batch too
+    let mut updated = config.clone();
+    let items: Vec<Result<()>> = items
+        .into_iter()
+        .map(|item| item.and_then(|item| push_new_item(&mut updated, item)))
+        .collect();
+
+    let results: Vec<AddItemResult> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| AddItemResult {
+            index,
+            ok: item.is_ok(),
+            error: item.as_ref().err().map(|e| e.to_string()),
+        })
+        .collect();
+    let failed = results.iter().filter(|result| !result.ok).count();
+    let added = results.len() - failed;
+
+    if atomic && failed > 0 {
+        warn!("Rejecting atomic batch: {} of {} items failed", failed, results.len());
+        let response = AddItemsResponse { committed: false, results };
+        return Ok((StatusCode::BAD_REQUEST, Json(response)).into_response());
+    }
+
+    if added > 0 {
+        updated.ensure_system_bypass();
+        *config = updated;
+        config.save_current()?;
+    }
+    info!("Added {} items in batch, {} failed", added, failed);
+
+    let response = AddItemsResponse { committed: added > 0, results };
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+/// Appends a batch item to its list, refusing one that is already there
+fn push_new_item(config: &mut PacConfig, item: NewItem) -> Result<()> {
+    match item {
+        NewItem::ProxyRule(item) => {
+            if config.proxy_rules.iter().any(|existing| existing.same_rule(&item)) {
+                return Err(ProxyCatError::Conflict(format!(
+                    "A proxy rule for {} via {}:{} already exists", item.rule.host, item.rule.proxy_host, item.rule.proxy_port
+                )));
+            }
+            config.proxy_rules.push_back(item);
+        }
+        NewItem::Bypass(item) => {
+            if config.bypass_list.iter().any(|existing| existing.same_entry(&item)) {
+                return Err(ProxyCatError::Conflict(format!("A bypass entry for {} already exists", item.host)));
+            }
+            config.bypass_list.push_back(item);
+        }
+        NewItem::External(item) => {
+            let name = &item.function.function_name;
+            if config.external_pac_functions.iter().any(|existing| existing.function.function_name == *name) {
+                return Err(ProxyCatError::Conflict(format!(
+                    "External function {} from {} is already loaded", name, item.function.original_url
+                )));
+            }
+            config.external_pac_functions.push_back(item);
+        }
+    }
+    Ok(())
+}
+
+/// Parses and validates the item in an add-item request for its target list
+fn parse_new_item(request: AddItemRequest) -> Result<NewItem> {
+    let item = match request.list_type.as_str() {
+        "proxy_rules" => {
+            let mut item = parse_item::<ProxyRuleItem>(&request.list_type, request.item)?;
+            item.normalize()?;
+            NewItem::ProxyRule(item)
+        }
+        "bypass_list" => {
+            let item = parse_item::<BypassListItem>(&request.list_type, request.item)?;
+            item.validate()?;
+            NewItem::Bypass(item)
+        }
+        "external_pac_functions" => {
+            let item = parse_item::<ExternalPacFunctionItem>(&request.list_type, request.item)?;
+            item.validate()?;
+            NewItem::External(item)
+        }
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {}", request.list_type))),
+    };
+    Ok(item)
 }
 
 /// Handles requests to add new items to any list
 async fn add_item_handler(
     State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Query(query): Query<AddItemQuery>,
     Json(request): Json<AddItemRequest>,
-) -> Result<StatusCode> {
+) -> Result<Json<AddItemResponse>> {
+    add_item(&config, ReqwestFetcher::shared(), &headers, query.dedupe, request).await
+}
+
+/// Adds one item, fetching an external PAC file with `fetcher` before the configuration is locked
+/// A fetch failure is returned as is, so the caller sees the 502/504 instead of a half-added item
+async fn add_item(
+    config: &SharedPacConfig,
+    fetcher: &impl PacFetcher,
+    headers: &HeaderMap,
+    dedupe: DedupePolicy,
+    request: AddItemRequest,
+) -> Result<Json<AddItemResponse>> {
     debug!("Handling add item request: {:?}", request);
+    let mut item = parse_new_item(request)?;
+    if let NewItem::External(external) = &mut item {
+        (external.function, external.validators) = PacConfig::fetch_external_function(fetcher, &external.function.original_url).await?;
+    }
+
     let mut config = config.write().await;
-    
-    match request.list_type.as_str() {
-        "proxy_rules" => {
-            let item = serde_json::from_value::<ProxyRuleItem>(request.item)
-                .map_err(|e| ProxyCatError::Internal(format!("Failed to parse ProxyRuleItem: {}", e)))?;
-            config.proxy_rules.push_back(item);
+    check_version(headers)?;
+    let mut outcome = AddItemOutcome::Added;
+
+    match item {
+        NewItem::ProxyRule(item) => {
+            match config.proxy_rules.iter_mut().find(|existing| existing.same_rule(&item)) {
+                Some(existing) => {
+                    outcome = dedupe_existing(&mut existing.enabled, dedupe, &format!(
+                        "A proxy rule for {} via {}:{} already exists", item.rule.host, item.rule.proxy_host, item.rule.proxy_port
+                    ))?;
+                }
+                None => config.proxy_rules.push_back(item),
+            }
         }
-        "bypass_list" => {
-            let item = serde_json::from_value::<BypassListItem>(request.item)
-                 .map_err(|e| ProxyCatError::Internal(format!("Failed to parse BypassListItem: {}", e)))?;
-            config.bypass_list.push_back(item);
+        NewItem::Bypass(item) => {
+            match config.bypass_list.iter_mut().find(|existing| existing.same_entry(&item)) {
+                Some(existing) => {
+                    outcome = dedupe_existing(&mut existing.enabled, dedupe, &format!(
+                        "A bypass entry for {} already exists", item.host
+                    ))?;
+                }
+                None => config.bypass_list.push_back(item),
+            }
+            config.ensure_system_bypass();
         }
-        "external_pac_functions" => {
-             let item = serde_json::from_value::<ExternalPacFunctionItem>(request.item)
-                 .map_err(|e| ProxyCatError::Internal(format!("Failed to parse ExternalPacFunctionItem: {}", e)))?;
-            // Load the external PAC file before adding it to the list
-            // Note: load_external_pac logs errors internally but doesn't return Result
-            // Consider refactoring load_external_pac to return Result if needed
-            config.load_external_pac(&item.function.original_url).await;
-            // We might still want to add the item even if loading failed, maybe add it disabled?
-            // For now, just add it. Consider the implications.
-             config.external_pac_functions.push_back(item);
+        item @ NewItem::External(_) => push_new_item(&mut config, item)?,
+    }
+
+    config.save_current()?;
+
+    Ok(Json(AddItemResponse { outcome }))
+}
+
+/// Applies the dedupe policy to an item that is already in the list
+fn dedupe_existing(enabled: &mut bool, policy: DedupePolicy, duplicate: &str) -> Result<AddItemOutcome> {
+    match policy {
+        DedupePolicy::Reject => Err(ProxyCatError::Conflict(duplicate.to_string())),
+        DedupePolicy::Enable => {
+            info!("{}, enabling it instead of adding a copy", duplicate);
+            *enabled = true;
+            Ok(AddItemOutcome::ReEnabled)
         }
-         _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {}", request.list_type))),
     }
+}
+
+/// Handles requests to re-apply the PAC URL to Windows
+/// Setting it again makes Windows and browsers drop any cached copy of the PAC,
+/// and retries an apply that failed on startup
+async fn apply_handler(State(state): State<AppState>) -> Result<Json<ApplyResponse>> {
+    tokio::task::spawn_blocking(move || apply_pac(&state, &WindowsProxy))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("Apply task failed: {}", e)))?
+}
+
+/// Points `proxy` at the PAC URL and records the outcome for /status
+fn apply_pac(state: &AppState, proxy: &impl SystemProxy) -> Result<Json<ApplyResponse>> {
+    debug!("Handling apply request");
+    let pac_url = APP_CONFIG.get_pac_url()?;
+    let result = system_events::reapply_pac(proxy, &pac_url, SystemEvent::ApplyRequested);
+    *lock_or_recover(&state.pac_apply, "PAC apply status") = PacApplyStatus {
+        applied: result.is_ok(),
+        attempts: 1,
+        error: result.as_ref().err().map(ToString::to_string),
+    };
+    Ok(Json(ApplyResponse { drifted: result? }))
+}
+
+/// Handles requests to preview what /apply would change, without changing anything
+async fn apply_preview_handler() -> Result<impl IntoResponse> {
+    debug!("Handling apply preview request");
+    let pac_url = APP_CONFIG.get_pac_url()?;
+    let preview = tokio::task::spawn_blocking(move || proxy_config::apply_preview(&WindowsProxy, &pac_url))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("Apply preview task failed: {}", e)))??;
+    Ok((
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(preview)
+    ))
+}
+
+/// Request body for storing a proxy credential
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoreCredentialRequest {
+    key: String,
+    username: String,
+    password: String,
+}
+
+/// Handles requests to list the stored credential keys
+/// Passwords are never returned; keys that rules reference but nothing is stored
+/// under are listed as unresolved
+async fn credentials_handler(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    debug!("Handling credentials request");
+    let credentials = state.credentials.list()?;
+    let config = state.pac_config.read().await;
+    let mut unresolved: Vec<&str> = config.proxy_rules
+        .iter()
+        .filter_map(|item| item.rule.credential_key.as_deref())
+        .filter(|key| !credentials.iter().any(|credential| credential.key == *key))
+        .collect();
+    unresolved.sort();
+    unresolved.dedup();
+    Ok((
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(serde_json::json!({
+            "credentials": credentials,
+            "unresolved": unresolved,
+        }))
+    ))
+}
+
+/// Handles requests to store a proxy credential under a key
+async fn store_credential_handler(
+    State(state): State<AppState>,
+    Json(request): Json<StoreCredentialRequest>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling store credential request for {}", request.key);
+    let credential = ProxyCredential {
+        username: request.username,
+        password: request.password,
+    };
+    state.credentials.set(&request.key, &credential)?;
+    Ok((StatusCode::OK, format!("Stored credential '{}'", request.key)))
+}
+
+/// Handles requests to delete a stored proxy credential
+async fn delete_credential_handler(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling delete credential request for {key}");
+    if !state.credentials.delete(&key)? {
+        return Err(ProxyCatError::Internal(format!("Credential '{}' not found", key)));
+    }
+    Ok((StatusCode::OK, format!("Deleted credential '{}'", key)))
+}
+
+/// Handles requests to list the available profiles
+async fn profiles_handler(State(config): State<SharedPacConfig>) -> impl IntoResponse {
+    debug!("Handling profiles request");
+    let config = config.read().await;
+    let store = ProfileStore::load_or_migrate(&config);
+    (
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(store.summary())
+    )
+}
+
+/// Handles requests to create a new profile from a copy of the live configuration
+async fn create_profile_handler(
+    State(config): State<SharedPacConfig>,
+    Path(name): Path<String>,
+) -> Result<StatusCode> {
+    debug!("Handling create profile request for {name}");
+    let config = config.write().await;
+    let mut store = ProfileStore::load_or_migrate(&config);
+    store.create(&name, config.clone())?;
+    store.save()?;
+    Ok(StatusCode::CREATED)
+}
 
+/// Handles requests to delete a profile
+async fn delete_profile_handler(
+    State(config): State<SharedPacConfig>,
+    Path(name): Path<String>,
+) -> Result<StatusCode> {
+    debug!("Handling delete profile request for {name}");
+    let config = config.write().await;
+    let mut store = ProfileStore::load_or_migrate(&config);
+    store.delete(&name)?;
+    store.save()?;
+    Ok(StatusCode::OK)
+}
+
+/// Handles requests to make a profile the live configuration
+async fn activate_profile_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode> {
+    debug!("Handling activate profile request for {name}");
+    activate_profile(&config, Arc::new(WindowsProxy), &headers, &name).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Makes the profile `name` the live configuration
+/// Re-applies the PAC URL so the system picks up the new rules
+async fn activate_profile(
+    config: &SharedPacConfig,
+    proxy: Arc<impl SystemProxy + Send + Sync + 'static>,
+    headers: &HeaderMap,
+    name: &str,
+) -> Result<()> {
+    let mut config = config.write().await;
+    check_version(headers)?;
+    let mut store = ProfileStore::load_or_migrate(&config);
+    *config = store.activate(name, &config)?;
+    config.ensure_system_bypass();
+    store.save()?;
     config.save_current()?;
 
+    if let Err(e) = set_pac_file_blocking(proxy, APP_CONFIG.get_pac_url()?).await {
+        error!("Failed to re-apply PAC after activating profile {}: {}", name, e);
+    }
+    Ok(())
+}
+
+/// Activates the profile mapped to the current network, if any
+async fn switch_network_profile(config: &SharedPacConfig) {
+    let signature = match tokio::task::spawn_blocking(system_events::current_network_signature).await {
+        Ok(Ok(signature)) => signature,
+        Ok(Err(e)) => {
+            warn!("Failed to identify the current network: {}", e);
+            return;
+        }
+        Err(e) => {
+            error!("Network detection task failed: {}", e);
+            return;
+        }
+    };
+    debug!("Current network: {:?}", signature);
+    if let Err(e) = profiles::apply_network_profile(config, &signature).await {
+        error!("Failed to switch profile for the current network: {}", e);
+    }
+}
+
+/// Handles requests for the network-to-profile mappings along with the current network
+/// and the profile it maps to
+async fn network_profiles_handler(State(config): State<SharedPacConfig>) -> Result<impl IntoResponse> {
+    debug!("Handling network profiles request");
+    let signature = tokio::task::spawn_blocking(system_events::current_network_signature)
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("Network detection task failed: {}", e)))??;
+    let config = config.read().await;
+    let store = ProfileStore::load_or_migrate(&config);
+    let profile = store.profile_for_network(&signature);
+    Ok((
+        StatusCode::OK,
+        [("Cache-Control", "no-cache")],
+        Json(serde_json::json!({
+            "network_profiles": store.network_profiles,
+            "current_network": signature,
+            "profile": profile,
+        }))
+    ))
+}
+
+/// Handles requests to replace the network-to-profile mappings
+async fn set_network_profiles_handler(
+    State(config): State<SharedPacConfig>,
+    Json(mappings): Json<Vec<NetworkProfileMapping>>,
+) -> Result<StatusCode> {
+    debug!("Handling set network profiles request with {} mappings", mappings.len());
+    let config = config.write().await;
+    let mut store = ProfileStore::load_or_migrate(&config);
+    store.set_network_profiles(mappings)?;
+    store.save()?;
     Ok(StatusCode::OK)
 }
+
+/// Handles requests to replace an entire list in one step
+/// Every item is parsed and validated before anything changes, so a single bad item rejects the request
+async fn set_list_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Path(list_id): Path<String>,
+    Json(items): Json<Vec<serde_json::Value>>,
+) -> Result<StatusCode> {
+    debug!("Handling set list request for {list_id} with {} items", items.len());
+    let mut config = config.write().await;
+    check_version(&headers)?;
+
+    match list_id.as_str() {
+        "proxyRules" => {
+            config.proxy_rules = parse_list_items(items, ProxyRuleItem::normalize)?;
+        }
+        "bypassList" => {
+            config.bypass_list = parse_list_items(items, |item: &mut BypassListItem| item.validate())?;
+            config.ensure_system_bypass();
+        }
+        "externalPacFunctions" => {
+            config.external_pac_functions = parse_list_items(items, |item: &mut ExternalPacFunctionItem| item.validate())?;
+        }
+        _ => return Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
+    }
+
+    config.save_current()?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Parses the item of an add-item request for `list_type`
+/// Unknown fields are rejected, so an item shaped for another list fails with the shape
+/// this list expects rather than being half-read
+fn parse_item<T: DeserializeOwned>(list_type: &str, value: serde_json::Value) -> Result<T> {
+    serde_json::from_value::<T>(value).map_err(|e| {
+        let shape = match list_type {
+            "proxy_rules" => r#"{"rule": {"host": "example.com", "proxy_host": "proxy.corp", "proxy_port": 8080}, "enabled": true}"#,
+            "bypass_list" => r#"{"host": "example.com", "enabled": true, "match": "host"}"#,
+            _ => r#"{"function": {"original_url": "http://host/proxy.pac", "function_name": "", "function_text": ""}, "enabled": true}"#,
+        };
+        ProxyCatError::Internal(format!("Invalid item for {}: {}; send an item like {}", list_type, e, shape))
+    })
+}
+
+/// Parses and validates every value as a list item, failing on the first invalid one
+/// `prepare` may normalize the item as well as validate it
+fn parse_list_items<T: DeserializeOwned>(
+    values: Vec<serde_json::Value>,
+    prepare: fn(&mut T) -> Result<()>,
+) -> Result<VecDeque<T>> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let mut item = serde_json::from_value::<T>(value)
+                .map_err(|e| ProxyCatError::Internal(format!("Failed to parse item {}: {}", index, e)))?;
+            prepare(&mut item)?;
+            Ok(item)
+        })
+        .collect()
+}
+
+/// Handles requests for the current host, port and PAC path
+async fn server_settings_handler() -> Result<Json<ServerSettings>> {
+    debug!("Handling server settings request");
+    Ok(Json(current_server_settings()?))
+}
+
+/// Handles requests to change the host, port or PAC path at runtime
+async fn update_server_settings_handler(
+    State(state): State<AppState>,
+    Json(update): Json<ServerSettingsUpdate>,
+) -> Result<Json<ServerSettings>> {
+    debug!("Handling server settings update: {:?}", update);
+    Ok(Json(update_server_settings(&state, Arc::new(WindowsProxy), update).await?))
+}
+
+/// Changes the host, port or PAC path
+/// Restarts the server with the new settings and points the system at the new PAC URL
+async fn update_server_settings(
+    state: &AppState,
+    proxy: Arc<impl SystemProxy + Send + Sync + 'static>,
+    update: ServerSettingsUpdate,
+) -> Result<ServerSettings> {
+    let current = current_server_settings()?;
+    let port = update.port.unwrap_or(current.port);
+    if port == 0 {
+        return Err(ProxyCatError::Internal("Port must not be 0".to_string()));
+    }
+    let host = update.host.map(|host| host.trim().to_string()).unwrap_or_else(|| current.host.clone());
+    if !serves_loopback_host(&host) {
+        let listen_ip = server_addr(port).ip();
+        return Err(ProxyCatError::Internal(format!(
+            "Host '{}' can't reach the server, which only listens on {}; use localhost or {}", host, listen_ip, listen_ip
+        )));
+    }
+    let pac_path = match update.pac_path {
+        Some(pac_path) => constants::normalize_pac_path(&pac_path)?,
+        None => current.pac_path.clone(),
+    };
+    let settings = ServerSettings {
+        pac_url: format!("http://{}:{}{}", host, port, pac_path),
+        host,
+        port,
+        pac_path,
+    };
+    if settings == current {
+        return Ok(settings);
+    }
+
+    // Bind a new port before changing anything, so a conflict leaves the running server alone
+    let listener = if port != current.port {
+        let listener = TcpListener::bind(server_addr(port)).await
+            .map_err(|e| ProxyCatError::Internal(bind_error_message(server_addr(port), &e)))?;
+        Some(listener)
+    } else {
+        None
+    };
+
+    // The server task commits the settings, so a failed send leaves them all unchanged
+    info!("Server settings changed, restarting server at {}", settings.pac_url);
+    state.server_commands.send(ServerCommand::Restart { listener, settings: Some(settings.clone()) })
+        .map_err(|_| ProxyCatError::Internal("Server task is not running".to_string()))?;
+
+    if let Err(e) = set_pac_file_blocking(proxy, settings.pac_url.clone()).await {
+        error!("Failed to apply new PAC URL {}: {}", settings.pac_url, e);
+    }
+
+    Ok(settings)
+}
+
+/// Points `proxy` at `pac_url` on the blocking pool, as the WinInet calls block
+async fn set_pac_file_blocking(proxy: Arc<impl SystemProxy + Send + Sync + 'static>, pac_url: String) -> Result<()> {
+    tokio::task::spawn_blocking(move || proxy.set_pac_file(&pac_url))
+        .await
+        .map_err(|e| ProxyCatError::Internal(format!("Set PAC URL task failed: {}", e)))?
+}
+
+/// Handles requests to add bypass entries from a NO_PROXY style string
+async fn import_no_proxy_handler(
+    State(config): State<SharedPacConfig>,
+    headers: HeaderMap,
+    Json(request): Json<NoProxyImportRequest>,
+) -> Result<impl IntoResponse> {
+    debug!("Handling NO_PROXY import request: {}", request.no_proxy);
+    let entries = pac::parse_no_proxy(&request.no_proxy);
+    entries.iter().try_for_each(BypassListItem::validate)?;
+
+    let mut config = config.write().await;
+    check_version(&headers)?;
+    let added = config.merge_bypass_entries(entries);
+    config.save_current()?;
+    info!("Imported {} bypass entries from NO_PROXY", added);
+
+    Ok((StatusCode::OK, Json(serde_json::json!({ "added": added }))))
+}
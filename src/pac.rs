@@ -1,25 +1,105 @@
 use serde::{Serialize, Deserialize};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use std::collections::VecDeque;
-use crate::proxy_config::ProxyConfig;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{watch, RwLock};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use futures::stream::{self, StreamExt};
+use crate::credentials::validate_credential_key;
+use crate::fetcher::{ConditionalFetch, FetchValidators, PacFetcher};
 use crate::constants::APP_CONFIG;
 use log::{info, error, warn, debug};
 use crate::error::{Result, ProxyCatError};
 
+/// File the live configuration is persisted to by `save_current`
+pub const CURRENT_CONFIG_FILE: &str = "pac_config.json";
+
+/// File holding the configuration as it was before the last destructive change
+const UNDO_FILE: &str = "pac_config.undo.json";
+
+/// When `save_current` last wrote `CURRENT_CONFIG_FILE`, and the SHA-256 of what it wrote
+static LAST_SAVE: Mutex<Option<(Instant, [u8; 32])>> = Mutex::new(None);
+
+/// Weight given to the latest refresh when updating `ExternalPacHealth::success_rate`
+const HEALTH_SMOOTHING: f64 = 0.3;
+
+/// Version of the live configuration, bumped whenever it changes
+static CONFIG_VERSION: LazyLock<watch::Sender<u64>> = LazyLock::new(|| watch::channel(0).0);
+
+/// Hosts that always resolve to DIRECT and are seeded into every bypass list
+pub const LOOPBACK_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// Windows bypass token for hosts without a dot (intranet names)
+pub const WINDOWS_LOCAL_TOKEN: &str = "<local>";
+
+/// The kind of proxy server a rule points at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyType {
+    /// Plain HTTP proxy (PAC `PROXY`)
+    #[default]
+    Http,
+    /// HTTP proxy reached over TLS (PAC `HTTPS`)
+    Https,
+    /// SOCKS proxy (PAC `SOCKS`)
+    Socks,
+}
+
+/// Whether external PAC functions are consulted before or after the local proxy rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalPacPrecedence {
+    /// External functions run first, so any result they return wins
+    #[default]
+    Before,
+    /// Local proxy rules run first and external functions only see unmatched hosts
+    After,
+}
+
+impl ProxyType {
+    /// The keyword used for this proxy type in a PAC return value
+    pub fn pac_keyword(&self) -> &'static str {
+        match self {
+            ProxyType::Http => "PROXY",
+            ProxyType::Https => "HTTPS",
+            ProxyType::Socks => "SOCKS",
+        }
+    }
+}
+
 /// Represents a proxy rule with host and proxy configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProxyRule {
-    /// The hostname to match
+    /// The hostname to match, or a CIDR block ("203.0.113.0/24") to match the
+    /// destination address
     pub host: String,
     /// The proxy server hostname
     pub proxy_host: String,
     /// The proxy server port
     pub proxy_port: u16,
+    /// The kind of proxy server
+    #[serde(default)]
+    pub proxy_type: ProxyType,
+    /// Only apply the rule to URLs with this scheme (e.g. "https"); None applies to all URLs
+    #[serde(default)]
+    pub scheme: Option<String>,
+    /// Only apply the rule to https: URLs; a shortcut for the common case of `scheme`
+    #[serde(default)]
+    pub https_only: bool,
+    /// Key of the username and password for this proxy in the credential store
+    /// PAC can't pass credentials, so this is for tools that talk to the proxy directly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_key: Option<String>,
 }
 
 /// Represents an external PAC function with its modified name and content
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ExternalPacFunction {
     /// The original URL where the PAC file was loaded from
     pub original_url: String,
@@ -30,42 +110,390 @@ pub struct ExternalPacFunction {
 }
 
 /// Wrapper for ProxyRule with enabled/disabled state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ProxyRuleItem {
     /// The proxy rule
     pub rule: ProxyRule,
     /// Whether this rule is enabled
     pub enabled: bool,
+    /// Why the item exists or is disabled; emitted as a comment in the PAC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Tagged PAC views this rule is part of; an untagged rule is in every view
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
 /// Wrapper for bypass list item with enabled/disabled state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BypassListItem {
     /// The hostname or IP address to bypass
+    /// May carry a port ("example.com:8080", "[::1]:8080"), in which case only
+    /// URLs to that host on that port bypass the proxy
     pub host: String,
     /// Whether this bypass rule is enabled
     pub enabled: bool,
+    /// Why the item exists or is disabled; emitted as a comment in the PAC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Whether `host` is matched against the request's host or its full URL
+    #[serde(default, rename = "match")]
+    pub match_mode: BypassMatch,
+    /// One of the loopback defaults, which stay first, can't be deleted and can't be
+    /// moved or toggled without forcing; recomputed by `PacConfig::ensure_system_bypass`, so
+    /// values sent by clients are ignored
+    #[serde(default)]
+    pub system: bool,
+    /// Tagged PAC views this entry is part of; an untagged entry is in every view
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// What a bypass entry is matched against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BypassMatch {
+    /// The request's host, exactly or by domain suffix
+    #[default]
+    Host,
+    /// The full URL as a shell expression (e.g. "*/healthz"), for path-based bypass
+    Url,
+}
+
+/// Fetch statistics for an external PAC source, maintained by the refresh task
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalPacHealth {
+    /// "ok" after a successful refresh, otherwise the last error
+    pub last_status: Option<String>,
+    /// Number of refreshes in a row that failed
+    pub consecutive_failures: u32,
+    /// When the source was last fetched successfully, in seconds since the Unix epoch
+    pub last_success: Option<u64>,
+    /// Exponentially smoothed fraction of successful refreshes, from 0.0 to 1.0
+    pub success_rate: f64,
+    /// Whether the function was disabled because of repeated failures
+    pub auto_disabled: bool,
+}
+
+impl Default for ExternalPacHealth {
+    fn default() -> Self {
+        Self {
+            last_status: None,
+            consecutive_failures: 0,
+            last_success: None,
+            success_rate: 1.0,
+            auto_disabled: false,
+        }
+    }
 }
 
 /// Wrapper for ExternalPacFunction with enabled/disabled state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ExternalPacFunctionItem {
     /// The external PAC function
     pub function: ExternalPacFunction,
     /// Whether this function is enabled
     pub enabled: bool,
+    /// Fetch statistics for the function's source
+    #[serde(default)]
+    pub health: ExternalPacHealth,
+    /// Why the item exists or is disabled; emitted as a comment in the PAC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Label shown in the UI instead of the generated function name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Keeps the function at its current content; the refresh task skips pinned items
+    #[serde(default)]
+    pub pinned: bool,
+    /// Any non-empty result, including a bare "DIRECT", is final; otherwise a bare
+    /// "DIRECT" counts as no opinion and the later checks are consulted
+    /// On by default, which is how every external result was treated before the flag existed
+    #[serde(default = "default_authoritative")]
+    pub authoritative: bool,
+    /// ETag and Last-Modified from the last full fetch, sent so an unchanged source isn't re-downloaded
+    #[serde(default, skip_serializing_if = "FetchValidators::is_empty")]
+    pub validators: FetchValidators,
+    /// Tagged PAC views this function is part of; an untagged function is in every view
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl ProxyRule {
+    /// Strips a scheme pasted into `proxy_host` (e.g. "http://proxy.corp:8080"),
+    /// setting `proxy_type` from it and taking the port from it if one is given
+    pub fn normalize_proxy_host(&mut self) -> Result<()> {
+        let proxy_host = self.proxy_host.trim().to_string();
+        let Some((scheme, rest)) = proxy_host.split_once("://") else {
+            self.proxy_host = proxy_host;
+            return Ok(());
+        };
+
+        self.proxy_type = match scheme.to_ascii_lowercase().as_str() {
+            "http" => ProxyType::Http,
+            "https" => ProxyType::Https,
+            "socks" | "socks4" | "socks5" => ProxyType::Socks,
+            other => return Err(ProxyCatError::Internal(format!(
+                "Unsupported proxy scheme '{}' in proxy host {}", other, proxy_host
+            ))),
+        };
+
+        let authority = rest.trim_end_matches('/');
+        if authority.contains('/') {
+            return Err(ProxyCatError::Internal(format!("Proxy host {} must not contain a path", proxy_host)));
+        }
+        match split_proxy_address(authority) {
+            Some((host, Some(port))) => {
+                if self.proxy_port != 0 && self.proxy_port != port {
+                    return Err(ProxyCatError::Internal(format!(
+                        "Proxy host {} has port {} but the rule's proxy port is {}", proxy_host, port, self.proxy_port
+                    )));
+                }
+                self.proxy_host = host;
+                self.proxy_port = port;
+            }
+            Some((host, None)) => self.proxy_host = host,
+            None => self.proxy_host = authority.to_string(),
+        }
+        debug!("Normalized proxy host {} to {} {}:{}",
+            proxy_host, self.proxy_type.pac_keyword(), self.proxy_host, self.proxy_port);
+        Ok(())
+    }
+}
+
+impl ProxyRuleItem {
+    /// Whether both items describe the same rule, ignoring host case, trailing dots,
+    /// enabled state and notes
+    pub fn same_rule(&self, other: &ProxyRuleItem) -> bool {
+        let (a, b) = (&self.rule, &other.rule);
+        comparable_host(&a.host) == comparable_host(&b.host)
+            && comparable_host(&a.proxy_host) == comparable_host(&b.proxy_host)
+            && a.proxy_port == b.proxy_port
+            && a.proxy_type == b.proxy_type
+            && a.scheme.as_deref().map(str::to_ascii_lowercase) == b.scheme.as_deref().map(str::to_ascii_lowercase)
+            && a.https_only == b.https_only
+    }
+
+    /// Normalizes the proxy host and then validates the rule
+    /// Also rejects rules whose proxy is ProxyCat itself, which would loop requests back here
+    pub fn normalize(&mut self) -> Result<()> {
+        self.rule.normalize_proxy_host()?;
+        self.validate()?;
+        if APP_CONFIG.is_own_address(&self.rule.proxy_host, self.rule.proxy_port)? {
+            return Err(ProxyCatError::Internal(format!(
+                "Proxy rule for {} points at ProxyCat itself ({}:{}); use the address of the real proxy server",
+                self.rule.host, self.rule.proxy_host, self.rule.proxy_port
+            )));
+        }
+        if parse_cidr(&self.rule.host).is_some() {
+            warn!("Proxy rule for {} matches by destination address, browsers will resolve every host \
+                that reaches it via DNS", self.rule.host);
+        }
+        Ok(())
+    }
+
+    /// Checks that the rule has a host to match and a usable proxy target
+    pub fn validate(&self) -> Result<()> {
+        check_length("Proxy rule host", &self.rule.host, MAX_HOST_LEN)?;
+        check_length("Proxy host", &self.rule.proxy_host, MAX_HOST_LEN)?;
+        check_label(&self.note)?;
+        check_tags(&self.tags)?;
+        if self.rule.host.trim().is_empty() {
+            return Err(ProxyCatError::Internal("Proxy rule host must not be empty".to_string()));
+        }
+        if self.rule.proxy_host.trim().is_empty() {
+            return Err(ProxyCatError::Internal(format!("Proxy rule for {} has an empty proxy host", self.rule.host)));
+        }
+        if self.rule.proxy_port == 0 {
+            return Err(ProxyCatError::Internal(format!("Proxy rule for {} has proxy port 0", self.rule.host)));
+        }
+        if self.rule.https_only && self.rule.scheme.as_deref().is_some_and(|scheme| !scheme.eq_ignore_ascii_case("https")) {
+            return Err(ProxyCatError::Internal(format!(
+                "Proxy rule for {} is https-only but only applies to {:?} URLs", self.rule.host, self.rule.scheme
+            )));
+        }
+        if self.rule.proxy_host.contains("://") || self.rule.proxy_host.chars().any(char::is_whitespace) {
+            return Err(ProxyCatError::Internal(format!(
+                "Proxy rule for {} has an invalid proxy host {}", self.rule.host, self.rule.proxy_host
+            )));
+        }
+        if let Some(scheme) = self.rule.scheme.as_deref().filter(|scheme| !is_valid_scheme(scheme)) {
+            return Err(ProxyCatError::Internal(format!(
+                "Proxy rule for {} has an invalid scheme '{}': use lowercase letters, digits, '+', '.' or '-'",
+                self.rule.host, scheme
+            )));
+        }
+        if let Some(key) = &self.rule.credential_key {
+            validate_credential_key(key)?;
+        }
+        Ok(())
+    }
+}
+
+impl BypassListItem {
+    /// Checks that the entry has a host to bypass
+    pub fn validate(&self) -> Result<()> {
+        match self.match_mode {
+            BypassMatch::Host => check_length("Bypass host", split_bypass_port(&self.host).0, MAX_HOST_LEN)?,
+            BypassMatch::Url => check_length("Bypass URL pattern", &self.host, MAX_URL_LEN)?,
+        }
+        check_label(&self.note)?;
+        check_tags(&self.tags)?;
+        if self.host.trim().is_empty() {
+            return Err(ProxyCatError::Internal("Bypass host must not be empty".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether both items bypass the same thing, ignoring host case, trailing dots,
+    /// enabled state and notes
+    pub fn same_entry(&self, other: &BypassListItem) -> bool {
+        self.match_mode == other.match_mode && comparable_host(&self.host) == comparable_host(&other.host)
+    }
+}
+
+/// Longest hostname DNS allows
+pub const MAX_HOST_LEN: usize = 253;
+
+/// Longest URL or URL pattern accepted
+pub const MAX_URL_LEN: usize = 2048;
+
+/// Longest note or display name accepted
+pub const MAX_LABEL_LEN: usize = 500;
+
+/// Rejects values longer than `max` characters, so one bad entry can't bloat every PAC served
+pub fn check_length(what: &str, value: &str, max: usize) -> Result<()> {
+    let len = value.trim().chars().count();
+    if len > max {
+        return Err(ProxyCatError::Internal(format!(
+            "{} is {} characters long; the limit is {}", what, len, max
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `scheme` is a URL scheme as RFC 3986 spells it, in lowercase: a letter
+/// followed by letters, digits, '+', '.' or '-'
+/// Schemes are written into the PAC, so nothing else may get through
+pub fn is_valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+.-".contains(c))
+}
+
+/// Checks an optional note or display name against `MAX_LABEL_LEN`
+pub fn check_label(label: &Option<String>) -> Result<()> {
+    label.as_deref().map_or(Ok(()), |label| check_length("Label", label, MAX_LABEL_LEN))
+}
+
+/// Checks that every tag is non-empty and only uses letters, digits, '-' or '_',
+/// so it can be used in a /tagged/<tag>.pac path
+pub fn check_tags(tags: &[String]) -> Result<()> {
+    for tag in tags {
+        if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(ProxyCatError::Internal(format!("Invalid tag '{}': use letters, digits, '-' or '_'", tag)));
+        }
+    }
+    Ok(())
+}
+
+/// Whether an item with `tags` belongs in the view for `tag`; untagged items are in every view
+fn in_tagged_view(tags: &[String], tag: &str) -> bool {
+    tags.is_empty() || tags.iter().any(|item_tag| item_tag.eq_ignore_ascii_case(tag))
+}
+
+/// Lowercases a host and drops a trailing dot so equivalent spellings compare equal
+fn comparable_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl ExternalPacFunctionItem {
+    /// Checks that the function has a source URL and a name to call it by
+    pub fn validate(&self) -> Result<()> {
+        check_length("External PAC function URL", &self.function.original_url, MAX_URL_LEN)?;
+        check_label(&self.note)?;
+        check_label(&self.display_name)?;
+        check_tags(&self.tags)?;
+        if self.function.original_url.trim().is_empty() {
+            return Err(ProxyCatError::Internal("External PAC function URL must not be empty".to_string()));
+        }
+        if self.function.function_name.trim().is_empty() {
+            return Err(ProxyCatError::Internal(format!(
+                "External PAC function from {} has an empty function name", self.function.original_url
+            )));
+        }
+        Ok(())
+    }
 }
 
 /// Represents the Proxy Auto-Configuration (PAC) settings
 /// This struct contains the configuration needed to generate a PAC file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PacConfig {
+    /// Master switch; when off the PAC sends everything DIRECT but all rules are kept
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
     /// List of proxy rules for different hosts
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub proxy_rules: VecDeque<ProxyRuleItem>,
     /// List of hostnames and IP addresses to bypass the proxy
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub bypass_list: VecDeque<BypassListItem>,
     /// List of external PAC functions loaded from URLs
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub external_pac_functions: VecDeque<ExternalPacFunctionItem>,
+    /// Merge consecutive proxy rules with the same target into a single check
+    #[serde(default)]
+    pub collapse_proxy_rules: bool,
+    /// Consecutive refresh failures after which an external function is disabled (0 never disables)
+    #[serde(default = "default_external_failure_threshold")]
+    pub external_failure_threshold: u32,
+    /// Most external functions called from the PAC; the rest are left out with a warning (0 for no limit)
+    #[serde(default = "default_max_external_functions")]
+    pub max_external_functions: usize,
+    /// Whether external PAC functions are called before or after the proxy rules
+    #[serde(default)]
+    pub external_pac_precedence: ExternalPacPrecedence,
+    /// Lowercase `host` once at the top of the PAC so every check, including the
+    /// external functions, sees the same value; turn off for case-sensitive WPAD scripts
+    #[serde(default = "default_lowercase_host")]
+    pub lowercase_host: bool,
+    /// Bypass the proxy for plain host names, like `<local>` in the Windows bypass list
+    #[serde(default)]
+    pub bypass_local: bool,
+}
+
+/// Reads a list that is `null` in the file as empty, so one bad list in a hand-edited
+/// config doesn't cost the user the rest of it
+fn null_as_empty<'de, D, T>(deserializer: D) -> std::result::Result<VecDeque<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<VecDeque<T>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_external_failure_threshold() -> u32 {
+    3
+}
+
+fn default_max_external_functions() -> usize {
+    20
+}
+
+fn default_lowercase_host() -> bool {
+    true
+}
+
+fn default_authoritative() -> bool {
+    true
 }
 
 impl Default for PacConfig {
@@ -73,106 +501,41 @@ impl Default for PacConfig {
     fn default() -> Self {
         info!("Creating default PAC configuration");
         Self {
+            enabled: default_enabled(),
             proxy_rules: VecDeque::new(),
-            bypass_list: VecDeque::from([
-                BypassListItem {
-                    host: "localhost".to_string(),
+            bypass_list: LOOPBACK_HOSTS
+                .iter()
+                .map(|host| BypassListItem {
+                    host: host.to_string(),
                     enabled: true,
-                },
-                BypassListItem {
-                    host: "127.0.0.1".to_string(),
-                    enabled: true,
-                },
-                BypassListItem {
-                    host: "::1".to_string(),
-                    enabled: true,
-                },
-            ]),
+                    note: None,
+                    match_mode: BypassMatch::Host,
+                    system: true,
+                    tags: Vec::new(),
+                })
+                .collect(),
             external_pac_functions: VecDeque::new(),
+            collapse_proxy_rules: false,
+            external_failure_threshold: default_external_failure_threshold(),
+            max_external_functions: default_max_external_functions(),
+            external_pac_precedence: ExternalPacPrecedence::default(),
+            lowercase_host: default_lowercase_host(),
+            bypass_local: false,
         }
     }
 }
 
 impl PacConfig {
-    /// Creates a PAC configuration from Windows proxy settings
-    /// This function parses the Windows proxy configuration and converts it
-    /// into a format suitable for generating a PAC file
-    pub fn from_windows_config(config: &ProxyConfig) -> Self {
-        info!("Converting Windows proxy config to PAC config...");
-        let mut pac_config = Self::default();
-        
-        // Parse proxy server address if present
-        if let Some(proxy_server) = &config.proxy_server {
-            info!("Processing proxy server: {}", proxy_server);
-            if let Some((host, port)) = parse_proxy_server(proxy_server) {
-                // Add a default rule for all hosts
-                pac_config.proxy_rules.push_back(ProxyRuleItem {
-                    rule: ProxyRule {
-                        host: "*".to_string(),
-                        proxy_host: host.clone(),
-                        proxy_port: port,
-                    },
-                    enabled: true,
-                });
-                info!("Added default proxy rule - Host: *, Proxy: {}:{}", host, port);
-                
-                // Save the configuration after adding proxy rule
-                if let Err(e) = pac_config.save_current() {
-                    error!("Failed to save configuration after adding proxy rule: {}", e);
-                }
-            } else {
-                warn!("Failed to parse proxy server address");
-            }
-        } else {
-            info!("No proxy server configured");
-        }
-
-        // Parse bypass list if present
-        if let Some(bypass) = &config.proxy_bypass {
-            info!("Processing bypass list: {}", bypass);
-            let bypass_items: VecDeque<BypassListItem> = bypass
-                .split(';')
-                .filter(|s| !s.is_empty())
-                .map(|s| BypassListItem {
-                    host: s.to_string(),
-                    enabled: true,
-                })
-                .collect();
-            pac_config.bypass_list = bypass_items;
-            debug!("Parsed bypass list: {:?}", pac_config.bypass_list);
-        } else {
-            info!("No bypass list configured");
-        }
-
-        // Add default bypass entries if not present
-        for default in ["localhost", "127.0.0.1", "::1"] {
-            if !pac_config.bypass_list.iter().any(|item| item.host == default) {
-                info!("Adding default bypass entry: {}", default);
-                pac_config.bypass_list.push_back(BypassListItem {
-                    host: default.to_string(),
-                    enabled: true,
-                });
-            }
-        }
-
-        // Save the configuration after adding default entries
-        if let Err(e) = pac_config.save_current() {
-            error!("Failed to save configuration after adding default entries: {}", e);
-        }
-
-        debug!("Final PAC configuration: {:?}", pac_config);
-        pac_config
-    }
-
     /// Helper function to find the FindProxyForURL function in the text
-    fn find_proxy_function(content: &str) -> Option<(usize, usize)> {
+    /// Returns the byte range of the function, from `function` to its closing brace
+    pub fn find_proxy_function(content: &str) -> Option<(usize, usize)> {
         let function_start = content.find("function FindProxyForURL")?;
         let mut brace_count = 0;
         let mut in_function = false;
         let mut end_pos = 0;
 
         // Scan through the content to find the matching closing brace
-        for (i, c) in content[function_start..].chars().enumerate() {
+        for (i, c) in content[function_start..].char_indices() {
             if c == '{' {
                 brace_count += 1;
                 in_function = true;
@@ -200,163 +563,1073 @@ impl PacConfig {
             .collect::<String>()
     }
 
+    /// Fetches a PAC file and extracts its FindProxyForURL function, renamed
+    /// with a suffix derived from the URL
+    /// Returns the validators the server sent with it, so the first refresh can already be conditional
+    pub async fn fetch_external_function(fetcher: &impl PacFetcher, url: &str) -> Result<(ExternalPacFunction, FetchValidators)> {
+        match Self::refresh_external_function(fetcher, url, &FetchValidators::default()).await? {
+            ExternalRefresh::Updated { function, validators } => Ok((function, validators)),
+            ExternalRefresh::Unchanged => Err(ProxyCatError::Pac(format!("{} answered 304 to an unconditional request", url))),
+        }
+    }
+
+    /// Re-fetches an external PAC file, sending the validators from the previous fetch
+    /// A 304 leaves the stored function as it is, so the file is only re-parsed when it changed
+    pub async fn refresh_external_function(
+        fetcher: &impl PacFetcher,
+        url: &str,
+        validators: &FetchValidators,
+    ) -> Result<ExternalRefresh> {
+        match fetcher.fetch_conditional(url, validators).await? {
+            ConditionalFetch::NotModified => {
+                debug!("External PAC at {} is unchanged", url);
+                Ok(ExternalRefresh::Unchanged)
+            }
+            ConditionalFetch::Modified { content, validators } => Ok(ExternalRefresh::Updated {
+                function: Self::external_function_from_content(url, &content)?,
+                validators,
+            }),
+        }
+    }
+
+    /// Builds the renamed external function from the PAC file fetched from `url`
+    fn external_function_from_content(url: &str, content: &str) -> Result<ExternalPacFunction> {
+        // Generate a unique suffix from the URL
+        let suffix = Self::generate_function_suffix(url);
+        let new_function_name = format!("FindProxyForURL_{}", suffix);
+
+        let function_text = Self::extract_external_function(content, &new_function_name)
+            .ok_or_else(|| ProxyCatError::Pac(format!("No FindProxyForURL function found in the PAC file from {}", url)))?;
+
+        Ok(ExternalPacFunction {
+            original_url: url.to_string(),
+            function_name: new_function_name,
+            function_text: normalize_line_endings(&function_text),
+        })
+    }
+
+    /// Turns a PAC file into a definition of `function_name` that can sit next to other PACs
+    /// A file that is just FindProxyForURL has the function renamed. Anything else
+    /// around it (helper functions, variables, comments) is kept by wrapping the whole
+    /// file in a closure that returns its FindProxyForURL, so the helpers stay
+    /// private to it and can't collide with another PAC's
+    pub fn extract_external_function(content: &str, function_name: &str) -> Option<String> {
+        let (start, end) = Self::find_proxy_function(content)?;
+        let outside = format!("{}{}", &content[..start], &content[end..]);
+
+        if outside.trim().is_empty() {
+            return Some(content[start..end].replacen(
+                "function FindProxyForURL",
+                &format!("function {}", function_name),
+                1,
+            ));
+        }
+
+        debug!("PAC for {} defines more than FindProxyForURL, keeping the whole file", function_name);
+        Some(format!(
+            "var {} = (function () {{\n{}\nreturn FindProxyForURL;\n}})();",
+            function_name,
+            content.trim()
+        ))
+    }
+
     /// Loads additional proxy rules from an external PAC file
     /// This function fetches the PAC file from the specified URL and parses it
     /// to extract proxy rules, appending them to the existing configuration
-    pub async fn load_external_pac(&mut self, url: &str) {
+    /// Returns false if a function with the same name was already loaded
+    pub async fn load_external_pac(&mut self, fetcher: &impl PacFetcher, url: &str) -> Result<bool> {
         info!("Loading additional PAC file from {}...", url);
-        if let Ok(response) = reqwest::get(url).await {
-            if let Ok(content) = response.text().await {
-                // Try to find the FindProxyForURL function in the content
-                if let Some((start, end)) = Self::find_proxy_function(&content) {
-                    let original_function = &content[start..end];
-                    
-                    // Generate a unique suffix from the URL
-                    let suffix = Self::generate_function_suffix(url);
-                    let new_function_name = format!("FindProxyForURL_{}", suffix);
-
-                    // Replace the function name
-                    let modified_function = original_function.replace(
-                        "function FindProxyForURL",
-                        &format!("function {}", new_function_name)
-                    );
-                    // Check if this function name already exists
-                    if self.external_pac_functions.iter().any(|f| f.function.function_name == new_function_name) {
-                        info!("Function {} already exists, skipping", new_function_name);
-                        return;
-                    }
+        let (function, validators) = Self::fetch_external_function(fetcher, url).await?;
+        Ok(self.add_external_function(function, validators))
+    }
 
-                    // Store the external PAC function
-                    self.external_pac_functions.push_back(ExternalPacFunctionItem {
-                        function: ExternalPacFunction {
-                            original_url: url.to_string(),
-                            function_name: new_function_name,
-                            function_text: modified_function,
-                        },
-                        enabled: true,
-                    });
-
-                    info!("Successfully added external PAC function from {}", url);
-                    
-                    // Save the updated configuration
-                    if let Err(e) = self.save_current() {
-                        error!("Failed to save configuration after adding external PAC: {}", e);
-                    }
-                } else {
-                    warn!("No FindProxyForURL function found in the PAC file");
+    /// Appends a fetched external function, with the validators it was fetched with, and saves the configuration
+    /// Returns false if a function with the same name was already loaded
+    pub fn add_external_function(&mut self, function: ExternalPacFunction, validators: FetchValidators) -> bool {
+        // Check if this function name already exists
+        if self.external_pac_functions.iter().any(|f| f.function.function_name == function.function_name) {
+            info!("Function {} already exists, skipping", function.function_name);
+            return false;
+        }
+
+        // Store the external PAC function
+        let name = function.function_name.clone();
+        self.external_pac_functions.push_back(ExternalPacFunctionItem {
+            function,
+            enabled: true,
+            health: ExternalPacHealth {
+                last_status: Some("ok".to_string()),
+                last_success: Some(unix_now()),
+                ..ExternalPacHealth::default()
+            },
+            note: None,
+            display_name: None,
+            pinned: false,
+            authoritative: true,
+            validators,
+            tags: Vec::new(),
+        });
+
+        info!("Successfully added external PAC function {}", name);
+
+        // Save the updated configuration
+        if let Err(e) = self.save_current() {
+            error!("Failed to save configuration after adding external PAC: {}", e);
+        }
+        true
+    }
+
+    /// Records the outcome of refreshing the external function named `function_name`
+    /// Failing sources are disabled once they reach `external_failure_threshold`
+    /// consecutive failures, and re-enabled by the next successful refresh
+    /// Returns whether the function, its state or its status changed; the smoothed
+    /// statistics alone don't count and are written with the next save
+    pub fn record_external_refresh(&mut self, function_name: &str, outcome: Result<ExternalRefresh>) -> bool {
+        let threshold = self.external_failure_threshold;
+        let saved_state = |item: &ExternalPacFunctionItem| (
+            item.function.function_text.clone(),
+            item.validators.clone(),
+            item.enabled,
+            item.health.last_status.clone(),
+            item.health.consecutive_failures,
+            item.health.auto_disabled,
+        );
+        let Some(item) = self.external_pac_functions
+            .iter_mut()
+            .find(|item| item.function.function_name == function_name) else {
+            debug!("External function {} was removed during refresh", function_name);
+            return false;
+        };
+        let before = saved_state(item);
+        let health = &mut item.health;
+
+        match outcome {
+            Ok(refresh) => {
+                health.last_status = Some("ok".to_string());
+                health.consecutive_failures = 0;
+                health.last_success = Some(unix_now());
+                health.success_rate += HEALTH_SMOOTHING * (1.0 - health.success_rate);
+                if let ExternalRefresh::Updated { function, validators } = refresh {
+                    item.function.function_text = function.function_text;
+                    item.validators = validators;
+                }
+                if health.auto_disabled {
+                    info!("Re-enabling external function {} after a successful refresh", function_name);
+                    health.auto_disabled = false;
+                    item.enabled = true;
+                }
+            }
+            Err(e) => {
+                warn!("Failed to refresh external function {}: {}", function_name, e);
+                health.last_status = Some(e.to_string());
+                health.consecutive_failures += 1;
+                health.success_rate -= HEALTH_SMOOTHING * health.success_rate;
+                if threshold > 0 && health.consecutive_failures >= threshold && item.enabled {
+                    warn!("Disabling external function {} after {} consecutive failures",
+                        function_name, health.consecutive_failures);
+                    health.auto_disabled = true;
+                    item.enabled = false;
                 }
-            } else {
-                error!("Failed to read PAC file content");
             }
-        } else {
-            error!("Failed to fetch PAC file from {}", url);
         }
+
+        before != saved_state(item)
     }
 
     /// Saves the PAC configuration to a file
-    pub fn save_to_file(&self, path: &str) -> Result<()> {
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.write_file(path)?;
+        Ok(())
+    }
+
+    /// Writes the configuration to a file and returns the JSON written
+    fn write_file(&self, path: impl AsRef<Path>) -> Result<String> {
         let json = serde_json::to_string_pretty(self)
             .map_err(|e| ProxyCatError::Pac(format!("Failed to serialize PAC config: {}", e)))?;
-        std::fs::write(path, json)
+        std::fs::write(path, &json)
             .map_err(|e| ProxyCatError::Pac(format!("Failed to write PAC config file: {}", e)))?;
-        Ok(())
+        Ok(json)
     }
 
     /// Loads a PAC configuration from a file
-    pub fn load_from_file(path: &str) -> Result<Self> {
-        let content = std::fs::read_to_string(path)
+    /// The file is parsed as it is read, so a large configuration isn't held in memory twice
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)
             .map_err(|e| ProxyCatError::Pac(format!("Failed to read PAC config file: {}", e)))?;
-        serde_json::from_str(&content)
-            .map_err(|e| ProxyCatError::Pac(format!("Failed to deserialize PAC config: {}", e)))
+        Self::load_from_reader(BufReader::new(file))
+    }
+
+    /// Loads a PAC configuration from JSON read from `reader`
+    pub fn load_from_reader(reader: impl Read) -> Result<Self> {
+        let mut config: Self = serde_json::from_reader(reader)
+            .map_err(|e| ProxyCatError::Pac(format!("Failed to deserialize PAC config: {}", e)))?;
+        config.ensure_system_bypass();
+        Ok(config)
+    }
+
+    /// Makes the loopback defaults the first bypass entries, marked as system entries
+    /// Missing ones are added back; an existing entry keeps its enabled state and note
+    pub fn ensure_system_bypass(&mut self) {
+        let mut system = VecDeque::with_capacity(LOOPBACK_HOSTS.len());
+        for host in LOOPBACK_HOSTS {
+            let existing = self.bypass_list
+                .iter()
+                .position(|item| item.match_mode == BypassMatch::Host && item.host.eq_ignore_ascii_case(host))
+                .and_then(|index| self.bypass_list.remove(index));
+            let item = existing.unwrap_or_else(|| {
+                info!("Adding default bypass entry: {}", host);
+                BypassListItem {
+                    host: host.to_string(),
+                    enabled: true,
+                    note: None,
+                    match_mode: BypassMatch::Host,
+                    system: true,
+                    tags: Vec::new(),
+                }
+            });
+            system.push_back(BypassListItem { system: true, ..item });
+        }
+        for item in self.bypass_list.iter_mut() {
+            item.system = false;
+        }
+        system.append(&mut self.bypass_list);
+        self.bypass_list = system;
     }
 
     /// Saves the current PAC configuration to the default location
+    /// Every save counts as a change to the live configuration and bumps its version
     pub fn save_current(&self) -> Result<()> {
-        self.save_to_file("pac_config.json")
+        let json = self.write_file(APP_CONFIG.data_path(CURRENT_CONFIG_FILE)?)?;
+        if let Ok(mut last_save) = LAST_SAVE.lock() {
+            *last_save = Some((Instant::now(), Sha256::digest(json.as_bytes()).into()));
+        }
+        mark_config_changed();
+        Ok(())
+    }
+
+    /// Loads the PAC configuration last written by `save_current`
+    pub fn load_current() -> Result<Self> {
+        Self::load_from_file(APP_CONFIG.data_path(CURRENT_CONFIG_FILE)?)
+    }
+
+    /// Returns when `save_current` last wrote the configuration, if ever
+    pub fn last_saved_at() -> Option<Instant> {
+        LAST_SAVE.lock().ok().and_then(|last_save| last_save.map(|(at, _)| at))
+    }
+
+    /// Whether `content` is exactly what `save_current` last wrote
+    pub fn is_own_save(content: &[u8]) -> bool {
+        let digest: [u8; 32] = Sha256::digest(content).into();
+        LAST_SAVE.lock().ok().and_then(|last_save| *last_save).is_some_and(|(_, saved)| saved == digest)
+    }
+
+    /// Saves this configuration so the next destructive change can be undone
+    pub fn save_undo_snapshot(&self) -> Result<()> {
+        self.save_to_file(APP_CONFIG.data_path(UNDO_FILE)?)
+    }
+
+    /// Loads and removes the undo snapshot
+    pub fn take_undo_snapshot() -> Result<Self> {
+        let path = APP_CONFIG.data_path(UNDO_FILE)?;
+        let snapshot = Self::load_from_file(&path)?;
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove undo snapshot: {}", e);
+        }
+        Ok(snapshot)
+    }
+
+    /// Permanently removes the disabled external functions and returns how many were dropped
+    pub fn compact_external_functions(&mut self) -> usize {
+        let before = self.external_pac_functions.len();
+        self.external_pac_functions.retain(|item| item.enabled);
+        before - self.external_pac_functions.len()
+    }
+
+    /// Returns a copy with the disabled items left out of every list
+    pub fn without_disabled(&self) -> Self {
+        let mut config = self.clone();
+        config.proxy_rules.retain(|item| item.enabled);
+        config.bypass_list.retain(|item| item.enabled);
+        config.external_pac_functions.retain(|item| item.enabled);
+        config
+    }
+
+    /// Returns a copy with only the items in the view for `tag`: those tagged with it
+    /// (case-insensitive) and untagged ones. The loopback bypass defaults are always kept
+    pub fn tagged_view(&self, tag: &str) -> Self {
+        let mut config = self.clone();
+        config.proxy_rules.retain(|item| in_tagged_view(&item.tags, tag));
+        config.bypass_list.retain(|item| item.system || in_tagged_view(&item.tags, tag));
+        config.external_pac_functions.retain(|item| in_tagged_view(&item.tags, tag));
+        config
+    }
+
+    /// Finds the items in a list whose text contains `query` (case-insensitive)
+    /// Proxy rules match on host, proxy and note; bypass entries on host and note;
+    /// external functions on URL, function name, display name and note.
+    /// `enabled` narrows the result to enabled or disabled items
+    pub fn search(&self, list_id: &str, query: &str, enabled: Option<bool>) -> Result<Vec<SearchHit>> {
+        let query = query.trim().to_lowercase();
+        let wanted = |fields: &[&str], item_enabled: bool| {
+            enabled.is_none_or(|enabled| enabled == item_enabled)
+                && (query.is_empty() || fields.iter().any(|field| field.to_lowercase().contains(&query)))
+        };
+
+        match list_id {
+            "proxyRules" => Ok(search_hits(&self.proxy_rules, |item| {
+                let proxy = format!("{}:{}", item.rule.proxy_host, item.rule.proxy_port);
+                wanted(&[&item.rule.host, &proxy, item.note.as_deref().unwrap_or_default()], item.enabled)
+            })),
+            "bypassList" => Ok(search_hits(&self.bypass_list, |item| {
+                wanted(&[&item.host, item.note.as_deref().unwrap_or_default()], item.enabled)
+            })),
+            "externalPacFunctions" => Ok(search_hits(&self.external_pac_functions, |item| {
+                wanted(&[
+                    &item.function.original_url,
+                    &item.function.function_name,
+                    item.display_name.as_deref().unwrap_or_default(),
+                    item.note.as_deref().unwrap_or_default(),
+                ], item.enabled)
+            })),
+            _ => Err(ProxyCatError::Internal(format!("Invalid list type: {list_id}"))),
+        }
+    }
+
+    /// Appends bypass entries that aren't already in the list, returning how many were added
+    pub fn merge_bypass_entries(&mut self, entries: Vec<BypassListItem>) -> usize {
+        let mut added = 0;
+        for entry in entries {
+            if self.bypass_list.iter().any(|item| item.same_entry(&entry)) {
+                debug!("Bypass entry {} already present, skipping", entry.host);
+                continue;
+            }
+            self.bypass_list.push_back(entry);
+            added += 1;
+        }
+        added
+    }
+
+    /// Validates every item in every list
+    pub fn validate(&self) -> Result<()> {
+        self.proxy_rules.iter().try_for_each(ProxyRuleItem::validate)?;
+        self.bypass_list.iter().try_for_each(BypassListItem::validate)?;
+        self.external_pac_functions.iter().try_for_each(ExternalPacFunctionItem::validate)
     }
 }
 
-/// Parses a proxy server string in the format "host:port"
-/// Returns a tuple of (host, port) if successful, None otherwise
-fn parse_proxy_server(proxy: &str) -> Option<(String, u16)> {
+/// An item whose persisted and live versions differ
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedItem<T> {
+    /// The item as it was saved
+    pub saved: T,
+    /// The item as it is in memory
+    pub live: T,
+}
+
+/// Differences between the saved and live versions of a single list
+#[derive(Debug, Clone, Serialize)]
+pub struct ListDiff<T> {
+    /// Items present in memory but not on disk
+    pub added: Vec<T>,
+    /// Items present on disk but not in memory
+    pub removed: Vec<T>,
+    /// Items present in both with different contents
+    pub modified: Vec<ModifiedItem<T>>,
+}
+
+impl<T: Clone + PartialEq> ListDiff<T> {
+    /// Compares two lists, matching items for which `same` returns true
+    fn between(saved: &VecDeque<T>, live: &VecDeque<T>, same: impl Fn(&T, &T) -> bool) -> Self {
+        let mut matched = vec![false; saved.len()];
+        let mut added = Vec::new();
+        let mut modified = Vec::new();
+
+        for item in live {
+            let found = saved
+                .iter()
+                .enumerate()
+                .position(|(i, old)| !matched[i] && same(old, item));
+            match found {
+                Some(i) => {
+                    matched[i] = true;
+                    if saved[i] != *item {
+                        modified.push(ModifiedItem {
+                            saved: saved[i].clone(),
+                            live: item.clone(),
+                        });
+                    }
+                }
+                None => added.push(item.clone()),
+            }
+        }
+
+        let removed = saved
+            .iter()
+            .zip(matched)
+            .filter(|(_, was_matched)| !was_matched)
+            .map(|(item, _)| item.clone())
+            .collect();
+
+        Self { added, removed, modified }
+    }
+
+    /// Returns true if the two lists were identical
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// An item found by `PacConfig::search`, with its index in the list
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    /// Position of the item in its list, as used by /toggle, /note and /move
+    pub index: usize,
+    pub item: serde_json::Value,
+}
+
+/// Collects the items accepted by `matches` along with their indices
+fn search_hits<T: Serialize>(items: &VecDeque<T>, matches: impl Fn(&T) -> bool) -> Vec<SearchHit> {
+    items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| matches(item))
+        .filter_map(|(index, item)| serde_json::to_value(item).ok().map(|item| SearchHit { index, item }))
+        .collect()
+}
+
+/// Summary of what changed between the saved and the live PAC configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigDiff {
+    /// Whether any list differs
+    pub has_changes: bool,
+    /// Changes to the proxy rules, matched by host, target, type and scheme
+    pub proxy_rules: ListDiff<ProxyRuleItem>,
+    /// Changes to the bypass list, matched by host and match mode
+    pub bypass_list: ListDiff<BypassListItem>,
+    /// Changes to the external PAC functions, matched by function name
+    pub external_pac_functions: ListDiff<ExternalPacFunctionItem>,
+}
+
+impl ConfigDiff {
+    /// Computes the changes needed to go from `saved` to `live`
+    pub fn between(saved: &PacConfig, live: &PacConfig) -> Self {
+        let proxy_rules = ListDiff::between(&saved.proxy_rules, &live.proxy_rules, ProxyRuleItem::same_rule);
+        let bypass_list = ListDiff::between(&saved.bypass_list, &live.bypass_list, BypassListItem::same_entry);
+        let external_pac_functions = ListDiff::between(
+            &saved.external_pac_functions,
+            &live.external_pac_functions,
+            |a, b| a.function.function_name == b.function.function_name,
+        );
+        let has_changes = !(proxy_rules.is_empty() && bypass_list.is_empty() && external_pac_functions.is_empty());
+
+        Self {
+            has_changes,
+            proxy_rules,
+            bypass_list,
+            external_pac_functions,
+        }
+    }
+}
+
+/// Port Windows uses for a proxy server given without one
+pub const DEFAULT_PROXY_PORT: u16 = 80;
+
+/// Parses a proxy server string such as "proxy:8080", "[2001:db8::1]:8080" or "2001:db8::1"
+/// Returns a tuple of (host, port) if successful, None otherwise. IPv6 hosts keep
+/// their brackets so they can be emitted as-is; a missing port is `DEFAULT_PROXY_PORT`
+pub fn parse_proxy_server(proxy: &str) -> Option<(String, u16)> {
     debug!("Parsing proxy server string: {}", proxy);
-    let parts: Vec<&str> = proxy.split(':').collect();
-    if parts.len() == 2 {
-        if let Ok(port) = parts[1].parse::<u16>() {
-            debug!("Successfully parsed proxy server - Host: {}, Port: {}", parts[0], port);
-            return Some((parts[0].to_string(), port));
+    match split_proxy_address(proxy) {
+        Some((host, port)) => {
+            let port = port.unwrap_or(DEFAULT_PROXY_PORT);
+            debug!("Successfully parsed proxy server - Host: {}, Port: {}", host, port);
+            Some((host, port))
+        }
+        None => {
+            warn!("Failed to parse proxy server string: {}", proxy);
+            None
+        }
+    }
+}
+
+/// Splits a proxy address into its host and explicit port, if any
+/// IPv6 literals, bracketed or not, come back in brackets. Returns None for an
+/// empty host, an invalid IPv6 literal or a port that isn't 1-65535
+pub fn split_proxy_address(proxy: &str) -> Option<(String, Option<u16>)> {
+    let proxy = proxy.trim();
+    let parse_port = |port: &str| port.parse::<u16>().ok().filter(|port| *port != 0);
+
+    if let Some(rest) = proxy.strip_prefix('[') {
+        let (address, after) = rest.split_once(']')?;
+        let address: Ipv6Addr = address.parse().ok()?;
+        let port = match after {
+            "" => None,
+            _ => Some(parse_port(after.strip_prefix(':')?)?),
+        };
+        return Some((format!("[{}]", address), port));
+    }
+
+    if proxy.matches(':').count() > 1 {
+        let address: Ipv6Addr = proxy.parse().ok()?;
+        return Some((format!("[{}]", address), None));
+    }
+
+    let (host, port) = match proxy.split_once(':') {
+        Some((host, port)) => (host, Some(parse_port(port)?)),
+        None => (proxy, None),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port))
+}
+
+/// Parses the Windows proxy server setting into catch-all proxy rules
+/// Accepts a bare "host:port" as well as the protocol-scoped form
+/// "http=proxy1:8080;https=proxy2:8443;socks=proxy3:1080". Protocol-scoped
+/// entries only apply to URLs of that scheme, except "socks" which Windows
+/// uses for every protocol without its own entry, so it is emitted last
+pub fn parse_proxy_server_rules(proxy_server: &str) -> Vec<ProxyRule> {
+    let catch_all = |proxy_type: ProxyType, scheme: Option<String>, host: String, port: u16| ProxyRule {
+        host: "*".to_string(),
+        proxy_host: host,
+        proxy_port: port,
+        proxy_type,
+        scheme,
+        https_only: false,
+        credential_key: None,
+    };
+
+    if !proxy_server.contains('=') {
+        return parse_proxy_server(proxy_server.trim())
+            .map(|(host, port)| vec![catch_all(ProxyType::Http, None, host, port)])
+            .unwrap_or_default();
+    }
+
+    let mut rules = Vec::new();
+    let mut socks_rules = Vec::new();
+    for entry in proxy_server.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let Some((protocol, address)) = entry.split_once('=') else {
+            warn!("Ignoring proxy server entry without a protocol: {}", entry);
+            continue;
+        };
+        let protocol = protocol.trim().to_ascii_lowercase();
+        let Some((host, port)) = parse_proxy_server(address.trim()) else {
+            warn!("Ignoring malformed proxy server entry: {}", entry);
+            continue;
+        };
+        if protocol == "socks" {
+            socks_rules.push(catch_all(ProxyType::Socks, None, host, port));
+        } else if is_valid_scheme(&protocol) {
+            rules.push(catch_all(ProxyType::Http, Some(protocol), host, port));
+        } else {
+            warn!("Ignoring proxy server entry with an invalid protocol: {}", entry);
+        }
+    }
+    rules.extend(socks_rules);
+    rules
+}
+
+/// Builds the PAC statement for a group of proxy rules sharing one target and scheme
+/// A single-rule group produces the same statement the rule would on its own
+fn proxy_rule_statement(rules: &[&ProxyRule], lowercase: bool) -> String {
+    let first = rules[0];
+    let target = format!("{} {}:{}", first.proxy_type.pac_keyword(), first.proxy_host, first.proxy_port);
+
+    let mut conditions = Vec::new();
+    let scheme = match (&first.scheme, first.https_only) {
+        (Some(scheme), _) => Some(scheme.as_str()),
+        (None, true) => Some("https"),
+        (None, false) => None,
+    };
+    if let Some(scheme) = scheme {
+        let prefix = format!("{}:", scheme);
+        conditions.push(format!("url.substring(0, {}) === '{}'", prefix.len(), prefix));
+    }
+    // A catch-all anywhere in the group makes the host checks redundant
+    if !rules.iter().any(|rule| rule.host == "*") {
+        let hosts = rules.iter().map(|rule| rule_host_condition(&rule.host, lowercase)).collect::<Vec<_>>();
+        if hosts.len() > 1 && !conditions.is_empty() {
+            conditions.push(format!("({})", hosts.join(" || ")));
+        } else {
+            conditions.push(hosts.join(" || "));
+        }
+    }
+
+    if conditions.is_empty() {
+        format!("return '{}';", target)
+    } else {
+        format!("if ({}) return '{}';", conditions.join(" && "), target)
+    }
+}
+
+/// Splits rules into groups to emit as one statement each
+/// When collapsing, consecutive enabled rules with the same target and scheme
+/// share a group; rules are never reordered, so precedence is unchanged.
+/// Disabled rules are dropped, except those with a note, which form their own group
+fn group_proxy_rules(rules: &VecDeque<ProxyRuleItem>, collapse: bool) -> Vec<Vec<&ProxyRuleItem>> {
+    let mut groups: Vec<Vec<&ProxyRuleItem>> = Vec::new();
+    for item in rules.iter().filter(|item| item.enabled || item.note.is_some()) {
+        let rule = &item.rule;
+        let same_target = |other: &&ProxyRuleItem| {
+            other.enabled
+                && item.enabled
+                && other.rule.proxy_type == rule.proxy_type
+                && other.rule.proxy_host == rule.proxy_host
+                && other.rule.proxy_port == rule.proxy_port
+                && other.rule.scheme == rule.scheme
+                && other.rule.https_only == rule.https_only
+        };
+        match groups.last_mut() {
+            Some(group) if collapse && group.first().is_some_and(same_target) => group.push(item),
+            _ => groups.push(vec![item]),
+        }
+    }
+    groups
+}
+
+/// Builds the PAC lines for a group from `group_proxy_rules`: the notes of its
+/// rules as comments, followed by the statement if the rules are enabled
+fn proxy_rule_group_lines(group: &[&ProxyRuleItem], lowercase: bool) -> Vec<String> {
+    let mut lines: Vec<String> = group
+        .iter()
+        .filter_map(|item| note_comment(item.note.as_deref(), item.enabled))
+        .collect();
+    if group[0].enabled {
+        let rules = group.iter().map(|item| &item.rule).collect::<Vec<_>>();
+        lines.push(proxy_rule_statement(&rules, lowercase));
+    }
+    lines
+}
+
+/// Formats an item's note as a PAC comment, marking notes of disabled items
+fn note_comment(note: Option<&str>, enabled: bool) -> Option<String> {
+    if APP_CONFIG.get_minify_pac() {
+        return None;
+    }
+    // Keep the note on one line so it can't end the comment early
+    let note = note?.split_whitespace().collect::<Vec<_>>().join(" ");
+    if note.is_empty() {
+        None
+    } else if enabled {
+        Some(format!("// {}", note))
+    } else {
+        Some(format!("// DISABLED: {}", note))
+    }
+}
+
+/// Splits a bypass entry into its host and optional port
+/// "example.com:8080" and "[::1]:8080" carry a port; bare IPv6 such as "::1" does not
+fn split_bypass_port(entry: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = entry.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            let port = after.strip_prefix(':').and_then(|p| p.parse().ok());
+            return (host, port);
+        }
+    }
+    match entry.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (entry, None),
+        },
+        _ => (entry, None),
+    }
+}
+
+/// Parses an IPv4 CIDR block such as "10.0.0.0/8" into its network and netmask
+pub fn parse_cidr(entry: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let (network, prefix) = entry.split_once('/')?;
+    let network: Ipv4Addr = network.parse().ok()?;
+    let prefix: u32 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    Some((network, Ipv4Addr::from(mask)))
+}
+
+/// Parses a browser/curl-style NO_PROXY value into bypass entries
+/// Entries are comma separated; ".corp.example" keeps its leading dot so it
+/// matches subdomains, CIDR blocks are kept as-is for isInNet matching, and
+/// plain hosts match exactly. The "*" wildcard has no per-host meaning and is skipped
+pub fn parse_no_proxy(value: &str) -> Vec<BypassListItem> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| {
+            if *entry == "*" {
+                warn!("Skipping '*' NO_PROXY entry, disable the proxy rules instead");
+                false
+            } else {
+                true
+            }
+        })
+        .map(|entry| BypassListItem {
+            host: entry.to_string(),
+            enabled: true,
+            note: None,
+            match_mode: BypassMatch::Host,
+            system: false,
+            tags: Vec::new(),
+        })
+        .collect()
+}
+
+/// Derives HTTP_PROXY, HTTPS_PROXY and NO_PROXY for CLI tools that don't read the PAC
+/// The proxies come from the first enabled "*" rule covering each scheme; NO_PROXY is the
+/// enabled host bypass entries, with leading-dot domains kept. URL patterns have no
+/// NO_PROXY form and are left out
+pub fn proxy_env_vars(config: &PacConfig) -> Vec<(&'static str, String)> {
+    if !config.enabled {
+        return Vec::new();
+    }
+    let catch_all = |scheme: &str| {
+        config.proxy_rules
+            .iter()
+            .filter(|item| item.enabled && item.rule.host == "*")
+            .map(|item| &item.rule)
+            .find(|rule| match rule.scheme.as_deref() {
+                Some(rule_scheme) => rule_scheme.eq_ignore_ascii_case(scheme),
+                None => !rule.https_only || scheme == "https",
+            })
+    };
+
+    let mut vars = Vec::new();
+    for (name, scheme) in [("HTTP_PROXY", "http"), ("HTTPS_PROXY", "https")] {
+        if let Some(rule) = catch_all(scheme) {
+            vars.push((name, proxy_env_url(rule)));
+        }
+    }
+    let no_proxy: Vec<&str> = config.bypass_list
+        .iter()
+        .filter(|item| item.enabled && item.match_mode == BypassMatch::Host)
+        .map(|item| item.host.as_str())
+        .collect();
+    if !no_proxy.is_empty() {
+        vars.push(("NO_PROXY", no_proxy.join(",")));
+    }
+    vars
+}
+
+/// The proxy as a URL, e.g. "http://proxy.corp:8080"
+fn proxy_env_url(rule: &ProxyRule) -> String {
+    let scheme = match rule.proxy_type {
+        ProxyType::Http => "http",
+        ProxyType::Https => "https",
+        ProxyType::Socks => "socks5",
+    };
+    let host = if rule.proxy_host.contains(':') && !rule.proxy_host.starts_with('[') {
+        format!("[{}]", rule.proxy_host)
+    } else {
+        rule.proxy_host.clone()
+    };
+    format!("{}://{}:{}", scheme, host, rule.proxy_port)
+}
+
+/// The proxy environment variables as cmd `set` lines followed by PowerShell `$env:` lines
+pub fn proxy_env_script(config: &PacConfig) -> String {
+    let vars = proxy_env_vars(config);
+    let mut script = String::from("REM cmd\r\n");
+    for (name, value) in &vars {
+        script.push_str(&format!("set {}={}\r\n", name, value));
+    }
+    script.push_str("\r\n# PowerShell\r\n");
+    for (name, value) in &vars {
+        script.push_str(&format!("$env:{}='{}'\r\n", name, value.replace('\'', "''")));
+    }
+    script
+}
+
+/// SHA-256 of the generated PAC as lowercase hex
+/// Machines serving the same merged PAC report the same fingerprint; the header
+/// comment is left out since its timestamp changes every time
+pub fn pac_content_hash(config: &PacConfig) -> String {
+    Sha256::digest(generate_pac_body(config).as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Builds a Windows bypass string ("<local>;10.0.0.0/8;.corp") from the enabled bypass entries
+/// `bypass_local` becomes the leading `<local>` token. URL pattern entries have no
+/// Windows equivalent and are left out
+pub fn bypass_list_to_windows_string(items: &[BypassListItem], bypass_local: bool) -> String {
+    let local = bypass_local.then(|| WINDOWS_LOCAL_TOKEN.to_string());
+    let hosts = items
+        .iter()
+        .filter(|item| item.enabled)
+        .filter(|item| {
+            if item.match_mode == BypassMatch::Url {
+                debug!("Leaving URL bypass entry '{}' out of the Windows bypass list", item.host);
+                false
+            } else {
+                true
+            }
+        })
+        .map(|item| item.host.clone());
+    local.into_iter().chain(hosts).collect::<Vec<_>>().join(";")
+}
+
+/// Parses a Windows bypass string into bypass entries and whether `<local>` was present
+/// This is the inverse of `bypass_list_to_windows_string`
+pub fn bypass_list_from_windows_string(value: &str) -> (Vec<BypassListItem>, bool) {
+    let mut bypass_local = false;
+    let items = value
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| {
+            if entry.eq_ignore_ascii_case(WINDOWS_LOCAL_TOKEN) {
+                bypass_local = true;
+                false
+            } else {
+                true
+            }
+        })
+        .map(|entry| BypassListItem {
+            host: entry.to_string(),
+            enabled: true,
+            note: None,
+            match_mode: BypassMatch::Host,
+            system: false,
+            tags: Vec::new(),
+        })
+        .collect();
+    (items, bypass_local)
+}
+
+/// Builds the PAC expression for a bypass entry
+/// URL entries are matched against the whole URL with shExpMatch.
+/// IPv4 CIDR blocks use isInNet. The PAC `host` argument never includes a port,
+/// so entries with a port also compare against the port parsed from `url`
+/// (80/443 when implicit)
+fn bypass_condition(entry: &str, match_mode: BypassMatch, lowercase: bool) -> String {
+    if match_mode == BypassMatch::Url {
+        return format!("shExpMatch(url, {})", js_string(entry));
+    }
+    if let Some((network, mask)) = parse_cidr(entry) {
+        return format!("isInNet(host, '{}', '{}')", network, mask);
+    }
+    match split_bypass_port(entry) {
+        (host, Some(port)) => format!("({} && __pc_urlPort(url) === {})", host_condition(host, lowercase), port),
+        (host, None) => host_condition(host, lowercase),
+    }
+}
+
+/// Builds the PAC expression that matches a configured host pattern against `host`
+/// A leading dot (".corp.example") matches every subdomain via dnsDomainIs;
+/// anything else must equal the host exactly
+/// With `lowercase` set the PAC lowercases `host`, so the pattern is lowercased to match;
+/// otherwise both are compared as written
+fn host_condition(pattern: &str, lowercase: bool) -> String {
+    let pattern = if lowercase { pattern.to_lowercase() } else { pattern.to_string() };
+    if pattern.starts_with('.') {
+        format!("dnsDomainIs(host, {})", js_string(&pattern))
+    } else {
+        format!("host === {}", js_string(&pattern))
+    }
+}
+
+/// Builds the PAC expression that matches a proxy rule's host pattern
+/// A CIDR block ("203.0.113.0/24") matches on the destination address, which
+/// makes the client resolve the host; anything else is matched as in `host_condition`
+fn rule_host_condition(pattern: &str, lowercase: bool) -> String {
+    match parse_cidr(pattern) {
+        Some((network, mask)) => format!("isInNet(dnsResolve(host), '{}', '{}')", network, mask),
+        None => host_condition(pattern, lowercase),
+    }
+}
+
+/// Quotes `value` as a single-quoted JavaScript string literal
+/// Entries are only checked for length, so quotes, backslashes and line breaks in
+/// them must not be able to end the literal and break the whole PAC
+fn js_string(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('\'');
+    for c in value.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '\'' => literal.push_str("\\'"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\u{2028}' => literal.push_str("\\u2028"),
+            '\u{2029}' => literal.push_str("\\u2029"),
+            c => literal.push(c),
         }
     }
-    warn!("Failed to parse proxy server string");
-    None
+    literal.push('\'');
+    literal
 }
 
+/// Converts CRLF and lone CR line endings to LF and makes sure the text ends with a newline
+/// Keeps functions from servers with Windows or mixed line endings from mangling the master PAC
+pub fn normalize_line_endings(text: &str) -> String {
+    let mut normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+    if !normalized.ends_with('\n') {
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// PAC served when no rule could ever return anything but DIRECT
+pub const MINIMAL_DIRECT_PAC: &str = r#"function FindProxyForURL(url, host){return "DIRECT";}"#;
+
 /// Type alias for thread-safe shared access to PAC configuration
 pub type SharedPacConfig = Arc<RwLock<PacConfig>>;
 
 /// Generates the content of a PAC file based on the current configuration
 /// The PAC file contains JavaScript code that browsers use to determine
 /// whether to use a proxy for a given URL
+/// Unless the PAC is minified it starts with a comment naming the build, the time
+/// and the item counts, so a PAC found in a browser can be traced back to its source
 pub fn generate_pac_content(config: &PacConfig) -> String {
+    let body = generate_pac_body(config);
+    if APP_CONFIG.get_minify_pac() {
+        return body;
+    }
+    format!("{}\n{}", pac_header_comment(config, SystemTime::now()), body)
+}
+
+/// The header comment of a generated PAC
+fn pac_header_comment(config: &PacConfig, generated_at: SystemTime) -> String {
+    let enabled_rules = config.proxy_rules.iter().filter(|item| item.enabled).count();
+    let enabled_bypass = config.bypass_list.iter().filter(|item| item.enabled).count();
+    let enabled_external = config.external_pac_functions.iter().filter(|item| item.enabled).count();
+    format!(
+        "// ProxyCat v{} ({}) - generated {} - proxy rules: {}, bypass entries: {}, external functions: {}{}",
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH"),
+        httpdate::fmt_http_date(generated_at),
+        enabled_rules,
+        enabled_bypass,
+        enabled_external,
+        if config.enabled { "" } else { " - master switch off" }
+    )
+}
+
+/// The PAC script itself, without the header comment
+fn generate_pac_body(config: &PacConfig) -> String {
     info!("Generating PAC file content...");
+
+    if !config.enabled {
+        debug!("Master switch is off, generating minimal PAC");
+        return MINIMAL_DIRECT_PAC.to_string();
+    }
+
+    // With nothing that could return a proxy every request goes DIRECT,
+    // so skip the scaffold and serve the smallest valid PAC
+    let has_proxy_rules = config.proxy_rules.iter().any(|item| item.enabled);
+    let has_external_functions = config.external_pac_functions.iter().any(|item| item.enabled);
+    if !has_proxy_rules && !has_external_functions {
+        debug!("No enabled proxy rules or external PAC functions, generating minimal PAC");
+        return MINIMAL_DIRECT_PAC.to_string();
+    }
     
+    // Notes on bypass entries, shown above the bypass check
+    let bypass_notes = config.bypass_list
+        .iter()
+        .filter_map(|item| {
+            note_comment(item.note.as_deref(), item.enabled)
+                .map(|comment| format!("{}\n    ", comment))
+        })
+        .collect::<String>();
+
     // Generate bypass list check
-    let bypass_list = if config.bypass_list.is_empty() {
+    let local_check = config.bypass_local.then(|| "isPlainHostName(host)".to_string());
+    // System entries go first whatever order the list is in. Loopback hosts are
+    // left out, since the loopback check ahead of the bypass list already returns DIRECT
+    let bypass_checks = local_check
+        .into_iter()
+        .chain(config.bypass_list
+            .iter()
+            .filter(|item| item.enabled && item.system)
+            .chain(config.bypass_list.iter().filter(|item| item.enabled && !item.system))
+            .filter(|item| item.match_mode != BypassMatch::Host || !LOOPBACK_HOSTS.contains(&item.host.as_str()))
+            .map(|item| bypass_condition(&item.host, item.match_mode, config.lowercase_host)))
+        .collect::<Vec<_>>();
+    let bypass_list = if bypass_checks.is_empty() {
         "false".to_string()
     } else {
-        config.bypass_list
-            .iter()
-            .filter(|item| item.enabled)
-            .map(|item| format!("host === '{}'", item.host))
-            .collect::<Vec<_>>()
-            .join(" || ")
+        bypass_checks.join(" || ")
     };
 
+    // Loopback is always direct, regardless of how the bypass list is toggled
+    let loopback_check = LOOPBACK_HOSTS
+        .iter()
+        .map(|host| format!("host === '{}'", host))
+        .collect::<Vec<_>>()
+        .join(" || ");
+
     // Generate proxy rules
-    let proxy_rules = config.proxy_rules
+    let proxy_rules = group_proxy_rules(&config.proxy_rules, config.collapse_proxy_rules)
         .iter()
-        .filter(|item| item.enabled)
-        .map(|item| {
-            if item.rule.host == "*" {
-                format!("return 'PROXY {}:{}';", item.rule.proxy_host, item.rule.proxy_port)
-            } else {
-                format!("if (host == '{}') return 'PROXY {}:{}';", 
-                    item.rule.host, item.rule.proxy_host, item.rule.proxy_port)
-            }
-        })
+        .flat_map(|group| proxy_rule_group_lines(group, config.lowercase_host))
         .collect::<Vec<_>>()
         .join("\n    ");
 
+    // Enabled functions left out because they duplicate another or exceed the cap
+    let skipped_externals = skipped_external_functions(config);
+
     // Generate external PAC functions
     let external_functions = config.external_pac_functions
         .iter()
-        .filter(|item| item.enabled)
-        .map(|item| item.function.function_text.clone())
+        .filter(|item| item.enabled && !skipped_externals.contains_key(item.function.function_name.as_str()))
+        .map(|item| normalize_line_endings(&item.function.function_text))
         .collect::<Vec<_>>()
-        .join("\n\n");
+        .join("\n");
 
     // Generate external PAC function calls
+    // Result variables are named by list position so they are valid and unique
+    // whatever characters the function names carry. Only authoritative functions
+    // end the lookup with a bare DIRECT
     let external_calls = config.external_pac_functions
         .iter()
-        .filter(|item| item.enabled)
-        .map(|item| {
-            format!(
-                "    // Try external PAC function from {}\n    const result{} = {}(url, host);\n    if (!isEmptyStringSafe(result{})) return result{};",
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let note = note_comment(item.note.as_deref(), item.enabled)
+                .map(|comment| format!("    {}\n", comment));
+            if !item.enabled {
+                return note;
+            }
+            if let Some(reason) = skipped_externals.get(item.function.function_name.as_str()) {
+                return Some(format!("{}    // Skipped external PAC function from {}: {}",
+                    note.unwrap_or_default(), item.function.original_url, reason));
+            }
+            let condition = if item.authoritative {
+                format!("!__pc_isEmptyStringSafe(result_{index})")
+            } else {
+                format!("!__pc_isEmptyStringSafe(result_{index}) && !__pc_isDirectOnly(result_{index})")
+            };
+            Some(format!(
+                "{}    // Try external PAC function from {}\n    const result_{index} = {}(url, host);\n    if ({}) return result_{index};",
+                note.unwrap_or_default(),
                 item.function.original_url,
-                item.function.function_name, 
                 item.function.function_name,
-                item.function.function_name,
-                item.function.function_name
-            )
+                condition
+            ))
         })
         .collect::<Vec<_>>()
         .join("\n");
 
+    let external_block = format!("// Try external PAC functions\n    {}", external_calls);
+    let proxy_rules_block = format!("// Proxy rules - check each rule against the host\n    {}", proxy_rules);
+    let (first_block, second_block) = match config.external_pac_precedence {
+        ExternalPacPrecedence::Before => (external_block, proxy_rules_block),
+        ExternalPacPrecedence::After => (proxy_rules_block, external_block),
+    };
+
+    // External functions are called with the lowercased host too
+    let host_normalization = if config.lowercase_host {
+        "// Hostnames are case-insensitive, so compare them in lowercase everywhere\n    host = host.toLowerCase();\n"
+    } else {
+        ""
+    };
+
+    // ProxyCat's own helpers carry the __pc_ prefix, shared with the evaluator's shims,
+    // so a function of the same name in an inlined external PAC can't replace them
     let content = format!(
         r#"
 function FindProxyForURL(url, host) {{
-
-    function isEmptyStringSafe(str) {{
+    {}
+    function __pc_isEmptyStringSafe(str) {{
         // Handle null/undefined
         if (str == null) return true;
         // Handle non-string types
@@ -364,27 +1637,47 @@ function FindProxyForURL(url, host) {{
         return str.length === 0;
     }}
 
+    function __pc_isDirectOnly(str) {{
+        // A bare DIRECT, as opposed to a list such as "PROXY a:8080; DIRECT"
+        return str.trim().toUpperCase() === 'DIRECT';
+    }}
+
+    function __pc_urlPort(url) {{
+        // Port from the URL, or the scheme's default when none is given
+        var match = /^([a-z][a-z0-9+.-]*):\/\/(?:[^@\/]*@)?(\[[^\]]*\]|[^:\/?#]*)(?::(\d+))?/i.exec(url);
+        if (!match) return -1;
+        if (match[3]) return parseInt(match[3], 10);
+        var scheme = match[1].toLowerCase();
+        return scheme === 'https' ? 443 : (scheme === 'http' ? 80 : -1);
+    }}
+
     // All external PAC functions first
     {}
     
-    // Bypass list - URLs matching these patterns will bypass the proxy
+    // Loopback - never proxy requests to this machine, including ProxyCat itself
     if ({}) {{
         return "DIRECT";
     }}
     
-    // Try external PAC functions
+    // Bypass list - URLs matching these patterns will bypass the proxy
+    {}if ({}) {{
+        return "DIRECT";
+    }}
+    
     {}
 
-    // Proxy rules - check each rule against the host
     {}
     
     // Default to direct connection if no rules match
     return "DIRECT";
 }}"#,
+        host_normalization,
         external_functions,
+        loopback_check,
+        bypass_notes,
         bypass_list,
-        external_calls,
-        proxy_rules
+        first_block,
+        second_block
     );
 
     debug!("Generated PAC file content with {} proxy rules and {} external PAC functions", 
@@ -394,40 +1687,175 @@ function FindProxyForURL(url, host) {{
     content
 }
 
-/// Creates a shared PAC configuration by reading Windows proxy settings
-/// This function initializes the PAC configuration from the current Windows
-/// proxy settings and wraps it in a thread-safe shared structure
-pub fn create_shared_config() -> SharedPacConfig {
-    info!("Creating shared PAC configuration...");
-    
-    // Try to load existing configuration
-    let pac_config = match PacConfig::load_from_file(APP_CONFIG.config_file) {
-        Ok(config) => {
-            info!("Loaded existing configuration from file");
-            config
-        }
-        Err(e) => {
-            warn!("Could not load configuration file: {}", e);
-            info!("Creating new configuration from Windows settings");
-            
-            // Create new config from Windows settings
-            let windows_config = ProxyConfig::from_windows().unwrap_or_else(|e| {
-                error!("Failed to read Windows proxy settings: {}", e);
-                info!("Using empty proxy configuration");
-                ProxyConfig::new()
-            });
-            
-            let config = PacConfig::from_windows_config(&windows_config);
-            
-            // Save the new configuration
-            if let Err(e) = config.save_to_file(APP_CONFIG.config_file) {
-                error!("Failed to save initial configuration: {}", e);
-            }
-            
-            config
-        }
+/// Describes the configuration as a Chrome extension `chrome.proxy` ProxyConfig
+/// A single catch-all proxy with host bypasses maps to `fixed_servers`; anything
+/// that needs per-host logic is sent as `pac_script` with the generated PAC
+pub fn generate_proxy_config_json(config: &PacConfig) -> serde_json::Value {
+    let rules: Vec<&ProxyRule> = config.proxy_rules.iter().filter(|item| item.enabled).map(|item| &item.rule).collect();
+    let has_external_functions = config.external_pac_functions.iter().any(|item| item.enabled);
+    if !config.enabled || (rules.is_empty() && !has_external_functions) {
+        return serde_json::json!({ "mode": "direct" });
+    }
+
+    let bypass: Vec<&BypassListItem> = config.bypass_list.iter().filter(|item| item.enabled).collect();
+    let fixed_server = match rules.as_slice() {
+        [rule] if rule.host == "*" && rule.scheme.is_none() && !rule.https_only => Some(rule),
+        _ => None,
     };
+    let bypass_is_host_only = bypass.iter().all(|item| item.match_mode == BypassMatch::Host);
+
+    match fixed_server {
+        Some(rule) if !has_external_functions && bypass_is_host_only => {
+            let scheme = match rule.proxy_type {
+                ProxyType::Http => "http",
+                ProxyType::Https => "https",
+                ProxyType::Socks => "socks5",
+            };
+            // Chrome spells domain suffixes as "*.example.com" rather than ".example.com"
+            let local = config.bypass_local.then(|| WINDOWS_LOCAL_TOKEN.to_string());
+            let bypass_list: Vec<String> = local
+                .into_iter()
+                .chain(bypass.iter().map(|item| match item.host.strip_prefix('.') {
+                    Some(domain) => format!("*.{}", domain),
+                    None => item.host.clone(),
+                }))
+                .collect();
+            serde_json::json!({
+                "mode": "fixed_servers",
+                "rules": {
+                    "singleProxy": {
+                        "scheme": scheme,
+                        "host": rule.proxy_host,
+                        "port": rule.proxy_port,
+                    },
+                    "bypassList": bypass_list,
+                },
+            })
+        }
+        _ => serde_json::json!({
+            "mode": "pac_script",
+            "pacScript": { "data": generate_pac_content(config) },
+        }),
+    }
+}
+
+/// Finds enabled external functions that shouldn't be called, with the reason for each
+/// A function whose body matches an earlier one (the same PAC fetched under another
+/// URL) is redundant, and functions past `max_external_functions` are dropped to
+/// bound the work done for every request
+fn skipped_external_functions(config: &PacConfig) -> HashMap<&str, String> {
+    let mut skipped = HashMap::new();
+    let mut bodies: HashMap<String, &str> = HashMap::new();
+    let mut included = 0;
+    let mut over_limit = 0;
+
+    for item in config.external_pac_functions.iter().filter(|item| item.enabled) {
+        let name = item.function.function_name.as_str();
+        // Each function was renamed after its URL, so compare the text without the name
+        let body = normalize_line_endings(&item.function.function_text).replacen(name, "", 1);
+        if let Some(original) = bodies.get(&body) {
+            debug!("External function {} duplicates {}, calling it once", name, original);
+            skipped.insert(name, format!("same body as {}", original));
+            continue;
+        }
+        bodies.insert(body, name);
 
-    info!("Created shared PAC configuration");
-    Arc::new(RwLock::new(pac_config))
-} 
\ No newline at end of file
+        if config.max_external_functions > 0 && included >= config.max_external_functions {
+            skipped.insert(name, format!("over the limit of {} external functions", config.max_external_functions));
+            over_limit += 1;
+            continue;
+        }
+        included += 1;
+    }
+
+    if over_limit > 0 {
+        warn!("{} enabled external PAC functions exceed the limit of {} and were left out of the PAC",
+            over_limit, config.max_external_functions);
+    }
+    skipped
+}
+
+/// Outcome of re-fetching an external PAC source
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalRefresh {
+    /// The source sent new content, with the validators to send next time
+    Updated { function: ExternalPacFunction, validators: FetchValidators },
+    /// The source answered 304 Not Modified
+    Unchanged,
+}
+
+/// Re-fetches the external sources, given as URL and stored validators, with at most
+/// `concurrency` requests in flight
+/// The results are in the order of `sources` whatever order the fetches finish in, so
+/// the generated PAC is the same from run to run
+pub async fn fetch_external_functions(
+    fetcher: &impl PacFetcher,
+    sources: &[(String, FetchValidators)],
+    concurrency: usize,
+) -> Vec<Result<ExternalRefresh>> {
+    // Owned sources keep the futures free of borrowed closure arguments, which spawned tasks can't hold
+    stream::iter(sources.to_vec())
+        .map(|(url, validators)| async move {
+            PacConfig::refresh_external_function(fetcher, &url, &validators).await
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Re-fetches every unpinned external PAC source and records the outcome in its health stats
+/// The sources are fetched without holding the lock so slow servers don't block the PAC
+pub async fn refresh_external_functions(config: &SharedPacConfig, fetcher: &impl PacFetcher, concurrency: usize) {
+    let sources: Vec<(String, (String, FetchValidators))> = config.read().await
+        .external_pac_functions
+        .iter()
+        .filter(|item| !item.pinned)
+        .map(|item| (
+            item.function.function_name.clone(),
+            (item.function.original_url.clone(), item.validators.clone()),
+        ))
+        .collect();
+    if sources.is_empty() {
+        return;
+    }
+
+    debug!("Refreshing {} external PAC sources", sources.len());
+    let (function_names, sources): (Vec<String>, Vec<(String, FetchValidators)>) = sources.into_iter().unzip();
+    let outcomes = fetch_external_functions(fetcher, &sources, concurrency).await;
+
+    let mut config = config.write().await;
+    let mut changed = false;
+    for (function_name, outcome) in function_names.iter().zip(outcomes) {
+        changed |= config.record_external_refresh(function_name, outcome);
+    }
+    if !changed {
+        return;
+    }
+    if let Err(e) = config.save_current() {
+        error!("Failed to save configuration after refreshing external PAC sources: {}", e);
+    }
+}
+
+/// Returns the version of the live configuration
+pub fn config_version() -> u64 {
+    *CONFIG_VERSION.borrow()
+}
+
+/// Bumps the configuration version, notifying subscribers, and returns the new version
+pub fn mark_config_changed() -> u64 {
+    CONFIG_VERSION.send_modify(|version| *version += 1);
+    config_version()
+}
+
+/// Returns a receiver that is notified whenever the configuration version changes
+pub fn subscribe_config_changes() -> watch::Receiver<u64> {
+    CONFIG_VERSION.subscribe()
+}
+
+/// Current time in seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,340 @@
+use serde::Serialize;
+use boa_engine::{js_string, Context, JsResult, JsValue, NativeFunction, Source};
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+use log::debug;
+use crate::error::{Result, ProxyCatError};
+use crate::pac::{generate_pac_content, PacConfig};
+
+/// Loop iterations a PAC may run per evaluation before it is stopped
+const LOOP_ITERATION_LIMIT: u64 = 1_000_000;
+
+/// Nested calls a PAC may make before it is stopped
+const RECURSION_LIMIT: usize = 512;
+
+/// The PAC helper functions browsers provide, written in JavaScript
+/// `dnsResolve` and `myIpAddress` are native and registered separately;
+/// `alert` collects its messages instead of showing them
+const PAC_UTILS: &str = r#"
+var __pc_alerts = [];
+
+function alert(message) {
+    __pc_alerts.push(String(message));
+}
+
+function isPlainHostName(host) {
+    return host.indexOf('.') < 0;
+}
+
+function dnsDomainIs(host, domain) {
+    return host.length >= domain.length &&
+        host.substring(host.length - domain.length) == domain;
+}
+
+function localHostOrDomainIs(host, hostdom) {
+    return host == hostdom || hostdom.lastIndexOf(host + '.', 0) == 0;
+}
+
+function isResolvable(host) {
+    return dnsResolve(host) !== null;
+}
+
+function dnsDomainLevels(host) {
+    return host.split('.').length - 1;
+}
+
+function convert_addr(ipchars) {
+    var bytes = ipchars.split('.');
+    return ((bytes[0] & 0xff) << 24 |
+        (bytes[1] & 0xff) << 16 |
+        (bytes[2] & 0xff) << 8 |
+        (bytes[3] & 0xff)) >>> 0;
+}
+
+function isInNet(ipaddr, pattern, maskstr) {
+    var test = /^(\d{1,4})\.(\d{1,4})\.(\d{1,4})\.(\d{1,4})$/.exec(ipaddr);
+    if (test == null) {
+        ipaddr = dnsResolve(ipaddr);
+        if (ipaddr == null) return false;
+    } else if (test[1] > 255 || test[2] > 255 || test[3] > 255 || test[4] > 255) {
+        return false;
+    }
+    var host = convert_addr(ipaddr);
+    var pat = convert_addr(pattern);
+    var mask = convert_addr(maskstr);
+    return (host & mask) >>> 0 == (pat & mask) >>> 0;
+}
+
+function shExpMatch(url, pattern) {
+    pattern = pattern.replace(/[.+^${}()|[\]\\]/g, '\\$&');
+    pattern = pattern.replace(/\*/g, '.*').replace(/\?/g, '.');
+    return new RegExp('^' + pattern + '$').test(url);
+}
+
+var __pc_weekdays = ['SUN', 'MON', 'TUE', 'WED', 'THU', 'FRI', 'SAT'];
+var __pc_months = ['JAN', 'FEB', 'MAR', 'APR', 'MAY', 'JUN', 'JUL', 'AUG', 'SEP', 'OCT', 'NOV', 'DEC'];
+
+function __pc_now(args) {
+    var date = new Date();
+    var gmt = args.length > 0 && args[args.length - 1] == 'GMT';
+    return {
+        gmt: gmt,
+        day: gmt ? date.getUTCDay() : date.getDay(),
+        date: gmt ? date.getUTCDate() : date.getDate(),
+        month: gmt ? date.getUTCMonth() : date.getMonth(),
+        year: gmt ? date.getUTCFullYear() : date.getFullYear(),
+        seconds: gmt
+            ? date.getUTCHours() * 3600 + date.getUTCMinutes() * 60 + date.getUTCSeconds()
+            : date.getHours() * 3600 + date.getMinutes() * 60 + date.getSeconds()
+    };
+}
+
+function __pc_in_range(value, low, high) {
+    return low <= high ? value >= low && value <= high : value >= low || value <= high;
+}
+
+function weekdayRange() {
+    var now = __pc_now(arguments);
+    var count = arguments.length - (now.gmt ? 1 : 0);
+    var low = __pc_weekdays.indexOf(String(arguments[0]));
+    if (low < 0) return false;
+    if (count < 2) return now.day == low;
+    var high = __pc_weekdays.indexOf(String(arguments[1]));
+    return high >= 0 && __pc_in_range(now.day, low, high);
+}
+
+function dateRange() {
+    var now = __pc_now(arguments);
+    var count = arguments.length - (now.gmt ? 1 : 0);
+    var values = [];
+    for (var i = 0; i < count; i++) values.push(arguments[i]);
+    // Each argument is a day (1-31), a month name or a four digit year
+    function kind(value) {
+        if (typeof value == 'string') return 'month';
+        return value > 31 ? 'year' : 'day';
+    }
+    function field(value) {
+        var k = kind(value);
+        if (k == 'month') return __pc_months.indexOf(value);
+        return value;
+    }
+    function current(k) {
+        return k == 'month' ? now.month : (k == 'year' ? now.year : now.date);
+    }
+    if (count == 1) {
+        return current(kind(values[0])) == field(values[0]);
+    }
+    // Ranges list the same fields on both sides, e.g. (day, month, day, month)
+    var half = count / 2;
+    if (count % 2 != 0) return false;
+    var low = 0, high = 0, value = 0;
+    for (var j = 0; j < half; j++) {
+        var k = kind(values[j]);
+        var scale = k == 'year' ? 10000 : (k == 'month' ? 100 : 1);
+        low += field(values[j]) * scale;
+        high += field(values[j + half]) * scale;
+        value += current(k) * scale;
+    }
+    return __pc_in_range(value, low, high);
+}
+
+function timeRange() {
+    var now = __pc_now(arguments);
+    var count = arguments.length - (now.gmt ? 1 : 0);
+    var a = [];
+    for (var i = 0; i < count; i++) a.push(Number(arguments[i]));
+    var hour = Math.floor(now.seconds / 3600);
+    switch (count) {
+        case 1: return hour == a[0];
+        case 2: return __pc_in_range(hour, a[0], a[1] - 1) || (a[0] == a[1] && hour == a[0]);
+        case 4: return __pc_in_range(now.seconds, a[0] * 3600 + a[1] * 60, a[2] * 3600 + a[3] * 60 - 1);
+        case 6: return __pc_in_range(now.seconds, a[0] * 3600 + a[1] * 60 + a[2], a[3] * 3600 + a[4] * 60 + a[5]);
+        default: return false;
+    }
+}
+"#;
+
+/// The decision a PAC made for one URL
+#[derive(Debug, Clone, Serialize)]
+pub struct PacEvaluation {
+    pub url: String,
+    /// Host passed to FindProxyForURL, taken from the URL
+    pub host: String,
+    /// What FindProxyForURL returned, e.g. "DIRECT" or "PROXY proxy.corp:8080"
+    pub result: String,
+    /// Messages passed to alert() while evaluating
+    pub alerts: Vec<String>,
+}
+
+/// The decision for one URL of a batch, or why it couldn't be made
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchEvaluation {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    /// Why this URL could not be evaluated, e.g. it doesn't parse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The decision for a URL with the configuration as it is and with one external function left out
+#[derive(Debug, Clone, Serialize)]
+pub struct ExclusionEvaluation {
+    /// Name of the external function that was left out
+    pub function_name: String,
+    /// The decision with the configuration as it is
+    pub current: PacEvaluation,
+    /// The decision with the function disabled
+    pub without: PacEvaluation,
+    /// Whether leaving the function out changes the decision
+    pub changed: bool,
+}
+
+/// Evaluates `url` against the PAC generated from `config` and again with the external
+/// function at `index` disabled, without changing `config`
+/// Blocks on DNS lookups made by the script, so call it off the async runtime
+pub fn evaluate_without_external(config: &PacConfig, index: usize, url: &str) -> Result<ExclusionEvaluation> {
+    let mut without = config.clone();
+    let item = without.external_pac_functions.get_mut(index)
+        .ok_or_else(|| ProxyCatError::Internal(format!("Invalid index {index} for externalPacFunctions")))?;
+    item.enabled = false;
+    let function_name = item.function.function_name.clone();
+
+    let current = evaluate_pac(&generate_pac_content(config), url)?;
+    let without = evaluate_pac(&generate_pac_content(&without), url)?;
+    Ok(ExclusionEvaluation {
+        function_name,
+        changed: current.result != without.result,
+        current,
+        without,
+    })
+}
+
+/// Runs FindProxyForURL from `pac` for `url` with the standard PAC helpers defined
+/// Blocks on DNS lookups made by the script, so call it off the async runtime
+pub fn evaluate_pac(pac: &str, url: &str) -> Result<PacEvaluation> {
+    evaluate_pac_for_host(pac, url, &host_from_url(url)?)
+}
+
+/// Like `evaluate_pac`, but passes `host` to FindProxyForURL as given
+/// URL parsing lowercases the host, while a browser may pass it in its original case
+pub fn evaluate_pac_for_host(pac: &str, url: &str, host: &str) -> Result<PacEvaluation> {
+    let mut context = pac_context()?;
+    context.eval(Source::from_bytes(pac)).map_err(|e| script_error("load the PAC", e))?;
+    find_proxy(&mut context, url, host.to_string())
+}
+
+/// Runs FindProxyForURL from `pac` for each of `urls`, loading the PAC once
+/// A URL that fails doesn't stop the rest; only a PAC that doesn't load is an error
+pub fn evaluate_pac_batch(pac: &str, urls: &[String]) -> Result<Vec<BatchEvaluation>> {
+    let mut context = pac_context()?;
+    context.eval(Source::from_bytes(pac)).map_err(|e| script_error("load the PAC", e))?;
+
+    Ok(urls.iter().map(|url| {
+        let evaluation = host_from_url(url).and_then(|host| {
+            context.eval(Source::from_bytes("__pc_alerts = [];"))
+                .map_err(|e| script_error("reset the alerts", e))?;
+            find_proxy(&mut context, url, host)
+        });
+        match evaluation {
+            Ok(evaluation) => BatchEvaluation {
+                url: evaluation.url,
+                host: Some(evaluation.host),
+                result: Some(evaluation.result),
+                error: None,
+            },
+            Err(e) => BatchEvaluation {
+                url: url.clone(),
+                host: None,
+                result: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }).collect())
+}
+
+/// Calls FindProxyForURL in a context the PAC has been loaded into
+fn find_proxy(context: &mut Context, url: &str, host: String) -> Result<PacEvaluation> {
+    let call = format!(
+        "FindProxyForURL({}, {})",
+        serde_json::to_string(url).unwrap_or_default(),
+        serde_json::to_string(&host).unwrap_or_default()
+    );
+    let result = context.eval(Source::from_bytes(&call))
+        .and_then(|value| value.to_string(context))
+        .map_err(|e| script_error("run FindProxyForURL", e))?
+        .to_std_string_escaped();
+
+    let alerts = context.eval(Source::from_bytes("JSON.stringify(__pc_alerts)"))
+        .and_then(|value| value.to_string(context))
+        .map(|json| serde_json::from_str(&json.to_std_string_escaped()).unwrap_or_default())
+        .unwrap_or_default();
+
+    debug!("PAC decision for {}: {}", url, result);
+    Ok(PacEvaluation {
+        url: url.to_string(),
+        host,
+        result,
+        alerts,
+    })
+}
+
+/// Builds a JS context with the PAC helper functions and runtime limits in place
+fn pac_context() -> Result<Context> {
+    let mut context = Context::default();
+    context.runtime_limits_mut().set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+    context.runtime_limits_mut().set_recursion_limit(RECURSION_LIMIT);
+
+    context.register_global_callable(js_string!("dnsResolve"), 1, NativeFunction::from_fn_ptr(dns_resolve))
+        .map_err(|e| script_error("define dnsResolve", e))?;
+    context.register_global_callable(js_string!("myIpAddress"), 0, NativeFunction::from_fn_ptr(my_ip_address))
+        .map_err(|e| script_error("define myIpAddress", e))?;
+    context.eval(Source::from_bytes(PAC_UTILS))
+        .map_err(|e| script_error("define the PAC helpers", e))?;
+    Ok(context)
+}
+
+/// The host FindProxyForURL is called with: the URL's host without IPv6 brackets
+fn host_from_url(url: &str) -> Result<String> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| ProxyCatError::Internal(format!("Invalid URL '{}': {}", url, e)))?;
+    let host = parsed.host_str()
+        .ok_or_else(|| ProxyCatError::Internal(format!("URL '{}' has no host", url)))?;
+    Ok(host.trim_start_matches('[').trim_end_matches(']').to_string())
+}
+
+fn script_error(action: &str, e: impl std::fmt::Display) -> ProxyCatError {
+    ProxyCatError::Pac(format!("Failed to {}: {}", action, e))
+}
+
+/// PAC `dnsResolve`: the host's first IPv4 address from the OS resolver, or null
+fn dns_resolve(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = match args.first() {
+        Some(host) => host.to_string(context)?.to_std_string_escaped(),
+        None => return Ok(JsValue::null()),
+    };
+    let address = (host.as_str(), 0)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.find(|addr| addr.is_ipv4()))
+        .map(|addr| addr.ip());
+    debug!("dnsResolve({}) = {:?}", host, address);
+    Ok(match address {
+        Some(ip) => JsValue::from(js_string!(ip.to_string())),
+        None => JsValue::null(),
+    })
+}
+
+/// PAC `myIpAddress`: the address of the interface used for outbound traffic
+/// Connecting a UDP socket picks the route without sending anything
+fn my_ip_address(_this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    let address = UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("192.0.2.1:80").map(|_| socket))
+        .and_then(|socket| socket.local_addr())
+        .map(|addr| addr.ip())
+        .ok()
+        .filter(|ip| !ip.is_unspecified())
+        .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+    Ok(JsValue::from(js_string!(address.to_string())))
+}
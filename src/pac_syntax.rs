@@ -0,0 +1,68 @@
+use serde::Serialize;
+use boa_interner::Interner;
+use boa_parser::{Parser, Source};
+use boa_parser::lexer::Error as LexError;
+use crate::pac::PacConfig;
+
+/// Outcome of checking a pasted PAC file before it is imported
+#[derive(Debug, Clone, Serialize)]
+pub struct PacValidation {
+    /// Whether a FindProxyForURL function was found
+    pub function_found: bool,
+    /// Byte offset where the FindProxyForURL function starts
+    pub function_start: Option<usize>,
+    /// Byte offset just past the function's closing brace
+    pub function_end: Option<usize>,
+    /// Whether the whole text parses as JavaScript
+    pub valid: bool,
+    /// The first syntax error, if the text does not parse
+    pub syntax_error: Option<PacSyntaxError>,
+}
+
+/// A JavaScript syntax error with its 1-based location in the pasted text
+#[derive(Debug, Clone, Serialize)]
+pub struct PacSyntaxError {
+    /// The parser's description of the error
+    pub message: String,
+    /// Line of the error, when the parser reports a position
+    pub line: Option<u32>,
+    /// Column of the error on that line
+    pub column: Option<u32>,
+}
+
+/// Locates FindProxyForURL in `text` and checks that the text parses as JavaScript
+pub fn validate_pac_text(text: &str) -> PacValidation {
+    let bounds = PacConfig::find_proxy_function(text);
+    let syntax_error = check_syntax(text).err();
+
+    PacValidation {
+        function_found: bounds.is_some(),
+        function_start: bounds.map(|(start, _)| start),
+        function_end: bounds.map(|(_, end)| end),
+        valid: syntax_error.is_none(),
+        syntax_error,
+    }
+}
+
+/// Parses `text` as a script without running it
+fn check_syntax(text: &str) -> std::result::Result<(), PacSyntaxError> {
+    let mut interner = Interner::default();
+    let result = Parser::new(Source::from_bytes(text)).parse_script(&mut interner);
+    let err = match result {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+
+    let position = match &err {
+        boa_parser::Error::Expected { span, .. } | boa_parser::Error::Unexpected { span, .. } => Some(span.start()),
+        boa_parser::Error::General { position, .. } => Some(*position),
+        boa_parser::Error::Lex { err: LexError::Syntax(_, position) } => Some(*position),
+        _ => None,
+    };
+
+    Err(PacSyntaxError {
+        message: err.to_string(),
+        line: position.map(|p| p.line_number()),
+        column: position.map(|p| p.column_number()),
+    })
+}
@@ -1,3 +1,19 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use serde::Serialize;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use proxycat::fetcher::{FetchValidators, PacFetcher, ValidatingFetcher};
+use proxycat::pac::{ExternalPacFunction, PacConfig, SharedPacConfig};
+use crate::error::{Result, ProxyCatError, FetchErrorKind};
+use crate::lock_or_recover;
+
+/// Host name Windows and browsers look up for Web Proxy Auto-Discovery
+pub const WPAD_HOST: &str = "wpad";
+
+/// Where the WPAD PAC file is served when the `wpad` host resolves
+pub const WPAD_URL: &str = "http://wpad/wpad.dat";
+
 /// Represents a PAC file URL with its description
 #[derive(Debug, Clone)]
 pub struct PacUrl {
@@ -7,20 +23,265 @@ pub struct PacUrl {
     pub description: String,
 }
 
+/// What happened to one PAC source at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupPacOutcome {
+    /// The PAC was fetched and added as an external function
+    Loaded,
+    /// The PAC was fetched but its function was already in the configuration
+    AlreadyLoaded,
+    /// The source was not fetched, e.g. because the WPAD host does not resolve
+    Skipped,
+    /// Fetching or parsing the PAC failed
+    Failed,
+}
+
+/// Outcome of loading one startup PAC source
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupPacSource {
+    pub url: String,
+    pub description: String,
+    pub outcome: StartupPacOutcome,
+    /// Why the source was skipped or failed
+    pub error: Option<String>,
+    /// Which stage of the fetch failed, when the failure was a network one
+    pub fetch_failure: Option<FetchErrorKind>,
+}
+
+/// Per-source outcomes of loading the default PAC URLs at startup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StartupPacReport {
+    pub sources: Vec<StartupPacSource>,
+    /// Whether every source has been tried; loading runs in the background after startup
+    pub complete: bool,
+}
+
+impl StartupPacReport {
+    /// Records the result of `PacConfig::load_external_pac` for `pac_url`
+    pub fn record(&mut self, pac_url: &PacUrl, result: Result<bool>) {
+        let (outcome, error) = match result {
+            Ok(true) => (StartupPacOutcome::Loaded, None),
+            Ok(false) => (StartupPacOutcome::AlreadyLoaded, None),
+            Err(e) => (StartupPacOutcome::Failed, Some(e)),
+        };
+        let fetch_failure = match &error {
+            Some(ProxyCatError::Fetch(e)) => Some(e.kind),
+            _ => None,
+        };
+        self.push(pac_url, outcome, error.map(|e| e.to_string()), fetch_failure);
+    }
+
+    /// Records that `pac_url` was not fetched
+    pub fn skip(&mut self, pac_url: &PacUrl, reason: &str) {
+        self.push(pac_url, StartupPacOutcome::Skipped, Some(reason.to_string()), None);
+    }
+
+    fn push(&mut self, pac_url: &PacUrl, outcome: StartupPacOutcome, error: Option<String>, fetch_failure: Option<FetchErrorKind>) {
+        self.sources.push(StartupPacSource {
+            url: pac_url.url.clone(),
+            description: pac_url.description.clone(),
+            outcome,
+            error,
+            fetch_failure,
+        });
+    }
+
+    /// Number of sources whose PAC is in the configuration
+    pub fn loaded(&self) -> usize {
+        self.sources.iter()
+            .filter(|source| matches!(source.outcome, StartupPacOutcome::Loaded | StartupPacOutcome::AlreadyLoaded))
+            .count()
+    }
+
+    /// One-line summary for the log, e.g. "Loaded 1/3 startup PAC sources"
+    pub fn summary(&self) -> String {
+        format!("Loaded {}/{} startup PAC sources", self.loaded(), self.sources.len())
+    }
+}
+
 /// Returns a Vec of known PAC file URLs and their descriptions in the order they should be loaded
-pub fn get_pac_urls() -> Vec<PacUrl> {
-    vec![
-        PacUrl {
-            url: "http://wpad/wpad.dat".to_string(),
+/// WPAD is only included when `enable_wpad` is set, since anyone on the network can answer for it
+pub fn get_pac_urls(enable_wpad: bool) -> Vec<PacUrl> {
+    let mut urls = Vec::new();
+    if enable_wpad {
+        urls.push(PacUrl {
+            url: WPAD_URL.to_string(),
             description: "WPAD (Web Proxy Auto-Discovery Protocol) PAC file".to_string(),
-        },
-        PacUrl {
-            url: "http://localhost:3333/files/proxy.pac".to_string(),
-            description: "itTLS PAC file".to_string(),
-        },
-        PacUrl {
-            url: "http://localhost:10224/proxy.pac".to_string(),
-            description: "avTune PAC file".to_string(),
-        },
-    ]
-} 
\ No newline at end of file
+        });
+    } else {
+        info!("WPAD import is disabled, pass --enable-wpad to load {}", WPAD_URL);
+    }
+    urls.push(PacUrl {
+        url: "http://localhost:3333/files/proxy.pac".to_string(),
+        description: "itTLS PAC file".to_string(),
+    });
+    urls.push(PacUrl {
+        url: "http://localhost:10224/proxy.pac".to_string(),
+        description: "avTune PAC file".to_string(),
+    });
+    urls
+}
+
+/// Loads the default PAC sources into `config`, recording each outcome in `report`
+/// Runs in the background so a slow source doesn't hold up the tray and server; the
+/// configuration is only locked to add each function, never while fetching
+/// Up to `concurrency` sources are fetched at once, but they are added in the order
+/// given, each as soon as it and every source before it have finished
+pub async fn load_startup_pacs(
+    config: &SharedPacConfig,
+    fetcher: &impl PacFetcher,
+    pac_urls: Vec<PacUrl>,
+    concurrency: usize,
+    report: &Mutex<StartupPacReport>,
+) {
+    let mut fetches = stream::iter(pac_urls.clone())
+        .map(|pac_url| async move { fetch_startup_pac(fetcher, &pac_url).await })
+        .buffered(concurrency.max(1));
+
+    let mut pac_urls = pac_urls.iter();
+    while let (Some(fetched), Some(pac_url)) = (fetches.next().await, pac_urls.next()) {
+        let Some(fetched) = fetched else {
+            lock_or_recover(report, "startup report").skip(pac_url, "WPAD host does not resolve");
+            continue;
+        };
+        let result = match fetched {
+            Ok((function, validators)) => Ok(config.write().await.add_external_function(function, validators)),
+            Err(e) => {
+                warn!("Failed to load {}: {}", pac_url.description, e);
+                Err(e)
+            }
+        };
+        lock_or_recover(report, "startup report").record(pac_url, result);
+    }
+
+    let mut report = lock_or_recover(report, "startup report");
+    report.complete = true;
+    info!("{}", report.summary());
+}
+
+/// Fetches one startup source, or returns None if it was skipped
+async fn fetch_startup_pac(fetcher: &impl PacFetcher, pac_url: &PacUrl) -> Option<Result<(ExternalPacFunction, FetchValidators)>> {
+    info!("Loading PAC file: {}", pac_url.description);
+    if pac_url.url == WPAD_URL {
+        // Anyone on the network can answer for wpad, so log where it points and insist on a real PAC
+        if !check_wpad_host().await {
+            return None;
+        }
+        return Some(PacConfig::fetch_external_function(&ValidatingFetcher::new(fetcher), &pac_url.url).await);
+    }
+    Some(PacConfig::fetch_external_function(fetcher, &pac_url.url).await)
+}
+
+/// Resolves the `wpad` host and logs where it points before anything is fetched from it
+/// Returns false when it does not resolve, so the import can be skipped
+/// Addresses outside the local network are logged as a warning, as they suggest a rogue WPAD answer
+pub async fn check_wpad_host() -> bool {
+    let addresses: Vec<IpAddr> = match tokio::net::lookup_host((WPAD_HOST, 80)).await {
+        Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+        Err(e) => {
+            info!("WPAD host '{}' does not resolve, skipping: {}", WPAD_HOST, e);
+            return false;
+        }
+    };
+    if addresses.is_empty() {
+        info!("WPAD host '{}' has no addresses, skipping", WPAD_HOST);
+        return false;
+    }
+
+    for ip in &addresses {
+        if is_local_network(ip) {
+            info!("WPAD host '{}' resolves to {}", WPAD_HOST, ip);
+        } else {
+            warn!("WPAD host '{}' resolves to {}, which is outside the local network", WPAD_HOST, ip);
+        }
+    }
+    true
+}
+
+/// Whether `ip` is a loopback, private or link-local address
+fn is_local_network(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            // fc00::/7 is unique local, fe80::/10 is link-local
+            v6.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::common::{pac_file, shared, temp_data_dir_async, MockFetcher, MockResponse};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn wpad_is_only_listed_when_enabled() {
+        assert!(get_pac_urls(false).iter().all(|pac_url| pac_url.url != WPAD_URL));
+        assert_eq!(get_pac_urls(true)[0].url, WPAD_URL);
+        assert_eq!(get_pac_urls(true).len(), get_pac_urls(false).len() + 1);
+    }
+
+    #[tokio::test]
+    async fn startup_never_fetches_wpad_unless_enabled() {
+        let fetcher = MockFetcher::default();
+        fetcher.respond(WPAD_URL, MockResponse::Pac(pac_file("PROXY rogue.example:8080")));
+        let config = shared(PacConfig::default());
+        let report = Mutex::new(StartupPacReport::default());
+
+        load_startup_pacs(&config, &fetcher, get_pac_urls(false), 4, &report).await;
+        assert!(!fetcher.requests().contains(&WPAD_URL.to_string()), "{:?}", fetcher.requests());
+        assert_eq!(fetcher.requests().len(), get_pac_urls(false).len());
+        assert!(lock_or_recover(&report, "startup report").complete);
+    }
+
+    #[tokio::test]
+    async fn report_reflects_a_mix_of_loaded_and_failed_sources() {
+        let (_guard, _) = temp_data_dir_async("startup_report").await;
+        let pac_url = |url: &str| PacUrl { url: url.to_string(), description: format!("PAC from {}", url) };
+        let fetcher = MockFetcher::default();
+        fetcher.respond("http://pac.corp/team.pac", MockResponse::Pac(pac_file("PROXY team.corp:3128")));
+        fetcher.respond("http://down.corp/proxy.pac", MockResponse::Fail(FetchErrorKind::Connect));
+        let config = shared(PacConfig::default());
+        let report = Mutex::new(StartupPacReport::default());
+
+        let sources = ["http://pac.corp/team.pac", "http://down.corp/proxy.pac", "http://pac.corp/team.pac"];
+        load_startup_pacs(&config, &fetcher, sources.iter().map(|url| pac_url(url)).collect(), 4, &report).await;
+
+        assert_eq!(config.read().await.external_pac_functions.len(), 1);
+        let report = lock_or_recover(&report, "startup report");
+        let outcomes: Vec<_> = report.sources.iter().map(|source| source.outcome).collect();
+        assert_eq!(outcomes, [StartupPacOutcome::Loaded, StartupPacOutcome::Failed, StartupPacOutcome::AlreadyLoaded]);
+        assert_eq!(report.sources[1].fetch_failure, Some(FetchErrorKind::Connect));
+        assert!(report.sources[1].error.is_some());
+        assert!(report.sources[0].error.is_none());
+        assert!(report.complete);
+        assert_eq!(report.summary(), "Loaded 2/3 startup PAC sources");
+    }
+
+    #[tokio::test]
+    async fn shared_config_is_filled_after_a_background_load() {
+        let (_guard, _) = temp_data_dir_async("startup_background").await;
+        let url = "http://pac.corp/slow.pac";
+        let fetcher = MockFetcher::default();
+        fetcher.respond(url, MockResponse::Pac(pac_file("PROXY slow.corp:8080")));
+        fetcher.delay(url, Duration::from_millis(50));
+        let config = shared(PacConfig::default());
+        let report = Arc::new(Mutex::new(StartupPacReport::default()));
+
+        let pac_urls = vec![PacUrl { url: url.to_string(), description: "Slow PAC".to_string() }];
+        let loading = tokio::spawn({
+            let (config, report) = (Arc::clone(&config), Arc::clone(&report));
+            async move { load_startup_pacs(&config, &fetcher, pac_urls, 4, &report).await }
+        });
+        assert!(config.read().await.external_pac_functions.is_empty(), "startup does not wait for the load");
+        assert!(!lock_or_recover(&report, "startup report").complete);
+
+        loading.await.unwrap();
+        assert_eq!(config.read().await.external_pac_functions.len(), 1);
+        assert!(lock_or_recover(&report, "startup report").complete);
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use log::{info, warn, error, debug};
+use crate::error::{Result, ProxyCatError};
+use crate::pac::{PacConfig, SharedPacConfig};
+use crate::constants::APP_CONFIG;
+
+/// Name of the profile the existing single configuration is migrated into
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// File the profiles are persisted to, in the data directory
+const PROFILES_FILE: &str = "profiles.json";
+
+/// A set of named PAC configurations that can be swapped in as the live configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileStore {
+    /// Name of the profile currently loaded as the live configuration
+    pub active_profile: String,
+    /// All saved profiles keyed by name
+    pub profiles: HashMap<String, PacConfig>,
+    /// Profiles to switch to when the network changes, checked in order
+    #[serde(default)]
+    pub network_profiles: Vec<NetworkProfileMapping>,
+}
+
+/// Binds a network, identified by its gateway's MAC address or its connection name, to a profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkProfileMapping {
+    /// Gateway MAC address such as "00-11-22-33-44-55", or a connection name such as "Wi-Fi"
+    pub signature: String,
+    /// Profile to activate while that network is connected
+    pub profile: String,
+}
+
+/// What identifies the networks the machine is currently connected to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct NetworkSignature {
+    /// Names of the connected adapters that have a default gateway
+    pub connection_names: Vec<String>,
+    /// MAC addresses of those adapters' gateways, as "aa-bb-cc-dd-ee-ff"
+    pub gateway_macs: Vec<String>,
+}
+
+impl NetworkSignature {
+    /// Whether `signature` names one of the connections or gateways
+    /// Case and the choice of ':' or '-' in MAC addresses don't matter
+    pub fn matches(&self, signature: &str) -> bool {
+        let wanted = normalize_signature(signature);
+        self.gateway_macs.iter()
+            .chain(&self.connection_names)
+            .any(|value| normalize_signature(value) == wanted)
+    }
+}
+
+fn normalize_signature(value: &str) -> String {
+    value.trim().to_lowercase().replace(':', "-")
+}
+
+/// Lightweight listing of the available profiles
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileSummary {
+    /// Name of the active profile
+    pub active_profile: String,
+    /// Names of all profiles, sorted alphabetically
+    pub profiles: Vec<String>,
+}
+
+impl ProfileStore {
+    /// Loads the profile store from disk
+    /// If no store exists yet, the live configuration becomes the default profile.
+    /// An unparseable store is renamed to `profiles.json.bak` first, so the next save can't overwrite it
+    pub fn load_or_migrate(live: &PacConfig) -> Self {
+        let path = APP_CONFIG.data_path(PROFILES_FILE).ok();
+        let content = path.as_ref().and_then(|path| std::fs::read_to_string(path).ok());
+        match (path, content) {
+            (Some(path), Some(content)) => match serde_json::from_str::<Self>(&content) {
+                Ok(store) => {
+                    debug!("Loaded {} profiles, active: {}", store.profiles.len(), store.active_profile);
+                    return store;
+                }
+                Err(e) => {
+                    let backup = path.with_extension("json.bak");
+                    match std::fs::rename(&path, &backup) {
+                        Ok(()) => warn!(
+                            "Failed to parse profiles file, moved it to {} and starting from the live configuration: {}",
+                            backup.display(), e
+                        ),
+                        Err(rename_error) => error!(
+                            "Failed to parse profiles file ({}) and to move it to {}: {}",
+                            e, backup.display(), rename_error
+                        ),
+                    }
+                }
+            },
+            _ => info!("No profiles file found, migrating live configuration into '{}' profile", DEFAULT_PROFILE),
+        }
+
+        Self {
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: HashMap::from([(DEFAULT_PROFILE.to_string(), live.clone())]),
+            network_profiles: Vec::new(),
+        }
+    }
+
+    /// Saves the profile store to disk
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| ProxyCatError::Pac(format!("Failed to serialize profiles: {}", e)))?;
+        std::fs::write(APP_CONFIG.data_path(PROFILES_FILE)?, json)
+            .map_err(|e| ProxyCatError::Pac(format!("Failed to write profiles file: {}", e)))?;
+        Ok(())
+    }
+
+    /// Returns the names of all profiles along with the active one
+    pub fn summary(&self) -> ProfileSummary {
+        let mut profiles: Vec<String> = self.profiles.keys().cloned().collect();
+        profiles.sort();
+        ProfileSummary {
+            active_profile: self.active_profile.clone(),
+            profiles,
+        }
+    }
+
+    /// Stores the live configuration under the active profile's name
+    pub fn capture_active(&mut self, live: &PacConfig) {
+        self.profiles.insert(self.active_profile.clone(), live.clone());
+    }
+
+    /// Adds a new profile with the given configuration
+    pub fn create(&mut self, name: &str, config: PacConfig) -> Result<()> {
+        validate_profile_name(name)?;
+        if self.profiles.contains_key(name) {
+            return Err(ProxyCatError::Conflict(format!("Profile '{}' already exists", name)));
+        }
+        self.profiles.insert(name.to_string(), config);
+        info!("Created profile '{}'", name);
+        Ok(())
+    }
+
+    /// Removes a profile; the active profile cannot be deleted
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if name == self.active_profile {
+            return Err(ProxyCatError::Internal(format!("Cannot delete the active profile '{}'", name)));
+        }
+        self.profiles.remove(name)
+            .ok_or_else(|| ProxyCatError::NotFound(format!("Profile '{}' not found", name)))?;
+        info!("Deleted profile '{}'", name);
+        Ok(())
+    }
+
+    /// Makes `name` the active profile and returns its configuration
+    /// The outgoing live configuration is kept under the previously active profile
+    pub fn activate(&mut self, name: &str, live: &PacConfig) -> Result<PacConfig> {
+        let config = self.profiles.get(name)
+            .cloned()
+            .ok_or_else(|| ProxyCatError::NotFound(format!("Profile '{}' not found", name)))?;
+        self.capture_active(live);
+        self.active_profile = name.to_string();
+        info!("Activated profile '{}'", name);
+        Ok(config)
+    }
+
+    /// Returns the profile that should be active on the network described by `signature`
+    /// The first matching mapping wins; an unknown network, or one mapped to a profile
+    /// that no longer exists, gets the default profile. None if no mappings are
+    /// configured, so networks never switch profiles unless asked to
+    pub fn profile_for_network(&self, signature: &NetworkSignature) -> Option<&str> {
+        if self.network_profiles.is_empty() {
+            return None;
+        }
+        let mapped = self.network_profiles.iter()
+            .find(|mapping| signature.matches(&mapping.signature))
+            .map(|mapping| mapping.profile.as_str());
+        match mapped {
+            Some(profile) if self.profiles.contains_key(profile) => Some(profile),
+            Some(profile) => {
+                warn!("Network is mapped to missing profile '{}', using '{}'", profile, DEFAULT_PROFILE);
+                Some(DEFAULT_PROFILE)
+            }
+            None => Some(DEFAULT_PROFILE),
+        }
+    }
+
+    /// Replaces the network mappings; every mapping must name an existing profile
+    pub fn set_network_profiles(&mut self, mappings: Vec<NetworkProfileMapping>) -> Result<()> {
+        if let Some(mapping) = mappings.iter().find(|mapping| !self.profiles.contains_key(&mapping.profile)) {
+            return Err(ProxyCatError::Internal(format!(
+                "Network '{}' is mapped to unknown profile '{}'", mapping.signature, mapping.profile
+            )));
+        }
+        self.network_profiles = mappings;
+        Ok(())
+    }
+}
+
+/// Activates the profile mapped to the network described by `signature`, if it isn't active already
+/// Returns the name of the profile that was activated
+pub async fn apply_network_profile(config: &SharedPacConfig, signature: &NetworkSignature) -> Result<Option<String>> {
+    let mut config = config.write().await;
+    let mut store = ProfileStore::load_or_migrate(&config);
+    let Some(profile) = store.profile_for_network(signature).map(str::to_string) else {
+        return Ok(None);
+    };
+    if profile == store.active_profile || !store.profiles.contains_key(&profile) {
+        return Ok(None);
+    }
+
+    info!("Network changed to {:?}, switching to profile '{}'", signature, profile);
+    *config = store.activate(&profile, &config)?;
+    config.ensure_system_bypass();
+    store.save()?;
+    config.save_current()?;
+    Ok(Some(profile))
+}
+
+/// Checks that a profile name is non-empty and only uses letters, digits, '-' or '_'
+fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return Err(ProxyCatError::Internal(format!(
+            "Invalid profile name '{}': use letters, digits, '-' or '_'", name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pac::{ProxyRule, ProxyRuleItem, ProxyType};
+
+    fn with_rule(host: &str) -> PacConfig {
+        let mut config = PacConfig::default();
+        config.proxy_rules.push_back(ProxyRuleItem {
+            rule: ProxyRule {
+                host: host.to_string(),
+                proxy_host: "proxy.corp".to_string(),
+                proxy_port: 8080,
+                proxy_type: ProxyType::Http,
+                scheme: None,
+                https_only: false,
+                credential_key: None,
+            },
+            enabled: true,
+            note: None,
+            tags: Vec::new(),
+        });
+        config
+    }
+
+    fn store(live: &PacConfig) -> ProfileStore {
+        ProfileStore {
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: HashMap::from([(DEFAULT_PROFILE.to_string(), live.clone())]),
+            network_profiles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn create_and_activate_swaps_the_live_config() {
+        let home = with_rule("home.example.com");
+        let mut store = store(&home);
+        store.create("work", with_rule("work.example.com")).unwrap();
+        assert_eq!(store.summary().profiles, ["default", "work"]);
+
+        // Edits made to the live config before switching are kept in the outgoing profile
+        let edited = with_rule("edited.example.com");
+        let live = store.activate("work", &edited).unwrap();
+        assert_eq!(live.proxy_rules[0].rule.host, "work.example.com");
+        assert_eq!(store.active_profile, "work");
+        assert_eq!(store.profiles[DEFAULT_PROFILE], edited);
+
+        let back = store.activate(DEFAULT_PROFILE, &live).unwrap();
+        assert_eq!(back, edited);
+    }
+
+    #[test]
+    fn invalid_or_duplicate_names_are_rejected() {
+        let mut store = store(&PacConfig::default());
+        assert!(matches!(store.create("has space", PacConfig::default()), Err(ProxyCatError::Internal(_))));
+        assert!(matches!(store.create(DEFAULT_PROFILE, PacConfig::default()), Err(ProxyCatError::Conflict(_))));
+        assert!(matches!(store.activate("missing", &PacConfig::default()), Err(ProxyCatError::NotFound(_))));
+        assert!(matches!(store.delete("missing"), Err(ProxyCatError::NotFound(_))));
+        assert!(store.delete(DEFAULT_PROFILE).is_err());
+    }
+
+    #[test]
+    fn network_signature_selects_its_profile_or_the_default() {
+        let mut store = store(&PacConfig::default());
+        store.create("office", with_rule("office.example.com")).unwrap();
+        store.create("vpn", with_rule("vpn.example.com")).unwrap();
+        let network = |name: &str, mac: &str| NetworkSignature {
+            connection_names: vec![name.to_string()],
+            gateway_macs: vec![mac.to_string()],
+        };
+        assert_eq!(store.profile_for_network(&network("Wi-Fi", "00-11-22-33-44-55")), None, "no mappings, no switching");
+
+        store.set_network_profiles(vec![
+            NetworkProfileMapping { signature: "00:11:22:33:44:55".to_string(), profile: "office".to_string() },
+            NetworkProfileMapping { signature: "Corp VPN".to_string(), profile: "vpn".to_string() },
+        ]).unwrap();
+        assert_eq!(store.profile_for_network(&network("Ethernet", "00-11-22-33-44-55")), Some("office"));
+        assert_eq!(store.profile_for_network(&network("corp vpn", "aa-bb-cc-dd-ee-ff")), Some("vpn"));
+        assert_eq!(store.profile_for_network(&network("Home Wi-Fi", "aa-bb-cc-dd-ee-ff")), Some(DEFAULT_PROFILE));
+    }
+
+    #[tokio::test]
+    async fn unparseable_store_is_moved_aside_before_migrating() {
+        let (_guard, dir) = crate::tests::common::temp_data_dir_async("profiles_unparseable").await;
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(PROFILES_FILE), "{ not json").unwrap();
+
+        let live = with_rule("live.example.com");
+        let store = ProfileStore::load_or_migrate(&live);
+        assert_eq!(store.profiles[DEFAULT_PROFILE], live);
+        assert_eq!(std::fs::read_to_string(dir.join("profiles.json.bak")).unwrap(), "{ not json");
+
+        store.save().unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("profiles.json.bak")).unwrap(), "{ not json", "saving keeps the backup");
+    }
+}
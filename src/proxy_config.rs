@@ -1,21 +1,10 @@
-use windows::Win32::Networking::WinInet::{
-    InternetQueryOptionW,
-    InternetSetOptionW,
-    INTERNET_OPTION_PER_CONNECTION_OPTION,
-    INTERNET_PER_CONN_OPTION_LISTW,
-    INTERNET_PER_CONN_OPTIONW,
-    INTERNET_PER_CONN_PROXY_SERVER,
-    INTERNET_PER_CONN_PROXY_BYPASS,
-    INTERNET_PER_CONN_AUTOCONFIG_URL,
-    INTERNET_OPTION_PROXY_SETTINGS_CHANGED,
-    INTERNET_OPTION_REFRESH,
-    INTERNET_OPTION_SETTINGS_CHANGED,
-};
-use windows::core::PWSTR;
-use std::ffi::OsString;
-use std::os::windows::ffi::OsStringExt;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 use log::{info, error, warn, debug};
-use crate::error::{Result, ProxyCatError};
+use serde::Serialize;
+use crate::constants::APP_CONFIG;
+use crate::error::Result;
+use crate::pac::{bypass_list_from_windows_string, parse_proxy_server_rules, PacConfig, ProxyRuleItem, SharedPacConfig};
 
 /// Represents the Windows proxy configuration settings
 /// This struct holds the proxy server address, bypass list, and auto-config URL
@@ -32,6 +21,12 @@ pub struct ProxyConfig {
     pub use_proxy: bool,
 }
 
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ProxyConfig {
     /// Creates a new empty proxy configuration
     pub fn new() -> Self {
@@ -44,159 +39,306 @@ impl ProxyConfig {
         }
     }
 
-    /// Reads the current proxy configuration from Windows settings
-    /// This function uses the Windows API to query the system's proxy settings
-    /// Returns a Result containing either the ProxyConfig or an error
-    pub fn from_windows() -> Result<Self> {
-        info!("Reading proxy configuration from Windows settings...");
-        unsafe {
-            let mut config = Self::new();
-            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
-            let mut options = [
-                INTERNET_PER_CONN_OPTIONW::default(),
-                INTERNET_PER_CONN_OPTIONW::default(),
-                INTERNET_PER_CONN_OPTIONW::default(),
-            ];
-
-            // Set up the option list structure
-            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
-            option_list.dwOptionCount = 3;
-            option_list.dwOptionError = 0;
-            option_list.pOptions = options.as_mut_ptr();
-
-            // Configure which options we want to query
-            options[0].dwOption = INTERNET_PER_CONN_PROXY_SERVER;
-            options[1].dwOption = INTERNET_PER_CONN_PROXY_BYPASS;
-            options[2].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
-
-            // Query the Windows API for proxy settings
-            if InternetQueryOptionW(
-                None,
-                INTERNET_OPTION_PER_CONNECTION_OPTION,
-                Some(&mut option_list as *mut _ as *mut _),
-                &mut (std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32),
-            ).is_ok() {
-                info!("Successfully queried Windows proxy settings");
-                
-                // Extract proxy server address
-                if !options[0].Value.pszValue.is_null() {
-                    config.proxy_server = Some(wide_to_string(options[0].Value.pszValue.0));
-                    debug!("Found proxy server: {:?}", config.proxy_server);
+    /// Creates a PAC configuration from these proxy settings
+    /// This function parses the Windows proxy configuration and converts it
+    /// into a format suitable for generating a PAC file
+    pub fn to_pac_config(&self) -> PacConfig {
+        info!("Converting Windows proxy config to PAC config...");
+        let mut pac_config = PacConfig::default();
+        
+        // Parse proxy server address if present
+        if let Some(proxy_server) = &self.proxy_server {
+            info!("Processing proxy server: {}", proxy_server);
+            let rules = parse_proxy_server_rules(proxy_server);
+            if rules.is_empty() {
+                warn!("Failed to parse proxy server address");
+            } else {
+                for rule in rules {
+                    info!("Added proxy rule - Host: {}, Scheme: {:?}, Proxy: {} {}:{}",
+                        rule.host, rule.scheme, rule.proxy_type.pac_keyword(), rule.proxy_host, rule.proxy_port);
+                    pac_config.proxy_rules.push_back(ProxyRuleItem {
+                        rule,
+                        enabled: true,
+                        note: None,
+                        tags: Vec::new(),
+                    });
                 }
 
-                // Extract proxy bypass list
-                if !options[1].Value.pszValue.is_null() {
-                    config.proxy_bypass = Some(wide_to_string(options[1].Value.pszValue.0));
-                    debug!("Found proxy bypass list: {:?}", config.proxy_bypass);
+                // Save the configuration after adding proxy rules
+                if let Err(e) = pac_config.save_current() {
+                    error!("Failed to save configuration after adding proxy rule: {}", e);
                 }
+            }
+        } else {
+            info!("No proxy server configured");
+        }
 
-                // Extract auto-config URL
-                if !options[2].Value.pszValue.is_null() {
-                    config.auto_config_url = Some(wide_to_string(options[2].Value.pszValue.0));
-                    debug!("Found auto-config URL: {:?}", config.auto_config_url);
-                }
+        // Parse bypass list if present
+        if let Some(bypass) = &self.proxy_bypass {
+            info!("Processing bypass list: {}", bypass);
+            let (bypass_items, bypass_local) = bypass_list_from_windows_string(bypass);
+            pac_config.bypass_list = bypass_items.into();
+            pac_config.bypass_local = bypass_local;
+            debug!("Parsed bypass list: {:?}, bypass local: {}", pac_config.bypass_list, bypass_local);
+        } else {
+            info!("No bypass list configured");
+        }
 
-                // Determine if proxy is enabled
-                config.use_proxy = config.proxy_server.is_some() || config.auto_config_url.is_some();
-                info!("Proxy enabled: {}", config.use_proxy);
-            } else {
-                warn!("No proxy settings found or error occurred while querying");
-            }
+        // Add default bypass entries if not present
+        pac_config.ensure_system_bypass();
 
-            Ok(config)
+        // Save the configuration after adding default entries
+        if let Err(e) = pac_config.save_current() {
+            error!("Failed to save configuration after adding default entries: {}", e);
         }
+
+        debug!("Final PAC configuration: {:?}", pac_config);
+        pac_config
     }
+}
 
-    /// Sets the Windows proxy configuration to use a PAC file
-    /// This function configures Windows to use the specified PAC file URL
-    pub fn set_pac_file(pac_url: &str) -> Result<()> {
-        info!("Setting Windows proxy configuration to use PAC file: {}", pac_url);
-        unsafe {
-            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
-            let mut options = [INTERNET_PER_CONN_OPTIONW::default()];
-
-            // Set up the option list structure
-            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
-            option_list.dwOptionCount = 1;
-            option_list.dwOptionError = 0;
-            option_list.pOptions = options.as_mut_ptr();
-
-            // Configure option for PAC file
-            options[0].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
-            let mut wide_url: Vec<u16> = pac_url.encode_utf16().chain(std::iter::once(0)).collect();
-            options[0].Value.pszValue = PWSTR::from_raw(wide_url.as_mut_ptr());
+/// Read/write access to the system's auto-config URL
+/// Lets code that re-applies the PAC run against something other than the live Windows settings
+pub trait SystemProxy {
+    /// Returns the currently configured auto-config URL (empty if none)
+    fn get_pac_file(&self) -> Result<String>;
+    /// Points the system at the given auto-config URL
+    fn set_pac_file(&self, pac_url: &str) -> Result<()>;
+    /// Removes the auto-config URL
+    fn clear_pac_file(&self) -> Result<()>;
+    /// Reads the full proxy configuration without changing it
+    fn current_config(&self) -> Result<ProxyConfig>;
+}
+
+/// Creates a shared PAC configuration by reading Windows proxy settings
+/// This function initializes the PAC configuration from the current Windows
+/// proxy settings and wraps it in a thread-safe shared structure
+pub fn create_shared_config(proxy: &impl SystemProxy) -> SharedPacConfig {
+    info!("Creating shared PAC configuration...");
+    
+    // Try to load existing configuration
+    let pac_config = match APP_CONFIG.data_path(APP_CONFIG.config_file).and_then(PacConfig::load_from_file) {
+        Ok(config) => {
+            info!("Loaded existing configuration from file");
+            config
+        }
+        Err(e) => {
+            warn!("Could not load configuration file: {}", e);
+            info!("Creating new configuration from Windows settings");
             
-            // Set the proxy configuration
-            if InternetSetOptionW(
-                None,
-                INTERNET_OPTION_PER_CONNECTION_OPTION,
-                Some(&option_list as *const _ as *const _),
-                std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32,
-            ).is_ok() {
-                info!("Successfully set PAC file configuration");
-
-                // Notify Windows that proxy settings have changed
-                let _ = InternetSetOptionW(None, INTERNET_OPTION_PROXY_SETTINGS_CHANGED, None, 0);
-                let _ = InternetSetOptionW(None, INTERNET_OPTION_REFRESH, None, 0);
-                let _ = InternetSetOptionW(None, INTERNET_OPTION_SETTINGS_CHANGED, None, 0);
-
-                info!("Successfully notified Windows of proxy settings change");
-                Ok(())
-            } else {
-                error!("Failed to set PAC file configuration");
-                Err(ProxyCatError::Windows("Failed to set PAC file configuration".to_string()))
+            // Create new config from Windows settings
+            // An empty result means Windows has no proxy; an error means we couldn't tell
+            let windows_config = proxy.current_config().unwrap_or_else(|e| {
+                error!("Could not query Windows proxy settings, nothing will be imported: {}", e);
+                info!("Using empty proxy configuration");
+                ProxyConfig::new()
+            });
+            
+            let config = windows_config.to_pac_config();
+            
+            // Save the new configuration
+            if let Err(e) = APP_CONFIG.data_path(APP_CONFIG.config_file).and_then(|path| config.save_to_file(path)) {
+                error!("Failed to save initial configuration: {}", e);
             }
+            
+            config
         }
-    }
+    };
+
+    info!("Created shared PAC configuration");
+    Arc::new(RwLock::new(pac_config))
+}
+
+/// One system setting with its current value and the value ProxyCat would set
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingChange {
+    pub setting: &'static str,
+    pub current: Option<String>,
+    pub proposed: Option<String>,
+    /// Whether applying would change the setting
+    pub pending: bool,
+}
+
+/// What applying the PAC would do to the system settings, computed without changing them
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplyPreview {
+    /// Whether any setting would change
+    pub pending: bool,
+    pub changes: Vec<SettingChange>,
+    /// Manual proxy server, which ProxyCat leaves alone
+    pub proxy_server: Option<String>,
+    /// Manual bypass list, which ProxyCat leaves alone
+    pub proxy_bypass: Option<String>,
+}
+
+/// Compares the system proxy settings with what applying `pac_url` would set
+/// ProxyCat only writes the auto-config URL, so that is the only setting that can change
+pub fn apply_preview(proxy: &impl SystemProxy, pac_url: &str) -> Result<ApplyPreview> {
+    let current = proxy.current_config()?;
+    let current_url = current.auto_config_url.filter(|url| !url.is_empty());
+    let auto_config_url = SettingChange {
+        setting: "auto_config_url",
+        pending: current_url.as_deref() != Some(pac_url),
+        current: current_url,
+        proposed: Some(pac_url.to_string()),
+    };
+    debug!("Apply preview for {}: pending = {}", pac_url, auto_config_url.pending);
+
+    Ok(ApplyPreview {
+        pending: auto_config_url.pending,
+        changes: vec![auto_config_url],
+        proxy_server: current.proxy_server,
+        proxy_bypass: current.proxy_bypass,
+    })
+}
 
-    /// Gets the current PAC file URL from Windows settings
-    pub fn get_pac_file() -> Result<String> {
-        info!("Getting current PAC file URL from Windows settings...");
-        unsafe {
-            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
-            let mut options = [INTERNET_PER_CONN_OPTIONW::default()];
-
-            // Set up the option list structure
-            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
-            option_list.dwOptionCount = 1;
-            option_list.dwOptionError = 0;
-            option_list.pOptions = options.as_mut_ptr();
-
-            // Configure option for PAC file
-            options[0].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
-
-            // Query the Windows API for PAC file URL
-            if InternetQueryOptionW(
-                None,
-                INTERNET_OPTION_PER_CONNECTION_OPTION,
-                Some(&mut option_list as *mut _ as *mut _),
-                &mut (std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32),
-            ).is_ok() {
-                if !options[0].Value.pszValue.is_null() {
-                    let url = wide_to_string(options[0].Value.pszValue.0);
-                    info!("Found PAC file URL: {}", url);
-                    Ok(url)
-                } else {
-                    info!("No PAC file URL found");
-                    Ok(String::new())
+/// Whether the PAC URL is applied to the system, as reported by /status
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PacApplyStatus {
+    pub applied: bool,
+    /// Tries made by the last apply
+    pub attempts: u32,
+    /// Why the last apply failed
+    pub error: Option<String>,
+}
+
+/// Points the system at `pac_url`, retrying up to `attempts` times
+/// The delay starts at `initial_delay` and doubles after each failure, since the
+/// setting can be refused briefly while a session or network is still coming up
+pub fn set_pac_file_with_retry(
+    proxy: &impl SystemProxy,
+    pac_url: &str,
+    attempts: u32,
+    initial_delay: std::time::Duration,
+) -> PacApplyStatus {
+    let mut status = PacApplyStatus::default();
+    let mut delay = initial_delay;
+    for attempt in 1..=attempts.max(1) {
+        status.attempts = attempt;
+        match proxy.set_pac_file(pac_url) {
+            Ok(()) => {
+                status.applied = true;
+                status.error = None;
+                break;
+            }
+            Err(e) => {
+                warn!("Attempt {} to set the auto-config URL failed: {}", attempt, e);
+                status.error = Some(e.to_string());
+                if attempt < attempts {
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
                 }
-            } else {
-                error!("Failed to query PAC file URL");
-                Err(ProxyCatError::Windows("Failed to query PAC file URL".to_string()))
             }
         }
     }
+    status
+}
+
+/// What the proxy monitor does when another app changes the auto-config URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MonitorMode {
+    /// Import the other app's PAC as an external function, then restore our URL
+    Import,
+    /// Only restore our URL, ignoring the other app's PAC
+    Restore,
+}
+
+/// What to do with the system auto-config URL when ProxyCat exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExitPolicy {
+    /// Put back the URL that was configured before startup, or clear it if there was none
+    Restore,
+    /// Always remove the auto-config URL
+    Clear,
+    /// Leave the system pointing at ProxyCat's PAC URL
+    Leave,
 }
 
-/// Converts a wide string pointer to a Rust String
-/// This is used to convert Windows API wide string responses to Rust strings
-fn wide_to_string(ptr: *const u16) -> String {
-    unsafe {
-        let mut len = 0;
-        while *ptr.add(len) != 0 {
-            len += 1;
+/// Applies the exit policy to the system proxy
+/// Nothing is changed if the system no longer points at `pac_url`, since the
+/// setting is then no longer ours to undo
+pub fn apply_exit_policy(
+    proxy: &impl SystemProxy,
+    policy: ExitPolicy,
+    pac_url: &str,
+    previous_pac_url: Option<&str>,
+) -> Result<()> {
+    if policy == ExitPolicy::Leave {
+        info!("Leaving system auto-config URL in place on exit");
+        return Ok(());
+    }
+
+    let current = proxy.get_pac_file()?;
+    if current != pac_url {
+        info!("System auto-config URL was changed to '{}' since startup, leaving it", current);
+        return Ok(());
+    }
+
+    match (policy, previous_pac_url) {
+        (ExitPolicy::Restore, Some(previous)) if !previous.is_empty() && previous != pac_url => {
+            info!("Restoring previous auto-config URL: {}", previous);
+            proxy.set_pac_file(previous)
         }
-        let slice = std::slice::from_raw_parts(ptr, len);
-        OsString::from_wide(slice).to_string_lossy().into_owned()
+        _ => proxy.clear_pac_file(),
+    }
+}
+
+/// Registry key holding the current user's Internet settings
+pub const INTERNET_SETTINGS_KEY: &str = r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+/// Builds a .reg file that points the current user's Internet settings at `pac_url`,
+/// for deploying ProxyCat's setting with regedit or Group Policy
+pub fn registry_export(pac_url: &str) -> String {
+    [
+        "Windows Registry Editor Version 5.00".to_string(),
+        String::new(),
+        format!("[{}]", INTERNET_SETTINGS_KEY),
+        format!("\"AutoConfigURL\"={}", reg_string(pac_url)),
+        String::new(),
+    ]
+    .join("\r\n")
+}
+
+/// Quotes a value as a .reg string, escaping backslashes and double quotes
+fn reg_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Decodes UTF-16, replacing invalid sequences such as unpaired surrogates
+/// Returns the string and whether any replacement happened
+pub fn decode_wide(wide: &[u16]) -> (String, bool) {
+    match String::from_utf16(wide) {
+        Ok(value) => (value, false),
+        Err(_) => (String::from_utf16_lossy(wide), true),
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_utf16_is_not_lossy() {
+        let wide: Vec<u16> = "proxy.corp:8080;<local>".encode_utf16().collect();
+        assert_eq!(decode_wide(&wide), ("proxy.corp:8080;<local>".to_string(), false));
+    }
+
+    #[test]
+    fn unpaired_surrogate_is_replaced_and_reported() {
+        let mut wide: Vec<u16> = "proxy".encode_utf16().collect();
+        wide.push(0xD800);
+        wide.extend(".corp".encode_utf16());
+        assert_eq!(decode_wide(&wide), ("proxy\u{FFFD}.corp".to_string(), true));
+    }
+
+    #[test]
+    fn registry_export_sets_the_escaped_auto_config_url() {
+        let reg = registry_export("http://127.0.0.1:12112/master.pac");
+        assert!(reg.starts_with("Windows Registry Editor Version 5.00\r\n"));
+        assert!(reg.contains(&format!("\r\n[{}]\r\n", INTERNET_SETTINGS_KEY)));
+        assert!(reg.contains("\r\n\"AutoConfigURL\"=\"http://127.0.0.1:12112/master.pac\"\r\n"));
+
+        let reg = registry_export(r#"file://\\share\pac "v2".pac"#);
+        assert!(reg.contains(r#""AutoConfigURL"="file://\\\\share\\pac \"v2\".pac""#), "{}", reg);
+    }
+}
@@ -0,0 +1,311 @@
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+use windows::Win32::Foundation::ERROR_BUFFER_OVERFLOW;
+use windows::Win32::NetworkManagement::IpHelper::{
+    GetAdaptersAddresses, GetIpNetEntry2, NotifyAddrChange, GAA_FLAG_INCLUDE_GATEWAYS,
+    GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER, GAA_FLAG_SKIP_MULTICAST,
+    IP_ADAPTER_ADDRESSES_LH, MIB_IPNET_ROW2,
+};
+use windows::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+use windows::Win32::Networking::WinSock::{AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR_IN, SOCKADDR_IN6, SOCKET_ADDRESS};
+use log::{info, error, warn, debug};
+use crate::error::{Result, ProxyCatError};
+use crate::profiles::NetworkSignature;
+use crate::proxy_config::{MonitorMode, SystemProxy};
+use proxycat::fetcher::PacFetcher;
+use crate::pac::SharedPacConfig;
+
+/// System events after which Windows may have dropped or stale-cached our PAC URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// The machine resumed from sleep or hibernation
+    Resumed,
+    /// An IP address was added, removed or changed on some interface
+    NetworkChanged,
+    /// The user asked for the PAC to be applied again from the interface
+    ApplyRequested,
+    /// A health check found the PAC URL had drifted and was asked to fix it
+    HealthCheck,
+}
+
+/// Whether the system points at our PAC URL, as reported by /health
+#[derive(Debug, Clone, Serialize)]
+pub struct PacHealth {
+    /// Whether the system auto-config URL is our PAC URL
+    pub in_sync: bool,
+    pub expected: String,
+    /// The system auto-config URL after any healing; None if it couldn't be read
+    pub current: Option<String>,
+    /// Whether the PAC was re-applied because it had drifted
+    pub healed: bool,
+    /// Why the URL couldn't be read or re-applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Detects sleep/resume by watching for wall-clock jumps between periodic ticks
+pub struct ResumeDetector {
+    last_tick: SystemTime,
+    threshold: Duration,
+}
+
+impl ResumeDetector {
+    /// Creates a detector that reports a resume when a tick arrives more than
+    /// `threshold` after the previous one
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            last_tick: SystemTime::now(),
+            threshold,
+        }
+    }
+
+    /// Records a tick and returns true if the gap since the last one suggests the system slept
+    pub fn tick(&mut self) -> bool {
+        let now = SystemTime::now();
+        let gap = now.duration_since(self.last_tick).unwrap_or_default();
+        self.last_tick = now;
+        gap > self.threshold
+    }
+}
+
+/// Starts a background thread that reports every IP address change on `sender`
+/// The thread exits when the receiving side is dropped
+pub fn spawn_network_change_listener(sender: UnboundedSender<SystemEvent>) {
+    thread::spawn(move || loop {
+        // With no handle or overlapped structure this blocks until an address changes
+        let status = unsafe { NotifyAddrChange(std::ptr::null_mut(), std::ptr::null()) };
+        if status != 0 {
+            error!("Network change notification failed with status {}, stopping listener", status);
+            return;
+        }
+        debug!("Network address change detected");
+        if sender.send(SystemEvent::NetworkChanged).is_err() {
+            return;
+        }
+    });
+}
+
+/// Describes the connected networks by the names of adapters with a default gateway
+/// and their gateways' MAC addresses
+pub fn current_network_signature() -> Result<NetworkSignature> {
+    let flags = GAA_FLAG_INCLUDE_GATEWAYS | GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size: u32 = 16 * 1024;
+    // u64 elements keep the adapter structures suitably aligned
+    let mut buffer: Vec<u64>;
+    loop {
+        buffer = vec![0; (size as usize).div_ceil(8)];
+        let status = unsafe {
+            GetAdaptersAddresses(AF_UNSPEC.0 as u32, flags, None, Some(buffer.as_mut_ptr().cast()), &mut size)
+        };
+        match status {
+            0 => break,
+            status if status == ERROR_BUFFER_OVERFLOW.0 => continue,
+            status => return Err(ProxyCatError::Internal(format!("Failed to list network adapters: status {}", status))),
+        }
+    }
+
+    let mut signature = NetworkSignature::default();
+    let mut adapter = buffer.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+    while !adapter.is_null() {
+        let current = unsafe { &*adapter };
+        adapter = current.Next;
+        if current.OperStatus != IfOperStatusUp || current.FirstGatewayAddress.is_null() {
+            continue;
+        }
+        if let Ok(name) = unsafe { current.FriendlyName.to_string() } {
+            signature.connection_names.push(name);
+        }
+        let mut gateway = current.FirstGatewayAddress;
+        while !gateway.is_null() {
+            let address = unsafe { &*gateway };
+            gateway = address.Next;
+            if let Some(mac) = gateway_mac(current, &address.Address) {
+                if !signature.gateway_macs.contains(&mac) {
+                    signature.gateway_macs.push(mac);
+                }
+            }
+        }
+    }
+    Ok(signature)
+}
+
+/// Looks up a gateway's MAC address in the neighbor cache
+fn gateway_mac(adapter: &IP_ADAPTER_ADDRESSES_LH, address: &SOCKET_ADDRESS) -> Option<String> {
+    if address.lpSockaddr.is_null() {
+        return None;
+    }
+    let mut row = MIB_IPNET_ROW2::default();
+    unsafe {
+        let family = (*address.lpSockaddr).sa_family;
+        if family == AF_INET {
+            row.Address.Ipv4 = *(address.lpSockaddr as *const SOCKADDR_IN);
+            row.InterfaceIndex = adapter.Anonymous1.Anonymous.IfIndex;
+        } else if family == AF_INET6 {
+            row.Address.Ipv6 = *(address.lpSockaddr as *const SOCKADDR_IN6);
+            row.InterfaceIndex = adapter.Ipv6IfIndex;
+        } else {
+            return None;
+        }
+        GetIpNetEntry2(&mut row).ok()?;
+    }
+    let length = (row.PhysicalAddressLength as usize).min(row.PhysicalAddress.len());
+    if length == 0 {
+        return None;
+    }
+    Some(row.PhysicalAddress[..length]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join("-"))
+}
+
+/// Re-applies our PAC URL in response to a system event
+/// The URL is always re-set so Windows refreshes any cached PAC, even when it still points at us
+/// Returns true if the system setting had drifted away from `pac_url`
+pub fn reapply_pac(proxy: &impl SystemProxy, pac_url: &str, event: SystemEvent) -> Result<bool> {
+    let drifted = match proxy.get_pac_file() {
+        Ok(current) => current != pac_url,
+        Err(e) => {
+            warn!("Failed to read current PAC URL after {:?}: {}", event, e);
+            true
+        }
+    };
+    if drifted {
+        info!("PAC URL changed during {:?}, restoring {}", event, pac_url);
+    } else {
+        info!("Refreshing PAC URL after {:?}", event);
+    }
+    proxy.set_pac_file(pac_url)?;
+    Ok(drifted)
+}
+
+/// Checks that the system points at `pac_url`, re-applying it first if `heal` is set and it doesn't
+pub fn check_pac_health(proxy: &impl SystemProxy, pac_url: &str, heal: bool) -> PacHealth {
+    let mut health = PacHealth {
+        in_sync: false,
+        expected: pac_url.to_string(),
+        current: None,
+        healed: false,
+        error: None,
+    };
+    match proxy.get_pac_file() {
+        Ok(current) => {
+            health.in_sync = current == pac_url;
+            health.current = Some(current);
+        }
+        Err(e) => health.error = Some(e.to_string()),
+    }
+    if health.in_sync || !heal {
+        return health;
+    }
+
+    if let Err(e) = reapply_pac(proxy, pac_url, SystemEvent::HealthCheck) {
+        warn!("Failed to heal PAC URL: {}", e);
+        health.error = Some(e.to_string());
+        return health;
+    }
+    health.healed = true;
+    // Read it back rather than assume the write took
+    match proxy.get_pac_file() {
+        Ok(current) => {
+            health.in_sync = current == pac_url;
+            health.current = Some(current);
+            health.error = None;
+        }
+        Err(e) => health.error = Some(e.to_string()),
+    }
+    health
+}
+
+/// Handles another app pointing the system at `foreign_url` instead of our `pac_url`
+/// In import mode the foreign PAC is added as an external function first; either
+/// way the system is pointed back at our URL
+pub async fn handle_foreign_pac(
+    proxy: &impl SystemProxy,
+    config: &SharedPacConfig,
+    fetcher: &impl PacFetcher,
+    mode: MonitorMode,
+    foreign_url: &str,
+    pac_url: &str,
+) -> Result<()> {
+    info!("System proxy configuration changed: {}", foreign_url);
+    match mode {
+        MonitorMode::Import => {
+            // load_external_pac saves the configuration when it adds the function
+            match config.write().await.load_external_pac(fetcher, foreign_url).await {
+                Ok(_) => info!("Loaded external PAC configuration from {}", foreign_url),
+                Err(e) => error!("Failed to import PAC from {}: {}", foreign_url, e),
+            }
+        }
+        MonitorMode::Restore => info!("Ignoring PAC from {} in restore mode", foreign_url),
+    }
+
+    proxy.set_pac_file(pac_url)?;
+    info!("Successfully restored proxy configuration");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::common::{pac_file, shared, temp_data_dir_async, MockFetcher, MockProxy, MockResponse};
+    use crate::pac::PacConfig;
+
+    const PAC_URL: &str = "http://127.0.0.1:12112/master.pac";
+
+    #[test]
+    fn reapply_restores_a_drifted_url() {
+        let proxy = MockProxy::pointing_at("http://other.corp/proxy.pac");
+        assert!(reapply_pac(&proxy, PAC_URL, SystemEvent::Resumed).unwrap());
+        assert_eq!(proxy.pac_url(), PAC_URL);
+    }
+
+    #[test]
+    fn reapply_refreshes_an_unchanged_url() {
+        let proxy = MockProxy::pointing_at(PAC_URL);
+        assert!(!reapply_pac(&proxy, PAC_URL, SystemEvent::NetworkChanged).unwrap());
+        // Set again anyway so Windows drops any cached PAC
+        assert_eq!(proxy.sets(), 1);
+    }
+
+    #[test]
+    fn reapply_treats_an_unreadable_url_as_drifted() {
+        let proxy = MockProxy { fail_reads: true, ..MockProxy::default() };
+        assert!(reapply_pac(&proxy, PAC_URL, SystemEvent::Resumed).unwrap());
+        assert_eq!(proxy.pac_url(), PAC_URL);
+    }
+
+    const FOREIGN_URL: &str = "http://vpn.corp/proxy.pac";
+
+    /// Handles another app pointing the system at FOREIGN_URL, returning the proxy,
+    /// the external functions afterwards and the URLs fetched
+    async fn foreign_change(mode: MonitorMode) -> (MockProxy, usize, Vec<String>) {
+        let (_guard, _) = temp_data_dir_async("foreign_pac").await;
+        let proxy = MockProxy::pointing_at(FOREIGN_URL);
+        let config = shared(PacConfig::default());
+        let fetcher = MockFetcher::default();
+        fetcher.respond(FOREIGN_URL, MockResponse::Pac(pac_file("PROXY vpn.corp:8080")));
+
+        handle_foreign_pac(&proxy, &config, &fetcher, mode, FOREIGN_URL, PAC_URL).await.unwrap();
+        let externals = config.read().await.external_pac_functions.len();
+        (proxy, externals, fetcher.requests())
+    }
+
+    #[tokio::test]
+    async fn restore_mode_reapplies_without_importing() {
+        let (proxy, externals, requests) = foreign_change(MonitorMode::Restore).await;
+        assert_eq!(proxy.pac_url(), PAC_URL);
+        assert_eq!(externals, 0);
+        assert!(requests.is_empty(), "the foreign PAC is not fetched");
+    }
+
+    #[tokio::test]
+    async fn import_mode_adds_the_foreign_pac_and_reapplies() {
+        let (proxy, externals, requests) = foreign_change(MonitorMode::Import).await;
+        assert_eq!(proxy.pac_url(), PAC_URL);
+        assert_eq!(externals, 1);
+        assert_eq!(requests, [FOREIGN_URL]);
+    }
+}
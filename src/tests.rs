@@ -0,0 +1,1177 @@
+//! Tests for the application side: handlers, startup and shutdown helpers
+
+use super::*;
+use axum::body::to_bytes;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use pac::MAX_HOST_LEN;
+
+#[path = "../tests/common/mod.rs"]
+pub(crate) mod common;
+use common::{bypass, external, pac_file, rule, shared, temp_data_dir_async, MemoryCredentials, MockFetcher, MockProxy, MockResponse};
+
+/// State for handlers that need more than the configuration, with the channel the
+/// server commands they send arrive on
+fn app_state(config: PacConfig) -> (AppState, UnboundedReceiver<ServerCommand>) {
+    let (server_commands, commands) = tokio::sync::mpsc::unbounded_channel();
+    let state = AppState {
+        pac_config: shared(config),
+        server_commands,
+        cors_origins: Arc::new(Vec::new()),
+        tray_available: true,
+        startup_report: Arc::new(Mutex::new(StartupPacReport::default())),
+        pac_cache_seconds: 0,
+        branding: Arc::new(Branding::default()),
+        pac_apply: Arc::new(Mutex::new(PacApplyStatus::default())),
+        credentials: Arc::new(MemoryCredentials::default()),
+        fetch_concurrency: 4,
+    };
+    (state, commands)
+}
+
+fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+    pairs.iter()
+        .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+        .collect()
+}
+
+async fn body_text(response: Response) -> String {
+    String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn lock_or_recover_returns_poisoned_value() {
+    let mutex = Arc::new(Mutex::new(Some("tray icon")));
+    let poisoner = Arc::clone(&mutex);
+    let _ = thread::spawn(move || {
+        let _guard = poisoner.lock().unwrap();
+        panic!("poison the mutex");
+    }).join();
+    assert!(mutex.is_poisoned());
+
+    let mut guard = lock_or_recover(&mutex, "tray icon");
+    assert_eq!(guard.take(), Some("tray icon"));
+    drop(guard);
+    assert!(lock_or_recover(&mutex, "tray icon").is_none());
+}
+
+fn import_fixture() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    config.proxy_rules.push_back(rule(".lab.corp", "socks.corp", 1080));
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    config.ensure_system_bypass();
+    config
+}
+
+async fn export(config: &SharedPacConfig, accept: &str) -> String {
+    let response = config_handler(
+        State(config.clone()),
+        headers(&[(header::ACCEPT, accept)]),
+        Query(ConfigQuery { include_disabled: true }),
+    ).await.unwrap();
+    body_text(response).await
+}
+
+#[tokio::test]
+async fn import_round_trips_yaml_and_json() {
+    let (_guard, _) = temp_data_dir_async("import_round_trip").await;
+    let source = shared(import_fixture());
+    for (accept, content_type) in [("application/yaml", "application/yaml"), ("application/json", "application/json")] {
+        let exported = export(&source, accept).await;
+        let target = shared(PacConfig::default());
+        import_handler(State(target.clone()), headers(&[(header::CONTENT_TYPE, content_type)]), exported)
+            .await
+            .unwrap();
+        assert_eq!(*target.read().await, import_fixture(), "{} round trip", content_type);
+        // What was there before the import can be restored
+        assert_eq!(PacConfig::take_undo_snapshot().unwrap(), PacConfig::default());
+    }
+}
+
+#[tokio::test]
+async fn import_rejects_items_add_item_would_reject() {
+    let (_guard, _) = temp_data_dir_async("import_invalid").await;
+    let too_long = format!("{}.com", "a".repeat(MAX_HOST_LEN));
+    let own_address = rule("example.com", "127.0.0.1", APP_CONFIG.get_port());
+    for item in [rule(&too_long, "proxy.corp", 8080), own_address] {
+        let mut imported = import_fixture();
+        imported.proxy_rules.push_back(item);
+        let config = shared(PacConfig::default());
+        let result = import_handler(
+            State(config.clone()),
+            HeaderMap::new(),
+            serde_json::to_string(&imported).unwrap(),
+        ).await;
+        assert!(result.is_err());
+        assert_eq!(*config.read().await, PacConfig::default());
+    }
+}
+
+/// A hostname of exactly `len` characters, in labels DNS accepts
+fn host_of_length(len: usize) -> String {
+    let mut host = String::new();
+    while host.len() < len {
+        if !host.is_empty() {
+            host.push('.');
+        }
+        let label = (len - host.len()).min(63);
+        host.push_str(&"a".repeat(label));
+    }
+    host
+}
+
+#[tokio::test]
+async fn host_length_is_limited_on_add_and_update() {
+    let (_guard, _) = temp_data_dir_async("host_length").await;
+    let config = shared(PacConfig::default());
+    let (longest, too_long) = (host_of_length(MAX_HOST_LEN), host_of_length(MAX_HOST_LEN + 1));
+    assert_eq!((longest.len(), too_long.len()), (MAX_HOST_LEN, MAX_HOST_LEN + 1));
+    let (fetcher, no_headers) = (MockFetcher::default(), HeaderMap::new());
+    let add = |request| add_item(&config, &fetcher, &no_headers, DedupePolicy::Reject, request);
+    let set_rules = |host: &str| set_list_handler(
+        State(config.clone()),
+        HeaderMap::new(),
+        Path("proxyRules".to_string()),
+        Json(vec![serde_json::to_value(rule(host, "proxy.corp", 8080)).unwrap()]),
+    );
+
+    assert!(add(add_request("proxy_rules", rule(&too_long, "proxy.corp", 8080))).await.is_err());
+    assert!(add(add_request("proxy_rules", rule("example.com", &too_long, 8080))).await.is_err());
+    assert!(add(add_request("bypass_list", bypass(&too_long))).await.is_err());
+    assert!(set_rules(&too_long).await.is_err());
+    assert!(config.read().await.proxy_rules.is_empty());
+
+    assert!(add(add_request("proxy_rules", rule(&longest, "proxy.corp", 8080))).await.is_ok());
+    assert!(add(add_request("bypass_list", bypass(&longest))).await.is_ok());
+    set_rules(&longest).await.unwrap();
+    assert_eq!(config.read().await.proxy_rules[0].rule.host, longest);
+}
+
+async fn preflight_origin(cors: CorsLayer, origin: &str) -> Option<String> {
+    use tower::ServiceExt;
+    let app = Router::new().route("/config", get(|| async { "{}" })).layer(cors);
+    let request = axum::http::Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/config")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    response.headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn requests_are_logged_with_path_and_status() {
+    use tower::ServiceExt;
+    let line = request_log_line(&Method::GET, "/status", StatusCode::NOT_FOUND, Duration::from_millis(3));
+    assert!(line.contains("/status"), "{}", line);
+    assert!(line.contains("404"), "{}", line);
+
+    let log = Arc::new(RequestLog {
+        pac_path: "/master.pac".to_string(),
+        pac_fetches: Mutex::new(PacFetchStats::default()),
+    });
+    let app = Router::new()
+        .route("/master.pac", get(|| async { "function FindProxyForURL() {}" }))
+        .route("/status", get(|| async { "{}" }))
+        .layer(middleware::from_fn_with_state(log.clone(), log_requests));
+    for path in ["/master.pac", "/status", "/master.pac"] {
+        let request = axum::http::Request::builder().uri(path).body(axum::body::Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(log.pac_fetches.lock().unwrap().count, 2, "only PAC fetches are summarized");
+}
+
+#[tokio::test]
+async fn cors_allows_only_own_and_configured_origins() {
+    let extra = vec!["https://dashboard.corp/".to_string()];
+    for origin in ["http://127.0.0.1:12112", "http://localhost:12112", "http://10.0.0.5:12112", "https://dashboard.corp"] {
+        let cors = cors_layer("10.0.0.5", 12112, &extra).unwrap();
+        assert_eq!(preflight_origin(cors, origin).await.as_deref(), Some(origin));
+    }
+    for origin in ["http://evil.example", "http://127.0.0.1:8080", "https://dashboard.corp.evil"] {
+        let cors = cors_layer("10.0.0.5", 12112, &extra).unwrap();
+        assert_eq!(preflight_origin(cors, origin).await, None, "{} should be rejected", origin);
+    }
+}
+
+#[test]
+fn cors_rejects_invalid_origins() {
+    assert!(cors_layer("127.0.0.1", 12112, &["http://bad\norigin".to_string()]).is_err());
+}
+
+#[tokio::test]
+async fn print_config_reports_saved_and_system_settings() {
+    let (_guard, _) = temp_data_dir_async("print_config").await;
+    let mut config = import_fixture();
+    config.proxy_rules[1].enabled = false;
+    config.save_to_file(APP_CONFIG.data_path(APP_CONFIG.config_file).unwrap()).unwrap();
+    let proxy = MockProxy {
+        proxy_server: Some("proxy.corp:8080".to_string()),
+        ..MockProxy::pointing_at("http://127.0.0.1:12112/master.pac")
+    };
+
+    let summary = effective_config_summary(&proxy).unwrap();
+    assert!(summary.contains(&format!("PAC URL:      {}", APP_CONFIG.get_pac_url().unwrap())));
+    assert!(summary.contains(&format!("Data dir:     {}", APP_CONFIG.get_data_dir().unwrap().display())));
+    assert!(summary.contains("Proxy rules:  2 (1 enabled)"));
+    assert!(summary.contains("Windows proxy server:     proxy.corp:8080"));
+    assert!(summary.contains("Windows proxy bypass:     (none)"));
+    assert!(summary.contains("Windows auto-config URL:  http://127.0.0.1:12112/master.pac"));
+}
+
+#[tokio::test]
+async fn print_config_survives_unreadable_settings() {
+    let (_guard, _) = temp_data_dir_async("print_config_unreadable").await;
+    let summary = effective_config_summary(&MockProxy { fail_reads: true, ..MockProxy::default() }).unwrap();
+    assert!(summary.contains("Saved config: unavailable"));
+    assert!(summary.contains("Windows proxy settings: unavailable"));
+}
+
+#[tokio::test]
+async fn set_list_replaces_the_whole_list() {
+    let (_guard, _) = temp_data_dir_async("set_list").await;
+    let config = shared(import_fixture());
+    let items = vec![
+        serde_json::to_value(rule("a.example.com", "proxy.corp", 8080)).unwrap(),
+        serde_json::to_value(rule("b.example.com", "proxy.corp", 3128)).unwrap(),
+    ];
+    set_list_handler(State(config.clone()), HeaderMap::new(), Path("proxyRules".to_string()), Json(items))
+        .await
+        .unwrap();
+
+    let hosts: Vec<String> = config.read().await.proxy_rules.iter().map(|item| item.rule.host.clone()).collect();
+    assert_eq!(hosts, ["a.example.com", "b.example.com"]);
+    assert_eq!(PacConfig::load_current().unwrap(), *config.read().await);
+}
+
+#[tokio::test]
+async fn set_list_with_one_invalid_item_changes_nothing() {
+    let (_guard, _) = temp_data_dir_async("set_list_invalid").await;
+    let config = shared(import_fixture());
+    let items = vec![
+        serde_json::to_value(bypass("a.example.com")).unwrap(),
+        serde_json::json!({"host": "", "enabled": true}),
+    ];
+    let result = set_list_handler(State(config.clone()), HeaderMap::new(), Path("bypassList".to_string()), Json(items)).await;
+    assert!(result.is_err());
+    assert_eq!(*config.read().await, import_fixture());
+
+    let result = set_list_handler(State(config.clone()), HeaderMap::new(), Path("unknown".to_string()), Json(Vec::new())).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn activating_a_profile_reapplies_the_pac() {
+    let (_guard, _) = temp_data_dir_async("activate_profile").await;
+    let config = shared(import_fixture());
+    let mut work = PacConfig::default();
+    work.proxy_rules.push_back(rule("work.example.com", "proxy.corp", 8080));
+    let mut store = ProfileStore::load_or_migrate(&*config.read().await);
+    store.create("work", work.clone()).unwrap();
+    store.save().unwrap();
+
+    let proxy = Arc::new(MockProxy::default());
+    activate_profile(&config, proxy.clone(), &HeaderMap::new(), "work").await.unwrap();
+    assert_eq!(*config.read().await, work);
+    assert_eq!(proxy.pac_url(), APP_CONFIG.get_pac_url().unwrap());
+    assert_eq!(ProfileStore::load_or_migrate(&work).active_profile, "work");
+
+    assert!(activate_profile(&config, proxy.clone(), &HeaderMap::new(), "missing").await.is_err());
+    assert_eq!(proxy.sets(), 1);
+}
+
+#[tokio::test]
+async fn page_embeds_the_live_config() {
+    let mut config = import_fixture();
+    config.proxy_rules[0].note = Some("</script><b>".to_string());
+    let (state, _commands) = app_state(config.clone());
+
+    let Html(page) = handler(State(state)).await.unwrap();
+    let start = page.find("window.__INITIAL_CONFIG__ = ").unwrap() + "window.__INITIAL_CONFIG__ = ".len();
+    let end = start + page[start..].find(";\n").unwrap();
+    let seeded: PacConfig = serde_json::from_str(&page[start..end]).unwrap();
+    assert_eq!(seeded, config);
+    assert!(!page[start..end].contains("</script>"));
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn server_starts_when_the_tray_icon_fails() {
+    let (_guard, _) = temp_data_dir_async("no_tray").await;
+    let port = free_port();
+    APP_CONFIG.update_port(port).unwrap();
+    let tray = optional_tray(|| Err::<(), _>(ProxyCatError::TrayIcon("no notification area".to_string())));
+    assert!(tray.is_none());
+
+    let (mut state, commands) = app_state(import_fixture());
+    state.tray_available = tray.is_some();
+    let (ready_tx, ready) = oneshot::channel();
+    tokio::spawn(run_server(state, commands, ready_tx));
+    assert!(ready.await.unwrap());
+
+    assert!(get_pac(port).await.unwrap().contains("proxy.corp"));
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let status = client.get(format!("http://127.0.0.1:{}/status", port))
+        .send().await.unwrap()
+        .text().await.unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["tray_available"], false);
+}
+
+#[tokio::test]
+async fn readiness_is_signalled_once_the_port_is_bound() {
+    let (_guard, _) = temp_data_dir_async("server_ready").await;
+    let port = free_port();
+    APP_CONFIG.update_port(port).unwrap();
+    let (state, commands) = app_state(import_fixture());
+    let (ready_tx, ready) = oneshot::channel();
+    tokio::spawn(run_server(state, commands, ready_tx));
+
+    assert!(ready.await.unwrap());
+    // Nothing has to be retried: the PAC is served as soon as readiness is reported
+    assert!(get_pac(port).await.unwrap().contains("proxy.corp"));
+}
+
+#[tokio::test]
+async fn readiness_is_refused_when_the_port_is_taken() {
+    let (_guard, _) = temp_data_dir_async("server_port_taken").await;
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    APP_CONFIG.update_port(taken.local_addr().unwrap().port()).unwrap();
+    let (state, commands) = app_state(import_fixture());
+    let (ready_tx, ready) = oneshot::channel();
+    tokio::spawn(run_server(state, commands, ready_tx));
+
+    assert!(!ready.await.unwrap());
+}
+
+#[tokio::test]
+async fn interface_is_opened_only_once_the_server_is_ready() {
+    let mut opened = false;
+    assert!(!open_when_ready(false, || opened = true));
+    assert!(!opened);
+
+    let (_guard, _) = temp_data_dir_async("open_on_startup").await;
+    let port = free_port();
+    APP_CONFIG.update_port(port).unwrap();
+    let (state, commands) = app_state(import_fixture());
+    let (ready_tx, ready) = oneshot::channel();
+    tokio::spawn(run_server(state, commands, ready_tx));
+
+    assert!(open_when_ready(matches!(ready.await, Ok(true)), || opened = true));
+    assert!(opened);
+    assert!(get_pac(port).await.is_ok(), "the page is only opened once it can be served");
+}
+
+async fn get_pac(port: u16) -> reqwest::Result<String> {
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let url = format!("http://127.0.0.1:{}{}", port, APP_CONFIG.get_pac_path().unwrap());
+    client.get(url).send().await?.error_for_status()?.text().await
+}
+
+#[tokio::test]
+async fn pac_is_served_on_the_new_port_after_a_change() {
+    let (_guard, _) = temp_data_dir_async("port_change").await;
+    let old_port = free_port();
+    APP_CONFIG.update_port(old_port).unwrap();
+    let (state, commands) = app_state(import_fixture());
+    let (ready_tx, ready) = oneshot::channel();
+    tokio::spawn(run_server(state.clone(), commands, ready_tx));
+    assert!(ready.await.unwrap());
+    assert!(get_pac(old_port).await.unwrap().contains("proxy.corp"));
+
+    let new_port = free_port();
+    let proxy = Arc::new(MockProxy::default());
+    let update = ServerSettingsUpdate { host: None, port: Some(new_port), pac_path: None };
+    let settings = update_server_settings(&state, proxy.clone(), update).await.unwrap();
+    assert_eq!(settings.port, new_port);
+    assert_eq!(proxy.pac_url(), settings.pac_url);
+
+    let mut served = get_pac(new_port).await;
+    for _ in 0..20 {
+        if served.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        served = get_pac(new_port).await;
+    }
+    assert!(served.unwrap().contains("proxy.corp"));
+    assert!(get_pac(old_port).await.is_err());
+    APP_CONFIG.update_port(12112).unwrap();
+}
+
+#[tokio::test]
+async fn server_settings_are_unchanged_unless_the_restart_is_accepted() {
+    let (_guard, _) = temp_data_dir_async("server_settings_refused").await;
+    let before = current_server_settings().unwrap();
+    let (state, commands) = app_state(PacConfig::default());
+    let proxy = Arc::new(MockProxy::default());
+    let update = |host: Option<&str>, pac_path: Option<&str>| ServerSettingsUpdate {
+        host: host.map(str::to_string),
+        port: None,
+        pac_path: pac_path.map(str::to_string),
+    };
+
+    for host in ["192.168.1.5", "proxycat.corp", "::1"] {
+        let error = update_server_settings(&state, proxy.clone(), update(Some(host), None)).await.unwrap_err();
+        assert!(error.to_string().contains("only listens on 127.0.0.1"), "{}", error);
+    }
+
+    drop(commands);
+    assert!(update_server_settings(&state, proxy.clone(), update(Some("localhost"), Some("/other.pac"))).await.is_err());
+    assert_eq!(current_server_settings().unwrap(), before, "a failed restart changes nothing");
+    assert_eq!(proxy.sets(), 0);
+}
+
+/// An add-item request for `item` in `list_type`
+fn add_request(list_type: &str, item: impl Serialize) -> AddItemRequest {
+    AddItemRequest { list_type: list_type.to_string(), item: serde_json::to_value(item).unwrap() }
+}
+
+/// An add-item request for an external function loaded from `url`
+fn external_request(url: &str) -> AddItemRequest {
+    add_request("external_pac_functions", ExternalPacFunctionItem {
+        function: ExternalPacFunction {
+            original_url: url.to_string(),
+            function_name: "FindProxyForURL".to_string(),
+            function_text: String::new(),
+        },
+        enabled: true,
+        health: ExternalPacHealth::default(),
+        note: None,
+        display_name: None,
+        pinned: false,
+        authoritative: true,
+        validators: Default::default(),
+        tags: Vec::new(),
+    })
+}
+
+/// A batch with a valid rule, an invalid rule, a loadable external function, one whose
+/// source is down, a repeat of the loadable one and a bypass entry the config already has
+fn mixed_batch() -> Vec<AddItemRequest> {
+    vec![
+        add_request("proxy_rules", rule("example.com", "proxy.corp", 8080)),
+        add_request("proxy_rules", rule("", "proxy.corp", 8080)),
+        external_request("http://pac.corp/team.pac"),
+        external_request("http://pac.corp/down.pac"),
+        external_request("http://pac.corp/team.pac"),
+        add_request("bypass_list", bypass("intranet.corp")),
+    ]
+}
+
+async fn add_batch(config: &SharedPacConfig, atomic: bool) -> (StatusCode, serde_json::Value) {
+    let fetcher = MockFetcher::default();
+    fetcher.respond("http://pac.corp/team.pac", MockResponse::Pac(pac_file("PROXY team.corp:3128")));
+    let response = add_items(config, &fetcher, 4, &HeaderMap::new(), atomic, mixed_batch()).await.unwrap();
+    let status = response.status();
+    (status, serde_json::from_str(&body_text(response).await).unwrap())
+}
+
+fn item_outcomes(response: &serde_json::Value) -> Vec<bool> {
+    response["results"].as_array().unwrap().iter().map(|result| result["ok"].as_bool().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn add_items_keeps_the_valid_items_of_a_mixed_batch() {
+    let (_guard, _) = temp_data_dir_async("add_items_partial").await;
+    let mut initial = PacConfig::default();
+    initial.bypass_list.push_back(bypass("intranet.corp"));
+    let config = shared(initial);
+
+    let (status, response) = add_batch(&config, false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["committed"], true);
+    assert_eq!(item_outcomes(&response), [true, false, true, false, false, false]);
+    assert!(response["results"][4]["error"].as_str().unwrap().contains("already loaded"));
+
+    let config = config.read().await;
+    assert_eq!(config.proxy_rules.len(), 1);
+    assert_eq!(config.bypass_list.iter().filter(|item| item.host == "intranet.corp").count(), 1);
+    assert_eq!(config.external_pac_functions.len(), 1);
+    assert!(config.external_pac_functions[0].function.function_text.contains("team.corp:3128"));
+    assert_eq!(PacConfig::load_current().unwrap(), *config);
+}
+
+#[tokio::test]
+async fn add_items_atomic_rejects_a_mixed_batch_whole() {
+    let (_guard, _) = temp_data_dir_async("add_items_atomic").await;
+    let mut initial = PacConfig::default();
+    initial.bypass_list.push_back(bypass("intranet.corp"));
+    let config = shared(initial.clone());
+
+    let (status, response) = add_batch(&config, true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response["committed"], false);
+    assert_eq!(item_outcomes(&response), [true, false, true, false, false, false]);
+    assert_eq!(*config.read().await, initial);
+    assert!(PacConfig::load_current().is_err(), "nothing is saved");
+}
+
+/// Adds `url` as an external function through the add-item path, serving `response` for it
+async fn add_external(config: &SharedPacConfig, url: &str, response: MockResponse) -> Result<Json<AddItemResponse>> {
+    let fetcher = MockFetcher::default();
+    fetcher.respond(url, response);
+    add_item(config, &fetcher, &HeaderMap::new(), DedupePolicy::Reject, external_request(url)).await
+}
+
+#[tokio::test]
+async fn add_item_loads_an_external_function_before_adding_it() {
+    let (_guard, _) = temp_data_dir_async("add_item_external").await;
+    let config = shared(PacConfig::default());
+
+    let url = "http://pac.corp/team.pac";
+    let response = add_external(&config, url, MockResponse::Pac(pac_file("PROXY team.corp:3128"))).await.unwrap();
+    assert!(matches!(response.0.outcome, AddItemOutcome::Added));
+    {
+        let config = config.read().await;
+        assert_eq!(config.external_pac_functions.len(), 1);
+        assert_ne!(config.external_pac_functions[0].function.function_name, "FindProxyForURL");
+        assert!(config.external_pac_functions[0].function.function_text.contains("team.corp:3128"));
+        assert_eq!(PacConfig::load_current().unwrap(), *config);
+    }
+
+    let error = add_external(&config, url, MockResponse::Pac(pac_file("PROXY team.corp:3128"))).await.unwrap_err();
+    assert_eq!(error.into_response().status(), StatusCode::CONFLICT);
+    assert_eq!(config.read().await.external_pac_functions.len(), 1);
+}
+
+#[tokio::test]
+async fn add_item_rejects_a_pac_without_find_proxy_for_url() {
+    let (_guard, _) = temp_data_dir_async("add_item_no_function").await;
+    let config = shared(PacConfig::default());
+
+    let url = "http://pac.corp/empty.pac";
+    let result = add_external(&config, url, MockResponse::Pac("var unused = 1;".to_string())).await;
+    assert!(matches!(result, Err(ProxyCatError::Pac(_))));
+    assert!(config.read().await.external_pac_functions.is_empty());
+    assert!(PacConfig::load_current().is_err(), "nothing is saved");
+}
+
+#[tokio::test]
+async fn add_item_returns_the_fetch_error_for_an_unreachable_pac() {
+    let (_guard, _) = temp_data_dir_async("add_item_fetch_error").await;
+    let config = shared(PacConfig::default());
+
+    for (kind, status) in [
+        (error::FetchErrorKind::Connect, StatusCode::BAD_GATEWAY),
+        (error::FetchErrorKind::Timeout, StatusCode::GATEWAY_TIMEOUT),
+    ] {
+        let error = add_external(&config, "http://pac.corp/down.pac", MockResponse::Fail(kind)).await.unwrap_err();
+        assert_eq!(error.into_response().status(), status);
+    }
+    assert!(config.read().await.external_pac_functions.is_empty());
+}
+
+#[tokio::test]
+async fn add_items_fetches_concurrently_and_keeps_the_request_order() {
+    let (_guard, _) = temp_data_dir_async("add_items_concurrent").await;
+    let config = shared(PacConfig::default());
+    let fetcher = MockFetcher::default();
+    let urls: Vec<String> = (1..=5).map(|n| format!("http://pac.corp/team{n}.pac")).collect();
+    for (n, url) in urls.iter().enumerate() {
+        fetcher.respond(url, MockResponse::Pac(pac_file(&format!("PROXY team{n}.corp:3128"))));
+        // Earlier sources answer last
+        fetcher.delay(url, Duration::from_millis(10 * (urls.len() - n) as u64));
+    }
+
+    let requests = urls.iter().map(|url| external_request(url)).collect();
+    let response = add_items(&config, &fetcher, 2, &HeaderMap::new(), true, requests).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(fetcher.most_in_flight(), 2);
+
+    let config = config.read().await;
+    let loaded: Vec<&str> = config.external_pac_functions.iter().map(|item| item.function.original_url.as_str()).collect();
+    assert_eq!(loaded, urls);
+    for (n, item) in config.external_pac_functions.iter().enumerate() {
+        assert!(item.function.function_text.contains(&format!("team{n}.corp:3128")));
+    }
+}
+
+#[tokio::test]
+async fn duplicates_are_rejected_or_re_enabled() {
+    let (_guard, _) = temp_data_dir_async("add_item_duplicates").await;
+    let mut initial = import_fixture();
+    initial.proxy_rules[0].enabled = false;
+    let config = shared(initial.clone());
+    let (fetcher, no_headers) = (MockFetcher::default(), HeaderMap::new());
+    let add = |request, dedupe| add_item(&config, &fetcher, &no_headers, dedupe, request);
+
+    // Hostnames compare without case or a trailing dot
+    let error = add(add_request("proxy_rules", rule("Example.COM.", "proxy.corp", 8080)), DedupePolicy::Reject)
+        .await.unwrap_err();
+    assert!(matches!(error, ProxyCatError::Conflict(_)), "{error}");
+    let error = add(add_request("bypass_list", bypass("INTRANET.corp.")), DedupePolicy::Reject).await.unwrap_err();
+    assert!(matches!(error, ProxyCatError::Conflict(_)), "{error}");
+    assert_eq!(*config.read().await, initial);
+
+    let response = add(add_request("proxy_rules", rule("example.com.", "PROXY.corp", 8080)), DedupePolicy::Enable)
+        .await.unwrap();
+    assert!(matches!(response.0.outcome, AddItemOutcome::ReEnabled));
+    assert_eq!(config.read().await.proxy_rules.len(), initial.proxy_rules.len());
+    assert!(config.read().await.proxy_rules[0].enabled);
+
+    // The same host through another proxy is a different rule
+    let response = add(add_request("proxy_rules", rule("example.com", "other.corp", 8080)), DedupePolicy::Reject)
+        .await.unwrap();
+    assert!(matches!(response.0.outcome, AddItemOutcome::Added));
+    assert_eq!(config.read().await.proxy_rules.len(), initial.proxy_rules.len() + 1);
+}
+
+#[tokio::test]
+async fn reset_from_windows_rebuilds_from_the_system_settings() {
+    let (_guard, _) = temp_data_dir_async("reset_from_windows").await;
+    let config = shared(import_fixture());
+    let proxy = MockProxy {
+        proxy_server: Some("proxy.corp:8080".to_string()),
+        proxy_bypass: Some("*.intranet.corp;<local>".to_string()),
+        ..MockProxy::default()
+    };
+
+    let Json(reset) = reset_from_windows(&config, &proxy, &HeaderMap::new()).await.unwrap();
+    assert_eq!(reset.proxy_rules.len(), 1);
+    assert_eq!(reset.proxy_rules[0].rule.proxy_host, "proxy.corp");
+    assert_eq!(reset.proxy_rules[0].rule.proxy_port, 8080);
+    assert!(reset.bypass_local);
+    assert!(reset.bypass_list.iter().any(|item| item.host == "*.intranet.corp"));
+    assert_eq!(*config.read().await, reset);
+    assert_eq!(PacConfig::load_current().unwrap(), reset);
+    assert_eq!(PacConfig::take_undo_snapshot().unwrap(), import_fixture());
+
+    let unreadable = MockProxy { fail_reads: true, ..MockProxy::default() };
+    assert!(reset_from_windows(&config, &unreadable, &HeaderMap::new()).await.is_err());
+    assert_eq!(*config.read().await, reset, "a failed read changes nothing");
+}
+
+/// Serves `app` on a free port and returns its base URL
+async fn serve(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    base_url
+}
+
+#[tokio::test]
+async fn probe_server_tells_a_running_server_from_a_dead_one() {
+    let timeout = Duration::from_millis(500);
+    let running = serve(Router::new().route("/status", get(|| async { "ok" }))).await;
+    assert!(probe_server(&running, timeout).await);
+
+    let failing = serve(Router::new().route("/status", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))).await;
+    assert!(!probe_server(&failing, timeout).await);
+
+    assert!(!probe_server(&format!("http://127.0.0.1:{}", free_port()), timeout).await, "nothing listening");
+
+    // Accepted by the OS but never answered, like a hung server
+    let hung = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let started = Instant::now();
+    assert!(!probe_server(&format!("http://{}", hung.local_addr().unwrap()), timeout).await);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+/// Waits for the next version message pushed on `socket`
+async fn next_pushed_version(
+    socket: &mut (impl futures::Stream<Item = tokio_tungstenite::tungstenite::Result<WsMessage>> + Unpin),
+) -> u64 {
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next()).await.unwrap().unwrap().unwrap();
+    let WsMessage::Text(text) = message else { panic!("unexpected message {:?}", message) };
+    serde_json::from_str::<serde_json::Value>(&text).unwrap()["version"].as_u64().unwrap()
+}
+
+#[tokio::test]
+async fn config_change_is_pushed_to_websocket_clients() {
+    let (_guard, _) = temp_data_dir_async("ws_push").await;
+    let base_url = serve(Router::new().route("/ws", get(ws_handler))).await;
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("{}/ws", base_url.replacen("http", "ws", 1)))
+        .await
+        .unwrap();
+
+    let connected_at = next_pushed_version(&mut socket).await;
+    assert_eq!(connected_at, pac::config_version());
+    let config = shared(import_fixture());
+    config.write().await.save_current().unwrap();
+    assert!(next_pushed_version(&mut socket).await > connected_at);
+}
+
+/// The status of a toggle, move or delete of the first bypass entry, a system entry
+async fn change_system_entry(config: &SharedPacConfig, action: &str, force: bool) -> StatusCode {
+    let path = |index| Path(("bypassList".to_string(), index));
+    let result = match action {
+        "toggle" => toggle_handler(State(config.clone()), HeaderMap::new(), path(0), Query(ForceQuery { force }))
+            .await.map(IntoResponse::into_response),
+        "move" => move_handler(
+            State(config.clone()), HeaderMap::new(), Path(("bypassList".to_string(), 0, 3)), Query(ForceQuery { force }),
+        ).await.map(IntoResponse::into_response),
+        // Deleting has no force override
+        _ => delete_handler(State(config.clone()), HeaderMap::new(), path(0))
+            .await.map(IntoResponse::into_response),
+    };
+    result.unwrap_or_else(IntoResponse::into_response).status()
+}
+
+#[tokio::test]
+async fn system_bypass_entries_are_only_changed_when_forced_and_never_deleted() {
+    let (_guard, _) = temp_data_dir_async("system_bypass").await;
+    let mut initial = PacConfig::default();
+    initial.bypass_list.push_back(bypass("intranet.corp"));
+    initial.ensure_system_bypass();
+    let config = shared(initial.clone());
+
+    for action in ["toggle", "move", "delete"] {
+        assert_eq!(change_system_entry(&config, action, false).await, StatusCode::BAD_REQUEST, "{action}");
+    }
+    assert_eq!(*config.read().await, initial);
+
+    assert_eq!(change_system_entry(&config, "toggle", true).await, StatusCode::OK);
+    assert!(!config.read().await.bypass_list[0].enabled);
+    assert!(!PacConfig::load_current().unwrap().bypass_list[0].enabled, "the opt-out survives a reload");
+    assert_eq!(change_system_entry(&config, "delete", true).await, StatusCode::BAD_REQUEST);
+    assert_eq!(config.read().await.bypass_list.len(), initial.bypass_list.len());
+}
+
+/// The JSON /validate-pac answers for `text`
+async fn validate(text: &str) -> serde_json::Value {
+    let response = validate_pac_handler(Json(ValidatePacRequest { text: text.to_string() })).await.into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    serde_json::from_str(&body_text(response).await).unwrap()
+}
+
+#[tokio::test]
+async fn validate_pac_reports_the_function_and_syntax_errors() {
+    let valid = validate("// Team PAC\nfunction FindProxyForURL(url, host) {\n    return \"DIRECT\";\n}\n").await;
+    assert_eq!(valid["valid"], true);
+    assert_eq!(valid["function_found"], true);
+    assert_eq!(valid["function_start"], 12);
+    assert!(valid["syntax_error"].is_null());
+
+    let no_function = validate("function helper(host) { return host; }").await;
+    assert_eq!(no_function["valid"], true);
+    assert_eq!(no_function["function_found"], false);
+    assert!(no_function["function_start"].is_null());
+
+    let broken = validate("function FindProxyForURL(url, host) {\n    return \"DIRECT\";\n    if (\n}\n").await;
+    assert_eq!(broken["valid"], false);
+    assert_eq!(broken["syntax_error"]["line"], 4);
+    assert!(broken["syntax_error"]["column"].as_u64().is_some());
+    assert!(!broken["syntax_error"]["message"].as_str().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn renaming_an_external_function_only_changes_its_label() {
+    let (_guard, _) = temp_data_dir_async("rename_external").await;
+    let mut initial = PacConfig::default();
+    initial.external_pac_functions.push_back(external(
+        "http://pac.corp/team.pac",
+        "FindProxyForURL_http___pac_corp_team_pac",
+        "return \"PROXY team.corp:3128\";",
+    ));
+    let config = shared(initial.clone());
+    let pac_before = generate_pac_content(&initial);
+
+    let rename = |name: Option<&str>| rename_external_handler(
+        State(config.clone()),
+        HeaderMap::new(),
+        Path(0),
+        Json(RenameRequest { display_name: name.map(str::to_string) }),
+    );
+    rename(Some("  Team PAC ")).await.unwrap();
+    {
+        let config = config.read().await;
+        let item = &config.external_pac_functions[0];
+        assert_eq!(item.display_name.as_deref(), Some("Team PAC"));
+        assert_eq!(item.function, initial.external_pac_functions[0].function);
+        assert_eq!(generate_pac_content(&config), pac_before);
+    }
+
+    rename(Some(" ")).await.unwrap();
+    assert_eq!(*config.read().await, initial, "a blank name goes back to the function name");
+}
+
+#[tokio::test]
+async fn pac_preview_shows_a_hypothetical_rule_without_adding_it() {
+    let (_guard, _) = temp_data_dir_async("pac_preview").await;
+    let config = shared(import_fixture());
+
+    let response = pac_preview_handler(
+        State(config.clone()),
+        Json(add_request("proxy_rules", rule("preview.example.com", "trial.corp", 3128))),
+    ).await.unwrap().into_response();
+    let preview = body_text(response).await;
+    assert!(preview.contains("'preview.example.com'"));
+    assert!(preview.contains("PROXY trial.corp:3128"));
+
+    assert_eq!(*config.read().await, import_fixture());
+    assert!(!generate_pac_content(&*config.read().await).contains("trial.corp"));
+    assert!(PacConfig::load_current().is_err(), "nothing is saved");
+}
+
+#[tokio::test]
+async fn pac_is_served_as_utf8_and_downloads_on_request() {
+    let (state, _) = app_state(import_fixture());
+    let pac = |download: Option<&str>| pac_handler(
+        State(state.clone()),
+        Query(PacQuery { download: download.map(str::to_string) }),
+    );
+
+    let served = pac(None).await.into_response();
+    assert_eq!(served.headers()[header::CONTENT_TYPE], "application/x-ns-proxy-autoconfig; charset=utf-8");
+    assert!(served.headers().get(header::CONTENT_DISPOSITION).is_none());
+
+    for download in ["1", "true"] {
+        let downloaded = pac(Some(download)).await.into_response();
+        assert_eq!(downloaded.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"proxy.pac\"");
+        assert!(body_text(downloaded).await.contains("function FindProxyForURL"));
+    }
+    assert!(pac(Some("0")).await.into_response().headers().get(header::CONTENT_DISPOSITION).is_none());
+}
+
+#[tokio::test]
+async fn pac_hash_changes_on_toggle_and_is_otherwise_stable() {
+    let (_guard, _) = temp_data_dir_async("pac_hash").await;
+    let config = shared(import_fixture());
+    let hash = || async { body_text(pac_hash_handler(State(config.clone())).await.into_response()).await };
+    let toggle = || toggle_handler(
+        State(config.clone()),
+        HeaderMap::new(),
+        Path(("proxyRules".to_string(), 0)),
+        Query(ForceQuery { force: false }),
+    );
+
+    let original = hash().await;
+    assert_eq!(original.len(), 64);
+    assert!(original.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(hash().await, original, "repeated calls give the same hash");
+
+    assert!(toggle().await.is_ok());
+    let toggled = hash().await;
+    assert_ne!(toggled, original);
+    assert_eq!(hash().await, toggled);
+
+    assert!(toggle().await.is_ok());
+    assert_eq!(hash().await, original, "toggling back restores the hash");
+}
+
+#[tokio::test]
+async fn compact_removes_only_disabled_external_functions() {
+    let (_guard, _) = temp_data_dir_async("compact").await;
+    let mut fixture = PacConfig::default();
+    for (index, name) in ["FindProxyForURL_kept", "FindProxyForURL_wpad", "FindProxyForURL_old"].into_iter().enumerate() {
+        let mut item = external(&format!("http://pac.corp/{index}.pac"), name, "return \"DIRECT\";");
+        item.enabled = index == 0;
+        fixture.external_pac_functions.push_back(item);
+    }
+    let config = shared(fixture.clone());
+
+    let Json(response) = compact_handler(State(config.clone()), HeaderMap::new()).await.unwrap();
+    assert_eq!(response.removed, 2);
+    let names: Vec<_> = config.read().await.external_pac_functions.iter()
+        .map(|item| item.function.function_name.clone())
+        .collect();
+    assert_eq!(names, ["FindProxyForURL_kept"]);
+
+    let Json(response) = compact_handler(State(config.clone()), HeaderMap::new()).await.unwrap();
+    assert_eq!(response.removed, 0, "nothing left to compact");
+
+    let Json(restored) = undo_handler(State(config.clone()), HeaderMap::new()).await.unwrap();
+    assert_eq!(restored.external_pac_functions, fixture.external_pac_functions);
+}
+
+#[tokio::test]
+async fn status_reports_the_auto_refresh_setting_the_template_reads() {
+    let (state, _commands) = app_state(PacConfig::default());
+    let initial = APP_CONFIG.get_auto_refresh();
+    for enabled in [false, true] {
+        let Json(changed) = auto_refresh_handler(Json(AutoRefreshBody { auto_refresh: enabled })).await;
+        assert_eq!(changed.auto_refresh, enabled);
+        let Json(status) = status_handler(State(state.clone())).await;
+        assert_eq!(status["auto_refresh"], enabled);
+    }
+    APP_CONFIG.update_auto_refresh(initial);
+
+    assert!(html_template::HTML_TEMPLATE.contains("status.auto_refresh"));
+}
+
+#[tokio::test]
+async fn version_reports_the_crate_version_and_build_metadata() {
+    let response = version_handler().await.into_response();
+    let version: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(version["target"], env!("BUILD_TARGET"));
+    assert!(version["git_hash"].is_string());
+    assert!(version["build_timestamp"].is_u64());
+}
+
+#[test]
+fn poll_backoff_grows_while_idle_and_resets_on_an_event() {
+    let base = Duration::from_millis(10);
+    let mut backoff = PollBackoff::new(base, Duration::from_millis(35));
+    let idle = |backoff: &mut PollBackoff, iterations: u32| {
+        (0..iterations).map(|_| backoff.next_delay(false)).last().unwrap()
+    };
+
+    assert_eq!(idle(&mut backoff, POLL_BACKOFF_STEP - 1), base);
+    assert_eq!(idle(&mut backoff, 1), base * 2);
+    assert_eq!(idle(&mut backoff, POLL_BACKOFF_STEP), Duration::from_millis(35), "capped at the maximum");
+    assert_eq!(idle(&mut backoff, POLL_BACKOFF_STEP * 20), Duration::from_millis(35));
+
+    assert_eq!(backoff.next_delay(true), base);
+    assert_eq!(backoff.next_delay(false), base, "idling starts over after an event");
+}
+
+#[test]
+fn pac_is_no_cache_by_default_and_cached_for_a_ttl() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    let mut headers = HeaderMap::new();
+    insert_pac_cache_headers(&mut headers, 0, now);
+    assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+    assert!(headers.get(header::EXPIRES).is_none());
+
+    let mut headers = HeaderMap::new();
+    insert_pac_cache_headers(&mut headers, 300, now);
+    assert_eq!(headers[header::CACHE_CONTROL], "max-age=300");
+    // 1_700_000_000 is Tue, 14 Nov 2023 22:13:20 GMT
+    assert_eq!(headers[header::EXPIRES], "Tue, 14 Nov 2023 22:18:20 GMT");
+}
+
+#[tokio::test]
+async fn master_switch_off_sends_everything_direct_and_on_restores_the_rules() {
+    let (_guard, _) = temp_data_dir_async("master_switch").await;
+    let mut fixture = PacConfig::default();
+    fixture.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    fixture.external_pac_functions.push_back(external(
+        "http://pac.corp/team.pac",
+        "FindProxyForURL_team",
+        "if (host == \"team.corp\") return \"PROXY team.corp:3128\";\n    return \"\";",
+    ));
+    let config = shared(fixture.clone());
+    let switch = |state: &str| master_switch_handler(State(config.clone()), Path(state.to_string()));
+    let decide = |pac: &str, url: &str| pac_eval::evaluate_pac(pac, url).unwrap().result;
+
+    assert!(switch("off").await.is_ok());
+    let pac = generate_pac_content(&*config.read().await);
+    for url in ["http://example.com/", "http://team.corp/"] {
+        assert_eq!(decide(&pac, url), "DIRECT");
+    }
+    assert!(!pac.contains("proxy.corp"));
+    let saved = PacConfig::load_current().unwrap();
+    assert!(!saved.enabled);
+    assert_eq!(saved.proxy_rules, fixture.proxy_rules, "the rules are kept while off");
+    assert_eq!(saved.external_pac_functions.len(), 1);
+
+    assert!(switch("on").await.is_ok());
+    let pac = generate_pac_content(&*config.read().await);
+    assert_eq!(decide(&pac, "http://example.com/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&pac, "http://team.corp/"), "PROXY team.corp:3128");
+
+    assert!(switch("maybe").await.is_err());
+}
+
+#[tokio::test]
+async fn batch_evaluation_decides_each_url() {
+    let mut fixture = PacConfig::default();
+    fixture.bypass_list.push_back(bypass("intranet.corp"));
+    fixture.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    let config = shared(fixture);
+    let batch = |urls: Vec<String>| evaluate_batch_handler(State(config.clone()), Json(EvaluateBatchRequest { urls }));
+
+    let urls = ["http://intranet.corp/wiki", "https://example.com/", "not a url"];
+    let Json(evaluations) = batch(urls.iter().map(|url| url.to_string()).collect()).await.unwrap();
+    let decisions: Vec<_> = evaluations.iter()
+        .map(|evaluation| (evaluation.url.as_str(), evaluation.host.as_deref(), evaluation.result.as_deref()))
+        .collect();
+    assert_eq!(decisions, [
+        ("http://intranet.corp/wiki", Some("intranet.corp"), Some("DIRECT")),
+        ("https://example.com/", Some("example.com"), Some("PROXY proxy.corp:8080")),
+        ("not a url", None, None),
+    ]);
+    assert!(evaluations[2].error.is_some(), "a bad URL doesn't stop the batch");
+
+    let too_many = vec!["http://example.com/".to_string(); EVALUATE_BATCH_LIMIT + 1];
+    assert!(batch(too_many).await.is_err());
+}
+
+#[test]
+fn mismatched_item_shape_names_the_expected_one() {
+    let error = parse_new_item(add_request("bypass_list", rule("example.com", "proxy.corp", 8080)))
+        .err()
+        .expect("a proxy rule is not a bypass entry");
+    let message = error.to_string();
+    assert!(message.contains("Invalid item for bypass_list"), "{}", message);
+    assert!(message.contains("unknown field `rule`"), "{}", message);
+    assert!(message.contains(r#"send an item like {"host": "example.com", "enabled": true, "match": "host"}"#), "{}", message);
+
+    let error = parse_new_item(add_request("proxy_rules", bypass("example.com"))).err().unwrap();
+    assert!(error.to_string().contains(r#"{"rule": {"host": "example.com""#), "{}", error);
+}
+
+async fn health(proxy: &MockProxy, heal: bool) -> (StatusCode, serde_json::Value) {
+    let response = pac_health(proxy, heal).unwrap().into_response();
+    let status = response.status();
+    (status, serde_json::from_str(&body_text(response).await).unwrap())
+}
+
+#[tokio::test]
+async fn health_is_503_until_the_pac_url_is_healed() {
+    let pac_url = APP_CONFIG.get_pac_url().unwrap();
+
+    let (status, body) = health(&MockProxy::pointing_at(&pac_url), false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["in_sync"], true);
+
+    let drifted = MockProxy::pointing_at("http://vpn.corp/proxy.pac");
+    let (status, body) = health(&drifted, false).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["current"], "http://vpn.corp/proxy.pac");
+    assert_eq!(drifted.sets(), 0, "checking alone changes nothing");
+
+    let (status, body) = health(&drifted, true).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["healed"], true);
+    assert_eq!(drifted.pac_url(), pac_url);
+}
+
+#[tokio::test]
+async fn bind_failures_tell_a_blocked_port_from_one_in_use() {
+    use std::io::{Error, ErrorKind};
+    let addr: SocketAddr = "127.0.0.1:12112".parse().unwrap();
+
+    let blocked = Error::new(ErrorKind::PermissionDenied, "access denied");
+    assert_eq!(BindFailure::classify(&blocked), BindFailure::Blocked);
+    assert!(bind_error_message(addr, &blocked).contains("firewall or security policy"));
+
+    let in_use = Error::new(ErrorKind::AddrInUse, "address in use");
+    assert_eq!(BindFailure::classify(&in_use), BindFailure::InUse);
+    assert!(bind_error_message(addr, &in_use).contains("Port 12112 is already in use"));
+
+    assert_eq!(BindFailure::classify(&Error::new(ErrorKind::AddrNotAvailable, "no such address")), BindFailure::Other);
+
+    // A port that is really taken comes back from the OS as in use
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let e = TcpListener::bind(taken.local_addr().unwrap()).await.unwrap_err();
+    assert_eq!(BindFailure::classify(&e), BindFailure::InUse);
+}
+
+#[test]
+fn move_within_inserts_before_the_target() {
+    let list = || VecDeque::from(["a", "b", "c", "d"]);
+    let moved = |from, to| {
+        let mut list = list();
+        let changed = move_within(&mut list, "proxyRules", from, to).unwrap();
+        (Vec::from(list), changed)
+    };
+
+    assert_eq!(moved(0, 2), (vec!["b", "a", "c", "d"], true));
+    assert_eq!(moved(0, 4), (vec!["b", "c", "d", "a"], true));
+    assert_eq!(moved(3, 1), (vec!["a", "d", "b", "c"], true));
+    assert_eq!(moved(1, 2), (vec!["a", "b", "c", "d"], false), "before the next item is where it already is");
+    assert!(move_within(&mut list(), "proxyRules", 4, 0).is_err());
+    assert!(move_within(&mut list(), "proxyRules", 0, 5).is_err());
+}
+
+#[test]
+fn dropping_an_item_lands_it_on_the_drop_target() {
+    // The page sends the slot after the target when dragging down, as below
+    assert!(html_template::HTML_TEMPLATE.contains("const toIndex = fromIndex < index ? index + 1 : index;"));
+    let to_index = |from: usize, dropped_on: usize| if from < dropped_on { dropped_on + 1 } else { dropped_on };
+
+    for from in 0..4 {
+        for dropped_on in (0..4).filter(|&index| index != from) {
+            let mut list = VecDeque::from(["a", "b", "c", "d"]);
+            let item = list[from];
+            move_within(&mut list, "proxyRules", from, to_index(from, dropped_on)).unwrap();
+            assert_eq!(list[dropped_on], item, "{from} dropped on {dropped_on}: {list:?}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn apply_points_the_system_at_the_pac_and_records_the_outcome() {
+    let (_guard, _) = temp_data_dir_async("apply").await;
+    let (state, _) = app_state(import_fixture());
+    let pac_url = APP_CONFIG.get_pac_url().unwrap();
+
+    let proxy = MockProxy::pointing_at("http://elsewhere.corp/proxy.pac");
+    let response = apply_pac(&state, &proxy).unwrap();
+    assert!(response.0.drifted);
+    assert_eq!(proxy.pac_url(), pac_url);
+    assert!(lock_or_recover(&state.pac_apply, "PAC apply status").applied);
+
+    let response = apply_pac(&state, &proxy).unwrap();
+    assert!(!response.0.drifted, "already pointing at the PAC");
+    assert_eq!(proxy.sets(), 2);
+
+    let refusing = MockProxy::refusing(1);
+    assert!(apply_pac(&state, &refusing).is_err());
+    let status = lock_or_recover(&state.pac_apply, "PAC apply status").clone();
+    assert!(!status.applied);
+    assert!(status.error.is_some());
+}
+
+async fn toggle_first_rule(config: &SharedPacConfig, if_match: &str) -> StatusCode {
+    let result = toggle_handler(
+        State(config.clone()),
+        headers(&[(header::IF_MATCH, if_match)]),
+        Path(("proxyRules".to_string(), 0)),
+        Query(ForceQuery { force: false }),
+    ).await;
+    match result {
+        Ok(response) => response.into_response().status(),
+        Err(e) => e.into_response().status(),
+    }
+}
+
+#[tokio::test]
+async fn stale_if_match_is_rejected_and_current_one_applies() {
+    let (_guard, _) = temp_data_dir_async("if_match").await;
+    let config = shared(import_fixture());
+    let seen = pac::config_version();
+    pac::mark_config_changed();
+
+    assert_eq!(toggle_first_rule(&config, &seen.to_string()).await, StatusCode::CONFLICT);
+    assert!(config.read().await.proxy_rules[0].enabled, "a stale toggle changes nothing");
+
+    let current = pac::config_version();
+    assert_eq!(toggle_first_rule(&config, &format!("W/\"{}\"", current)).await, StatusCode::OK);
+    assert!(!config.read().await.proxy_rules[0].enabled);
+
+    assert_eq!(toggle_first_rule(&config, "*").await, StatusCode::OK);
+    assert_eq!(toggle_first_rule(&config, "soon").await, StatusCode::BAD_REQUEST);
+}
+
+fn store_request(key: &str, username: &str, password: &str) -> Json<StoreCredentialRequest> {
+    Json(StoreCredentialRequest { key: key.to_string(), username: username.to_string(), password: password.to_string() })
+}
+
+#[tokio::test]
+async fn credentials_are_managed_without_exposing_the_secret() {
+    let mut config = import_fixture();
+    config.proxy_rules[0].rule.credential_key = Some("missing".to_string());
+    config.proxy_rules[1].rule.credential_key = Some("lab-socks".to_string());
+    let (state, _commands) = app_state(config);
+
+    store_credential_handler(State(state.clone()), store_request("lab-socks", "alice", "s3cret-pa55")).await.unwrap();
+    assert!(store_credential_handler(State(state.clone()), store_request("has space", "alice", "pw")).await.is_err());
+
+    let listed = body_text(credentials_handler(State(state.clone())).await.unwrap().into_response()).await;
+    assert!(!listed.contains("s3cret-pa55"));
+    let listed: serde_json::Value = serde_json::from_str(&listed).unwrap();
+    assert_eq!(listed["credentials"], serde_json::json!([{ "key": "lab-socks", "username": "alice" }]));
+    assert_eq!(listed["unresolved"], serde_json::json!(["missing"]));
+
+    // /config carries the key only
+    let exported = export(&state.pac_config, "application/json").await;
+    assert!(exported.contains(r#""credential_key":"lab-socks""#));
+    assert!(!exported.contains("s3cret-pa55"));
+    assert!(!exported.contains("alice"));
+
+    delete_credential_handler(State(state.clone()), Path("lab-socks".to_string())).await.unwrap();
+    assert!(state.credentials.get("lab-socks").unwrap().is_none());
+    assert!(delete_credential_handler(State(state.clone()), Path("lab-socks".to_string())).await.is_err());
+}
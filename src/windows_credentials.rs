@@ -0,0 +1,121 @@
+use windows::core::{HSTRING, PWSTR};
+use windows::Win32::Foundation::ERROR_NOT_FOUND;
+use windows::Win32::Security::Credentials::{
+    CredDeleteW, CredEnumerateW, CredFree, CredReadW, CredWriteW, CREDENTIALW,
+    CRED_ENUMERATE_FLAGS, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+};
+use log::{info, debug};
+use proxycat::credentials::{validate_credential_key, CredentialStore, CredentialSummary, ProxyCredential};
+use crate::error::{Result, ProxyCatError};
+
+/// Prefix of the Credential Manager targets ProxyCat owns, followed by the key
+const TARGET_PREFIX: &str = "ProxyCat:";
+
+/// Stores credentials as generic credentials in the Windows Credential Manager,
+/// under targets named "ProxyCat:<key>"
+pub struct WindowsCredentials;
+
+impl CredentialStore for WindowsCredentials {
+    fn list(&self) -> Result<Vec<CredentialSummary>> {
+        let filter = HSTRING::from(format!("{}*", TARGET_PREFIX));
+        let mut count = 0u32;
+        let mut credentials: *mut *mut CREDENTIALW = std::ptr::null_mut();
+        if let Err(e) = unsafe { CredEnumerateW(&filter, CRED_ENUMERATE_FLAGS(0), &mut count, &mut credentials) } {
+            if e.code() == ERROR_NOT_FOUND.to_hresult() {
+                return Ok(Vec::new());
+            }
+            return Err(ProxyCatError::Windows(format!("Failed to list stored credentials: {}", e)));
+        }
+
+        let mut summaries: Vec<CredentialSummary> = unsafe {
+            std::slice::from_raw_parts(credentials, count as usize)
+                .iter()
+                .filter_map(|credential| {
+                    let credential = &**credential;
+                    let target = credential.TargetName.to_string().ok()?;
+                    Some(CredentialSummary {
+                        key: target.strip_prefix(TARGET_PREFIX)?.to_string(),
+                        username: pwstr_to_string(credential.UserName),
+                    })
+                })
+                .collect()
+        };
+        unsafe { CredFree(credentials as *const _) };
+        summaries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(summaries)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<ProxyCredential>> {
+        validate_credential_key(key)?;
+        let target = HSTRING::from(format!("{}{}", TARGET_PREFIX, key));
+        let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+        if let Err(e) = unsafe { CredReadW(&target, CRED_TYPE_GENERIC, 0, &mut credential) } {
+            if e.code() == ERROR_NOT_FOUND.to_hresult() {
+                return Ok(None);
+            }
+            return Err(ProxyCatError::Windows(format!("Failed to read credential '{}': {}", key, e)));
+        }
+
+        let result = unsafe {
+            let stored = &*credential;
+            // The blob is the password as UTF-16, as Windows itself stores generic credentials
+            // An empty password comes back as a null blob, which from_raw_parts must not see
+            let blob: &[u8] = if stored.CredentialBlob.is_null() || stored.CredentialBlobSize == 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts(stored.CredentialBlob, stored.CredentialBlobSize as usize)
+            };
+            let wide: Vec<u16> = blob.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+            ProxyCredential {
+                username: pwstr_to_string(stored.UserName),
+                password: String::from_utf16_lossy(&wide),
+            }
+        };
+        unsafe { CredFree(credential as *const _) };
+        Ok(Some(result))
+    }
+
+    fn set(&self, key: &str, credential: &ProxyCredential) -> Result<()> {
+        validate_credential_key(key)?;
+        let mut target: Vec<u16> = format!("{}{}", TARGET_PREFIX, key).encode_utf16().chain(std::iter::once(0)).collect();
+        let mut username: Vec<u16> = credential.username.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut blob: Vec<u8> = credential.password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+        let stored = CREDENTIALW {
+            Type: CRED_TYPE_GENERIC,
+            TargetName: PWSTR(target.as_mut_ptr()),
+            UserName: PWSTR(username.as_mut_ptr()),
+            CredentialBlobSize: blob.len() as u32,
+            CredentialBlob: blob.as_mut_ptr(),
+            Persist: CRED_PERSIST_LOCAL_MACHINE,
+            ..Default::default()
+        };
+        unsafe { CredWriteW(&stored, 0) }
+            .map_err(|e| ProxyCatError::Windows(format!("Failed to store credential '{}': {}", key, e)))?;
+        info!("Stored credential '{}'", key);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<bool> {
+        validate_credential_key(key)?;
+        let target = HSTRING::from(format!("{}{}", TARGET_PREFIX, key));
+        match unsafe { CredDeleteW(&target, CRED_TYPE_GENERIC, 0) } {
+            Ok(()) => {
+                info!("Deleted credential '{}'", key);
+                Ok(true)
+            }
+            Err(e) if e.code() == ERROR_NOT_FOUND.to_hresult() => {
+                debug!("No credential '{}' to delete", key);
+                Ok(false)
+            }
+            Err(e) => Err(ProxyCatError::Windows(format!("Failed to delete credential '{}': {}", key, e))),
+        }
+    }
+}
+
+fn pwstr_to_string(value: PWSTR) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+    unsafe { value.to_string() }.unwrap_or_default()
+}
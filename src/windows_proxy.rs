@@ -0,0 +1,277 @@
+use windows::Win32::Networking::WinInet::{
+    InternetQueryOptionW,
+    InternetSetOptionW,
+    INTERNET_OPTION_PER_CONNECTION_OPTION,
+    INTERNET_PER_CONN_OPTION_LISTW,
+    INTERNET_PER_CONN_OPTIONW,
+    INTERNET_PER_CONN_PROXY_SERVER,
+    INTERNET_PER_CONN_PROXY_BYPASS,
+    INTERNET_PER_CONN_AUTOCONFIG_URL,
+    INTERNET_OPTION_PROXY_SETTINGS_CHANGED,
+    INTERNET_OPTION_REFRESH,
+    INTERNET_OPTION_SETTINGS_CHANGED,
+};
+use windows::core::PWSTR;
+use log::{info, error, warn, debug};
+use crate::error::{Result, ProxyCatError};
+use crate::proxy_config::{decode_wide, ProxyConfig, SystemProxy};
+
+/// The Windows per-connection proxy settings, read and written through WinInet
+pub struct WindowsProxy;
+
+impl WindowsProxy {
+    /// Reads the current proxy configuration from Windows settings
+    /// This function uses the Windows API to query the system's proxy settings
+    /// Returns an error if the query itself fails, and an empty ProxyConfig if
+    /// the query succeeds but no proxy is configured
+    fn read_config() -> Result<ProxyConfig> {
+        info!("Reading proxy configuration from Windows settings...");
+        unsafe {
+            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
+            let mut options = [
+                INTERNET_PER_CONN_OPTIONW::default(),
+                INTERNET_PER_CONN_OPTIONW::default(),
+                INTERNET_PER_CONN_OPTIONW::default(),
+            ];
+
+            // Set up the option list structure
+            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
+            option_list.dwOptionCount = 3;
+            option_list.dwOptionError = 0;
+            option_list.pOptions = options.as_mut_ptr();
+
+            // Configure which options we want to query
+            options[0].dwOption = INTERNET_PER_CONN_PROXY_SERVER;
+            options[1].dwOption = INTERNET_PER_CONN_PROXY_BYPASS;
+            options[2].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
+
+            // Query the Windows API for proxy settings
+            let query = InternetQueryOptionW(
+                None,
+                INTERNET_OPTION_PER_CONNECTION_OPTION,
+                Some(&mut option_list as *mut _ as *mut _),
+                &mut (std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32),
+            );
+
+            // Option values are only meaningful if the query succeeded
+            let settings = ["proxy server", "proxy bypass list", "auto-config URL"];
+            let values = query.map(|()| {
+                let mut values: [Option<String>; 3] = Default::default();
+                for ((value, option), setting) in values.iter_mut().zip(&options).zip(settings) {
+                    if !option.Value.pszValue.is_null() {
+                        *value = Some(wide_setting_to_string(option.Value.pszValue.0, setting));
+                    }
+                }
+                values
+            });
+
+            config_from_query_result(values)
+        }
+    }
+
+    /// Sets the Windows proxy configuration to use a PAC file
+    /// This function configures Windows to use the specified PAC file URL
+    fn write_pac_file(pac_url: &str) -> Result<()> {
+        info!("Setting Windows proxy configuration to use PAC file: {}", pac_url);
+        Self::set_auto_config_url(Some(pac_url))
+    }
+
+    /// Removes the auto-config URL from the Windows proxy configuration
+    fn clear_proxy() -> Result<()> {
+        info!("Clearing Windows auto-config URL");
+        Self::set_auto_config_url(None)
+    }
+
+    /// Writes the auto-config URL option and notifies Windows of the change
+    /// Passing None clears the option
+    fn set_auto_config_url(pac_url: Option<&str>) -> Result<()> {
+        unsafe {
+            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
+            let mut options = [INTERNET_PER_CONN_OPTIONW::default()];
+
+            // Set up the option list structure
+            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
+            option_list.dwOptionCount = 1;
+            option_list.dwOptionError = 0;
+
+            // Configure option for PAC file; a null value clears it
+            options[0].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
+            let mut wide_url: Option<Vec<u16>> = pac_url
+                .map(|url| url.encode_utf16().chain(std::iter::once(0)).collect());
+            if let Some(wide_url) = wide_url.as_mut() {
+                options[0].Value.pszValue = PWSTR::from_raw(wide_url.as_mut_ptr());
+            }
+            // Point the list at the options only once they are filled in
+            option_list.pOptions = options.as_mut_ptr();
+            
+            // Set the proxy configuration
+            if InternetSetOptionW(
+                None,
+                INTERNET_OPTION_PER_CONNECTION_OPTION,
+                Some(&option_list as *const _ as *const _),
+                std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32,
+            ).is_ok() {
+                info!("Successfully set PAC file configuration");
+
+                // Notify Windows that proxy settings have changed
+                let _ = InternetSetOptionW(None, INTERNET_OPTION_PROXY_SETTINGS_CHANGED, None, 0);
+                let _ = InternetSetOptionW(None, INTERNET_OPTION_REFRESH, None, 0);
+                let _ = InternetSetOptionW(None, INTERNET_OPTION_SETTINGS_CHANGED, None, 0);
+
+                info!("Successfully notified Windows of proxy settings change");
+                Ok(())
+            } else {
+                error!("Failed to set PAC file configuration");
+                Err(ProxyCatError::Windows("Failed to set PAC file configuration".to_string()))
+            }
+        }
+    }
+
+    /// Gets the current PAC file URL from Windows settings
+    fn read_pac_file() -> Result<String> {
+        info!("Getting current PAC file URL from Windows settings...");
+        unsafe {
+            let mut option_list = INTERNET_PER_CONN_OPTION_LISTW::default();
+            let mut options = [INTERNET_PER_CONN_OPTIONW::default()];
+
+            // Set up the option list structure
+            option_list.dwSize = std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32;
+            option_list.dwOptionCount = 1;
+            option_list.dwOptionError = 0;
+            option_list.pOptions = options.as_mut_ptr();
+
+            // Configure option for PAC file
+            options[0].dwOption = INTERNET_PER_CONN_AUTOCONFIG_URL;
+
+            // Query the Windows API for PAC file URL
+            if InternetQueryOptionW(
+                None,
+                INTERNET_OPTION_PER_CONNECTION_OPTION,
+                Some(&mut option_list as *mut _ as *mut _),
+                &mut (std::mem::size_of::<INTERNET_PER_CONN_OPTION_LISTW>() as u32),
+            ).is_ok() {
+                if !options[0].Value.pszValue.is_null() {
+                    let url = wide_setting_to_string(options[0].Value.pszValue.0, "auto-config URL");
+                    info!("Found PAC file URL: {}", url);
+                    Ok(url)
+                } else {
+                    info!("No PAC file URL found");
+                    Ok(String::new())
+                }
+            } else {
+                error!("Failed to query PAC file URL");
+                Err(ProxyCatError::Windows("Failed to query PAC file URL".to_string()))
+            }
+        }
+    }
+}
+
+impl SystemProxy for WindowsProxy {
+    fn get_pac_file(&self) -> Result<String> {
+        Self::read_pac_file()
+    }
+
+    fn set_pac_file(&self, pac_url: &str) -> Result<()> {
+        Self::write_pac_file(pac_url)
+    }
+
+    fn clear_pac_file(&self) -> Result<()> {
+        Self::clear_proxy()
+    }
+
+    fn current_config(&self) -> Result<ProxyConfig> {
+        Self::read_config()
+    }
+}
+
+/// Builds a configuration from the outcome of querying the proxy server,
+/// bypass list and auto-config URL options, in that order
+/// A failed query is an error; a successful query with nothing set is an empty config
+fn config_from_query_result(values: windows::core::Result<[Option<String>; 3]>) -> Result<ProxyConfig> {
+    let [proxy_server, proxy_bypass, auto_config_url] = values.map_err(|e| {
+        error!("Failed to query Windows proxy settings: {}", e);
+        ProxyCatError::Windows(format!(
+            "Failed to query proxy settings (error {:#010x}): {}", e.code().0, e.message()
+        ))
+    })?;
+    info!("Successfully queried Windows proxy settings");
+
+    let mut config = ProxyConfig::new();
+    config.proxy_server = proxy_server;
+    debug!("Found proxy server: {:?}", config.proxy_server);
+    config.proxy_bypass = proxy_bypass;
+    debug!("Found proxy bypass list: {:?}", config.proxy_bypass);
+    config.auto_config_url = auto_config_url;
+    debug!("Found auto-config URL: {:?}", config.auto_config_url);
+
+    // Determine if proxy is enabled
+    config.use_proxy = config.proxy_server.is_some() || config.auto_config_url.is_some();
+    if config.use_proxy {
+        info!("Proxy enabled: {}", config.use_proxy);
+    } else {
+        info!("No proxy settings configured in Windows");
+    }
+
+    Ok(config)
+}
+
+/// Converts a wide string pointer to a Rust String
+/// This is used to convert Windows API wide string responses to Rust strings
+/// The flag is true if invalid UTF-16 had to be replaced with U+FFFD
+fn wide_to_string(ptr: *const u16) -> (String, bool) {
+    unsafe {
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        let slice = std::slice::from_raw_parts(ptr, len);
+        decode_wide(slice)
+    }
+}
+
+/// Like `wide_to_string`, but logs a warning naming the setting if the value was mangled
+fn wide_setting_to_string(ptr: *const u16, setting: &str) -> String {
+    let (value, lossy) = wide_to_string(ptr);
+    if lossy {
+        warn!("Windows {} contains invalid UTF-16 and was decoded lossily, it may not match as expected: {}",
+            setting, value);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::core::HRESULT;
+
+    #[test]
+    fn failed_query_is_an_error() {
+        let failure = windows::core::Error::from(HRESULT(0x80070005u32 as i32));
+        match config_from_query_result(Err(failure)) {
+            Err(ProxyCatError::Windows(message)) => assert!(message.contains("0x80070005"), "{}", message),
+            other => panic!("expected a Windows error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nothing_configured_is_an_empty_config() {
+        let config = config_from_query_result(Ok([None, None, None])).unwrap();
+        assert!(!config.use_proxy);
+        assert!(config.proxy_server.is_none() && config.proxy_bypass.is_none() && config.auto_config_url.is_none());
+    }
+
+    #[test]
+    fn configured_values_are_kept() {
+        let config = config_from_query_result(Ok([
+            Some("proxy.corp:8080".to_string()),
+            Some("<local>".to_string()),
+            None,
+        ])).unwrap();
+        assert!(config.use_proxy);
+        assert_eq!(config.proxy_server.as_deref(), Some("proxy.corp:8080"));
+        assert_eq!(config.proxy_bypass.as_deref(), Some("<local>"));
+
+        let pac_only = config_from_query_result(Ok([None, None, Some("http://pac.corp/proxy.pac".to_string())])).unwrap();
+        assert!(pac_only.use_proxy);
+    }
+}
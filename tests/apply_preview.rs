@@ -0,0 +1,45 @@
+//! Previewing what /apply would change in the system proxy settings
+
+mod common;
+
+use common::MockProxy;
+use proxycat::proxy_config::apply_preview;
+
+const PAC_URL: &str = "http://127.0.0.1:12112/master.pac";
+
+#[test]
+fn a_different_system_url_is_a_pending_change() {
+    let proxy = MockProxy {
+        proxy_server: Some("proxy.corp:8080".to_string()),
+        proxy_bypass: Some("<local>".to_string()),
+        ..MockProxy::pointing_at("http://pac.corp/proxy.pac")
+    };
+    let preview = apply_preview(&proxy, PAC_URL).unwrap();
+    assert!(preview.pending);
+    assert_eq!(preview.changes.len(), 1);
+    assert_eq!(preview.changes[0].setting, "auto_config_url");
+    assert_eq!(preview.changes[0].current.as_deref(), Some("http://pac.corp/proxy.pac"));
+    assert_eq!(preview.changes[0].proposed.as_deref(), Some(PAC_URL));
+    assert_eq!(preview.proxy_server.as_deref(), Some("proxy.corp:8080"));
+    assert_eq!(preview.proxy_bypass.as_deref(), Some("<local>"));
+
+    assert_eq!(proxy.pac_url(), "http://pac.corp/proxy.pac", "the preview changes nothing");
+    assert_eq!(proxy.sets(), 0);
+}
+
+#[test]
+fn our_url_or_none_is_reported_accordingly() {
+    let preview = apply_preview(&MockProxy::pointing_at(PAC_URL), PAC_URL).unwrap();
+    assert!(!preview.pending);
+    assert!(!preview.changes[0].pending);
+
+    let preview = apply_preview(&MockProxy::default(), PAC_URL).unwrap();
+    assert!(preview.pending);
+    assert_eq!(preview.changes[0].current, None);
+}
+
+#[test]
+fn an_unreadable_system_is_an_error() {
+    let proxy = MockProxy { fail_reads: true, ..MockProxy::default() };
+    assert!(apply_preview(&proxy, PAC_URL).is_err());
+}
@@ -0,0 +1,48 @@
+//! Whether a bare DIRECT from an external PAC function ends the lookup
+
+mod common;
+
+use common::{external, rule};
+use proxycat::pac::{generate_pac_content, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+/// A WPAD-style function that sends the intranet DIRECT, ahead of a catch-all rule
+fn config(authoritative: bool) -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    let mut wpad = external(
+        "http://wpad/wpad.dat",
+        "FindProxyForURL_http___wpad_wpad_dat",
+        "if (dnsDomainIs(host, \".intranet.corp\")) return \"DIRECT\";\n    return \"\";",
+    );
+    wpad.authoritative = authoritative;
+    config.external_pac_functions.push_back(wpad);
+    config
+}
+
+fn decide(config: &PacConfig, url: &str) -> String {
+    evaluate_pac(&generate_pac_content(config), url).unwrap().result
+}
+
+#[test]
+fn authoritative_direct_ends_the_lookup() {
+    let config = config(true);
+    assert_eq!(decide(&config, "http://wiki.intranet.corp/"), "DIRECT");
+    assert_eq!(decide(&config, "http://example.com/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn non_authoritative_direct_falls_through_to_the_rules() {
+    let config = config(false);
+    assert_eq!(decide(&config, "http://wiki.intranet.corp/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://example.com/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn saved_functions_without_the_flag_stay_authoritative() {
+    let mut saved = serde_json::to_value(config(false)).unwrap();
+    saved["external_pac_functions"][0].as_object_mut().unwrap().remove("authoritative");
+    let loaded = PacConfig::load_from_reader(saved.to_string().as_bytes()).unwrap();
+    assert!(loaded.external_pac_functions[0].authoritative);
+    assert_eq!(decide(&loaded, "http://wiki.intranet.corp/"), "DIRECT");
+}
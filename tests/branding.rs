@@ -0,0 +1,49 @@
+//! White-label names in the served web interface
+
+#[path = "../src/html_template.rs"]
+mod html_template;
+
+use proxycat::branding::Branding;
+
+fn render(branding: &Branding) -> String {
+    html_template::render("{}", "\"1\"", &branding.page_title())
+}
+
+#[test]
+fn custom_app_name_titles_the_page() {
+    let branding = Branding::default().with_overrides(Some("Acme Proxy".to_string()), None, None);
+    let html = render(&branding);
+    assert!(html.contains("<title>Acme Proxy Configuration</title>"));
+    assert!(html.contains("<h1>Acme Proxy Configuration</h1>"));
+    assert!(!html.contains("ProxyCat Configuration"));
+    assert_eq!(branding.tooltip(), "Acme Proxy");
+}
+
+#[test]
+fn page_title_override_is_escaped() {
+    let branding = Branding::default().with_overrides(None, None, Some("Proxies <R&D>".to_string()));
+    let html = render(&branding);
+    assert!(html.contains("<title>Proxies &lt;R&amp;D&gt;</title>"));
+}
+
+#[test]
+fn falls_back_to_proxycat() {
+    assert!(render(&Branding::default()).contains("<title>ProxyCat Configuration</title>"));
+    let blank = Branding::default().with_overrides(Some("  ".to_string()), None, None);
+    assert_eq!(blank.tooltip(), "ProxyCat");
+}
+
+#[test]
+fn branding_file_is_read_and_cli_wins() {
+    let dir = std::env::temp_dir().join(format!("proxycat_branding_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(proxycat::branding::BRANDING_FILE), r#"{"app_name": "Acme Proxy", "tooltip": "Acme proxy settings"}"#).unwrap();
+    let loaded = Branding::load(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(loaded.app_name(), "Acme Proxy");
+    assert_eq!(loaded.tooltip(), "Acme proxy settings");
+    let overridden = loaded.with_overrides(None, Some("From the CLI".to_string()), None);
+    assert_eq!(overridden.tooltip(), "From the CLI");
+    assert_eq!(overridden.page_title(), "Acme Proxy Configuration");
+}
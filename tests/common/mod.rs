@@ -0,0 +1,301 @@
+//! Builders shared by the integration tests, so each test only spells out what it cares about
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{MutexGuard, RwLock};
+use proxycat::constants::APP_CONFIG;
+use proxycat::credentials::{validate_credential_key, CredentialStore, CredentialSummary, ProxyCredential};
+use proxycat::error::{FetchError, FetchErrorKind, ProxyCatError, Result};
+use proxycat::fetcher::{ConditionalFetch, FetchValidators, PacFetcher};
+use proxycat::proxy_config::{ProxyConfig, SystemProxy};
+use proxycat::pac::{
+    BypassListItem, BypassMatch, ExternalPacFunction, ExternalPacFunctionItem, ExternalPacHealth, PacConfig, ProxyRule,
+    ProxyRuleItem, ProxyType, SharedPacConfig,
+};
+
+/// An enabled HTTP proxy rule sending `host` to `proxy_host:proxy_port`
+pub fn rule(host: &str, proxy_host: &str, proxy_port: u16) -> ProxyRuleItem {
+    ProxyRuleItem {
+        rule: ProxyRule {
+            host: host.to_string(),
+            proxy_host: proxy_host.to_string(),
+            proxy_port,
+            proxy_type: ProxyType::Http,
+            scheme: None,
+            https_only: false,
+            credential_key: None,
+        },
+        enabled: true,
+        note: None,
+        tags: Vec::new(),
+    }
+}
+
+/// An enabled, user-added bypass entry matched against the host
+pub fn bypass(host: &str) -> BypassListItem {
+    BypassListItem {
+        host: host.to_string(),
+        enabled: true,
+        note: None,
+        match_mode: BypassMatch::Host,
+        system: false,
+        tags: Vec::new(),
+    }
+}
+
+/// An enabled external function fetched from `url`, with `body` as its body
+pub fn external(url: &str, function_name: &str, body: &str) -> ExternalPacFunctionItem {
+    ExternalPacFunctionItem {
+        function: ExternalPacFunction {
+            original_url: url.to_string(),
+            function_name: function_name.to_string(),
+            function_text: format!("function {}(url, host) {{\n    {}\n}}\n", function_name, body),
+        },
+        enabled: true,
+        health: ExternalPacHealth::default(),
+        note: None,
+        display_name: None,
+        pinned: false,
+        authoritative: true,
+        validators: Default::default(),
+        tags: Vec::new(),
+    }
+}
+
+/// Turns string literals into the owned tags items carry
+pub fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+}
+
+/// Wraps `config` the way the server shares it between handlers
+pub fn shared(config: PacConfig) -> SharedPacConfig {
+    Arc::new(RwLock::new(config))
+}
+
+static DATA_DIR_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Points the data directory at an empty temporary directory for the rest of the test
+/// The data directory and server settings are process-wide, so the returned guard keeps
+/// other tests in the same binary from using them until this one is done
+pub fn temp_data_dir(name: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+    let guard = DATA_DIR_LOCK.blocking_lock();
+    (guard, use_temp_data_dir(name))
+}
+
+/// `temp_data_dir` for async tests
+pub async fn temp_data_dir_async(name: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+    let guard = DATA_DIR_LOCK.lock().await;
+    (guard, use_temp_data_dir(name))
+}
+
+fn use_temp_data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("proxycat_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    APP_CONFIG.update_data_dir(dir.clone()).unwrap();
+    dir
+}
+
+/// Stands in for the Windows proxy settings
+#[derive(Default)]
+pub struct MockProxy {
+    /// The auto-config URL the system points at
+    pub pac_url: Mutex<String>,
+    /// The manual proxy server and bypass list reported by `current_config`
+    pub proxy_server: Option<String>,
+    pub proxy_bypass: Option<String>,
+    /// Makes every read fail, as when the settings can't be queried
+    pub fail_reads: bool,
+    /// Sets still to refuse before accepting one, as when Windows isn't ready yet
+    pub refused_sets: Mutex<u32>,
+    /// How many times the auto-config URL was set
+    pub sets: Mutex<u32>,
+}
+
+impl MockProxy {
+    pub fn pointing_at(pac_url: &str) -> Self {
+        Self { pac_url: Mutex::new(pac_url.to_string()), ..Self::default() }
+    }
+
+    /// A proxy that refuses the first `refused` sets
+    pub fn refusing(refused: u32) -> Self {
+        Self { refused_sets: Mutex::new(refused), ..Self::default() }
+    }
+
+    pub fn pac_url(&self) -> String {
+        self.pac_url.lock().unwrap().clone()
+    }
+
+    pub fn sets(&self) -> u32 {
+        *self.sets.lock().unwrap()
+    }
+}
+
+impl SystemProxy for MockProxy {
+    fn get_pac_file(&self) -> Result<String> {
+        if self.fail_reads {
+            return Err(ProxyCatError::Windows("Failed to query PAC file URL".to_string()));
+        }
+        Ok(self.pac_url())
+    }
+
+    fn set_pac_file(&self, pac_url: &str) -> Result<()> {
+        let mut refused = self.refused_sets.lock().unwrap();
+        if *refused > 0 {
+            *refused -= 1;
+            return Err(ProxyCatError::Windows("Failed to set PAC file configuration".to_string()));
+        }
+        *self.pac_url.lock().unwrap() = pac_url.to_string();
+        *self.sets.lock().unwrap() += 1;
+        Ok(())
+    }
+
+    fn clear_pac_file(&self) -> Result<()> {
+        self.pac_url.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn current_config(&self) -> Result<ProxyConfig> {
+        if self.fail_reads {
+            return Err(ProxyCatError::Windows("Failed to query proxy settings".to_string()));
+        }
+        let mut config = ProxyConfig::new();
+        config.proxy_server = self.proxy_server.clone();
+        config.proxy_bypass = self.proxy_bypass.clone();
+        config.auto_config_url = Some(self.pac_url()).filter(|url| !url.is_empty());
+        config.use_proxy = config.proxy_server.is_some() || config.auto_config_url.is_some();
+        Ok(config)
+    }
+}
+
+/// What `MockFetcher` answers for a URL
+#[derive(Clone)]
+pub enum MockResponse {
+    /// A 200 carrying this PAC file
+    Pac(String),
+    /// A 200 carrying this PAC file and these cache validators
+    Cached(String, FetchValidators),
+    /// A 304 to conditional requests
+    NotModified,
+    /// A failed request of this kind
+    Fail(FetchErrorKind),
+}
+
+/// Serves canned responses instead of fetching from the network
+/// URLs without a response get a 404
+#[derive(Default)]
+pub struct MockFetcher {
+    responses: Mutex<HashMap<String, MockResponse>>,
+    delays: Mutex<HashMap<String, Duration>>,
+    requests: Mutex<Vec<String>>,
+    in_flight: AtomicUsize,
+    most_in_flight: AtomicUsize,
+}
+
+impl MockFetcher {
+    /// Answers requests for `url` with `response` from now on
+    pub fn respond(&self, url: &str, response: MockResponse) {
+        self.responses.lock().unwrap().insert(url.to_string(), response);
+    }
+
+    /// Holds back the answer for `url` by `delay`, so fetches started together finish out of order
+    pub fn delay(&self, url: &str, delay: Duration) {
+        self.delays.lock().unwrap().insert(url.to_string(), delay);
+    }
+
+    /// The URLs fetched so far, in the order they were requested
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The most requests that were in flight at the same time
+    pub fn most_in_flight(&self) -> usize {
+        self.most_in_flight.load(Ordering::SeqCst)
+    }
+
+    async fn answer(&self, url: &str) -> Result<ConditionalFetch> {
+        self.requests.lock().unwrap().push(url.to_string());
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let delay = self.delays.lock().unwrap().get(url).copied();
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.response(url)
+    }
+
+    fn response(&self, url: &str) -> Result<ConditionalFetch> {
+        let fail = |kind, status| Err(ProxyCatError::Fetch(FetchError {
+            url: url.to_string(),
+            kind,
+            status,
+            detail: "mock".to_string(),
+        }));
+        match self.responses.lock().unwrap().get(url).cloned() {
+            Some(MockResponse::Pac(content)) => Ok(ConditionalFetch::Modified { content, validators: FetchValidators::default() }),
+            Some(MockResponse::Cached(content, validators)) => Ok(ConditionalFetch::Modified { content, validators }),
+            Some(MockResponse::NotModified) => Ok(ConditionalFetch::NotModified),
+            Some(MockResponse::Fail(kind)) => fail(kind, None),
+            None => fail(FetchErrorKind::HttpStatus, Some(404)),
+        }
+    }
+}
+
+impl PacFetcher for MockFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        match self.answer(url).await? {
+            ConditionalFetch::Modified { content, .. } => Ok(content),
+            ConditionalFetch::NotModified => Err(ProxyCatError::Pac(format!("{} answered 304 to a plain request", url))),
+        }
+    }
+
+    async fn fetch_conditional(&self, url: &str, _validators: &FetchValidators) -> Result<ConditionalFetch> {
+        self.answer(url).await
+    }
+}
+
+/// A PAC file whose `FindProxyForURL` returns `result`
+pub fn pac_file(result: &str) -> String {
+    format!("function FindProxyForURL(url, host) {{\n    return \"{}\";\n}}\n", result)
+}
+
+/// Keeps credentials in memory instead of the Windows Credential Manager
+#[derive(Default)]
+pub struct MemoryCredentials {
+    credentials: Mutex<HashMap<String, ProxyCredential>>,
+}
+
+impl CredentialStore for MemoryCredentials {
+    fn list(&self) -> Result<Vec<CredentialSummary>> {
+        let mut summaries: Vec<CredentialSummary> = self.credentials.lock().unwrap()
+            .iter()
+            .map(|(key, credential)| CredentialSummary {
+                key: key.clone(),
+                username: credential.username.clone(),
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(summaries)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<ProxyCredential>> {
+        validate_credential_key(key)?;
+        Ok(self.credentials.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, credential: &ProxyCredential) -> Result<()> {
+        validate_credential_key(key)?;
+        self.credentials.lock().unwrap().insert(key.to_string(), credential.clone());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<bool> {
+        validate_credential_key(key)?;
+        Ok(self.credentials.lock().unwrap().remove(key).is_some())
+    }
+}
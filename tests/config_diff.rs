@@ -0,0 +1,42 @@
+//! /config/diff compares the configuration on disk with the one in memory
+
+mod common;
+
+use common::{rule, temp_data_dir};
+use proxycat::pac::{ConfigDiff, PacConfig};
+
+#[test]
+fn unsaved_mutation_shows_up() {
+    let (_guard, _dir) = temp_data_dir("config_diff");
+    let mut live = PacConfig::default();
+    live.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    live.save_current().unwrap();
+
+    // Edit the live rule and add another for the same host that only covers https
+    live.proxy_rules[0].note = Some("Changed after saving".to_string());
+    let mut https = rule("example.com", "secure-proxy.corp", 8443);
+    https.rule.scheme = Some("https".to_string());
+    live.proxy_rules.push_back(https);
+
+    let diff = ConfigDiff::between(&PacConfig::load_current().unwrap(), &live);
+    assert!(diff.has_changes);
+    assert_eq!(diff.proxy_rules.modified.len(), 1);
+    assert_eq!(diff.proxy_rules.modified[0].live.note.as_deref(), Some("Changed after saving"));
+    assert_eq!(diff.proxy_rules.added.len(), 1);
+    assert_eq!(diff.proxy_rules.added[0].rule.proxy_host, "secure-proxy.corp");
+    assert!(diff.proxy_rules.removed.is_empty());
+}
+
+#[test]
+fn retargeted_rule_is_removed_and_added() {
+    let mut saved = PacConfig::default();
+    saved.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    let mut live = saved.clone();
+    live.proxy_rules[0].rule.proxy_port = 3128;
+
+    let diff = ConfigDiff::between(&saved, &live);
+    assert_eq!(diff.proxy_rules.removed[0].rule.proxy_port, 8080);
+    assert_eq!(diff.proxy_rules.added[0].rule.proxy_port, 3128);
+    assert!(diff.proxy_rules.modified.is_empty());
+    assert!(!ConfigDiff::between(&saved, &saved).has_changes);
+}
@@ -0,0 +1,78 @@
+//! Loading a configuration file parses it as it is read rather than from a string
+
+mod common;
+
+use std::path::PathBuf;
+use common::{bypass, external, rule};
+use proxycat::pac::PacConfig;
+
+/// A configuration of several MB, like one that has collected many WPAD imports
+fn large_config() -> PacConfig {
+    let mut config = PacConfig::default();
+    for i in 0..5_000 {
+        let mut item = rule(&format!("host{}.example.com", i), &format!("proxy{}.corp", i % 7), 8000 + (i % 100) as u16);
+        item.rule.https_only = i % 3 == 0;
+        item.enabled = i % 5 != 0;
+        item.note = (i % 10 == 0).then(|| format!("Rule number {}", i));
+        config.proxy_rules.push_back(item);
+        config.bypass_list.push_back(bypass(&format!("bypass{}.example.org", i)));
+    }
+    let body = "if (shExpMatch(host, \"*.wpad.corp\")) return \"PROXY wpad-proxy:3128\";\n".repeat(200);
+    for i in 0..200 {
+        config.external_pac_functions.push_back(external(
+            &format!("http://wpad{}.corp/wpad.dat", i),
+            &format!("FindProxyForURL_http___wpad{}_corp_wpad_dat", i),
+            &format!("{}return \"\";", body),
+        ));
+    }
+    config
+}
+
+#[test]
+fn large_config_loads_from_file() {
+    let config = large_config();
+    let path: PathBuf = std::env::temp_dir().join(format!("proxycat_large_config_{}.json", std::process::id()));
+    config.save_to_file(&path).unwrap();
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(size > 4_000_000, "expected a config of several MB, got {} bytes", size);
+
+    let loaded = PacConfig::load_from_file(&path);
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded, config);
+    // The reader-based path matches parsing the whole file as a string
+    let mut from_string: PacConfig = serde_json::from_str(&content).unwrap();
+    from_string.ensure_system_bypass();
+    assert_eq!(loaded, from_string);
+}
+
+#[test]
+fn truncated_file_is_an_error() {
+    let json = serde_json::to_string(&large_config()).unwrap();
+    let truncated = &json[..json.len() / 2];
+    assert!(PacConfig::load_from_reader(truncated.as_bytes()).is_err());
+}
+
+#[test]
+fn a_null_list_keeps_the_other_lists() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    config.external_pac_functions.push_back(external("http://pac.corp/team.pac", "FindProxyForURL_team", "return \"\";"));
+
+    for list in ["proxy_rules", "bypass_list", "external_pac_functions"] {
+        let mut json = serde_json::to_value(&config).unwrap();
+        json[list] = serde_json::Value::Null;
+        let loaded = PacConfig::load_from_reader(json.to_string().as_bytes())
+            .unwrap_or_else(|e| panic!("null {} should load: {}", list, e));
+
+        assert_eq!(loaded.proxy_rules.is_empty(), list == "proxy_rules");
+        assert_eq!(loaded.external_pac_functions.is_empty(), list == "external_pac_functions");
+        let has_intranet = loaded.bypass_list.iter().any(|item| item.host == "intranet.corp");
+        assert_eq!(has_intranet, list != "bypass_list");
+        // The loopback defaults come back even when the bypass list was null
+        assert!(loaded.bypass_list.iter().any(|item| item.system));
+    }
+}
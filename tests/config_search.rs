@@ -0,0 +1,41 @@
+//! Searching one list of the configuration
+
+mod common;
+
+use common::{bypass, rule};
+use proxycat::pac::PacConfig;
+
+fn config() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("api.example.com", "proxy.corp", 8080));
+    config.proxy_rules.push_back(rule("shop.example.com", "socks.corp", 1080));
+    config.proxy_rules.push_back(rule("intranet.corp", "proxy.corp", 3128));
+    config.proxy_rules[1].enabled = false;
+    config.proxy_rules[2].note = Some("Legacy EXAMPLE mirror".to_string());
+    config.bypass_list.push_back(bypass("build.example.com"));
+    config
+}
+
+fn indices(config: &PacConfig, list: &str, query: &str, enabled: Option<bool>) -> Vec<usize> {
+    config.search(list, query, enabled).unwrap().iter().map(|hit| hit.index).collect()
+}
+
+#[test]
+fn substrings_match_hosts_proxies_and_notes_ignoring_case() {
+    let config = config();
+    assert_eq!(indices(&config, "proxyRules", "Example", None), [0, 1, 2]);
+    assert_eq!(indices(&config, "proxyRules", "proxy.corp:80", None), [0]);
+    assert_eq!(indices(&config, "proxyRules", "", None), [0, 1, 2]);
+    assert!(indices(&config, "proxyRules", "nowhere", None).is_empty());
+
+    let hits = config.search("bypassList", "build", None).unwrap();
+    assert_eq!(hits[0].item["host"], "build.example.com");
+}
+
+#[test]
+fn enabled_narrows_the_hits() {
+    let config = config();
+    assert_eq!(indices(&config, "proxyRules", "example", Some(true)), [0, 2]);
+    assert_eq!(indices(&config, "proxyRules", "example", Some(false)), [1]);
+    assert!(config.search("unknownList", "", None).is_err());
+}
@@ -0,0 +1,38 @@
+//! Proxy rules reference credentials by key; the secrets stay in the credential store
+
+mod common;
+
+use proxycat::credentials::validate_credential_key;
+use proxycat::pac::{PacConfig, ProxyRuleItem, ProxyType};
+
+fn socks_rule(credential_key: Option<&str>) -> ProxyRuleItem {
+    let mut item = common::rule(".lab.corp", "socks.corp", 1080);
+    item.rule.proxy_type = ProxyType::Socks;
+    item.rule.credential_key = credential_key.map(str::to_string);
+    item
+}
+
+#[test]
+fn credential_key_round_trips_through_the_config() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(socks_rule(Some("lab-socks")));
+
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains(r#""credential_key":"lab-socks""#));
+    let parsed: PacConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.proxy_rules[0].rule.credential_key.as_deref(), Some("lab-socks"));
+}
+
+#[test]
+fn rules_without_a_key_omit_it() {
+    let json = serde_json::to_string(&socks_rule(None)).unwrap();
+    assert!(!json.contains("credential_key"));
+}
+
+#[test]
+fn invalid_keys_are_rejected() {
+    assert!(socks_rule(Some("has space")).validate().is_err());
+    assert!(socks_rule(Some("")).validate().is_err());
+    assert!(validate_credential_key("ProxyCat:nested").is_err());
+    assert!(validate_credential_key("corp.proxy_1-a").is_ok());
+}
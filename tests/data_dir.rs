@@ -0,0 +1,38 @@
+//! Resolving ProxyCat's files against the data directory
+
+mod common;
+
+use common::{rule, temp_data_dir};
+use proxycat::constants::APP_CONFIG;
+use proxycat::pac::{PacConfig, CURRENT_CONFIG_FILE};
+
+#[test]
+fn missing_directories_are_created() {
+    let (_guard, dir) = temp_data_dir("data_dir_create");
+    let nested = dir.join("nested").join("ProxyCat");
+    assert!(!nested.exists());
+
+    APP_CONFIG.update_data_dir(nested.clone()).unwrap();
+    assert!(nested.is_dir());
+    assert_eq!(APP_CONFIG.get_data_dir().unwrap(), nested);
+}
+
+#[test]
+fn file_names_resolve_inside_the_directory_and_absolute_paths_are_kept() {
+    let (_guard, dir) = temp_data_dir("data_dir_paths");
+    assert_eq!(APP_CONFIG.data_path("profiles.json").unwrap(), dir.join("profiles.json"));
+
+    let absolute = std::env::temp_dir().join("elsewhere.json");
+    assert_eq!(APP_CONFIG.data_path(absolute.to_str().unwrap()).unwrap(), absolute);
+}
+
+#[test]
+fn the_configuration_is_saved_to_and_loaded_from_the_directory() {
+    let (_guard, dir) = temp_data_dir("data_dir_config");
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+
+    config.save_current().unwrap();
+    assert!(dir.join(CURRENT_CONFIG_FILE).is_file());
+    assert_eq!(PacConfig::load_current().unwrap(), config);
+}
@@ -0,0 +1,51 @@
+//! Exporting the configuration as proxy environment variables for CLI tools
+
+mod common;
+
+use std::collections::VecDeque;
+use common::{bypass, rule};
+use proxycat::pac::{proxy_env_script, PacConfig};
+
+fn sample_config() -> PacConfig {
+    let mut config = PacConfig { bypass_list: VecDeque::new(), ..PacConfig::default() };
+    config.proxy_rules.push_back(rule("intranet.corp", "internal.corp", 3128));
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass("localhost"));
+    config.bypass_list.push_back(bypass(".corp.example"));
+    config.bypass_list.push_back(bypass("it's.corp"));
+    config
+}
+
+#[test]
+fn cmd_lines_set_each_variable() {
+    let script = proxy_env_script(&sample_config());
+    let cmd: Vec<&str> = script.split("\r\n").skip(1).take_while(|line| !line.is_empty()).collect();
+    assert_eq!(cmd, [
+        "set HTTP_PROXY=http://proxy.corp:8080",
+        "set HTTPS_PROXY=http://proxy.corp:8080",
+        "set NO_PROXY=localhost,.corp.example,it's.corp",
+    ]);
+}
+
+#[test]
+fn powershell_lines_quote_each_value() {
+    let script = proxy_env_script(&sample_config());
+    let powershell: Vec<&str> = script.split("\r\n")
+        .skip_while(|line| *line != "# PowerShell")
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .collect();
+    assert_eq!(powershell, [
+        "$env:HTTP_PROXY='http://proxy.corp:8080'",
+        "$env:HTTPS_PROXY='http://proxy.corp:8080'",
+        "$env:NO_PROXY='localhost,.corp.example,it''s.corp'",
+    ]);
+}
+
+#[test]
+fn nothing_is_exported_without_a_catch_all_or_bypass() {
+    let mut config = sample_config();
+    config.proxy_rules.retain(|item| item.rule.host != "*");
+    config.bypass_list.clear();
+    assert_eq!(proxy_env_script(&config), "REM cmd\r\n\r\n# PowerShell\r\n");
+}
@@ -0,0 +1,45 @@
+//! Evaluating a URL with one external function left out, as /evaluate/without/:index does
+
+mod common;
+
+use common::{external, rule};
+use proxycat::pac::PacConfig;
+use proxycat::pac_eval::evaluate_without_external;
+
+/// External functions run before the proxy rules, so the partner PAC decides partner.com
+fn config() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule(".partner.com", "proxy.corp", 8080));
+    config.external_pac_functions.push_back(external(
+        "http://pac.partner.com/proxy.pac",
+        "FindProxyForURL_http___pac_partner_com_proxy_pac",
+        "if (dnsDomainIs(host, \".partner.com\")) return \"PROXY partner-proxy:9000\";\n    return \"\";",
+    ));
+    config
+}
+
+#[test]
+fn excluding_the_deciding_function_changes_the_decision() {
+    let config = config();
+    let evaluation = evaluate_without_external(&config, 0, "https://www.partner.com/").unwrap();
+
+    assert_eq!(evaluation.function_name, "FindProxyForURL_http___pac_partner_com_proxy_pac");
+    assert_eq!(evaluation.current.result, "PROXY partner-proxy:9000");
+    assert_eq!(evaluation.without.result, "PROXY proxy.corp:8080");
+    assert!(evaluation.changed);
+    // The live configuration is left alone
+    assert!(config.external_pac_functions[0].enabled);
+}
+
+#[test]
+fn unrelated_urls_are_unchanged() {
+    let evaluation = evaluate_without_external(&config(), 0, "https://example.com/").unwrap();
+    assert_eq!(evaluation.current.result, "DIRECT");
+    assert_eq!(evaluation.without.result, "DIRECT");
+    assert!(!evaluation.changed);
+}
+
+#[test]
+fn unknown_index_is_an_error() {
+    assert!(evaluate_without_external(&config(), 1, "https://www.partner.com/").is_err());
+}
@@ -0,0 +1,47 @@
+//! What happens to the system auto-config URL when ProxyCat exits
+
+mod common;
+
+use common::MockProxy;
+use proxycat::proxy_config::{apply_exit_policy, ExitPolicy};
+
+const PAC_URL: &str = "http://127.0.0.1:12112/master.pac";
+const PREVIOUS: &str = "http://pac.corp/proxy.pac";
+
+#[test]
+fn restore_puts_back_the_previous_url() {
+    let proxy = MockProxy::pointing_at(PAC_URL);
+    apply_exit_policy(&proxy, ExitPolicy::Restore, PAC_URL, Some(PREVIOUS)).unwrap();
+    assert_eq!(proxy.pac_url(), PREVIOUS);
+}
+
+#[test]
+fn restore_clears_when_nothing_was_set_before() {
+    for previous in [None, Some(""), Some(PAC_URL)] {
+        let proxy = MockProxy::pointing_at(PAC_URL);
+        apply_exit_policy(&proxy, ExitPolicy::Restore, PAC_URL, previous).unwrap();
+        assert_eq!(proxy.pac_url(), "", "previous URL {:?}", previous);
+    }
+}
+
+#[test]
+fn clear_and_leave() {
+    let proxy = MockProxy::pointing_at(PAC_URL);
+    apply_exit_policy(&proxy, ExitPolicy::Clear, PAC_URL, Some(PREVIOUS)).unwrap();
+    assert_eq!(proxy.pac_url(), "");
+
+    let proxy = MockProxy::pointing_at(PAC_URL);
+    apply_exit_policy(&proxy, ExitPolicy::Leave, PAC_URL, Some(PREVIOUS)).unwrap();
+    assert_eq!(proxy.pac_url(), PAC_URL);
+}
+
+#[test]
+fn a_url_set_by_someone_else_is_left_alone() {
+    for policy in [ExitPolicy::Restore, ExitPolicy::Clear] {
+        let proxy = MockProxy::pointing_at("http://other.corp/other.pac");
+        apply_exit_policy(&proxy, policy, PAC_URL, Some(PREVIOUS)).unwrap();
+        assert_eq!(proxy.pac_url(), "http://other.corp/other.pac");
+    }
+    let unreadable = MockProxy { fail_reads: true, ..MockProxy::default() };
+    assert!(apply_exit_policy(&unreadable, ExitPolicy::Clear, PAC_URL, None).is_err());
+}
@@ -0,0 +1,50 @@
+//! Names of the variables holding the external PAC function results
+
+mod common;
+
+use common::external;
+use proxycat::pac::{generate_pac_content, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+#[test]
+fn prefix_sharing_function_names_get_distinct_result_variables() {
+    let mut config = PacConfig::default();
+    config.external_pac_functions.push_back(external(
+        "http://wpad/",
+        "FindProxyForURL_http___wpad",
+        "return \"\";",
+    ));
+    config.external_pac_functions.push_back(external(
+        "http://wpad/wpad.dat",
+        "FindProxyForURL_http___wpad_wpad_dat",
+        "return \"PROXY wpad.corp:3128\";",
+    ));
+
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains("const result_0 = FindProxyForURL_http___wpad(url, host);"), "{}", pac);
+    assert!(pac.contains("const result_1 = FindProxyForURL_http___wpad_wpad_dat(url, host);"), "{}", pac);
+    assert!(!pac.contains("resultFindProxyForURL"), "{}", pac);
+
+    // The script only evaluates if both declarations are valid and distinct
+    let decision = evaluate_pac(&pac, "http://example.com/").unwrap();
+    assert_eq!(decision.result, "PROXY wpad.corp:3128");
+}
+
+#[test]
+fn own_helpers_are_namespaced_apart_from_imported_ones() {
+    let mut config = PacConfig::default();
+    let mut imported = external("http://pac.corp/team.pac", "FindProxyForURL_team", "");
+    // An imported PAC with its own, differently behaving, isEmptyStringSafe
+    imported.function.function_text = "function isEmptyStringSafe(str) {\n    return true;\n}\n\
+        function FindProxyForURL_team(url, host) {\n    \
+        if (isEmptyStringSafe(host)) return \"PROXY team.corp:3128\";\n    return \"\";\n}\n".to_string();
+    config.external_pac_functions.push_back(imported);
+
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains("function __pc_isEmptyStringSafe(str)"), "{}", pac);
+    assert!(pac.contains("if (!__pc_isEmptyStringSafe(result_0)) return result_0;"), "{}", pac);
+    let unprefixed = pac.matches("isEmptyStringSafe(").count() - pac.matches("__pc_isEmptyStringSafe(").count();
+    assert_eq!(unprefixed, 2, "only the imported definition and call are unprefixed:\n{}", pac);
+
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY team.corp:3128");
+}
@@ -0,0 +1,45 @@
+//! Which external PAC functions the generated PAC calls: duplicates once, and no more than the cap
+
+mod common;
+
+use common::external;
+use proxycat::pac::{generate_pac_content, ExternalPacFunctionItem, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+/// A function named after `url` that sends everything to `proxy`
+fn team_pac(url: &str, proxy: &str) -> ExternalPacFunctionItem {
+    let name = format!("FindProxyForURL_{}", url.replace(|c: char| !c.is_alphanumeric(), "_"));
+    external(url, &name, &format!("return \"PROXY {}\";", proxy))
+}
+
+fn calls(pac: &str) -> usize {
+    pac.matches("// Try external PAC function from").count()
+}
+
+#[test]
+fn identical_functions_are_called_once() {
+    let mut config = PacConfig::default();
+    config.external_pac_functions.push_back(team_pac("http://pac.corp/team.pac", "team.corp:3128"));
+    config.external_pac_functions.push_back(team_pac("http://mirror.corp/team.pac", "team.corp:3128"));
+
+    let pac = generate_pac_content(&config);
+    assert_eq!(calls(&pac), 1);
+    assert!(pac.contains("// Skipped external PAC function from http://mirror.corp/team.pac: same body as"));
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY team.corp:3128");
+}
+
+#[test]
+fn functions_past_the_cap_are_left_out_with_a_warning() {
+    let mut config = PacConfig { max_external_functions: 2, ..PacConfig::default() };
+    for n in 0..3 {
+        config.external_pac_functions.push_back(team_pac(&format!("http://pac.corp/team{n}.pac"), &format!("team{n}.corp:3128")));
+    }
+
+    let pac = generate_pac_content(&config);
+    assert_eq!(calls(&pac), 2);
+    assert!(pac.contains("// Skipped external PAC function from http://pac.corp/team2.pac: over the limit of 2 external functions"));
+    assert!(!pac.contains("function FindProxyForURL_http___pac_corp_team2_pac"), "a skipped function isn't inlined");
+
+    config.max_external_functions = 0;
+    assert_eq!(calls(&generate_pac_content(&config)), 3, "0 means no limit");
+}
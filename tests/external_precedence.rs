@@ -0,0 +1,39 @@
+//! Whether external PAC functions or the local proxy rules get the first say
+
+mod common;
+
+use common::{external, rule};
+use proxycat::pac::{generate_pac_content, ExternalPacPrecedence, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+/// A rule and an external function that both claim every host, with different proxies
+fn config(precedence: ExternalPacPrecedence) -> PacConfig {
+    let mut config = PacConfig { external_pac_precedence: precedence, ..PacConfig::default() };
+    config.proxy_rules.push_back(rule("*", "local.corp", 8080));
+    config.external_pac_functions.push_back(external(
+        "http://pac.corp/team.pac",
+        "FindProxyForURL_http___pac_corp_team_pac",
+        "return \"PROXY team.corp:3128\";",
+    ));
+    config
+}
+
+fn externals_first(pac: &str) -> bool {
+    pac.find("// Try external PAC functions").unwrap() < pac.find("// Proxy rules").unwrap()
+}
+
+#[test]
+fn external_functions_run_first_by_default() {
+    let config = config(ExternalPacPrecedence::default());
+    let pac = generate_pac_content(&config);
+    assert!(externals_first(&pac));
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY team.corp:3128");
+}
+
+#[test]
+fn after_lets_the_local_rules_decide_first() {
+    let config = config(ExternalPacPrecedence::After);
+    let pac = generate_pac_content(&config);
+    assert!(!externals_first(&pac));
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY local.corp:8080");
+}
@@ -0,0 +1,126 @@
+//! Refreshing external PAC sources: what gets recorded and when it is saved
+
+mod common;
+
+use std::sync::Arc;
+use common::{pac_file, temp_data_dir_async, MockFetcher, MockResponse};
+use proxycat::error::FetchErrorKind;
+use proxycat::fetcher::FetchValidators;
+use proxycat::pac::{refresh_external_functions, PacConfig, SharedPacConfig};
+use tokio::sync::RwLock;
+
+const URL: &str = "http://pac.corp/proxy.pac";
+
+async fn loaded(fetcher: &MockFetcher, threshold: u32) -> SharedPacConfig {
+    fetcher.respond(URL, MockResponse::Pac(pac_file("PROXY corp:8080")));
+    let mut config = PacConfig { external_failure_threshold: threshold, ..PacConfig::default() };
+    assert!(config.load_external_pac(fetcher, URL).await.unwrap());
+    Arc::new(RwLock::new(config))
+}
+
+#[tokio::test]
+async fn only_changes_are_saved() {
+    let (_guard, _dir) = temp_data_dir_async("refresh_saves").await;
+    let fetcher = MockFetcher::default();
+    let config = loaded(&fetcher, 3).await;
+    let saved_at = PacConfig::last_saved_at();
+
+    fetcher.respond(URL, MockResponse::NotModified);
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert_eq!(PacConfig::last_saved_at(), saved_at, "a 304 is not a change");
+
+    fetcher.respond(URL, MockResponse::Pac(pac_file("PROXY corp:8080")));
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert_eq!(PacConfig::last_saved_at(), saved_at, "the same content is not a change");
+
+    fetcher.respond(URL, MockResponse::Pac(pac_file("PROXY other:3128")));
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert_ne!(PacConfig::last_saved_at(), saved_at);
+    let config = config.read().await;
+    assert!(config.external_pac_functions[0].function.function_text.contains("other:3128"));
+}
+
+#[tokio::test]
+async fn failing_source_is_disabled_then_re_enabled() {
+    let (_guard, _dir) = temp_data_dir_async("refresh_disable").await;
+    let fetcher = MockFetcher::default();
+    let config = loaded(&fetcher, 2).await;
+
+    fetcher.respond(URL, MockResponse::Fail(FetchErrorKind::Connect));
+    refresh_external_functions(&config, &fetcher, 4).await;
+    {
+        let config = config.read().await;
+        let item = &config.external_pac_functions[0];
+        assert!(item.enabled, "one failure is below the threshold");
+        assert_eq!(item.health.consecutive_failures, 1);
+    }
+
+    let saved_at = PacConfig::last_saved_at();
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert_ne!(PacConfig::last_saved_at(), saved_at);
+    {
+        let config = config.read().await;
+        let item = &config.external_pac_functions[0];
+        assert!(!item.enabled);
+        assert!(item.health.auto_disabled);
+        assert!(!PacConfig::load_current().unwrap().external_pac_functions[0].enabled);
+    }
+
+    fetcher.respond(URL, MockResponse::NotModified);
+    refresh_external_functions(&config, &fetcher, 4).await;
+    let config = config.read().await;
+    let item = &config.external_pac_functions[0];
+    assert!(item.enabled);
+    assert!(!item.health.auto_disabled);
+    assert_eq!(item.health.consecutive_failures, 0);
+    assert!(PacConfig::load_current().unwrap().external_pac_functions[0].enabled);
+}
+
+#[tokio::test]
+async fn the_first_load_keeps_the_validators_from_the_server() {
+    let (_guard, _dir) = temp_data_dir_async("load_validators").await;
+    let fetcher = MockFetcher::default();
+    let validators = FetchValidators {
+        etag: Some("\"v1\"".to_string()),
+        last_modified: Some("Wed, 14 Oct 2026 08:00:00 GMT".to_string()),
+    };
+    fetcher.respond(URL, MockResponse::Cached(pac_file("PROXY corp:8080"), validators.clone()));
+
+    let mut config = PacConfig::default();
+    assert!(config.load_external_pac(&fetcher, URL).await.unwrap());
+    assert_eq!(config.external_pac_functions[0].validators, validators);
+    assert_eq!(PacConfig::load_current().unwrap().external_pac_functions[0].validators, validators);
+}
+
+#[tokio::test]
+async fn windows_line_endings_are_normalized_on_load() {
+    let (_guard, _dir) = temp_data_dir_async("load_crlf").await;
+    let fetcher = MockFetcher::default();
+    let crlf = "function FindProxyForURL(url, host) {\r\n    return \"PROXY corp:8080\";\r\n}";
+    fetcher.respond(URL, MockResponse::Pac(crlf.to_string()));
+
+    let mut config = PacConfig::default();
+    assert!(config.load_external_pac(&fetcher, URL).await.unwrap());
+    let text = &config.external_pac_functions[0].function.function_text;
+    assert!(!text.contains('\r'), "{:?}", text);
+    assert!(text.contains("{\n    return \"PROXY corp:8080\";\n}"));
+    assert!(text.ends_with('\n'));
+}
+
+#[tokio::test]
+async fn pinned_functions_keep_their_content() {
+    let (_guard, _dir) = temp_data_dir_async("refresh_pinned").await;
+    let fetcher = MockFetcher::default();
+    let config = loaded(&fetcher, 3).await;
+    config.write().await.external_pac_functions[0].pinned = true;
+    let requests = fetcher.requests().len();
+
+    fetcher.respond(URL, MockResponse::Pac(pac_file("PROXY other:3128")));
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert_eq!(fetcher.requests().len(), requests, "a pinned source isn't fetched");
+    assert!(config.read().await.external_pac_functions[0].function.function_text.contains("corp:8080"));
+
+    config.write().await.external_pac_functions[0].pinned = false;
+    refresh_external_functions(&config, &fetcher, 4).await;
+    assert!(config.read().await.external_pac_functions[0].function.function_text.contains("other:3128"));
+}
@@ -0,0 +1,75 @@
+//! Classifying failed PAC fetches by the stage that failed
+
+use std::time::Duration;
+use proxycat::error::{FetchErrorKind, ProxyCatError};
+use proxycat::fetcher::{classify_request_error, PacFetcher, ReqwestFetcher};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Serves `response` verbatim to every connection, then closes it
+async fn raw_server(response: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}/proxy.pac", address)
+}
+
+async fn fetch_failure(url: &str) -> (FetchErrorKind, Option<u16>, String) {
+    match ReqwestFetcher::new().fetch(url).await {
+        Err(ProxyCatError::Fetch(e)) => (e.kind, e.status, e.to_string()),
+        other => panic!("expected a fetch error from {}, got {:?}", url, other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn refused_connection_is_a_connect_failure() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/proxy.pac", listener.local_addr().unwrap());
+    drop(listener);
+
+    let (kind, status, message) = fetch_failure(&url).await;
+    assert_eq!(kind, FetchErrorKind::Connect);
+    assert_eq!(status, None);
+    assert!(message.starts_with("Could not connect to"), "{}", message);
+}
+
+#[tokio::test]
+async fn error_status_is_an_http_status_failure() {
+    let url = raw_server("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+
+    let (kind, status, message) = fetch_failure(&url).await;
+    assert_eq!(kind, FetchErrorKind::HttpStatus);
+    assert_eq!(status, Some(403));
+    assert_eq!(message, format!("{} returned HTTP 403", url));
+}
+
+#[tokio::test]
+async fn truncated_body_is_a_body_failure() {
+    let url = raw_server("HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nfunction").await;
+
+    let (kind, _, message) = fetch_failure(&url).await;
+    assert_eq!(kind, FetchErrorKind::Body);
+    assert!(message.starts_with("Failed to read PAC file content"), "{}", message);
+}
+
+#[tokio::test]
+async fn slow_server_is_a_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/proxy.pac", listener.local_addr().unwrap());
+    // Accept the connection but never answer
+    let server = tokio::spawn(async move {
+        let (_socket, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    });
+
+    let client = reqwest::Client::builder().timeout(Duration::from_millis(100)).build().unwrap();
+    let error = client.get(&url).send().await.unwrap_err();
+    assert_eq!(classify_request_error(&error), FetchErrorKind::Timeout);
+    server.abort();
+}
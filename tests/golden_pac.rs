@@ -0,0 +1,118 @@
+//! Golden-file tests for the generated PAC
+//! Each fixture's output is compared with tests/golden/<name>.pac, ignoring indentation
+//! and blank lines. Run with UPDATE_GOLDEN=1 to rewrite the files after an intended change
+
+mod common;
+
+use std::path::PathBuf;
+use common::{bypass, external, rule};
+use proxycat::pac::{generate_pac_content, PacConfig, ProxyType};
+use proxycat::pac_eval::evaluate_pac;
+
+fn empty() -> PacConfig {
+    PacConfig::default()
+}
+
+fn one_rule() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    config
+}
+
+fn wildcard() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule(".internal.corp", "proxy.corp", 3128));
+    config
+}
+
+fn external_function() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.external_pac_functions.push_back(external(
+        "http://pac.corp/proxy.pac",
+        "FindProxyForURL_http___pac_corp_proxy_pac",
+        "if (dnsDomainIs(host, \".partner.com\")) return \"PROXY partner-proxy:9000\";\n    return \"\";",
+    ));
+    config
+}
+
+fn mixed() -> PacConfig {
+    let mut config = external_function();
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    let mut socks = rule(".lab.corp", "socks.corp", 1080);
+    socks.rule.proxy_type = ProxyType::Socks;
+    socks.note = Some("Lab traffic goes through SOCKS".to_string());
+    config.proxy_rules.push_back(socks);
+    let mut disabled = rule("old.example.com", "legacy.corp", 80);
+    disabled.enabled = false;
+    config.proxy_rules.push_back(disabled);
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    config
+}
+
+/// Drops the version/timestamp header, indentation and blank lines
+fn normalize(pac: &str) -> String {
+    pac.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("// ProxyCat v"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn check_golden(name: &str, config: &PacConfig) -> String {
+    let pac = generate_pac_content(config);
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.pac", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, normalize(&pac) + "\n").unwrap();
+    } else {
+        let golden = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Missing golden file {}: {}; run with UPDATE_GOLDEN=1", path.display(), e));
+        assert_eq!(normalize(&pac), normalize(&golden), "{} differs from {}", name, path.display());
+    }
+    pac
+}
+
+fn decide(pac: &str, url: &str) -> String {
+    evaluate_pac(pac, url).unwrap().result
+}
+
+#[test]
+fn empty_config() {
+    let pac = check_golden("empty", &empty());
+    assert_eq!(decide(&pac, "http://example.com/"), "DIRECT");
+}
+
+#[test]
+fn single_proxy_rule() {
+    let pac = check_golden("one_rule", &one_rule());
+    assert_eq!(decide(&pac, "http://example.com/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&pac, "http://example.org/"), "DIRECT");
+}
+
+#[test]
+fn wildcard_rule() {
+    let pac = check_golden("wildcard", &wildcard());
+    assert_eq!(decide(&pac, "http://git.internal.corp/"), "PROXY proxy.corp:3128");
+    assert_eq!(decide(&pac, "http://internal.corp.example/"), "DIRECT");
+    assert_eq!(pac.matches("host === 'localhost'").count(), 1, "loopback is checked once:\n{}", pac);
+    assert_eq!(decide(&pac, "http://localhost:12112/"), "DIRECT");
+}
+
+#[test]
+fn external_function_merge() {
+    let pac = check_golden("external_function", &external_function());
+    assert_eq!(decide(&pac, "https://api.partner.com/"), "PROXY partner-proxy:9000");
+    assert_eq!(decide(&pac, "https://example.com/"), "DIRECT");
+}
+
+#[test]
+fn mixed_config() {
+    let pac = check_golden("mixed", &mixed());
+    assert_eq!(decide(&pac, "http://example.com/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&pac, "http://build.lab.corp/"), "SOCKS socks.corp:1080");
+    assert_eq!(decide(&pac, "http://old.example.com/"), "DIRECT");
+    assert_eq!(decide(&pac, "http://intranet.corp/"), "DIRECT");
+    assert_eq!(decide(&pac, "http://www.partner.com/"), "PROXY partner-proxy:9000");
+    assert_eq!(decide(&pac, "http://localhost:8080/"), "DIRECT");
+}
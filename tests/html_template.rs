@@ -0,0 +1,25 @@
+//! The web interface must call the API on whatever host served it, not on the client's loopback
+
+/// The template is part of the binary, so its source is checked directly
+const TEMPLATE_SOURCE: &str = include_str!("../src/html_template.rs");
+
+#[test]
+fn template_has_no_loopback_fetch_targets() {
+    for (number, line) in TEMPLATE_SOURCE.lines().enumerate() {
+        if line.contains("fetch(") || line.contains("WebSocket(") {
+            assert!(
+                !line.contains("127.0.0.1") && !line.contains("localhost"),
+                "line {} of html_template.rs calls a loopback address: {}", number + 1, line.trim()
+            );
+        }
+    }
+}
+
+#[test]
+fn template_fetches_use_relative_urls() {
+    let absolute: Vec<&str> = TEMPLATE_SOURCE.lines()
+        .filter(|line| line.contains("fetch(`http") || line.contains("fetch(\"http") || line.contains("fetch('http"))
+        .map(str::trim)
+        .collect();
+    assert!(absolute.is_empty(), "absolute fetch URLs in html_template.rs: {:?}", absolute);
+}
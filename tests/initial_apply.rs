@@ -0,0 +1,37 @@
+//! Retrying the startup apply of the PAC URL when Windows refuses it
+
+mod common;
+
+use std::time::Duration;
+use common::MockProxy;
+use proxycat::proxy_config::{set_pac_file_with_retry, SystemProxy};
+
+const PAC_URL: &str = "http://127.0.0.1:12112/proxy.pac";
+
+#[test]
+fn transient_failure_is_retried_until_applied() {
+    let proxy = MockProxy::refusing(2);
+    let status = set_pac_file_with_retry(&proxy, PAC_URL, 5, Duration::from_millis(1));
+    assert!(status.applied);
+    assert_eq!(status.attempts, 3);
+    assert!(status.error.is_none());
+    assert_eq!(proxy.get_pac_file().unwrap(), PAC_URL);
+}
+
+#[test]
+fn persistent_failure_reports_the_last_error() {
+    let proxy = MockProxy::refusing(10);
+    let status = set_pac_file_with_retry(&proxy, PAC_URL, 3, Duration::from_millis(1));
+    assert!(!status.applied);
+    assert_eq!(status.attempts, 3);
+    assert!(status.error.unwrap().contains("Failed to set PAC file configuration"));
+    assert_eq!(proxy.get_pac_file().unwrap(), "");
+}
+
+#[test]
+fn first_success_does_not_retry() {
+    let proxy = MockProxy::refusing(0);
+    let status = set_pac_file_with_retry(&proxy, PAC_URL, 5, Duration::from_secs(60));
+    assert!(status.applied);
+    assert_eq!(status.attempts, 1);
+}
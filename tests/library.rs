@@ -0,0 +1,35 @@
+//! Using the library on its own: loading a configuration, generating its PAC and running it
+
+use proxycat::pac::{generate_pac_content, parse_proxy_server_rules, PacConfig, ProxyRuleItem};
+use proxycat::pac_eval::evaluate_pac;
+use proxycat::pac_syntax::validate_pac_text;
+
+const SAVED_CONFIG: &str = r#"{
+    "proxy_rules": [
+        { "rule": { "host": "example.com", "proxy_host": "proxy.corp", "proxy_port": 8080 }, "enabled": true }
+    ],
+    "bypass_list": [
+        { "host": "intranet.corp", "enabled": true }
+    ]
+}"#;
+
+#[test]
+fn saved_configuration_generates_a_working_pac() {
+    let config = PacConfig::load_from_reader(SAVED_CONFIG.as_bytes()).unwrap();
+    let pac = generate_pac_content(&config);
+    assert!(validate_pac_text(&pac).valid);
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY proxy.corp:8080");
+    assert_eq!(evaluate_pac(&pac, "http://intranet.corp/").unwrap().result, "DIRECT");
+    assert_eq!(evaluate_pac(&pac, "http://other.com/").unwrap().result, "DIRECT");
+}
+
+#[test]
+fn windows_proxy_setting_becomes_rules() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.extend(parse_proxy_server_rules("http=web.corp:8080;https=secure.corp:8443")
+        .into_iter()
+        .map(|rule| ProxyRuleItem { rule, enabled: true, note: None, tags: Vec::new() }));
+    let pac = generate_pac_content(&config);
+    assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "PROXY web.corp:8080");
+    assert_eq!(evaluate_pac(&pac, "https://example.com/").unwrap().result, "PROXY secure.corp:8443");
+}
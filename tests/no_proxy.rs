@@ -0,0 +1,47 @@
+//! Bypass entries imported from a NO_PROXY string
+
+mod common;
+
+use common::{bypass, rule};
+use proxycat::pac::{generate_pac_content, parse_no_proxy, BypassMatch, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+#[test]
+fn entries_are_split_trimmed_and_kept_as_written() {
+    let entries = parse_no_proxy(" localhost, .corp.example ,,10.0.0.0/8,*,intranet:8080 ");
+    let hosts: Vec<&str> = entries.iter().map(|item| item.host.as_str()).collect();
+    assert_eq!(hosts, ["localhost", ".corp.example", "10.0.0.0/8", "intranet:8080"]);
+    assert!(entries.iter().all(|item| item.enabled && !item.system));
+    assert!(parse_no_proxy("").is_empty());
+    assert!(parse_no_proxy("*").is_empty());
+}
+
+#[test]
+fn imported_entries_bypass_the_proxy() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    let added = config.merge_bypass_entries(parse_no_proxy(".corp.example,10.0.0.0/8"));
+    assert_eq!(added, 2);
+
+    let pac = generate_pac_content(&config);
+    let decide = |url| evaluate_pac(&pac, url).unwrap().result;
+    assert_eq!(decide("http://git.corp.example/"), "DIRECT");
+    assert_eq!(decide("http://10.1.2.3/"), "DIRECT");
+    assert_eq!(decide("http://11.1.2.3/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide("http://example.com/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn entries_already_listed_are_skipped_like_any_other_duplicate() {
+    let mut config = PacConfig::default();
+    config.bypass_list.clear();
+    config.bypass_list.push_back(bypass("Intranet.corp."));
+    let mut url_entry = bypass("*/healthz");
+    url_entry.match_mode = BypassMatch::Url;
+    config.bypass_list.push_back(url_entry);
+
+    let added = config.merge_bypass_entries(parse_no_proxy("intranet.corp,*/healthz"));
+    assert_eq!(added, 1, "the host entry duplicates a listed one, the URL pattern doesn't");
+    let hosts: Vec<&str> = config.bypass_list.iter().map(|item| item.host.as_str()).collect();
+    assert_eq!(hosts, ["Intranet.corp.", "*/healthz", "*/healthz"]);
+}
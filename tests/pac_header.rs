@@ -0,0 +1,36 @@
+//! The header comment naming the build and item counts at the top of the generated PAC
+
+mod common;
+
+use common::{bypass, external, rule};
+use proxycat::constants::APP_CONFIG;
+use proxycat::pac::{generate_pac_content, PacConfig};
+
+/// Minifying is a process-wide setting, so both cases are checked in one test
+#[test]
+fn header_has_version_and_counts_unless_minified() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("a.example.com", "proxy.corp", 8080));
+    config.proxy_rules.push_back(rule("b.example.com", "proxy.corp", 8080));
+    let mut disabled = rule("c.example.com", "proxy.corp", 8080);
+    disabled.enabled = false;
+    config.proxy_rules.push_back(disabled);
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    config.external_pac_functions.push_back(external("http://pac.corp/team.pac", "FindProxyForURL_team", "return \"\";"));
+    let bypass_entries = config.bypass_list.iter().filter(|item| item.enabled).count();
+
+    APP_CONFIG.update_minify_pac(false);
+    let pac = generate_pac_content(&config);
+    let header = pac.lines().next().unwrap();
+    assert!(header.starts_with(&format!("// ProxyCat v{} (", env!("CARGO_PKG_VERSION"))), "{}", header);
+    assert!(header.contains(" - generated "), "{}", header);
+    assert!(
+        header.ends_with(&format!("proxy rules: 2, bypass entries: {}, external functions: 1", bypass_entries)),
+        "{}", header
+    );
+
+    APP_CONFIG.update_minify_pac(true);
+    let minified = generate_pac_content(&config);
+    APP_CONFIG.update_minify_pac(false);
+    assert!(!minified.contains("// ProxyCat v"), "{}", minified);
+}
@@ -0,0 +1,36 @@
+//! Imported PAC functions calling the standard PAC helpers while being evaluated
+
+mod common;
+
+use common::external;
+use proxycat::pac::{generate_pac_content, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+/// A typical corporate PAC routing by domain and by address range
+fn corporate_pac() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.external_pac_functions.push_back(external(
+        "http://wpad.corp/wpad.dat",
+        "FindProxyForURL_http___wpad_corp_wpad_dat",
+        "if (dnsDomainIs(host, \".intranet.corp\")) return \"DIRECT\";\n    \
+         if (isInNet(host, \"10.0.0.0\", \"255.0.0.0\")) return \"PROXY lab.corp:3128\";\n    \
+         return \"PROXY proxy.corp:8080\";",
+    ));
+    config
+}
+
+fn decide(url: &str) -> String {
+    evaluate_pac(&generate_pac_content(&corporate_pac()), url).unwrap().result
+}
+
+#[test]
+fn dns_domain_is_matches_subdomains() {
+    assert_eq!(decide("http://wiki.intranet.corp/"), "DIRECT");
+    assert_eq!(decide("http://intranet.corp.example.com/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn is_in_net_matches_addresses_in_the_range() {
+    assert_eq!(decide("http://10.20.30.40/"), "PROXY lab.corp:3128");
+    assert_eq!(decide("http://11.20.30.40/"), "PROXY proxy.corp:8080");
+}
@@ -0,0 +1,43 @@
+//! Importing PAC files whose FindProxyForURL calls helpers defined next to it
+
+mod common;
+
+use common::{temp_data_dir_async, MockFetcher, MockResponse};
+use proxycat::pac::{generate_pac_content, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+/// A PAC whose FindProxyForURL depends on a local helper and variable, both named
+/// the same in every file so two imports would collide if they shared a scope
+fn pac_with_helper(suffix: &str, proxy: &str) -> String {
+    format!(
+        "// Generated by the network team\n\
+         var PROXY = \"PROXY {proxy}\";\n\
+         \n\
+         function isOurs(host) {{\n    return dnsDomainIs(host, \"{suffix}\");\n}}\n\
+         \n\
+         function FindProxyForURL(url, host) {{\n    if (isOurs(host)) return PROXY;\n    return \"\";\n}}\n"
+    )
+}
+
+#[tokio::test]
+async fn local_helpers_are_merged_and_stay_private_to_their_pac() {
+    let (_guard, _dir) = temp_data_dir_async("pac_import_helpers").await;
+    let fetcher = MockFetcher::default();
+    let mut config = PacConfig::default();
+    for (url, suffix, proxy) in [
+        ("http://pac.corp/eu.pac", ".eu.corp", "eu.corp:8080"),
+        ("http://pac.corp/us.pac", ".us.corp", "us.corp:3128"),
+    ] {
+        fetcher.respond(url, MockResponse::Pac(pac_with_helper(suffix, proxy)));
+        assert!(config.load_external_pac(&fetcher, url).await.unwrap());
+    }
+
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains("function isOurs(host)"), "{}", pac);
+    assert!(pac.contains("var PROXY = \"PROXY eu.corp:8080\";"), "{}", pac);
+
+    let decide = |url: &str| evaluate_pac(&pac, url).unwrap().result;
+    assert_eq!(decide("http://wiki.eu.corp/"), "PROXY eu.corp:8080");
+    assert_eq!(decide("http://wiki.us.corp/"), "PROXY us.corp:3128");
+    assert_eq!(decide("http://example.com/"), "DIRECT");
+}
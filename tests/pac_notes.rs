@@ -0,0 +1,50 @@
+//! Item notes carried into the generated PAC as comments
+
+mod common;
+
+use common::{bypass, external, rule};
+use proxycat::pac::{generate_pac_content, PacConfig};
+use proxycat::pac_eval::evaluate_pac;
+
+fn noted() -> PacConfig {
+    let mut config = PacConfig::default();
+    let mut sales = rule("sales.corp", "proxy.corp", 8080);
+    sales.note = Some("Sales  team\nproxy */ still a comment".to_string());
+    let mut retired = rule("old.corp", "legacy.corp", 3128);
+    retired.note = Some("Retired in March".to_string());
+    retired.enabled = false;
+    let mut silent = rule("quiet.corp", "legacy.corp", 3128);
+    silent.enabled = false;
+    config.proxy_rules.extend([sales, retired, silent]);
+
+    let mut intranet = bypass("intranet.corp");
+    intranet.note = Some("Intranet is direct".to_string());
+    config.bypass_list.push_back(intranet);
+
+    let mut partner = external("http://pac.corp/partner.pac", "FindProxyForURL_partner", "return \"\";");
+    partner.note = Some("Partner PAC".to_string());
+    config.external_pac_functions.push_back(partner);
+    config
+}
+
+#[test]
+fn notes_become_single_line_comments() {
+    let pac = generate_pac_content(&noted());
+    for comment in [
+        "// Sales team proxy */ still a comment",
+        "// DISABLED: Retired in March",
+        "// Intranet is direct",
+        "// Partner PAC",
+    ] {
+        assert_eq!(pac.lines().filter(|line| line.trim() == comment).count(), 1, "{} in\n{}", comment, pac);
+    }
+    assert!(!pac.contains("quiet.corp"), "a disabled rule without a note leaves nothing behind");
+    assert!(!pac.contains("legacy.corp"), "a disabled rule only leaves its note");
+}
+
+#[test]
+fn noted_pac_still_runs() {
+    let pac = generate_pac_content(&noted());
+    assert_eq!(evaluate_pac(&pac, "http://sales.corp/").unwrap().result, "PROXY proxy.corp:8080");
+    assert_eq!(evaluate_pac(&pac, "http://old.corp/").unwrap().result, "DIRECT");
+}
@@ -0,0 +1,17 @@
+//! Normalizing the path the PAC is served on
+
+use proxycat::constants::normalize_pac_path;
+
+#[test]
+fn leading_slash_is_added_once() {
+    assert_eq!(normalize_pac_path("proxy.pac").unwrap(), "/proxy.pac");
+    assert_eq!(normalize_pac_path("/proxy.pac").unwrap(), "/proxy.pac");
+    assert_eq!(normalize_pac_path("  pac/team-a.pac ").unwrap(), "/pac/team-a.pac");
+}
+
+#[test]
+fn empty_and_router_special_paths_are_rejected() {
+    for path in ["", "   ", "/", "/{name}.pac", "/:name", "/*rest", "/a b.pac", "/proxy.pac?x=1", "/caf\u{e9}.pac"] {
+        assert!(normalize_pac_path(path).is_err(), "{:?} should be rejected", path);
+    }
+}
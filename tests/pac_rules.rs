@@ -0,0 +1,178 @@
+//! Decisions made by the generated PAC for proxy rules and bypass entries
+
+mod common;
+
+use common::{bypass, rule};
+use proxycat::pac::{generate_pac_content, BypassMatch, PacConfig, MINIMAL_DIRECT_PAC};
+use proxycat::pac_eval::{evaluate_pac, evaluate_pac_for_host};
+
+fn decide(config: &PacConfig, url: &str) -> String {
+    evaluate_pac(&generate_pac_content(config), url).unwrap().result
+}
+
+/// A catch-all rule that would send everything to the proxy
+fn proxy_everything() -> PacConfig {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    config.proxy_rules.push_back(rule("localhost", "proxy.corp", 8080));
+    config.proxy_rules.push_back(rule("127.0.0.1", "proxy.corp", 8080));
+    config
+}
+
+#[test]
+fn loopback_is_direct_with_bypass_entries_disabled() {
+    let mut config = proxy_everything();
+    config.bypass_list.iter_mut().for_each(|item| item.enabled = false);
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    config.bypass_list.back_mut().unwrap().enabled = false;
+
+    assert_eq!(decide(&config, "http://localhost:8080/"), "DIRECT");
+    assert_eq!(decide(&config, "http://127.0.0.1/"), "DIRECT");
+    assert_eq!(decide(&config, "http://[::1]:3000/"), "DIRECT");
+    // Only loopback itself is forced DIRECT
+    assert_eq!(decide(&config, "http://localhost.example.com/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://intranet.corp/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn empty_config_serves_the_minimal_pac() {
+    let mut config = PacConfig::default();
+    config.bypass_list.push_back(bypass("intranet.corp"));
+    let mut disabled = rule("example.com", "proxy.corp", 8080);
+    disabled.enabled = false;
+    config.proxy_rules.push_back(disabled);
+
+    for config in [PacConfig::default(), config] {
+        let pac = generate_pac_content(&config);
+        assert!(pac.trim_end().ends_with(MINIMAL_DIRECT_PAC.trim_end()), "unexpected PAC:\n{}", pac);
+        assert_eq!(evaluate_pac(&pac, "http://example.com/").unwrap().result, "DIRECT");
+    }
+}
+
+#[test]
+fn leading_dot_matches_subdomains_with_dns_domain_is() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule(".corp.example", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass(".direct.corp.example"));
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains("dnsDomainIs(host, '.corp.example')"));
+    assert!(pac.contains("dnsDomainIs(host, '.direct.corp.example')"));
+
+    assert_eq!(decide(&config, "http://git.corp.example/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://a.b.corp.example/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://build.direct.corp.example/"), "DIRECT");
+    assert_eq!(decide(&config, "http://notcorp.example/"), "DIRECT");
+}
+
+#[test]
+fn bypass_with_port_only_matches_that_port() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass("intranet.corp:8443"));
+    config.bypass_list.push_back(bypass("web.corp:80"));
+
+    assert_eq!(decide(&config, "https://intranet.corp:8443/app"), "DIRECT");
+    assert_eq!(decide(&config, "https://intranet.corp/app"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://intranet.corp:8080/"), "PROXY proxy.corp:8080");
+    // The scheme's default port counts as given
+    assert_eq!(decide(&config, "http://web.corp/"), "DIRECT");
+    assert_eq!(decide(&config, "https://web.corp/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn collapsing_rules_keeps_every_decision() {
+    let mut config = PacConfig::default();
+    for host in ["a.example.com", ".corp.example", "b.example.com"] {
+        config.proxy_rules.push_back(rule(host, "proxy.corp", 8080));
+    }
+    config.proxy_rules.push_back(rule("c.example.com", "other.corp", 3128));
+    config.proxy_rules.push_back(rule("d.example.com", "proxy.corp", 8080));
+    let mut collapsed = config.clone();
+    collapsed.collapse_proxy_rules = true;
+
+    let plain_pac = generate_pac_content(&config);
+    let collapsed_pac = generate_pac_content(&collapsed);
+    assert!(collapsed_pac.len() < plain_pac.len());
+    for url in [
+        "http://a.example.com/", "http://b.example.com/", "http://git.corp.example/", "http://c.example.com/",
+        "http://d.example.com/", "http://e.example.com/",
+    ] {
+        assert_eq!(
+            evaluate_pac(&collapsed_pac, url).unwrap().result,
+            evaluate_pac(&plain_pac, url).unwrap().result,
+            "decisions differ for {}", url
+        );
+    }
+    assert_eq!(decide(&collapsed, "http://c.example.com/"), "PROXY other.corp:3128");
+}
+
+#[test]
+fn cidr_rules_match_the_resolved_address_and_host_rules_stay_as_they_were() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("203.0.113.0/24", "dc.corp", 3128));
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains("isInNet(dnsResolve(host), '203.0.113.0', '255.255.255.0')"), "{}", pac);
+    assert!(pac.contains("host === 'example.com'"), "{}", pac);
+    assert_eq!(pac.matches("dnsResolve(host)").count(), 1, "host rules don't resolve");
+
+    assert_eq!(decide(&config, "http://203.0.113.7/"), "PROXY dc.corp:3128");
+    assert_eq!(decide(&config, "http://198.51.100.7/"), "DIRECT");
+    assert_eq!(decide(&config, "http://example.com/"), "PROXY proxy.corp:8080");
+}
+
+#[test]
+fn mixed_case_hosts_match_lowercase_rules() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule(".Example.COM", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass("Intranet.Corp"));
+    config.proxy_rules.push_back(rule("*", "fallback.corp", 3128));
+    // URL parsing would lowercase the host, so pass it the way a browser might
+    let decide = |config: &PacConfig, host: &str| {
+        evaluate_pac_for_host(&generate_pac_content(config), &format!("http://{}/", host), host).unwrap().result
+    };
+
+    assert_eq!(decide(&config, "WWW.Example.com"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "INTRANET.corp"), "DIRECT");
+    assert_eq!(decide(&config, "www.example.com"), "PROXY proxy.corp:8080");
+
+    // Without lowercasing, hosts and patterns are compared exactly as written
+    config.lowercase_host = false;
+    assert_eq!(decide(&config, "WWW.Example.COM"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "www.example.com"), "PROXY fallback.corp:3128");
+    assert_eq!(decide(&config, "Intranet.Corp"), "DIRECT");
+    assert_eq!(decide(&config, "INTRANET.corp"), "PROXY fallback.corp:3128");
+}
+
+#[test]
+fn url_mode_bypass_matches_the_path_and_host_mode_only_the_host() {
+    let mut config = proxy_everything();
+    let mut health = bypass("*/healthz");
+    health.match_mode = BypassMatch::Url;
+    config.bypass_list.push_back(health);
+    config.bypass_list.push_back(bypass("status.corp"));
+
+    assert_eq!(decide(&config, "http://api.example.com/healthz"), "DIRECT");
+    assert_eq!(decide(&config, "http://api.example.com/orders"), "PROXY proxy.corp:8080");
+    assert_eq!(decide(&config, "http://status.corp/anything"), "DIRECT");
+    // A host entry never looks at the path
+    assert_eq!(decide(&config, "http://example.com/status.corp"), "PROXY proxy.corp:8080");
+    assert!(generate_pac_content(&config).contains("shExpMatch(url, '*/healthz')"));
+}
+
+#[test]
+fn quotes_and_backslashes_in_entries_keep_the_pac_loadable() {
+    let mut config = proxy_everything();
+    let mut url_entry = bypass("*/it's/*");
+    url_entry.match_mode = BypassMatch::Url;
+    config.bypass_list.push_back(url_entry);
+    config.bypass_list.push_back(bypass("o'brien.corp"));
+    config.bypass_list.push_back(bypass("back\\slash.corp"));
+
+    let pac = generate_pac_content(&config);
+    assert!(pac.contains(r"shExpMatch(url, '*/it\'s/*')"), "{}", pac);
+    assert!(pac.contains(r"host === 'back\\slash.corp'"), "{}", pac);
+    assert_eq!(decide(&config, "http://example.com/it's/here"), "DIRECT");
+    assert_eq!(decide(&config, "http://example.com/its/here"), "PROXY proxy.corp:8080");
+}
@@ -0,0 +1,33 @@
+//! The configuration as a Chrome extension ProxyConfig
+
+mod common;
+
+use common::{bypass, rule};
+use proxycat::pac::{generate_proxy_config_json, PacConfig};
+
+#[test]
+fn a_catch_all_proxy_becomes_fixed_servers() {
+    let mut config = PacConfig::default();
+    config.proxy_rules.push_back(rule("*", "proxy.corp", 8080));
+    config.bypass_list.push_back(bypass(".intranet.corp"));
+    config.bypass_list.push_back(bypass("10.0.0.0/8"));
+
+    let json = generate_proxy_config_json(&config);
+    assert_eq!(json["mode"], "fixed_servers");
+    assert_eq!(json["rules"]["singleProxy"], serde_json::json!({ "scheme": "http", "host": "proxy.corp", "port": 8080 }));
+    let bypass_list: Vec<&str> = json["rules"]["bypassList"].as_array().unwrap().iter().map(|v| v.as_str().unwrap()).collect();
+    assert!(bypass_list.contains(&"*.intranet.corp"), "{:?}", bypass_list);
+    assert!(bypass_list.contains(&"10.0.0.0/8"), "{:?}", bypass_list);
+    assert!(bypass_list.contains(&"localhost"), "{:?}", bypass_list);
+}
+
+#[test]
+fn per_host_rules_fall_back_to_the_pac_and_no_rules_to_direct() {
+    let mut config = PacConfig::default();
+    assert_eq!(generate_proxy_config_json(&config), serde_json::json!({ "mode": "direct" }));
+
+    config.proxy_rules.push_back(rule("example.com", "proxy.corp", 8080));
+    let json = generate_proxy_config_json(&config);
+    assert_eq!(json["mode"], "pac_script");
+    assert!(json["pacScript"]["data"].as_str().unwrap().contains("function FindProxyForURL"));
+}
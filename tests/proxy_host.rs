@@ -0,0 +1,42 @@
+//! Cleaning up the proxy host users paste into a rule
+
+mod common;
+
+use common::rule;
+use proxycat::constants::APP_CONFIG;
+use proxycat::pac::{ProxyRule, ProxyType};
+
+fn normalized(proxy_host: &str, proxy_port: u16) -> proxycat::error::Result<(ProxyType, String, u16)> {
+    let mut rule: ProxyRule = rule("example.com", proxy_host, proxy_port).rule;
+    rule.normalize_proxy_host()?;
+    Ok((rule.proxy_type, rule.proxy_host, rule.proxy_port))
+}
+
+#[test]
+fn scheme_sets_the_type_and_is_stripped() {
+    assert_eq!(normalized("http://proxy.corp:8080", 0).unwrap(), (ProxyType::Http, "proxy.corp".to_string(), 8080));
+    assert_eq!(normalized(" HTTPS://proxy.corp/ ", 443).unwrap(), (ProxyType::Https, "proxy.corp".to_string(), 443));
+    assert_eq!(normalized("socks5://socks.corp:1080", 1080).unwrap(), (ProxyType::Socks, "socks.corp".to_string(), 1080));
+    assert_eq!(normalized("proxy.corp", 3128).unwrap(), (ProxyType::Http, "proxy.corp".to_string(), 3128));
+}
+
+#[test]
+fn unusable_urls_are_rejected() {
+    assert!(normalized("ftp://proxy.corp:21", 0).is_err(), "unsupported scheme");
+    assert!(normalized("http://proxy.corp:8080/proxy", 0).is_err(), "path");
+    assert!(normalized("http://proxy.corp:8080", 3128).is_err(), "conflicting port");
+}
+
+#[test]
+fn rules_pointing_at_proxycat_itself_are_rejected() {
+    let port = APP_CONFIG.get_port();
+    for own in ["localhost", "127.0.0.1", "[::1]"] {
+        let mut item = rule("example.com", own, port);
+        let error = item.normalize().unwrap_err();
+        assert!(error.to_string().contains("points at ProxyCat itself"), "{}: {}", own, error);
+    }
+
+    // The same loopback host on another port, or a real proxy on ours, is fine
+    assert!(rule("example.com", "127.0.0.1", port.wrapping_add(1)).normalize().is_ok());
+    assert!(rule("example.com", "proxy.corp", port).normalize().is_ok());
+}
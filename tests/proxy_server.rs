@@ -0,0 +1,94 @@
+//! Importing the Windows proxy server setting, including the per-protocol form
+
+mod common;
+
+use common::rule;
+use proxycat::pac::{generate_pac_content, parse_proxy_server, parse_proxy_server_rules, PacConfig, ProxyRuleItem, ProxyType};
+use proxycat::pac_eval::evaluate_pac;
+
+fn summary(proxy_server: &str) -> Vec<(Option<String>, ProxyType, String, u16)> {
+    parse_proxy_server_rules(proxy_server)
+        .into_iter()
+        .map(|rule| (rule.scheme, rule.proxy_type, rule.proxy_host, rule.proxy_port))
+        .collect()
+}
+
+#[test]
+fn multi_protocol_setting_gives_one_rule_per_protocol() {
+    let rules = summary("http=web.corp:8080; https=secure.corp:8443;ftp=ftp.corp;socks=socks.corp:1080");
+    assert_eq!(rules, [
+        (Some("http".to_string()), ProxyType::Http, "web.corp".to_string(), 8080),
+        (Some("https".to_string()), ProxyType::Http, "secure.corp".to_string(), 8443),
+        (Some("ftp".to_string()), ProxyType::Http, "ftp.corp".to_string(), 80),
+        // SOCKS covers every protocol, so it goes last
+        (None, ProxyType::Socks, "socks.corp".to_string(), 1080),
+    ]);
+    assert_eq!(summary("proxy.corp:3128"), [(None, ProxyType::Http, "proxy.corp".to_string(), 3128)]);
+}
+
+#[test]
+fn proxy_addresses_parse_across_ipv6() {
+    let parsed = |proxy: &str| parse_proxy_server(proxy);
+    assert_eq!(parsed("[::1]:3128"), Some(("[::1]".to_string(), 3128)));
+    assert_eq!(parsed("proxy:8080"), Some(("proxy".to_string(), 8080)));
+    assert_eq!(parsed("[2001:db8::1]:8080"), Some(("[2001:db8::1]".to_string(), 8080)));
+    // A bare IPv6 literal has no room for a port, so it gets the default one
+    assert_eq!(parsed("2001:db8::1"), Some(("[2001:db8::1]".to_string(), 80)));
+
+    for malformed in ["", ":8080", "proxy:", "proxy:0", "proxy:65536", "proxy:http", "[::1", "[::1]3128", "[not-ipv6]:80", "a:b:c"] {
+        assert_eq!(parsed(malformed), None, "{:?} should be rejected", malformed);
+    }
+}
+
+#[test]
+fn protocols_that_are_not_schemes_are_skipped() {
+    let rules = summary("HTTP=web.corp:8080;ht'tp=evil.corp:80;=empty.corp:80;gopher+x=g.corp:70");
+    let schemes: Vec<_> = rules.iter().map(|rule| rule.0.as_deref()).collect();
+    assert_eq!(schemes, [Some("http"), Some("gopher+x")]);
+}
+
+#[test]
+fn invalid_schemes_are_rejected() {
+    for scheme in ["https", "svn+ssh", "z39.50r"] {
+        let mut item = rule("*", "proxy.corp", 8080);
+        item.rule.scheme = Some(scheme.to_string());
+        assert!(item.validate().is_ok(), "{} should be accepted", scheme);
+    }
+    for scheme in ["", "HTTPS", "1http", "http'); alert('x", "ht tp"] {
+        let mut item = rule("*", "proxy.corp", 8080);
+        item.rule.scheme = Some(scheme.to_string());
+        assert!(item.validate().is_err(), "{:?} should be rejected", scheme);
+    }
+}
+
+#[test]
+fn scheme_rules_only_match_their_scheme() {
+    let mut config = PacConfig::default();
+    for item in parse_proxy_server_rules("https=secure.corp:8443") {
+        config.proxy_rules.push_back(ProxyRuleItem { rule: item, ..rule("*", "unused", 1) });
+    }
+    let pac = generate_pac_content(&config);
+    let decide = |url| evaluate_pac(&pac, url).unwrap().result;
+    assert_eq!(decide("https://example.com/"), "PROXY secure.corp:8443");
+    assert_eq!(decide("http://example.com/"), "DIRECT");
+}
+
+#[test]
+fn https_only_rules_get_the_https_guard() {
+    let mut config = PacConfig::default();
+    let mut secure = rule("secure.example.com", "secure.corp", 8443);
+    secure.rule.https_only = true;
+    config.proxy_rules.push_back(secure);
+    config.proxy_rules.push_back(rule("plain.example.com", "proxy.corp", 8080));
+
+    let pac = generate_pac_content(&config);
+    let guarded: Vec<_> = pac.lines().filter(|line| line.contains("url.substring(0, 6) === 'https:'")).collect();
+    assert_eq!(guarded.len(), 1, "{}", pac);
+    assert!(guarded[0].contains("secure.example.com"));
+
+    let decide = |url| evaluate_pac(&pac, url).unwrap().result;
+    assert_eq!(decide("https://secure.example.com/"), "PROXY secure.corp:8443");
+    assert_eq!(decide("http://secure.example.com/"), "DIRECT");
+    assert_eq!(decide("http://plain.example.com/"), "PROXY proxy.corp:8080");
+    assert_eq!(decide("https://plain.example.com/"), "PROXY proxy.corp:8080");
+}
@@ -0,0 +1,81 @@
+//! Tagged PAC views, as served from /tagged/<tag>.pac
+
+mod common;
+
+use common::{bypass, external, rule, tags};
+use proxycat::pac::{generate_pac_content, PacConfig, ProxyRuleItem, LOOPBACK_HOSTS};
+use proxycat::pac_eval::evaluate_pac;
+
+fn config() -> PacConfig {
+    let mut config = PacConfig::default();
+    let rules: [(&str, &str, &[&str]); 4] = [
+        ("global.example.com", "global-proxy", &[]),
+        ("dev.example.com", "dev-proxy", &["dev"]),
+        ("prod.example.com", "prod-proxy", &["prod"]),
+        ("shared.example.com", "shared-proxy", &["dev", "prod"]),
+    ];
+    for (host, proxy_host, item_tags) in rules {
+        config.proxy_rules.push_back(ProxyRuleItem { tags: tags(item_tags), ..rule(host, proxy_host, 8080) });
+    }
+    let mut build = bypass("build.corp");
+    build.tags = tags(&["prod"]);
+    config.bypass_list.push_back(build);
+    let mut dev_pac = external("http://pac.dev.corp/proxy.pac", "FindProxyForURL_http___pac_dev_corp_proxy_pac", "return \"\";");
+    dev_pac.tags = tags(&["dev"]);
+    config.external_pac_functions.push_back(dev_pac);
+    config
+}
+
+fn hosts(config: &PacConfig) -> Vec<&str> {
+    config.proxy_rules.iter().map(|item| item.rule.host.as_str()).collect()
+}
+
+#[test]
+fn tagged_view_keeps_matching_and_untagged_items() {
+    let config = config();
+    let dev = config.tagged_view("dev");
+    assert_eq!(hosts(&dev), ["global.example.com", "dev.example.com", "shared.example.com"]);
+    assert_eq!(dev.external_pac_functions.len(), 1);
+    assert!(dev.bypass_list.iter().all(|item| item.host != "build.corp"));
+
+    let prod = config.tagged_view("PROD");
+    assert_eq!(hosts(&prod), ["global.example.com", "prod.example.com", "shared.example.com"]);
+    assert!(prod.external_pac_functions.is_empty());
+    assert!(prod.bypass_list.iter().any(|item| item.host == "build.corp"));
+
+    // The loopback defaults are in every view
+    for view in [&dev, &prod, &config.tagged_view("unknown")] {
+        for host in LOOPBACK_HOSTS {
+            assert!(view.bypass_list.iter().any(|item| item.system && item.host == host));
+        }
+    }
+}
+
+#[test]
+fn tagged_pac_decides_only_with_its_items() {
+    let pac = generate_pac_content(&config().tagged_view("dev"));
+    assert!(!pac.contains("prod-proxy"));
+    assert!(pac.contains("FindProxyForURL_http___pac_dev_corp_proxy_pac"));
+    let decide = |url| evaluate_pac(&pac, url).unwrap().result;
+    assert_eq!(decide("http://dev.example.com/"), "PROXY dev-proxy:8080");
+    assert_eq!(decide("http://global.example.com/"), "PROXY global-proxy:8080");
+    assert_eq!(decide("http://prod.example.com/"), "DIRECT");
+}
+
+#[test]
+fn master_pac_includes_everything() {
+    let pac = generate_pac_content(&config());
+    for proxy in ["global-proxy", "dev-proxy", "prod-proxy", "shared-proxy", "build.corp"] {
+        assert!(pac.contains(proxy), "master PAC is missing {}", proxy);
+    }
+    assert!(pac.contains("FindProxyForURL_http___pac_dev_corp_proxy_pac"));
+    assert_eq!(evaluate_pac(&pac, "http://prod.example.com/").unwrap().result, "PROXY prod-proxy:8080");
+}
+
+#[test]
+fn tags_are_validated() {
+    let tagged = |item_tags: &[&str]| ProxyRuleItem { tags: tags(item_tags), ..rule("a.example.com", "proxy", 8080) };
+    assert!(tagged(&["dev-1", "team_a"]).validate().is_ok());
+    assert!(tagged(&["dev.pac"]).validate().is_err());
+    assert!(tagged(&[""]).validate().is_err());
+}
@@ -0,0 +1,30 @@
+//! Converting the bypass list to and from the Windows ProxyOverride string
+
+mod common;
+
+use common::bypass;
+use proxycat::pac::{bypass_list_from_windows_string, bypass_list_to_windows_string, BypassMatch};
+
+#[test]
+fn local_cidr_and_domain_entries_round_trip() {
+    let (items, bypass_local) = bypass_list_from_windows_string("<local>;10.0.0.0/8;.corp");
+    assert!(bypass_local);
+    let hosts: Vec<&str> = items.iter().map(|item| item.host.as_str()).collect();
+    assert_eq!(hosts, ["10.0.0.0/8", ".corp"]);
+    assert!(items.iter().all(|item| item.enabled && item.match_mode == BypassMatch::Host));
+
+    assert_eq!(bypass_list_to_windows_string(&items, bypass_local), "<local>;10.0.0.0/8;.corp");
+}
+
+#[test]
+fn without_local_disabled_and_url_entries_are_left_out() {
+    let (items, bypass_local) = bypass_list_from_windows_string(" intranet.corp ; ;<LOCAL>");
+    assert!(bypass_local);
+    assert_eq!(bypass_list_to_windows_string(&items, false), "intranet.corp");
+
+    let mut disabled = bypass("old.corp");
+    disabled.enabled = false;
+    let mut url = bypass("*/healthz");
+    url.match_mode = BypassMatch::Url;
+    assert_eq!(bypass_list_to_windows_string(&[bypass(".corp"), disabled, url], true), "<local>;.corp");
+}